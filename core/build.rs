@@ -0,0 +1,99 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+// With the `build-payloads` feature, the DA extensions are rebuilt from source
+// instead of using the prebuilt payloads/da_x.bin.
+//
+// PENUMBRA_DA_X_SRC points to the extension sources (defaults to payloads/da_x),
+// which must have a Makefile that writes da_x.bin into $BUILD_DIR.
+// CROSS_COMPILE selects the toolchain prefix (defaults to arm-none-eabi-).
+fn main() {
+    embed_loaders();
+    build_payloads();
+}
+
+fn build_payloads() {
+    println!("cargo:rerun-if-env-changed=PENUMBRA_DA_X_SRC");
+    println!("cargo:rerun-if-env-changed=CROSS_COMPILE");
+
+    if env::var_os("CARGO_FEATURE_BUILD_PAYLOADS").is_none() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let src = env::var_os("PENUMBRA_DA_X_SRC")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| manifest_dir.join("payloads").join("da_x"));
+    let cross = env::var("CROSS_COMPILE").unwrap_or_else(|_| "arm-none-eabi-".to_string());
+
+    if !src.join("Makefile").is_file() {
+        panic!(
+            "build-payloads: no Makefile in {}, set PENUMBRA_DA_X_SRC to the DA extension sources",
+            src.display()
+        );
+    }
+    println!("cargo:rerun-if-changed={}", src.display());
+
+    let status = Command::new("make")
+        .arg("-C")
+        .arg(&src)
+        .env("CROSS_COMPILE", &cross)
+        .env("BUILD_DIR", &out_dir)
+        .status()
+        .unwrap_or_else(|e| panic!("build-payloads: failed to run make: {e}"));
+
+    if !status.success() {
+        panic!("build-payloads: building the DA extensions failed ({status})");
+    }
+    if !out_dir.join("da_x.bin").is_file() {
+        panic!("build-payloads: make succeeded but did not produce $BUILD_DIR/da_x.bin");
+    }
+}
+
+// With the `embedded-loaders` feature, every .bin / .penumbra-loader file in
+// PENUMBRA_LOADERS_DIR (defaults to payloads/loaders) gets built into the binary,
+// as fallbacks picked by hw_code when no DA was selected. See da/catalog.rs.
+fn embed_loaders() {
+    println!("cargo:rerun-if-env-changed=PENUMBRA_LOADERS_DIR");
+
+    if env::var_os("CARGO_FEATURE_EMBEDDED_LOADERS").is_none() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let dir = env::var_os("PENUMBRA_LOADERS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| manifest_dir.join("payloads").join("loaders"));
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut loaders: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("embedded-loaders: can't read {}: {e}", dir.display()))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "bin" || ext == "penumbra-loader")
+        })
+        .collect();
+    loaders.sort();
+
+    let mut list = String::from("&[\n");
+    for path in &loaders {
+        let name = path.file_name().unwrap().to_string_lossy();
+        list.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            path.canonicalize().unwrap()
+        ));
+    }
+    list.push(']');
+
+    std::fs::write(out_dir.join("embedded_loaders.rs"), list)
+        .unwrap_or_else(|e| panic!("embedded-loaders: failed to write the loader list: {e}"));
+}
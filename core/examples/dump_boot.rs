@@ -0,0 +1,62 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+// Reads the boot partition (boot_a on A/B devices) to a file and prints its SHA-256.
+// With "mock" instead of a DA file it runs against MockMTKPort, a simulated
+// device whose partitions read back as zeros.
+//
+//   cargo run --example dump_boot -- <DA file | mock> [output]
+use penumbra::{Device, MTKPort, MockMTKPort, find_mtk_port};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    env_logger::init();
+    let mut args = std::env::args().skip(1);
+    let da_path = args
+        .next()
+        .expect("Usage: dump_boot <DA file | mock> [output]");
+    let output = PathBuf::from(args.next().unwrap_or_else(|| "boot.img".to_string()));
+    let (port, da_data) = if da_path == "mock" {
+        let mut port = MockMTKPort::new();
+        port.open().await?;
+        (Box::new(port) as Box<dyn MTKPort>, MockMTKPort::da_file())
+    } else {
+        (wait_for_device().await, std::fs::read(&da_path)?)
+    };
+
+    let mut device = Device::init(port, da_data).await?;
+    device.enter_da_mode().await?;
+
+    let info = device
+        .watch_info()
+        .expect("no device info")
+        .borrow()
+        .clone();
+    let name = ["boot", "boot_a"]
+        .into_iter()
+        .find(|name| info.partitions.iter().any(|part| part.name == *name))
+        .expect("no boot partition on this device");
+
+    let mut progress = |read: usize, total: usize| {
+        eprint!("\r{} {}/{} bytes", name, read, total);
+    };
+    let sha256 = device
+        .read_partition_to(name, &output, &mut progress)
+        .await?;
+    eprintln!();
+    println!("Saved {} to {} (sha256 {})", name, output.display(), sha256);
+    Ok(())
+}
+
+async fn wait_for_device() -> Box<dyn MTKPort> {
+    println!("Waiting for a device...");
+    loop {
+        if let Some(port) = find_mtk_port().await {
+            return port;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
@@ -0,0 +1,53 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+// Boots the DA and prints the partition table. Without a DA file it runs
+// against MockMTKPort, a simulated device with a canned GPT.
+//
+//   cargo run --example list_partitions -- [DA file]
+use penumbra::{Device, MTKPort, MockMTKPort, find_mtk_port};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    env_logger::init();
+    let (port, da_data) = match std::env::args().nth(1) {
+        Some(da_path) => (wait_for_device().await, std::fs::read(&da_path)?),
+        None => {
+            println!("No DA file given, using the mock device");
+            let mut port = MockMTKPort::new();
+            port.open().await?;
+            (Box::new(port) as Box<dyn MTKPort>, MockMTKPort::da_file())
+        }
+    };
+
+    let mut device = Device::init(port, da_data).await?;
+    device.enter_da_mode().await?;
+
+    let info = device
+        .watch_info()
+        .expect("no device info")
+        .borrow()
+        .clone();
+    println!(
+        "{} ({:?}), {} partitions",
+        info.chipset,
+        info.storage,
+        info.partitions.len()
+    );
+    for part in &info.partitions {
+        println!("{:<20} {:#012x} {:#x}", part.name, part.address, part.size);
+    }
+    Ok(())
+}
+
+async fn wait_for_device() -> Box<dyn MTKPort> {
+    println!("Waiting for a device...");
+    loop {
+        if let Some(port) = find_mtk_port().await {
+            return port;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
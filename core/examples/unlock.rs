@@ -0,0 +1,43 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+// Unlocks the bootloader. Only checks and reports what would be written unless
+// --write is given, the original seccfg is saved to seccfg-backup.bin either way.
+//
+//   cargo run --example unlock -- <DA file> [--write]
+use penumbra::core::seccfg::LockFlag;
+use penumbra::{Device, find_mtk_port};
+use std::path::Path;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let da_path = args.first().expect("Usage: unlock <DA file> [--write]");
+    let write = args.iter().any(|arg| arg == "--write");
+    let da_data = std::fs::read(da_path)?;
+
+    println!("Waiting for a device...");
+    let port = loop {
+        if let Some(port) = find_mtk_port().await {
+            break port;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    let mut device = Device::init(port, da_data).await?;
+    device.set_dry_run(!write);
+    let report = device
+        .change_lock_state(LockFlag::Unlock, Some(Path::new("seccfg-backup.bin")))
+        .await?;
+
+    println!("Before: {} ({:?})", report.before, report.algo);
+    match report.after {
+        Some(after) => println!("After:  {}", after),
+        None if !write => println!("Dry run, nothing written. Pass --write to unlock."),
+        None => println!("Written, not read back"),
+    }
+    Ok(())
+}
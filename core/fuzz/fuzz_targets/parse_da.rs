@@ -0,0 +1,19 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use penumbra::da::{DAFile, LoaderBundle};
+
+// Loaders come from whatever the user picked in the file browser, so any input
+// has to end in Ok or Err, never a panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(da_file) = DAFile::parse_da(data) {
+        for da in &da_file.das {
+            let _ = (da.get_da1(), da.get_da2());
+        }
+    }
+    let _ = LoaderBundle::parse(data);
+});
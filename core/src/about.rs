@@ -0,0 +1,99 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+
+// What Penumbra embeds or adapts from other projects, so frontends can show
+// proper attribution without hardcoding it. Keep this in sync with the file
+// headers when adding derived code.
+
+pub const LICENSE: &str = "AGPL-3.0-or-later";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentKind {
+    // Binary blob shipped inside the library
+    Payload,
+    // Ported logic (protocol definitions, algorithms, patterns...)
+    Code,
+}
+
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub name: &'static str,
+    pub kind: ComponentKind,
+    // Where the component lives in this crate
+    pub location: &'static str,
+    pub origin: &'static str,
+    pub origin_url: &'static str,
+    pub copyright: &'static str,
+    pub license: &'static str,
+}
+
+const MTKCLIENT: &str = "mtkclient";
+const MTKCLIENT_COPYRIGHT: &str = "2018–2024 bkerler";
+
+static COMPONENTS: &[Component] = &[
+    Component {
+        name: "DA extensions",
+        kind: ComponentKind::Payload,
+        location: "payloads/da_x.bin",
+        origin: MTKCLIENT,
+        origin_url: "https://github.com/bkerler/mtkclient/tree/main/src/da_x",
+        copyright: MTKCLIENT_COPYRIGHT,
+        license: "GPL-3.0-or-later",
+    },
+    Component {
+        name: "XFlash extension commands",
+        kind: ComponentKind::Code,
+        location: "src/da/xflash/exts.rs",
+        origin: MTKCLIENT,
+        origin_url: "https://github.com/bkerler/mtkclient/blob/main/mtkclient/Library/DA/xflash/extension/xflash.py",
+        copyright: MTKCLIENT_COPYRIGHT,
+        license: "GPL-3.0-or-later",
+    },
+    Component {
+        name: "XFlash command definitions",
+        kind: ComponentKind::Code,
+        location: "src/da/xflash/cmds.rs",
+        origin: MTKCLIENT,
+        origin_url: "https://github.com/bkerler/mtkclient/blob/main/mtkclient/Library/DA/xflash/xflash_param.py",
+        copyright: MTKCLIENT_COPYRIGHT,
+        license: "GPL-3.0-or-later",
+    },
+    Component {
+        name: "Seccfg parsing and hashing",
+        kind: ComponentKind::Code,
+        location: "src/core/seccfg.rs",
+        origin: MTKCLIENT,
+        origin_url: "https://github.com/bkerler/mtkclient/blob/main/mtkclient/Library/Hardware/seccfg.py",
+        copyright: MTKCLIENT_COPYRIGHT,
+        license: "GPL-3.0-or-later",
+    },
+    Component {
+        name: "Carbonara protection patterns",
+        kind: ComponentKind::Code,
+        location: "src/exploit/carbonara.rs",
+        origin: MTKCLIENT,
+        origin_url: "https://github.com/bkerler/mtkclient",
+        copyright: MTKCLIENT_COPYRIGHT,
+        license: "GPL-3.0-or-later",
+    },
+];
+
+// Every embedded payload and adapted piece of code, with where it comes from.
+// Anything not listed here is Penumbra's own code, under LICENSE.
+pub fn components() -> &'static [Component] {
+    COMPONENTS
+}
+
+// Plain text attribution notice, one paragraph per component.
+pub fn notice() -> String {
+    let mut out = format!("Penumbra is licensed under the {}.\n", LICENSE);
+    for c in COMPONENTS {
+        out.push_str(&format!(
+            "\n{} ({})\n  Derived from {}: {}\n  Copyright (C) {}\n  License: {}\n",
+            c.name, c.location, c.origin, c.origin_url, c.copyright, c.license
+        ));
+    }
+    out
+}
@@ -0,0 +1,656 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use penumbra::core::audit::{AuditLog, format_timestamp};
+use penumbra::core::crashlog::{CrashRecord, parse_crash_log};
+use penumbra::core::fsprobe::{self, FsKind};
+use penumbra::core::policy::SafetyPolicy;
+use penumbra::core::power::PowerProfile;
+use penumbra::core::profile::ProfileStore;
+use penumbra::core::report::{SessionReport, SessionTrace};
+use penumbra::core::seccfg::{LockFlag, SecCfgV4Algo};
+use penumbra::core::throttle::WriteThrottle;
+use penumbra::da::{DAFile, DaEnvConfig, DaLogChannel, DaLogLevel, LoaderBundle};
+use penumbra::exploit::ExploitPolicy;
+use penumbra::{Device, find_mtk_port};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "Usage: penumbra <command> [options]
+
+Commands:
+  history    Show the destructive operations done so far
+             --file <path>        History file (default: $PENUMBRA_HISTORY or the data dir)
+             --device <soc id>    Only entries for this SoC ID (prefix match)
+             --partition <name>   Only entries touching this partition
+             --failed             Only failed operations
+  bundle     Create or inspect a .penumbra-loader bundle
+             create <out> --da <path> [--preloader <path>] [--patches <path>]
+                    [--name <name>] [--hw-code <hex>]... [--notes <text>]
+             info <bundle>
+  probe      Identify the filesystem in partition dumps (ext4, erofs, f2fs)
+             <dump>...
+  crashlog   Show the recent panic reasons from the expdb/logdb crash logs
+             <dump>...            Parse dumps instead of reading the device
+             --da <path>          DA file to boot, to read them from the device
+             --out <dir>          Also save the raw partitions there
+             --restore <dir>      Write back crash logs saved with --out
+  report     Bundle what a bug report needs into one JSON file to attach to an issue
+             --trace <path>       Event trace of the session that failed
+                                  (default: $PENUMBRA_TRACE or the data dir)
+             --da <path>          DA file that was used
+             --device             Also read the info of the connected device (needs --da)
+             --redact             Replace the SoC ID and MEID with a hash of them
+             --out <path>         Where to write it (default: penumbra-report-<time>.json)
+  unlock     Unlock the bootloader by rewriting seccfg
+  lock       Lock the bootloader again
+             --da <path>          DA file to boot (required)
+             --backup <path>      Where to save the current seccfg
+                                  (default: seccfg-<soc id>-<time>.bin)
+             --algo <algo>        Force the seccfg algorithm (sw, hw, hwv3, hwv4)
+             --dry-run            Check everything, but don't write
+             --fastboot           Reboot to fastboot afterwards and print its serial
+             --low-power          Go easy on devices running off USB power alone
+             --write-rate <KiB/s> Limit how fast seccfg gets written
+             --write-delay <ms>   Wait between write packets
+             --exploit <mode>     Patch DA2 with an exploit: auto (default), skip,
+                                  force, or an exploit name
+             --da-log <level>     DA log level: trace, debug, info (default),
+                                  warning or error
+             --da-log-channel <c> Where the DA logs to: none, uart (default), usb
+                                  or both
+             --no-profile         Don't use or update the settings saved for this device
+                                  ($PENUMBRA_PROFILES or the data dir)";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("history") => history(&args[1..]),
+        Some("bundle") => bundle(&args[1..]),
+        Some("probe") => probe(&args[1..]),
+        Some("crashlog") => crashlog(&args[1..]),
+        Some("report") => report(&args[1..]),
+        Some("unlock") => lock_state(LockFlag::Unlock, &args[1..]),
+        Some("lock") => lock_state(LockFlag::Lock, &args[1..]),
+        Some("help" | "--help" | "-h") | None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(cmd) => Err(format!("Unknown command '{}'\n\n{}", cmd, USAGE)),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn history(args: &[String]) -> Result<(), String> {
+    let mut file = None;
+    let mut device = None;
+    let mut partition = None;
+    let mut failed_only = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--file" => file = Some(PathBuf::from(value()?)),
+            "--device" => device = Some(value()?.to_ascii_lowercase()),
+            "--partition" => partition = Some(value()?),
+            "--failed" => failed_only = true,
+            _ => return Err(format!("Unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let path = file
+        .or_else(AuditLog::default_path)
+        .ok_or("Could not find the history file, use --file")?;
+    let entries = AuditLog::new(&path)
+        .entries()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let entries: Vec<_> = entries
+        .iter()
+        .filter(|e| {
+            device
+                .as_ref()
+                .is_none_or(|d| e.chip.soc_id_hex().starts_with(d))
+        })
+        .filter(|e| partition.as_ref().is_none_or(|p| &e.partition == p))
+        .filter(|e| !failed_only || !e.succeeded())
+        .collect();
+
+    if entries.is_empty() {
+        println!("No matching entries in {}", path.display());
+        return Ok(());
+    }
+
+    for entry in entries {
+        let short = |hash: &Option<String>| match hash {
+            Some(hash) => hash.chars().take(12).collect(),
+            None => "-".to_string(),
+        };
+        println!(
+            "{}  {:<16}  hw {:04x}  {:<7} {:<16} {} -> {}  {}",
+            format_timestamp(entry.timestamp),
+            entry.chip.soc_id_hex().chars().take(16).collect::<String>(),
+            entry.chip.hw_code,
+            entry.operation,
+            entry.partition,
+            short(&entry.hash_before),
+            short(&entry.hash_after),
+            entry.result
+        );
+    }
+
+    Ok(())
+}
+
+fn bundle(args: &[String]) -> Result<(), String> {
+    match args.first().map(String::as_str) {
+        Some("create") => bundle_create(&args[1..]),
+        Some("info") => match args.get(1) {
+            Some(path) => bundle_info(Path::new(path)),
+            None => Err(format!("bundle info needs a file\n\n{}", USAGE)),
+        },
+        _ => Err(format!("Unknown bundle command\n\n{}", USAGE)),
+    }
+}
+
+fn bundle_create(args: &[String]) -> Result<(), String> {
+    let read =
+        |path: &str| std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e));
+
+    let mut out = None;
+    let mut bundle = LoaderBundle::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--da" => bundle.da = read(&value()?)?,
+            "--preloader" => bundle.preloader = Some(read(&value()?)?),
+            "--patches" => {
+                let path = value()?;
+                let text = String::from_utf8(read(&path)?)
+                    .map_err(|_| format!("{} is not a text file", path))?;
+                bundle.patches = Some(text);
+            }
+            "--name" => bundle.metadata.name = value()?,
+            "--hw-code" => {
+                let code = value()?;
+                let code = u16::from_str_radix(code.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("Invalid hw_code '{}'", code))?;
+                bundle.metadata.hw_codes.push(code);
+            }
+            "--notes" => bundle.metadata.notes = value()?,
+            _ if !arg.starts_with("--") && out.is_none() => out = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let out = out.ok_or("bundle create needs an output file")?;
+    if bundle.da.is_empty() {
+        return Err("bundle create needs --da".to_string());
+    }
+    // Refuse to pack something the loader won't accept later
+    bundle
+        .da_file()
+        .map_err(|e| format!("The DA file is not valid: {}", e))?;
+
+    bundle
+        .save(&out)
+        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+    println!("Wrote {}", out.display());
+    Ok(())
+}
+
+fn bundle_info(path: &Path) -> Result<(), String> {
+    let bundle = LoaderBundle::load(path)
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+    let da = bundle
+        .da_file()
+        .map_err(|e| format!("The bundled DA is not valid: {}", e))?;
+
+    let meta = &bundle.metadata;
+    let codes: Vec<String> = meta.hw_codes.iter().map(|c| format!("{:04x}", c)).collect();
+    println!(
+        "Name:      {}",
+        if meta.name.is_empty() {
+            "-"
+        } else {
+            &meta.name
+        }
+    );
+    println!(
+        "HW codes:  {}",
+        if codes.is_empty() {
+            "any".to_string()
+        } else {
+            codes.join(", ")
+        }
+    );
+    println!(
+        "DA:        {:?}, {} bytes, {} SoC entries",
+        da.da_type,
+        bundle.da.len(),
+        da.das.len()
+    );
+    match &bundle.preloader {
+        Some(preloader) => println!("Preloader: {} bytes", preloader.len()),
+        None => println!("Preloader: -"),
+    }
+    match &bundle.patches {
+        Some(patches) => println!("Patches:   {} lines", patches.lines().count()),
+        None => println!("Patches:   -"),
+    }
+    for line in meta.notes.lines() {
+        println!("Notes:     {}", line);
+    }
+    Ok(())
+}
+
+fn probe(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(format!("probe needs at least one dump\n\n{}", USAGE));
+    }
+
+    for path in args.iter().map(Path::new) {
+        let result = fsprobe::probe_file(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        println!("{}: {}", path.display(), result.kind);
+        if !matches!(
+            result.kind,
+            FsKind::Blank | FsKind::Unknown | FsKind::AndroidSparse
+        ) {
+            println!("  Label:     {}", result.label.as_deref().unwrap_or("-"));
+            println!("  UUID:      {}", result.uuid.as_deref().unwrap_or("-"));
+            println!("  Block:     {} bytes", result.block_size);
+        }
+        if let Some(total) = result.total_bytes {
+            println!("  Size:      {} bytes", total);
+        }
+        if let Some(free) = result.free_bytes {
+            println!("  Free:      {} bytes", free);
+        }
+        for hint in &result.hints {
+            println!("  - {}", hint);
+        }
+    }
+    Ok(())
+}
+
+fn crashlog(args: &[String]) -> Result<(), String> {
+    let mut da = None;
+    let mut out = None;
+    let mut restore = None;
+    let mut dumps = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--da" => da = Some(PathBuf::from(value()?)),
+            "--out" => out = Some(PathBuf::from(value()?)),
+            "--restore" => restore = Some(PathBuf::from(value()?)),
+            opt if opt.starts_with("--") => {
+                return Err(format!("Unknown option '{}'\n\n{}", arg, USAGE));
+            }
+            _ => dumps.push(PathBuf::from(arg)),
+        }
+    }
+
+    if da.is_some() && !dumps.is_empty() {
+        return Err("Either parse dumps or read the device with --da, not both".to_string());
+    }
+
+    // Dumps on their own don't need a device, named after the partition they came from
+    let Some(da) = da else {
+        if dumps.is_empty() {
+            return Err(format!(
+                "crashlog needs --da or dumps to parse\n\n{}",
+                USAGE
+            ));
+        }
+        let mut records = Vec::new();
+        for path in &dumps {
+            let data = std::fs::read(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            records.extend(parse_crash_log(&name, &data));
+        }
+        print_crash_records(&records);
+        return Ok(());
+    };
+
+    let da_data =
+        std::fs::read(&da).map_err(|e| format!("Failed to read {}: {}", da.display(), e))?;
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    runtime.block_on(async {
+        println!("Waiting for a device...");
+        let port = loop {
+            if let Some(port) = find_mtk_port().await {
+                break port;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        };
+        let mut device = Device::init(port, da_data)
+            .await
+            .map_err(|e| format!("Device init failed: {}", e))?;
+
+        if let Some(dir) = &restore {
+            let restored = device
+                .restore_crash_logs(dir, &mut |_, _, _| {})
+                .await
+                .map_err(|e| format!("Restore failed: {}", e))?;
+            if restored.is_empty() {
+                println!("No crash logs in {}", dir.display());
+            } else {
+                println!("Restored {}", restored.join(", "));
+            }
+            return Ok(());
+        }
+
+        if let Some(dir) = &out {
+            let written = device
+                .dump_crash_logs(dir, &mut |_, _, _| {})
+                .await
+                .map_err(|e| format!("Dump failed: {}", e))?;
+            for path in written {
+                println!("Saved {}", path.display());
+            }
+        }
+        let records = device
+            .crash_reasons()
+            .await
+            .map_err(|e| format!("Failed to read the crash logs: {}", e))?;
+        print_crash_records(&records);
+        Ok::<(), String>(())
+    })
+}
+
+fn print_crash_records(records: &[CrashRecord]) {
+    if records.is_empty() {
+        println!("No panic reasons found");
+        return;
+    }
+    for record in records {
+        println!("{}", record);
+        for line in &record.context {
+            println!("    {}", line);
+        }
+    }
+}
+
+fn report(args: &[String]) -> Result<(), String> {
+    let mut trace = None;
+    let mut da = None;
+    let mut with_device = false;
+    let mut redact = false;
+    let mut out = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--trace" => trace = Some(PathBuf::from(value()?)),
+            "--da" => da = Some(PathBuf::from(value()?)),
+            "--device" => with_device = true,
+            "--redact" => redact = true,
+            "--out" => out = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("Unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+    if with_device && da.is_none() {
+        return Err(format!("--device needs --da\n\n{}", USAGE));
+    }
+
+    // No trace is fine, the rest still helps
+    let events = match trace.or_else(SessionTrace::default_path) {
+        Some(path) if path.exists() => SessionTrace::load(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+        Some(path) => {
+            println!("No trace at {}, leaving it out", path.display());
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    let mut report = SessionReport::new(events);
+
+    if let Some(da) = &da {
+        let da_data =
+            std::fs::read(da).map_err(|e| format!("Failed to read {}: {}", da.display(), e))?;
+        let da_file = DAFile::parse_da(&da_data)
+            .map_err(|e| format!("{} is not a valid DA: {}", da.display(), e))?;
+        report = report.with_da(&da_file, &da_data);
+
+        if with_device {
+            let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+            let info = runtime.block_on(async {
+                println!("Waiting for a device...");
+                let port = loop {
+                    if let Some(port) = find_mtk_port().await {
+                        break port;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                };
+                let device = Device::init(port, da_data)
+                    .await
+                    .map_err(|e| format!("Device init failed: {}", e))?;
+                device
+                    .watch_info()
+                    .map(|info| info.borrow().clone())
+                    .ok_or_else(|| "Device info not available".to_string())
+            })?;
+            report = report.with_device(&info);
+        }
+    }
+
+    if redact {
+        report.redact();
+    }
+
+    let out = out.unwrap_or_else(|| {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        PathBuf::from(format!("penumbra-report-{}.json", time))
+    });
+    report
+        .save(&out)
+        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+
+    println!(
+        "Wrote {} ({} events, {} failed operations)",
+        out.display(),
+        report.events.len(),
+        report.failures.len()
+    );
+    if !redact && report.device.is_some() {
+        println!("It contains the SoC ID and MEID of the device, use --redact to leave them out");
+    }
+    Ok(())
+}
+
+fn lock_state(flag: LockFlag, args: &[String]) -> Result<(), String> {
+    let mut da = None;
+    let mut backup = None;
+    let mut algo = None;
+    let mut dry_run = false;
+    let mut fastboot = false;
+    let mut low_power = false;
+    let mut throttle = WriteThrottle::default();
+    let mut use_profile = true;
+    let mut exploit = None;
+    let mut da_env = DaEnvConfig::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--da" => da = Some(PathBuf::from(value()?)),
+            "--backup" => backup = Some(PathBuf::from(value()?)),
+            "--algo" => algo = Some(parse_algo(&value()?)?),
+            "--dry-run" => dry_run = true,
+            "--fastboot" => fastboot = true,
+            "--low-power" => low_power = true,
+            "--no-profile" => use_profile = false,
+            "--exploit" => exploit = Some(ExploitPolicy::parse(&value()?)?),
+            "--da-log" => da_env.log_level = DaLogLevel::parse(&value()?)?,
+            "--da-log-channel" => da_env.log_channel = DaLogChannel::parse(&value()?)?,
+            "--write-rate" => {
+                let rate: u64 = value()?
+                    .parse()
+                    .map_err(|_| "--write-rate needs a number (KiB/s)".to_string())?;
+                throttle =
+                    WriteThrottle::rate(rate.saturating_mul(1024)).with_delay(throttle.delay);
+            }
+            "--write-delay" => {
+                let ms = value()?
+                    .parse()
+                    .map_err(|_| "--write-delay needs a number (ms)".to_string())?;
+                throttle.delay = Duration::from_millis(ms);
+            }
+            _ => return Err(format!("Unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let da = da.ok_or_else(|| format!("--da is required\n\n{}", USAGE))?;
+    let da_data =
+        std::fs::read(&da).map_err(|e| format!("Failed to read {}: {}", da.display(), e))?;
+    // The workspace's .penumbra.conf, same rules as the TUI. A broken one stops
+    // the job before anything is touched, the defaults may not protect what it should
+    let policy = std::env::current_dir()
+        .and_then(|dir| SafetyPolicy::for_workspace(&dir))
+        .map_err(|e| format!("Refusing to run with a broken policy file: {}", e))?;
+
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    runtime.block_on(async {
+        println!("Waiting for a device...");
+        let port = loop {
+            if let Some(port) = find_mtk_port().await {
+                break port;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        };
+
+        let mut device = Device::init(port, da_data)
+            .await
+            .map_err(|e| format!("Device init failed: {}", e))?;
+        device.set_dry_run(dry_run);
+        device.set_seccfg_algo(algo);
+        // Options given on the command line win over the saved profile
+        if use_profile {
+            device.set_profile_store(ProfileStore::default_path().map(ProfileStore::new));
+            if device.apply_known_profile().is_some() {
+                println!("Using the settings saved for this device");
+            }
+        }
+        if low_power {
+            device.set_power_profile(PowerProfile::LowPower);
+        }
+        if !throttle.is_unlimited() {
+            device.set_write_throttle(throttle);
+        }
+        if let Some(exploit) = exploit {
+            device.set_exploit_policy(exploit);
+        }
+        if da_env != DaEnvConfig::default() {
+            device.set_da_env(da_env);
+        }
+        if let Some(source) = &policy.source {
+            println!("Policy:     {}", source.display());
+        }
+        device.set_safety_policy(policy);
+
+        let info = device
+            .watch_info()
+            .map(|info| info.borrow().clone())
+            .ok_or("Device info not available")?;
+        println!("Device:     {} ({})", info.chipset, info.chip);
+
+        let backup = backup.unwrap_or_else(|| {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            PathBuf::from(format!(
+                "seccfg-{}-{}.bin",
+                hex_prefix(&info.chip.soc_id),
+                time
+            ))
+        });
+
+        let report = device
+            .change_lock_state(flag, Some(&backup))
+            .await
+            .map_err(|e| format!("Failed: {}", e))?;
+
+        println!("Before:     {}", report.before);
+        println!("Algorithm:  {:?}", report.algo);
+        if let Some(path) = &report.backup {
+            println!("Backup:     {}", path.display());
+        }
+        match report.after {
+            Some(after) => println!("After:      {} (verified by readback)", after),
+            None if dry_run => println!("After:      nothing written (dry run)"),
+            None => println!("After:      not read back (low power)"),
+        }
+
+        if fastboot {
+            println!("Rebooting to fastboot...");
+            let fastboot = device
+                .reboot_to_fastboot(std::time::Duration::from_secs(60))
+                .await
+                .map_err(|e| format!("Fastboot handoff failed: {}", e))?;
+            println!("Fastboot:   {} ({})", fastboot.serial, fastboot.location);
+        }
+        Ok::<(), String>(())
+    })
+}
+
+fn parse_algo(name: &str) -> Result<SecCfgV4Algo, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "sw" => Ok(SecCfgV4Algo::SW),
+        "hw" => Ok(SecCfgV4Algo::HW),
+        "hwv3" => Ok(SecCfgV4Algo::HWv3),
+        "hwv4" => Ok(SecCfgV4Algo::HWv4),
+        _ => Err(format!("Unknown seccfg algorithm '{}'", name)),
+    }
+}
+
+// Enough of the SoC ID to tell backups of different devices apart
+fn hex_prefix(data: &[u8]) -> String {
+    data.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
@@ -2,7 +2,7 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
-use crate::connection::port::{ConnectionType, KNOWN_PORTS, MTKPort};
+use crate::connection::port::{ConnectionType, MTKPort, connection_type_for};
 use log::{debug, error, info};
 use rusb::{Context, Device, DeviceHandle, GlobalContext, UsbContext};
 use rusb::{Direction, Recipient, RequestType};
@@ -12,6 +12,9 @@ use tokio::io::{Error, ErrorKind, Result};
 use tokio::sync::Mutex;
 use tokio::task;
 
+const CLASS_CDC_COMM: u8 = 0x02;
+const CLASS_CDC_DATA: u8 = 0x0A;
+
 #[derive(Debug, Clone)]
 pub struct UsbMTKPort {
     handle: Arc<Mutex<DeviceHandle<Context>>>,
@@ -25,6 +28,10 @@ pub struct UsbMTKPort {
     out_max_packet_size: usize,
     vid: u16,
     pid: u16,
+    // Interfaces claimed on open, the CDC communication one (if any) and the data one
+    interfaces: Vec<u8>,
+    // Target of the CDC class requests in setup_cdc
+    control_interface: u16,
 }
 
 impl UsbMTKPort {
@@ -39,6 +46,8 @@ impl UsbMTKPort {
         out_max_packet_size: usize,
         vid: u16,
         pid: u16,
+        interfaces: Vec<u8>,
+        control_interface: u16,
     ) -> Self {
         Self {
             handle: Arc::new(Mutex::new(handle)),
@@ -52,12 +61,47 @@ impl UsbMTKPort {
             out_max_packet_size,
             vid,
             pid,
+            interfaces,
+            control_interface,
+        }
+    }
+
+    // Looks for the CDC communication and data interfaces. Most BROMs expose them
+    // as 0 and 1, but some only have the data one, or use other numbers.
+    // Without a CDC data interface, falls back to the first one with bulk endpoints.
+    fn find_cdc_interfaces(device: &Device<Context>) -> Option<(Option<u8>, u8)> {
+        let config = device.active_config_descriptor().ok()?;
+        let mut comm = None;
+        let mut data = None;
+        let mut bulk = None;
+
+        for interface in config.interfaces() {
+            for desc in interface.descriptors() {
+                match desc.class_code() {
+                    CLASS_CDC_COMM if comm.is_none() => comm = Some(desc.interface_number()),
+                    CLASS_CDC_DATA if data.is_none() => data = Some(desc.interface_number()),
+                    _ => {}
+                }
+                let has_bulk = desc
+                    .endpoint_descriptors()
+                    .any(|ep| ep.transfer_type() == rusb::TransferType::Bulk);
+                if has_bulk && bulk.is_none() {
+                    bulk = Some(desc.interface_number());
+                }
+            }
         }
+
+        let data = data.or(bulk)?;
+        debug!("CDC interfaces: comm {:?}, data {}", comm, data);
+        Some((comm, data))
     }
 
     // This just serve the purpose of finding bEndpointAddress for bulk IN and OUT, as well
-    // as their max packet sizes.
-    fn find_bulk_endpoints(device: &Device<Context>) -> Option<(u8, usize, u8, usize)> {
+    // as their max packet sizes, on the given (data) interface.
+    fn find_bulk_endpoints(
+        device: &Device<Context>,
+        data_interface: u8,
+    ) -> Option<(u8, usize, u8, usize)> {
         let config = device.active_config_descriptor().ok()?;
         let mut in_ep = None;
         let mut in_sz = None;
@@ -66,6 +110,9 @@ impl UsbMTKPort {
 
         for interface in config.interfaces() {
             for interface_desc in interface.descriptors() {
+                if interface_desc.interface_number() != data_interface {
+                    continue;
+                }
                 for endpoint in interface_desc.endpoint_descriptors() {
                     if endpoint.transfer_type() == rusb::TransferType::Bulk {
                         match endpoint.direction() {
@@ -89,11 +136,11 @@ impl UsbMTKPort {
 
     pub async fn setup_cdc(&self) -> Result<()> {
         let handle = self.handle.clone();
+        let cdc_interface = self.control_interface;
 
         task::spawn_blocking(move || -> Result<()> {
             let handle = handle.blocking_lock();
 
-            const CDC_INTERFACE: u16 = 1;
             const SET_LINE_CODING: u8 = 0x20;
             const SET_CONTROL_LINE_STATE: u8 = 0x22;
             const LINE_CODING: [u8; 7] = [0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x08];
@@ -107,7 +154,7 @@ impl UsbMTKPort {
                     request_type,
                     SET_LINE_CODING,
                     0,
-                    CDC_INTERFACE,
+                    cdc_interface,
                     &LINE_CODING,
                     Duration::from_millis(100),
                 )
@@ -118,7 +165,7 @@ impl UsbMTKPort {
                     request_type,
                     SET_CONTROL_LINE_STATE,
                     CONTROL_LINE_STATE,
-                    CDC_INTERFACE,
+                    cdc_interface,
                     &[],
                     Duration::from_millis(100),
                 )
@@ -133,12 +180,7 @@ impl UsbMTKPort {
         let descriptor = device.device_descriptor().ok()?;
         let (vid, pid) = (descriptor.vendor_id(), descriptor.product_id());
 
-        let connection_type = match (vid, pid) {
-            (0x0e8d, 0x0003) => ConnectionType::Brom,
-            (0x0e8d, 0x2000) => ConnectionType::Preloader,
-            (0x0e8d, 0x2001) => ConnectionType::Da,
-            _ => return None,
-        };
+        let connection_type = connection_type_for(vid, pid)?;
 
         let baudrate = match connection_type {
             ConnectionType::Brom => 115_200,
@@ -149,8 +191,13 @@ impl UsbMTKPort {
 
         let handle = tokio::task::block_in_place(|| device.open().ok())?;
 
+        let (comm_interface, data_interface) = Self::find_cdc_interfaces(&device)?;
         let (in_endpoint, in_max_packet_size, out_endpoint, out_max_packet_size) =
-            Self::find_bulk_endpoints(&device)?;
+            Self::find_bulk_endpoints(&device, data_interface)?;
+
+        let mut interfaces: Vec<u8> = comm_interface.into_iter().collect();
+        interfaces.push(data_interface);
+        let control_interface = comm_interface.unwrap_or(data_interface) as u16;
 
         Some(Self::new(
             handle,
@@ -163,6 +210,8 @@ impl UsbMTKPort {
             out_max_packet_size,
             vid,
             pid,
+            interfaces,
+            control_interface,
         ))
     }
 
@@ -193,12 +242,13 @@ impl MTKPort for UsbMTKPort {
 
         let handle = self.handle.clone();
         let port_name = self.port_name.clone();
+        let interfaces = self.interfaces.clone();
 
         // RUSB is sync, so we need to spawn blocking here
         tokio::task::spawn_blocking(move || -> Result<()> {
             let handle = handle.blocking_lock();
 
-            for interface in 0..=1 {
+            for interface in interfaces {
                 #[cfg(not(target_os = "windows"))]
                 {
                     match handle.kernel_driver_active(interface) {
@@ -262,11 +312,12 @@ impl MTKPort for UsbMTKPort {
 
         let handle = self.handle.clone();
         let port_name = self.port_name.clone();
+        let interfaces = self.interfaces.clone();
 
         tokio::task::spawn_blocking(move || -> Result<()> {
             let handle = handle.blocking_lock();
 
-            for iface in 0..=1 {
+            for iface in interfaces {
                 if let Err(e) = handle.release_interface(iface) {
                     error!("Failed to release interface {}: {:?}", iface, e);
                 }
@@ -0,0 +1,434 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::port::{ConnectionType, MTKPort};
+use crate::core::checksums::crc32;
+use crate::da::xflash::cmds::{Cmd, DataType};
+use log::debug;
+use std::collections::VecDeque;
+use std::fmt;
+use tokio::io::{Error, ErrorKind, Result};
+
+const SECTOR: usize = 512;
+const GPT_ENTRIES: usize = 128;
+const GPT_ENTRY_SIZE: usize = 128;
+// Header, entries and the protective MBR
+const GPT_END: usize = SECTOR * 2 + GPT_ENTRIES * GPT_ENTRY_SIZE;
+
+// How the mock answers what it doesn't know, same codes as a real DA
+const STATUS_UNSUPPORTED_CMD: u32 = 0xC0010003;
+const STATUS_UNSUPPORTED_CTRL_CODE: u32 = 0xC0010004;
+
+// ReadData answers come in packets this big at most
+const READ_CHUNK: usize = 0x10000;
+
+// MT6768, with the sub code and version of a retail unit
+const MOCK_HW_CODE: u16 = 0x0707;
+const MOCK_HW_SUB_CODE: u16 = 0x8A00;
+const MOCK_HW_VER: u16 = 0xCA00;
+
+// Name and size of the partitions on the canned disk, laid out back to back
+const MOCK_PARTITIONS: &[(&str, u64)] = &[
+    ("proinfo", 0x30_0000),
+    ("nvram", 0x40_0000),
+    ("seccfg", 0x80_0000),
+    ("lk_a", 0x20_0000),
+    ("boot_a", 0x200_0000),
+    ("super", 0x1_2000_0000),
+    ("userdata", 0x1_0000_0000),
+];
+const FIRST_USABLE_LBA: u64 = 0x800;
+
+// Stands in for a device already running a V5 (XFlash) DA, with a disk image
+// as its storage. Knows enough of the protocol to attach, read the chip ID and
+// read flash, so the examples and tests run without hardware. Everything else
+// gets an error status, the way an older DA answers codes it doesn't have.
+pub struct MockMTKPort {
+    disk: Vec<u8>,
+    is_open: bool,
+    // Host to device bytes that don't make a whole frame yet
+    rx: Vec<u8>,
+    // Device to host bytes waiting to be read
+    tx: VecDeque<u8>,
+    state: MockState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MockState {
+    Idle,
+    // DeviceCtrl was accepted, the code comes next
+    DeviceCtrl,
+    // ReadData was accepted, the parameters come next
+    ReadParams,
+    // A chunk was sent, waiting for the host to ack it
+    Reading { addr: u64, left: u64 },
+}
+
+impl Default for MockMTKPort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MockMTKPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockMTKPort")
+            .field("disk_len", &self.disk.len())
+            .field("is_open", &self.is_open)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl MockMTKPort {
+    // A device with the canned GPT, see mock_disk()
+    pub fn new() -> Self {
+        Self::with_disk(mock_disk())
+    }
+
+    // `disk` is the start of the storage, reads past its end return zeros
+    pub fn with_disk(disk: Vec<u8>) -> Self {
+        MockMTKPort {
+            disk,
+            is_open: false,
+            rx: Vec::new(),
+            tx: VecDeque::new(),
+            state: MockState::Idle,
+        }
+    }
+
+    // Smallest V5 loader Device::init accepts for this device: one SoC entry
+    // and no regions. Nothing gets uploaded, the mock DA is already running.
+    pub fn da_file() -> Vec<u8> {
+        let mut da = vec![0u8; 0x6C + 0xDC];
+        da[0..18].copy_from_slice(b"MTK_DOWNLOAD_AGENT");
+        let id = b"MOCK_DA_MT6768";
+        da[0x20..0x20 + id.len()].copy_from_slice(id);
+        da[0x60..0x64].copy_from_slice(&4u32.to_le_bytes());
+        da[0x68..0x6C].copy_from_slice(&1u32.to_le_bytes());
+
+        let entry = &mut da[0x6C..];
+        entry[0x00..0x02].copy_from_slice(&0xDADAu16.to_le_bytes());
+        entry[0x02..0x04].copy_from_slice(&0x6768u16.to_le_bytes());
+        entry[0x04..0x06].copy_from_slice(&MOCK_HW_SUB_CODE.to_le_bytes());
+        entry[0x06..0x08].copy_from_slice(&MOCK_HW_VER.to_le_bytes());
+        da
+    }
+
+    fn queue_frame(&mut self, payload: &[u8]) {
+        self.tx.extend((Cmd::Magic as u32).to_le_bytes());
+        self.tx
+            .extend((DataType::ProtocolFlow as u32).to_le_bytes());
+        self.tx.extend((payload.len() as u32).to_le_bytes());
+        self.tx.extend(payload);
+    }
+
+    fn queue_status(&mut self, status: u32) {
+        self.queue_frame(&status.to_le_bytes());
+    }
+
+    // Next ReadData packet, from the disk image or zeros past its end
+    fn queue_chunk(&mut self, addr: u64, left: u64) {
+        let len = left.min(READ_CHUNK as u64) as usize;
+        let mut chunk = vec![0u8; len];
+        if let Ok(start) = usize::try_from(addr)
+            && start < self.disk.len()
+        {
+            let end = self.disk.len().min(start + len);
+            chunk[..end - start].copy_from_slice(&self.disk[start..end]);
+        }
+        self.queue_frame(&chunk);
+        self.state = MockState::Reading {
+            addr: addr + len as u64,
+            left: left - len as u64,
+        };
+    }
+
+    fn handle_frame(&mut self, payload: &[u8]) -> Result<()> {
+        let word = || {
+            payload
+                .get(0..4)
+                .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Mock DA expected a u32, got {} bytes", payload.len()),
+                    )
+                })
+        };
+
+        match self.state {
+            MockState::Idle => match Cmd::try_from(word()?) {
+                Ok(Cmd::DeviceCtrl) => {
+                    self.queue_status(0);
+                    self.state = MockState::DeviceCtrl;
+                }
+                Ok(Cmd::ReadData) => {
+                    self.queue_status(0);
+                    self.state = MockState::ReadParams;
+                }
+                cmd => {
+                    debug!("[Mock] Unsupported command {:X?}", cmd);
+                    self.queue_status(STATUS_UNSUPPORTED_CMD);
+                }
+            },
+            MockState::DeviceCtrl => {
+                self.state = MockState::Idle;
+                let data = match Cmd::try_from(word()?) {
+                    // High speed
+                    Ok(Cmd::GetUsbSpeed) => 1u32.to_le_bytes().to_vec(),
+                    Ok(Cmd::GetChipId) => [MOCK_HW_CODE, MOCK_HW_SUB_CODE, MOCK_HW_VER, 0]
+                        .iter()
+                        .flat_map(|field| field.to_le_bytes())
+                        .collect(),
+                    code => {
+                        debug!("[Mock] Unsupported DeviceCtrl code {:X?}", code);
+                        self.queue_status(STATUS_UNSUPPORTED_CTRL_CODE);
+                        return Ok(());
+                    }
+                };
+                self.queue_status(0);
+                self.queue_frame(&data);
+                self.queue_status(0);
+            }
+            MockState::ReadParams => {
+                // Storage u32, part u32, address u64, size u64, NAND fields
+                let field = |at: usize| {
+                    payload
+                        .get(at..at + 8)
+                        .map(|f| u64::from_le_bytes(f.try_into().unwrap()))
+                        .ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::InvalidData,
+                                "Mock DA got short ReadData parameters",
+                            )
+                        })
+                };
+                let (addr, size) = (field(8)?, field(16)?);
+                debug!("[Mock] ReadData {:#X}+{:#X}", addr, size);
+
+                // One for the data, one for the parameters
+                self.queue_status(0);
+                self.queue_status(0);
+                self.queue_chunk(addr, size);
+            }
+            MockState::Reading { addr, left } => {
+                // The ack
+                word()?;
+                self.queue_status(0);
+                if left > 0 {
+                    self.queue_chunk(addr, left);
+                } else {
+                    self.state = MockState::Idle;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl MTKPort for MockMTKPort {
+    async fn open(&mut self) -> Result<()> {
+        self.is_open = true;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.is_open = false;
+        self.rx.clear();
+        self.tx.clear();
+        self.state = MockState::Idle;
+        Ok(())
+    }
+
+    // A real port would time out, the mock knows right away nothing is coming
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.is_open {
+            return Err(Error::new(ErrorKind::NotConnected, "Mock port is closed"));
+        }
+        if self.tx.len() < buf.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Mock DA has {} bytes to send, {} were read",
+                    self.tx.len(),
+                    buf.len()
+                ),
+            ));
+        }
+        let len = buf.len();
+        for (dst, src) in buf.iter_mut().zip(self.tx.drain(..len)) {
+            *dst = src;
+        }
+        Ok(buf.len())
+    }
+
+    // Headers and payloads can come in separate writes, frames are only
+    // handled once they're complete
+    async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        if !self.is_open {
+            return Err(Error::new(ErrorKind::NotConnected, "Mock port is closed"));
+        }
+        self.rx.extend_from_slice(buf);
+
+        while self.rx.len() >= 12 {
+            let magic = u32::from_le_bytes(self.rx[0..4].try_into().unwrap());
+            if magic != Cmd::Magic as u32 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Mock DA got a frame with magic {:#010X}", magic),
+                ));
+            }
+            let len = u32::from_le_bytes(self.rx[8..12].try_into().unwrap()) as usize;
+            if self.rx.len() < 12 + len {
+                break;
+            }
+            let payload: Vec<u8> = self.rx.drain(..12 + len).skip(12).collect();
+            self.handle_frame(&payload)?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    // The DA is already up, nothing to sync
+    async fn handshake(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_connection_type(&self) -> ConnectionType {
+        ConnectionType::Da
+    }
+
+    fn get_baudrate(&self) -> u32 {
+        0
+    }
+
+    fn get_port_name(&self) -> String {
+        String::from("mock")
+    }
+}
+
+// Protective MBR, GPT header at LBA 1 and the entry array at LBA 2, with
+// MOCK_PARTITIONS on it. No backup GPT, the disk image stops after the entries.
+pub fn mock_disk() -> Vec<u8> {
+    let mut disk = vec![0u8; GPT_END];
+    disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let mut lba = FIRST_USABLE_LBA;
+    for (i, &(name, size)) in MOCK_PARTITIONS.iter().enumerate() {
+        let sectors = size / SECTOR as u64;
+        let entry = &mut disk[SECTOR * 2 + i * GPT_ENTRY_SIZE..][..GPT_ENTRY_SIZE];
+        // Basic data type GUID, unique GUID made up from the index
+        entry[0..16].copy_from_slice(&[
+            0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26,
+            0x99, 0xC7,
+        ]);
+        entry[16..32].fill(i as u8 + 1);
+        entry[32..40].copy_from_slice(&lba.to_le_bytes());
+        entry[40..48].copy_from_slice(&(lba + sectors - 1).to_le_bytes());
+        for (j, c) in name.encode_utf16().enumerate() {
+            entry[56 + j * 2..58 + j * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        lba += sectors;
+    }
+    let last_usable = lba - 1;
+    let entries_crc = crc32(&disk[SECTOR * 2..GPT_END]);
+
+    let hdr = &mut disk[SECTOR..SECTOR * 2];
+    hdr[0..8].copy_from_slice(b"EFI PART");
+    hdr[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    hdr[12..16].copy_from_slice(&92u32.to_le_bytes());
+    hdr[24..32].copy_from_slice(&1u64.to_le_bytes());
+    hdr[32..40].copy_from_slice(&(last_usable + 34).to_le_bytes());
+    hdr[40..48].copy_from_slice(&FIRST_USABLE_LBA.to_le_bytes());
+    hdr[48..56].copy_from_slice(&last_usable.to_le_bytes());
+    hdr[56..72].fill(0x4D);
+    hdr[72..80].copy_from_slice(&2u64.to_le_bytes());
+    hdr[80..84].copy_from_slice(&(GPT_ENTRIES as u32).to_le_bytes());
+    hdr[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+    hdr[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let hdr_crc = crc32(&hdr[..92]);
+    hdr[16..20].copy_from_slice(&hdr_crc.to_le_bytes());
+    disk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Device;
+    use crate::core::benchmark::BenchmarkOptions;
+
+    // Same steps as examples/list_partitions.rs
+    #[tokio::test]
+    async fn list_partitions() {
+        let mut port = MockMTKPort::new();
+        port.open().await.unwrap();
+        let mut device = Device::init(Box::new(port), MockMTKPort::da_file())
+            .await
+            .unwrap();
+        device.enter_da_mode().await.unwrap();
+
+        let info = device.watch_info().unwrap().borrow().clone();
+        assert_eq!(info.chip.hw_code, MOCK_HW_CODE);
+        assert_eq!(info.gpt_error, None);
+
+        let names: Vec<&str> = info.partitions.iter().map(|p| p.name.as_str()).collect();
+        let expected: Vec<&str> = MOCK_PARTITIONS.iter().map(|&(name, _)| name).collect();
+        assert_eq!(names, expected);
+
+        let userdata = info.partitions.last().unwrap();
+        assert_eq!(userdata.size, 0x1_0000_0000);
+        assert_eq!(userdata.address, info.partitions[5].address + 0x1_2000_0000);
+    }
+
+    #[tokio::test]
+    async fn read_past_disk_image() {
+        let mut port = MockMTKPort::with_disk(vec![0xAB; 0x100]);
+        port.open().await.unwrap();
+        let mut device = Device::init(Box::new(port), MockMTKPort::da_file())
+            .await
+            .unwrap();
+
+        // Spans several packets, only the first 0x100 bytes are on the image
+        let mut progress = |_read: usize, _total: usize| {};
+        let data = device
+            .read_flash(0x80, READ_CHUNK * 2 + 0x10, &mut progress)
+            .await
+            .unwrap();
+        assert_eq!(data.len(), READ_CHUNK * 2 + 0x10);
+        assert!(data[..0x80].iter().all(|&b| b == 0xAB));
+        assert!(data[0x80..].iter().all(|&b| b == 0));
+    }
+
+    #[tokio::test]
+    async fn benchmark_writes_need_a_scratch_partition() {
+        let mut port = MockMTKPort::new();
+        port.open().await.unwrap();
+        let mut device = Device::init(Box::new(port), MockMTKPort::da_file())
+            .await
+            .unwrap();
+        device.enter_da_mode().await.unwrap();
+        let nvram = device.watch_info().unwrap().borrow().partitions[1].clone();
+        assert_eq!(nvram.name, "nvram");
+
+        // The default address is the GPT
+        let mut options = BenchmarkOptions::new(0x1000);
+        options.write = true;
+        let err = device.benchmark_with(&options).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        options.addr = nvram.address;
+        let err = device.benchmark_with(&options).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        // Running over the end of a partition counts as outside of it
+        options.addr = nvram.address + nvram.size - 0x800;
+        let err = device.benchmark_with(&options).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -2,6 +2,8 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
+pub mod mock_backend;
+pub use mock_backend::MockMTKPort;
 pub mod serial_backend;
 pub use serial_backend::SerialMTKPort;
 #[cfg(feature = "libusb")]
@@ -2,10 +2,17 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
-use crate::connection::port::{ConnectionType, KNOWN_PORTS, MTKPort};
-use log::{debug, error, info};
+use crate::connection::diagnostics::{looks_like_at_traffic, port_users};
+use crate::connection::port::{ConnectionType, MTKPort, connection_type_for, is_known_port};
+use crate::connection::short_read::{
+    MAX_EMPTY_READS, READ_IDLE_TIMEOUT, ShortRead, ShortReadCause,
+};
+use log::{debug, error, info, warn};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
 use tokio::io::Result;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
 use tokio_serial::{
     SerialPort, SerialPortBuilderExt, SerialPortInfo, SerialPortType, SerialStream,
 };
@@ -32,18 +39,18 @@ impl SerialMTKPort {
 
     pub fn from_port_info(port_info: SerialPortInfo) -> Option<Self> {
         let connection_type = match &port_info.port_type {
-            SerialPortType::UsbPort(usb_info) => match (usb_info.vid, usb_info.pid) {
-                (0x0e8d, 0x0003) => ConnectionType::Brom,
-                (0x0e8d, 0x2000) => ConnectionType::Preloader,
-                (0x0e8d, 0x2001) => ConnectionType::Da,
-                _ => {
-                    error!(
-                        "Unknown MTK port type: {:04x}:{:04x}",
-                        usb_info.vid, usb_info.pid
-                    );
-                    return None;
+            SerialPortType::UsbPort(usb_info) => {
+                match connection_type_for(usb_info.vid, usb_info.pid) {
+                    Some(connection_type) => connection_type,
+                    None => {
+                        error!(
+                            "Unknown MTK port type: {:04x}:{:04x}",
+                            usb_info.vid, usb_info.pid
+                        );
+                        return None;
+                    }
                 }
-            },
+            }
             _ => {
                 error!("Not a USB serial port");
                 return None;
@@ -63,11 +70,13 @@ impl SerialMTKPort {
 impl MTKPort for SerialMTKPort {
     async fn open(&mut self) -> Result<()> {
         if !self.is_open {
-            self.port = Some(
-                tokio_serial::new(&self.port_info.port_name, self.baudrate)
-                    .timeout(std::time::Duration::from_millis(1000))
-                    .open_native_async()?,
-            );
+            let port = tokio_serial::new(&self.port_info.port_name, self.baudrate)
+                .timeout(std::time::Duration::from_millis(1000))
+                .open_native_async()
+                .map_err(|e| busy_error(&self.port_info.port_name, e.into()))?;
+            #[cfg(target_os = "linux")]
+            lock_port(&port, &self.port_info.port_name)?;
+            self.port = Some(port);
             self.is_open = true;
             info!(
                 "Opened MTK serial port: {} with baudrate {}",
@@ -85,15 +94,66 @@ impl MTKPort for SerialMTKPort {
         Ok(())
     }
 
+    // Not tokio's read_exact, which gives up with UnexpectedEof on the first empty
+    // read. Those happen on a CDC device that's just slow, so only a port that's
+    // gone or READ_IDLE_TIMEOUT without a single byte ends the read early.
     async fn read_exact(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if let Some(port) = &mut self.port {
-            port.read_exact(buf).await
-        } else {
-            Err(std::io::Error::new(
+        let Some(port) = &mut self.port else {
+            return Err(std::io::Error::new(
                 std::io::ErrorKind::NotConnected,
                 "Port is not open",
-            ))
+            ));
+        };
+        let port_name = &self.port_info.port_name;
+        let expected = buf.len();
+        let short_read = |received, cause| ShortRead {
+            port: port_name.clone(),
+            expected,
+            received,
+            cause,
+        };
+
+        let mut received = 0;
+        let mut empty_reads = 0;
+        let mut last_progress = Instant::now();
+        while received < expected {
+            let idle = last_progress.elapsed();
+            if idle >= READ_IDLE_TIMEOUT {
+                return Err(short_read(received, ShortReadCause::Stalled(idle)).into());
+            }
+
+            let result =
+                match timeout(READ_IDLE_TIMEOUT - idle, port.read(&mut buf[received..])).await {
+                    Ok(result) => result,
+                    Err(_) => continue,
+                };
+            match result {
+                Ok(0) => {
+                    empty_reads += 1;
+                    if empty_reads > MAX_EMPTY_READS || !port_present(port_name) {
+                        return Err(short_read(received, ShortReadCause::Disconnected).into());
+                    }
+                    debug!(
+                        "Empty read on {} ({}/{})",
+                        port_name, empty_reads, MAX_EMPTY_READS
+                    );
+                    tokio::time::sleep(EMPTY_READ_BACKOFF).await;
+                }
+                Ok(n) => {
+                    received += n;
+                    empty_reads = 0;
+                    last_progress = Instant::now();
+                }
+                // The port's own timeout, only READ_IDLE_TIMEOUT counts here
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) if !port_present(port_name) => {
+                    debug!("Read on {} failed with the port gone: {}", port_name, e);
+                    return Err(short_read(received, ShortReadCause::Disconnected).into());
+                }
+                Err(e) => return Err(e),
+            }
         }
+        Ok(received)
     }
 
     async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
@@ -121,6 +181,9 @@ impl MTKPort for SerialMTKPort {
 
     async fn handshake(&mut self) -> Result<()> {
         if let Some(port) = &mut self.port {
+            // Whatever came back instead of 0x5F, to spot someone else on the port
+            let mut unexpected = Vec::new();
+            let mut warned = false;
             loop {
                 port.write_all(&[0xA0]).await?;
 
@@ -129,6 +192,19 @@ impl MTKPort for SerialMTKPort {
                     Ok(_) if response[0] == 0x5F => break,
                     Ok(_) | Err(_) => {
                         info!("Received byte: 0x{:02X}", response[0]);
+                        if unexpected.len() == 64 {
+                            unexpected.remove(0);
+                        }
+                        unexpected.push(response[0]);
+                        if !warned && looks_like_at_traffic(&unexpected) {
+                            warn!(
+                                "AT commands on {}, another program (most likely ModemManager) is talking to the device",
+                                self.port_info.port_name
+                            );
+                            warned = true;
+                        }
+                        // Don't hammer the port, the overall timeout is enforced by Connection
+                        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
                     }
                 }
             }
@@ -185,14 +261,23 @@ impl MTKPort for SerialMTKPort {
     }
 }
 
+// Between empty reads, so a device sending ZLPs doesn't spin us
+const EMPTY_READ_BACKOFF: Duration = Duration::from_millis(10);
+
+// Whether the OS still lists the port, i.e. the device is still attached.
+// Listing failing tells us nothing, so that counts as present.
+fn port_present(port_name: &str) -> bool {
+    serialport::available_ports()
+        .map(|ports| ports.iter().any(|p| p.port_name == port_name))
+        .unwrap_or(true)
+}
+
 pub fn find_mtk_serial_ports() -> Vec<SerialPortInfo> {
     match serialport::available_ports() {
         Ok(ports) => ports
             .into_iter()
             .filter(|p| match &p.port_type {
-                SerialPortType::UsbPort(usb_info) => KNOWN_PORTS
-                    .iter()
-                    .any(|(vid, pid)| usb_info.vid == *vid && usb_info.pid == *pid),
+                SerialPortType::UsbPort(usb_info) => is_known_port(usb_info.vid, usb_info.pid),
                 _ => false,
             })
             .collect(),
@@ -202,3 +287,40 @@ pub fn find_mtk_serial_ports() -> Vec<SerialPortInfo> {
         }
     }
 }
+
+// A port someone else has open fails with EBUSY, say who when we can tell
+fn busy_error(port_name: &str, err: std::io::Error) -> std::io::Error {
+    let users = port_users(port_name);
+    if users.is_empty() {
+        return err;
+    }
+    let users: Vec<String> = users.iter().map(|user| user.to_string()).collect();
+    std::io::Error::new(
+        std::io::ErrorKind::ResourceBusy,
+        format!("{} is in use by {} ({})", port_name, users.join(", "), err),
+    )
+}
+
+// Only advisory, but keeps out other tools that lock the port too, and
+// catches a second Penumbra instance before both start talking over each other
+#[cfg(target_os = "linux")]
+fn lock_port(port: &SerialStream, port_name: &str) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: the fd belongs to `port`, which outlives the call
+    if unsafe { libc::flock(port.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    if err.kind() != std::io::ErrorKind::WouldBlock {
+        debug!("Could not lock {}: {}", port_name, err);
+        return Ok(());
+    }
+    Err(busy_error(
+        port_name,
+        std::io::Error::new(
+            std::io::ErrorKind::ResourceBusy,
+            format!("{} is locked by another program", port_name),
+        ),
+    ))
+}
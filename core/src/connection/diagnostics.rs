@@ -0,0 +1,259 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// Tells ModemManager to leave Mediatek ports alone, it otherwise probes them with
+// AT commands right in the middle of the handshake
+pub const MODEM_MANAGER_RULE: &str = r#"# Installed by Penumbra: keep ModemManager away from Mediatek ports
+ACTION!="remove", ATTRS{idVendor}=="0e8d", ENV{ID_MM_DEVICE_IGNORE}="1"
+"#;
+pub const MODEM_MANAGER_RULE_PATH: &str = "/etc/udev/rules.d/70-penumbra-mm-ignore.rules";
+
+// Things that commonly go wrong on the host side before we even get to talk
+// to the device. Frontends can show `hint()` to the user as-is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Remediation {
+    InstallUdevRule,
+    AddUserToDialout,
+    StopModemManager,
+    IgnoreInModemManager,
+    InstallWinUsbDriver,
+    InstallMtkDriver,
+    GrantMacOsPermission,
+    DeviceBusy,
+}
+
+impl Remediation {
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Remediation::InstallUdevRule => {
+                "No udev rule for Mediatek devices found. Add one to /etc/udev/rules.d, e.g.: \
+                 SUBSYSTEM==\"usb\", ATTR{idVendor}==\"0e8d\", MODE=\"0666\", TAG+=\"uaccess\" \
+                 and run `udevadm control --reload-rules`"
+            }
+            Remediation::AddUserToDialout => {
+                "Permission denied on the serial port. Add your user to the dialout group \
+                 (or uucp on Arch) and log in again"
+            }
+            Remediation::StopModemManager => {
+                "ModemManager is running and may grab the port. Stop it with \
+                 `systemctl stop ModemManager` while using Penumbra"
+            }
+            Remediation::IgnoreInModemManager => {
+                "To keep ModemManager off Mediatek ports for good, add a udev rule: \
+                 ATTRS{idVendor}==\"0e8d\", ENV{ID_MM_DEVICE_IGNORE}=\"1\" \
+                 and run `udevadm control --reload-rules`"
+            }
+            Remediation::InstallWinUsbDriver => {
+                "The device has no WinUSB driver bound. Install it with Zadig \
+                 (select the Mediatek device and pick WinUSB)"
+            }
+            Remediation::InstallMtkDriver => {
+                "The Mediatek VCOM driver doesn't seem to be installed. Install the Mediatek \
+                 USB drivers and reconnect the device"
+            }
+            Remediation::GrantMacOsPermission => {
+                "macOS denied access to the device. Allow the terminal in \
+                 System Settings > Privacy & Security and reconnect the device"
+            }
+            Remediation::DeviceBusy => {
+                "The device is in use by another program (another flash tool?). Close it and retry"
+            }
+        }
+    }
+}
+
+impl fmt::Display for Remediation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.hint())
+    }
+}
+
+// Tries to figure out why opening or claiming the port failed.
+// Pass the error returned by `MTKPort::open` if there's one, or `None` to only
+// run the host environment checks (useful while still waiting for a device).
+pub fn diagnose(err: Option<&Error>) -> Vec<Remediation> {
+    let mut hints = Vec::new();
+
+    if let Some(err) = err {
+        // The libusb backend wraps rusb errors as strings, so look at both
+        let msg = err.to_string();
+        let denied = err.kind() == ErrorKind::PermissionDenied || msg.contains("Access");
+        let busy = msg.contains("Busy") || err.kind() == ErrorKind::ResourceBusy;
+        let unsupported = msg.contains("NotSupported") || err.kind() == ErrorKind::Unsupported;
+
+        if busy {
+            hints.push(Remediation::DeviceBusy);
+        }
+
+        if cfg!(target_os = "linux") && denied {
+            if udev_rule_present() {
+                hints.push(Remediation::AddUserToDialout);
+            } else {
+                hints.push(Remediation::InstallUdevRule);
+            }
+        }
+
+        if cfg!(target_os = "windows") {
+            if cfg!(feature = "libusb") && (unsupported || msg.contains("NotFound")) {
+                hints.push(Remediation::InstallWinUsbDriver);
+            } else if !cfg!(feature = "libusb") && err.kind() == ErrorKind::NotFound {
+                hints.push(Remediation::InstallMtkDriver);
+            }
+        }
+
+        if cfg!(target_os = "macos") && denied {
+            hints.push(Remediation::GrantMacOsPermission);
+        }
+    }
+
+    if cfg!(target_os = "linux") {
+        // Harmless once it's been told to ignore the port
+        if modem_manager_running() && !modem_manager_rule_present() {
+            hints.push(Remediation::StopModemManager);
+            hints.push(Remediation::IgnoreInModemManager);
+        }
+        // cdc_acm + dialout is enough for the serial backend, raw USB access needs the rule
+        if cfg!(feature = "libusb")
+            && !udev_rule_present()
+            && !hints.contains(&Remediation::InstallUdevRule)
+        {
+            hints.push(Remediation::InstallUdevRule);
+        }
+    }
+
+    hints
+}
+
+// A process other than us with the port open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortUser {
+    pub pid: u32,
+    pub name: String,
+}
+
+impl fmt::Display for PortUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (pid {})", self.name, self.pid)
+    }
+}
+
+// Who else has `port` (e.g. /dev/ttyACM0) open, found by going through
+// /proc/*/fd. Linux only, and only processes we're allowed to look into,
+// which ModemManager usually isn't unless we're root. Empty elsewhere.
+pub fn port_users(port: &str) -> Vec<PortUser> {
+    if !cfg!(target_os = "linux") {
+        return Vec::new();
+    }
+    let Ok(target) = std::fs::canonicalize(port) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let own_pid = std::process::id();
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            if pid == own_pid {
+                return None;
+            }
+            let has_port = std::fs::read_dir(entry.path().join("fd"))
+                .ok()?
+                .flatten()
+                .any(|fd| std::fs::read_link(fd.path()).is_ok_and(|link| link == target));
+            if !has_port {
+                return None;
+            }
+            let name = std::fs::read_to_string(entry.path().join("comm"))
+                .map(|comm| comm.trim().to_string())
+                .unwrap_or_default();
+            Some(PortUser { pid, name })
+        })
+        .collect()
+}
+
+// ModemManager's probes ("AT\r", "AT+GCAP\r"...) or the answers to them, showing
+// up where the handshake expects single bytes
+pub fn looks_like_at_traffic(data: &[u8]) -> bool {
+    data.windows(3)
+        .any(|w| w[0] == b'A' && w[1] == b'T' && matches!(w[2], b'\r' | b'+' | b'E' | b'Z'))
+        || data.windows(4).any(|w| w == b"OK\r\n")
+}
+
+// Writes MODEM_MANAGER_RULE to `path` (MODEM_MANAGER_RULE_PATH by default) and
+// reloads the udev rules, it applies from the next time the device is plugged in.
+// Needs root, the error says so if we aren't.
+pub fn install_modem_manager_rule(path: Option<&Path>) -> Result<PathBuf, Error> {
+    let path = path.unwrap_or(Path::new(MODEM_MANAGER_RULE_PATH));
+    std::fs::write(path, MODEM_MANAGER_RULE).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "Could not write {} ({}), it needs root. Run as root or add it by hand:\n{}",
+                path.display(),
+                e,
+                MODEM_MANAGER_RULE
+            ),
+        )
+    })?;
+
+    let status = std::process::Command::new("udevadm")
+        .args(["control", "--reload-rules"])
+        .status()?;
+    if !status.success() {
+        return Err(Error::other(format!(
+            "Wrote {}, but reloading the udev rules failed ({})",
+            path.display(),
+            status
+        )));
+    }
+    Ok(path.to_path_buf())
+}
+
+fn modem_manager_running() -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        std::fs::read_to_string(entry.path().join("comm"))
+            .map(|comm| comm.trim() == "ModemManager")
+            .unwrap_or(false)
+    })
+}
+
+fn udev_rule_present() -> bool {
+    udev_rules_contain(|rule| rule.contains("0e8d"))
+}
+
+// `matches` gets each rule file, lowercased
+fn udev_rules_contain(matches: impl Fn(&str) -> bool) -> bool {
+    const RULE_DIRS: &[&str] = &[
+        "/etc/udev/rules.d",
+        "/run/udev/rules.d",
+        "/lib/udev/rules.d",
+        "/usr/lib/udev/rules.d",
+    ];
+
+    RULE_DIRS.iter().any(|dir| {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return false;
+        };
+        entries.flatten().any(|entry| {
+            std::fs::read_to_string(entry.path())
+                .map(|rule| matches(&rule.to_lowercase()))
+                .unwrap_or(false)
+        })
+    })
+}
+
+fn modem_manager_rule_present() -> bool {
+    udev_rules_contain(|rule| rule.contains("0e8d") && rule.contains("id_mm_device_ignore"))
+}
@@ -0,0 +1,97 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::Connection;
+use crate::exploit::{BootStage, find_exploit};
+use log::warn;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// A command we didn't send because the target config says the device would
+// refuse it anyway. Sending it regardless usually just leaves the device stuck
+// waiting for data, so we fail early with something to try instead.
+// Wrapped in an io::Error, use `CommandRefused::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRefused {
+    pub command: &'static str,
+    pub reason: String,
+    // What to try instead: an exploit that gets around it, or a signed DA
+    pub alternative: String,
+}
+
+impl CommandRefused {
+    pub fn from_error(err: &Error) -> Option<&CommandRefused> {
+        err.get_ref()?.downcast_ref::<CommandRefused>()
+    }
+}
+
+impl fmt::Display for CommandRefused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} refused: {}. {}",
+            self.command, self.reason, self.alternative
+        )
+    }
+}
+
+impl std::error::Error for CommandRefused {}
+
+impl From<CommandRefused> for Error {
+    fn from(err: CommandRefused) -> Self {
+        Error::new(ErrorKind::PermissionDenied, err)
+    }
+}
+
+fn bootrom_bypass() -> Option<String> {
+    find_exploit(BootStage::Brom).map(|meta| format!("{} may be able to get around it.", meta.name))
+}
+
+// Checks run before the BROM / preloader commands that the secure configuration
+// can block. They only kick in once get_target_config() has been called. No
+// exploit we have runs before these commands (Carbonara needs DA1 running), so
+// there's nothing that lifts them.
+impl Connection {
+    pub(crate) fn check_send_da(&self, sig_len: u32) -> Result<(), CommandRefused> {
+        let Some(config) = self.target_config() else {
+            return Ok(());
+        };
+
+        if sig_len == 0 && (config.sbc || config.daa) {
+            warn!(
+                "Refusing to send an unsigned DA, target config: {:?}",
+                config
+            );
+            return Err(CommandRefused {
+                command: "SendDA",
+                reason: "secure boot is on and the DA has no signature".to_string(),
+                alternative: bootrom_bypass().unwrap_or_else(|| {
+                    "Use the signed DA from this device's firmware.".to_string()
+                }),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_jump_da(&self) -> Result<(), CommandRefused> {
+        let Some(config) = self.target_config() else {
+            return Ok(());
+        };
+
+        // With DAA only a DA that passed the signature check in SendDA can be run
+        if config.daa && !self.da_accepted() {
+            warn!(
+                "Refusing to jump without an accepted DA, target config: {:?}",
+                config
+            );
+            return Err(CommandRefused {
+                command: "JumpDA",
+                reason: "DA authentication is on and no signed DA was accepted".to_string(),
+                alternative: bootrom_bypass()
+                    .unwrap_or_else(|| "Send a DA signed for this device first.".to_string()),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// Bucket i holds calls that took [2^i, 2^(i+1)) microseconds, the last one
+// everything slower (over half an hour, a hung port more or less)
+const BUCKETS: usize = 32;
+// Calls at least this many buckets (powers of two) above the median are outliers
+const OUTLIER_BUCKETS: usize = 3;
+
+// Per call timings, shared by every clone of a Connection. Off by default,
+// see Connection::set_latency_tracking.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyRecorder {
+    enabled: AtomicBool,
+    calls: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    failed: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            failed: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyRecorder {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, call: &'static str, started: Instant, ok: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let elapsed = started.elapsed();
+        let Ok(mut calls) = self.calls.lock() else {
+            return;
+        };
+        let histogram = calls.entry(call).or_default();
+        histogram.buckets[bucket(elapsed)] += 1;
+        histogram.count += 1;
+        histogram.total += elapsed;
+        histogram.max = histogram.max.max(elapsed);
+        if !ok {
+            histogram.failed += 1;
+        }
+    }
+
+    // Everything recorded so far, starting over afterwards
+    pub(crate) fn take(&self) -> LatencyReport {
+        let calls = match self.calls.lock() {
+            Ok(mut calls) => std::mem::take(&mut *calls),
+            Err(_) => return LatencyReport::default(),
+        };
+        LatencyReport {
+            calls: calls
+                .into_iter()
+                .map(|(call, histogram)| histogram.summarize(call))
+                .collect(),
+        }
+    }
+}
+
+impl Histogram {
+    // Percentiles are the upper bound of the bucket they fall in, so they're
+    // off by up to 2x, which is plenty to tell 1 ms from 500 ms
+    fn percentile(&self, pct: u64) -> Duration {
+        let rank = (self.count * pct).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_limit(i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn summarize(&self, call: &'static str) -> CallLatency {
+        let median = bucket(self.percentile(50));
+        let outliers = self.buckets[(median + OUTLIER_BUCKETS).min(BUCKETS)..]
+            .iter()
+            .sum();
+        CallLatency {
+            call,
+            count: self.count,
+            failed: self.failed,
+            total: self.total,
+            p50: self.percentile(50),
+            p90: self.percentile(90),
+            p99: self.percentile(99),
+            max: self.max,
+            outliers,
+        }
+    }
+}
+
+fn bucket(elapsed: Duration) -> usize {
+    let micros = elapsed.as_micros().max(1);
+    (micros.ilog2() as usize).min(BUCKETS - 1)
+}
+
+fn bucket_limit(bucket: usize) -> Duration {
+    Duration::from_micros(1u64 << (bucket + 1))
+}
+
+// Timings of one kind of protocol call (send, get_status, read_data...)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallLatency {
+    pub call: &'static str,
+    pub count: u64,
+    // Calls that returned an error, timeouts included
+    pub failed: u64,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    // Calls that took 8x the median or more
+    pub outliers: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub calls: Vec<CallLatency>,
+}
+
+impl LatencyReport {
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    // Calls with outliers or failures, worst first by time lost
+    pub fn suspicious(&self) -> Vec<&CallLatency> {
+        let mut calls: Vec<_> = self
+            .calls
+            .iter()
+            .filter(|call| call.outliers > 0 || call.failed > 0)
+            .collect();
+        calls.sort_by_key(|call| Reverse(call.total));
+        calls
+    }
+}
+
+impl fmt::Display for CallLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {:>8} calls, total {:>9.1?}, p50 {:>9.1?}, p90 {:>9.1?}, p99 {:>9.1?}, max {:>9.1?}",
+            self.call, self.count, self.total, self.p50, self.p90, self.p99, self.max
+        )?;
+        if self.outliers > 0 {
+            write!(f, ", {} outliers", self.outliers)?;
+        }
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for call in &self.calls {
+            writeln!(f, "{}", call)?;
+        }
+        Ok(())
+    }
+}
@@ -3,17 +3,108 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 mod backend;
+pub mod cancel;
 mod command;
+pub mod diagnostics;
+pub mod gate;
+pub mod latency;
+pub mod pmic;
 pub mod port;
+pub mod short_read;
+pub mod stats;
+pub mod transport;
+use crate::connection::cancel::CancelToken;
 use crate::connection::command::Command;
+use crate::connection::diagnostics::port_users;
+use crate::connection::latency::{LatencyRecorder, LatencyReport};
 use crate::connection::port::{ConnectionType, MTKPort};
-use log::{debug, error, info};
+use crate::connection::stats::{ConnectionStats, Counters};
+use crate::connection::transport::TransportConfig;
+use crate::core::checksums::{da_checksum, mtk_checksum, sha256};
+use crate::core::chip::ChipIdentity;
+use crate::da::SecureBootRejection;
+use crate::exploit::BootStage;
+pub use backend::MockMTKPort;
+use log::{debug, error, info, warn};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::io::Result;
+use tokio::sync::Mutex;
 
-#[derive(Debug)]
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Largest transfer a single LegacyRead/LegacyWrite can carry
+pub const LEGACY_MAX_CHUNK: usize = 0x400;
+
+// SendDA payload is streamed in packets this big, same as mtkclient
+const SEND_DA_CHUNK: usize = 0x400;
+
+pub const HANDSHAKE_TIMEOUT_HINT: &str = "No response from device during handshake. \
+    Check the cable and hold the volume keys while plugging the device in";
+
+#[derive(Debug, Clone)]
+pub struct HandshakeOptions {
+    // Overall time budget for the handshake, retries included
+    pub timeout: Duration,
+    // Lets the caller abort the wait from elsewhere (e.g. the user pressing Esc)
+    pub cancel: Option<CancelToken>,
+}
+
+impl Default for HandshakeOptions {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            cancel: None,
+        }
+    }
+}
+
+// Security settings fused in the device, as reported by GetTargetConfig
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TargetConfig {
+    pub raw: u32,
+    pub sbc: bool, // Secure boot
+    pub sla: bool, // Serial link authorization
+    pub daa: bool, // Download agent authorization (DA must be signed)
+}
+
+impl TargetConfig {
+    pub fn from_raw(raw: u32) -> Self {
+        Self {
+            raw,
+            sbc: raw & 0x1 != 0,
+            sla: raw & 0x2 != 0,
+            daa: raw & 0x4 != 0,
+        }
+    }
+}
+
+// The port is shared between every clone of a Connection, so monitoring tasks
+// can keep a handle while a protocol is running.
+pub type SharedPort = Arc<Mutex<Box<dyn MTKPort>>>;
+
+// What the device told us (or we did to it) during this session. Lives behind
+// the shared Arc so Device and the protocol see the same thing.
+#[derive(Debug, Clone, Copy)]
+struct Session {
+    connection_type: ConnectionType,
+    target_config: Option<TargetConfig>,
+    // SendDA went through, signature check included
+    da_accepted: bool,
+}
+
+// Cheap to clone handle to the device port. Every clone shares the port, the
+// counters and the session state. Each I/O call locks the port on its own, so a
+// command and its response are not atomic: only one handle at a time should
+// drive the protocol (Device does that behind its own lock), the others are for
+// stats and state.
+#[derive(Debug, Clone)]
 pub struct Connection {
-    pub port: Box<dyn MTKPort>,
-    pub connection_type: ConnectionType,
+    port: SharedPort,
+    stats: Arc<Counters>,
+    latency: Arc<LatencyRecorder>,
+    transport: Arc<RwLock<TransportConfig>>,
+    session: Arc<RwLock<Session>>,
     pub baudrate: u32,
 }
 
@@ -23,16 +114,108 @@ impl Connection {
         let baudrate = port.get_baudrate();
 
         Connection {
-            port,
-            connection_type,
+            port: Arc::new(Mutex::new(port)),
+            stats: Arc::new(Counters::default()),
+            latency: Arc::new(LatencyRecorder::default()),
+            transport: Arc::new(RwLock::new(TransportConfig::default())),
+            session: Arc::new(RwLock::new(Session {
+                connection_type,
+                target_config: None,
+                da_accepted: false,
+            })),
             baudrate,
         }
     }
 
+    fn session(&self) -> Session {
+        *self.session.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update_session(&self, f: impl FnOnce(&mut Session)) {
+        f(&mut self.session.write().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    pub fn connection_type(&self) -> ConnectionType {
+        self.session().connection_type
+    }
+
+    pub fn set_connection_type(&self, connection_type: ConnectionType) {
+        self.update_session(|s| s.connection_type = connection_type);
+    }
+
+    pub fn target_config(&self) -> Option<TargetConfig> {
+        self.session().target_config
+    }
+
+    fn da_accepted(&self) -> bool {
+        self.session().da_accepted
+    }
+
+    fn set_da_accepted(&self, accepted: bool) {
+        self.update_session(|s| s.da_accepted = accepted);
+    }
+
+    pub async fn read_exact(&self, buf: &mut [u8]) -> Result<usize> {
+        let result = self.port.lock().await.read_exact(buf).await;
+        match &result {
+            Ok(n) => self.stats.add_rx(*n),
+            Err(_) => self.stats.add_error(),
+        }
+        result
+    }
+
+    pub async fn write_all(&self, buf: &[u8]) -> Result<()> {
+        let result = self.port.lock().await.write_all(buf).await;
+        match &result {
+            Ok(_) => self.stats.add_tx(buf.len()),
+            Err(_) => self.stats.add_error(),
+        }
+        result
+    }
+
+    pub async fn flush(&self) -> Result<()> {
+        self.port.lock().await.flush().await
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+
+    // For protocols to call whenever they resend something that didn't go through
+    pub fn record_retry(&self) {
+        self.stats.add_retry();
+    }
+
+    // Timeouts dropped before reaching the port don't show up as I/O errors
+    pub fn record_error(&self) {
+        self.stats.add_error();
+    }
+
+    // Times protocol calls (see record_latency) when enabled. Cheap, but not free,
+    // so it's off unless someone is looking at the numbers.
+    pub fn set_latency_tracking(&self, enabled: bool) {
+        self.latency.set_enabled(enabled);
+    }
+
+    pub fn latency_tracking(&self) -> bool {
+        self.latency.is_enabled()
+    }
+
+    // For protocols to call once a send/status/read round is over, `call` names
+    // the kind of call in the report
+    pub fn record_latency(&self, call: &'static str, started: Instant, ok: bool) {
+        self.latency.record(call, started, ok);
+    }
+
+    // Timings recorded since the last call
+    pub fn take_latency_report(&self) -> LatencyReport {
+        self.latency.take()
+    }
+
     pub async fn write(&mut self, data: &[u8], size: usize) -> Result<Vec<u8>> {
-        self.port.write_all(data).await?;
+        self.write_all(data).await?;
         let mut buf = vec![0u8; size];
-        self.port.read_exact(&mut buf).await?;
+        self.read_exact(&mut buf).await?;
         Ok(buf)
     }
 
@@ -52,27 +235,61 @@ impl Connection {
     }
 
     pub async fn echo(&mut self, data: &[u8], size: usize) -> Result<()> {
-        self.port.write_all(data).await?;
+        self.write_all(data).await?;
         let mut buf = vec![0u8; size];
-        self.port.read_exact(&mut buf).await?;
+        self.read_exact(&mut buf).await?;
         return self.check(&buf, data);
     }
 
     pub async fn handshake(&mut self) -> Result<()> {
+        self.handshake_with(&HandshakeOptions::default()).await
+    }
+
+    pub async fn handshake_with(&mut self, options: &HandshakeOptions) -> Result<()> {
         info!("Starting handshake...");
-        self.port.handshake().await?;
+
+        // Both backends retry internally until the device answers, so the only
+        // way out for an unresponsive device is to drop the future from here.
+        let cancelled = async {
+            match &options.cancel {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let handshake = async { self.port.lock().await.handshake().await };
+
+        tokio::select! {
+            result = tokio::time::timeout(options.timeout, handshake) => match result {
+                Ok(res) => res?,
+                Err(_) => {
+                    error!("Handshake timed out after {:?}", options.timeout);
+                    let port_name = self.port.lock().await.get_port_name();
+                    return Err(handshake_timeout(&port_name));
+                }
+            },
+            _ = cancelled => {
+                info!("Handshake cancelled by user");
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    "Handshake cancelled",
+                ));
+            }
+        }
+
         info!("Handshake completed!");
         Ok(())
     }
 
     pub async fn jump_da(&mut self, address: u32) -> Result<()> {
         debug!("Jump to DA at 0x{:08X}", address);
+        self.check_jump_da()?;
 
         self.echo(&[Command::JumpDa as u8], 1).await?;
         self.echo(&address.to_le_bytes(), 4).await?;
 
         let mut status = [0u8; 2];
-        self.port.read_exact(&mut status).await?;
+        self.read_exact(&mut status).await?;
 
         let status_val = u16::from_le_bytes(status);
         if status_val != 0 {
@@ -83,21 +300,43 @@ impl Connection {
         Ok(())
     }
 
+    // Leaves download mode and lets the boot chain carry on: the preloader goes
+    // on to load LK, the BootROM loads the preloader from storage.
+    pub async fn jump_bl(&mut self) -> Result<()> {
+        debug!("Jump to bootloader");
+
+        self.echo(&[Command::JumpBl as u8], 1).await?;
+
+        let mut status = [0u8; 2];
+        self.read_exact(&mut status).await?;
+
+        let status_val = u16::from_le_bytes(status);
+        if status_val != 0 {
+            error!("JumpBL failed with status: {:04X}", status_val);
+            return Err(std::io::Error::other("JumpBL failed"));
+        }
+
+        Ok(())
+    }
+
     pub async fn send_da(
         &mut self,
         da_data: &[u8],
         da_len: u32,
         address: u32,
         sig_len: u32,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<()> {
         debug!("Sending DA, size: {}", da_data.len());
+        self.check_send_da(sig_len)?;
+        self.set_da_accepted(false);
         self.echo(&[Command::SendDa as u8], 1).await?;
         self.echo(&address.to_be_bytes(), 4).await?;
         self.echo(&(da_len).to_be_bytes(), 4).await?;
         self.echo(&sig_len.to_be_bytes(), 4).await?;
 
         let mut status = [0u8; 2];
-        self.port.read_exact(&mut status).await?;
+        self.read_exact(&mut status).await?;
         let status_val = u16::from_be_bytes(status);
         debug!("Received status: 0x{:04X}", status_val);
 
@@ -108,16 +347,45 @@ impl Connection {
             );
         }
 
-        self.port.write_all(da_data).await?;
+        let mut sent = 0;
+        for chunk in da_data.chunks(SEND_DA_CHUNK) {
+            self.write_all(chunk).await?;
+            sent += chunk.len();
+            progress(sent, da_data.len());
+        }
 
         debug!("DA sent!");
 
         let mut checksum = [0u8; 2];
-        self.port.read_exact(&mut checksum).await?;
-        debug!("Received checksum: {:02X}{:02X}", checksum[0], checksum[1]);
+        self.read_exact(&mut checksum).await?;
+        let received = u16::from_be_bytes(checksum);
+        let expected = da_checksum(da_data);
+        debug!(
+            "Received checksum: {:04X}, expected {:04X}",
+            received, expected
+        );
 
         let mut status = [0u8; 2];
-        self.port.read_exact(&mut status).await?;
+        self.read_exact(&mut status).await?;
+
+        if received != expected {
+            self.record_error();
+            // The hash is only there to tell which DA it was in the logs
+            error!(
+                "SendDA checksum mismatch: device computed {:04X}, expected {:04X} (DA sha256 {})",
+                received,
+                expected,
+                hex::encode(sha256(da_data))
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "DA upload got corrupted: the device computed checksum {:04X}, expected {:04X}. \
+                     Check the DA file and try another cable or port",
+                    received, expected
+                ),
+            ));
+        }
 
         let status_val = u16::from_be_bytes(status);
         debug!("Received final status: 0x{:04X}", status_val);
@@ -126,6 +394,13 @@ impl Connection {
                 "SendDA data transfer failed with status: {:04X}",
                 status_val
             );
+            // The signature gets checked once the whole DA is received, so with
+            // DAA enabled a failure here means the DA wasn't accepted.
+            if self.target_config().is_some_and(|config| config.daa) {
+                return Err(
+                    SecureBootRejection::new(BootStage::Da1, Some(status_val as u32)).into(),
+                );
+            }
             return Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "SendDA data transfer failed",
@@ -133,6 +408,7 @@ impl Connection {
             .into());
         }
 
+        self.set_da_accepted(true);
         Ok(())
     }
 
@@ -142,8 +418,8 @@ impl Connection {
         let mut hw_code = [0u8; 2];
         let mut status = [0u8; 2];
 
-        self.port.read_exact(&mut hw_code).await?;
-        self.port.read_exact(&mut status).await?;
+        self.read_exact(&mut hw_code).await?;
+        self.read_exact(&mut status).await?;
 
         let status_val = u16::from_le_bytes(status);
         if status_val != 0 {
@@ -154,7 +430,56 @@ impl Connection {
         Ok(u16::from_le_bytes(hw_code) as u32)
     }
 
-    pub async fn get_hw_sw_ver(&mut self) -> Result<(u16, u16, u16)> {
+    // Preloader version. The BootROM doesn't know this command and just echoes it
+    // back, so None means we're talking to the BootROM rather than the preloader.
+    pub async fn get_pl_ver(&mut self) -> Result<Option<u8>> {
+        self.write_all(&[Command::GetPlVer as u8]).await?;
+
+        let mut ver = [0u8; 1];
+        self.read_exact(&mut ver).await?;
+
+        if ver[0] == Command::GetPlVer as u8 {
+            debug!("GetPlVer echoed back, device is in BROM mode");
+            return Ok(None);
+        }
+        debug!("Preloader version: {}", ver[0]);
+        Ok(Some(ver[0]))
+    }
+
+    // BootROM version, answered by both the BootROM and the preloader
+    pub async fn get_br_ver(&mut self) -> Result<u8> {
+        self.write_all(&[Command::GetBrVer as u8]).await?;
+
+        let mut ver = [0u8; 1];
+        self.read_exact(&mut ver).await?;
+
+        debug!("BootROM version: {}", ver[0]);
+        Ok(ver[0])
+    }
+
+    pub async fn get_target_config(&mut self) -> Result<TargetConfig> {
+        self.echo(&[Command::GetTargetConfig as u8], 1).await?;
+
+        let mut config = [0u8; 4];
+        let mut status = [0u8; 2];
+
+        self.read_exact(&mut config).await?;
+        self.read_exact(&mut status).await?;
+
+        let status_val = u16::from_be_bytes(status);
+        if status_val != 0 {
+            error!("GetTargetConfig failed with status: {:04X}", status_val);
+            return Err(std::io::Error::other("GetTargetConfig failed"));
+        }
+
+        let target_config = TargetConfig::from_raw(u32::from_be_bytes(config));
+        debug!("Target config: {:?}", target_config);
+        self.update_session(|s| s.target_config = Some(target_config));
+        Ok(target_config)
+    }
+
+    // (hw_sub_code, hw_ver, sw_ver), see get_chip_identity
+    async fn get_hw_sw_ver(&mut self) -> Result<(u16, u16, u16)> {
         self.echo(&[Command::GetHwSwVer as u8], 1).await?;
 
         let mut hw_sub_code = [0u8; 2];
@@ -162,10 +487,10 @@ impl Connection {
         let mut sw_ver = [0u8; 2];
         let mut status = [0u8; 2];
 
-        self.port.read_exact(&mut hw_sub_code).await?;
-        self.port.read_exact(&mut hw_ver).await?;
-        self.port.read_exact(&mut sw_ver).await?;
-        self.port.read_exact(&mut status).await?;
+        self.read_exact(&mut hw_sub_code).await?;
+        self.read_exact(&mut hw_ver).await?;
+        self.read_exact(&mut sw_ver).await?;
+        self.read_exact(&mut status).await?;
 
         let status_val = u16::from_le_bytes(status);
         if status_val != 0 {
@@ -186,14 +511,14 @@ impl Connection {
         self.echo(&[Command::GetSocId as u8], 1).await?;
 
         let mut length_bytes = [0u8; 4];
-        self.port.read_exact(&mut length_bytes).await?;
+        self.read_exact(&mut length_bytes).await?;
         let length = u32::from_be_bytes(length_bytes) as usize;
 
         let mut soc_id = vec![0u8; length];
-        self.port.read_exact(&mut soc_id).await?;
+        self.read_exact(&mut soc_id).await?;
 
         let mut status_bytes = [0u8; 2];
-        self.port.read_exact(&mut status_bytes).await?;
+        self.read_exact(&mut status_bytes).await?;
         let status = u16::from_le_bytes(status_bytes);
 
         if status != 0 {
@@ -208,14 +533,14 @@ impl Connection {
         self.echo(&[Command::GetMeId as u8], 1).await?;
 
         let mut length_bytes = [0u8; 4];
-        self.port.read_exact(&mut length_bytes).await?;
+        self.read_exact(&mut length_bytes).await?;
         let length = u32::from_be_bytes(length_bytes) as usize;
 
         let mut meid = vec![0u8; length];
-        self.port.read_exact(&mut meid).await?;
+        self.read_exact(&mut meid).await?;
 
         let mut status_bytes = [0u8; 2];
-        self.port.read_exact(&mut status_bytes).await?;
+        self.read_exact(&mut status_bytes).await?;
         let status = u16::from_le_bytes(status_bytes);
 
         if status != 0 {
@@ -225,4 +550,145 @@ impl Connection {
 
         Ok(meid)
     }
+
+    // Everything the BootROM tells about the chip. The versions are only nice
+    // to have, a BootROM that won't give them leaves them at 0.
+    pub async fn get_chip_identity(&mut self) -> Result<ChipIdentity> {
+        let hw_code = self.get_hw_code().await? as u16;
+        let (hw_sub_code, hw_ver, sw_ver) = match self.get_hw_sw_ver().await {
+            Ok(versions) => versions,
+            Err(e) => {
+                warn!("Could not read HW/SW version: {}", e);
+                (0, 0, 0)
+            }
+        };
+        Ok(ChipIdentity {
+            hw_code,
+            hw_sub_code,
+            hw_ver,
+            sw_ver,
+            soc_id: self.get_soc_id().await?,
+            meid: self.get_meid().await?,
+        })
+    }
+
+    // Preloader-only flash access, for when there's no DA to talk to.
+    // Addresses are byte offsets in the user area, like read_flash.
+    pub async fn legacy_read(&mut self, addr: u64, size: usize) -> Result<Vec<u8>> {
+        if size > LEGACY_MAX_CHUNK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("LegacyRead is limited to {:#X} bytes", LEGACY_MAX_CHUNK),
+            ));
+        }
+
+        self.echo(&[Command::LegacyRead as u8], 1).await?;
+        self.echo(&addr.to_be_bytes(), 8).await?;
+        self.echo(&(size as u32).to_be_bytes(), 4).await?;
+
+        let mut status = [0u8; 2];
+        self.read_exact(&mut status).await?;
+        let status_val = u16::from_be_bytes(status);
+        if status_val != 0 {
+            error!("LegacyRead failed with status: {:04X}", status_val);
+            return Err(std::io::Error::other(format!(
+                "LegacyRead failed with status 0x{:04X}",
+                status_val
+            )));
+        }
+
+        let mut data = vec![0u8; size];
+        self.read_exact(&mut data).await?;
+
+        self.read_exact(&mut status).await?;
+        let status_val = u16::from_be_bytes(status);
+        if status_val != 0 {
+            error!("LegacyRead data failed with status: {:04X}", status_val);
+            return Err(std::io::Error::other("LegacyRead data transfer failed"));
+        }
+
+        Ok(data)
+    }
+
+    pub async fn legacy_write(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        if data.len() > LEGACY_MAX_CHUNK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("LegacyWrite is limited to {:#X} bytes", LEGACY_MAX_CHUNK),
+            ));
+        }
+
+        self.echo(&[Command::LegacyWrite as u8], 1).await?;
+        self.echo(&addr.to_be_bytes(), 8).await?;
+        self.echo(&(data.len() as u32).to_be_bytes(), 4).await?;
+
+        let mut status = [0u8; 2];
+        self.read_exact(&mut status).await?;
+        let status_val = u16::from_be_bytes(status);
+        if status_val != 0 {
+            error!("LegacyWrite failed with status: {:04X}", status_val);
+            return Err(std::io::Error::other(format!(
+                "LegacyWrite failed with status 0x{:04X}",
+                status_val
+            )));
+        }
+
+        self.write_all(data).await?;
+
+        // Same additive checksum the DA uses for write chunks
+        let expected = mtk_checksum(data);
+        let mut checksum = [0u8; 2];
+        self.read_exact(&mut checksum).await?;
+        if u16::from_be_bytes(checksum) != expected {
+            error!(
+                "LegacyWrite checksum mismatch: expected {:04X}, got {:02X}{:02X}",
+                expected, checksum[0], checksum[1]
+            );
+            return Err(std::io::Error::other("LegacyWrite checksum mismatch"));
+        }
+
+        self.read_exact(&mut status).await?;
+        let status_val = u16::from_be_bytes(status);
+        if status_val != 0 {
+            error!("LegacyWrite data failed with status: {:04X}", status_val);
+            return Err(std::io::Error::other("LegacyWrite data transfer failed"));
+        }
+
+        Ok(())
+    }
+}
+
+// Another process sitting on the port (ModemManager probing it, usually) looks
+// just like a dead device from here, so check that before blaming the cable
+fn handshake_timeout(port_name: &str) -> std::io::Error {
+    let users = port_users(port_name);
+    if users.is_empty() {
+        return std::io::Error::new(std::io::ErrorKind::TimedOut, HANDSHAKE_TIMEOUT_HINT);
+    }
+    let users: Vec<String> = users.iter().map(|user| user.to_string()).collect();
+    std::io::Error::new(
+        std::io::ErrorKind::ResourceBusy,
+        format!(
+            "No response from device during handshake, {} has {} open too",
+            users.join(", "),
+            port_name
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_session() {
+        let conn = Connection::new(Box::new(MockMTKPort::new()));
+        let handle = conn.clone();
+
+        conn.set_connection_type(ConnectionType::Brom);
+        conn.set_da_accepted(true);
+
+        assert_eq!(handle.connection_type(), ConnectionType::Brom);
+        assert!(handle.da_accepted());
+    }
 }
@@ -0,0 +1,324 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::Connection;
+use crate::connection::command::Command;
+use log::{debug, error};
+use std::fmt;
+use std::time::Duration;
+use tokio::io::Result;
+
+// PMIC wrap registers shared by the MT63xx PMICs
+const PMIC_HWCID: u16 = 0x0008;
+const PMIC_SWCID: u16 = 0x000A;
+
+// RTC registers, relative to the RTC block of the PMIC (see rtc_regs). Same
+// layout as Linux's rtc-mt6397 driver.
+const RTC_BBPU: u16 = 0x0000;
+const RTC_TC_SEC: u16 = 0x000A;
+const RTC_TC_MIN: u16 = 0x000C;
+const RTC_TC_HOU: u16 = 0x000E;
+const RTC_TC_DOM: u16 = 0x0010;
+const RTC_TC_MTH: u16 = 0x0014;
+const RTC_TC_YEA: u16 = 0x0016;
+// Set while the RTC is still latching a write
+const RTC_BBPU_CBUSY: u16 = 1 << 6;
+// The year register counts from here
+const RTC_MIN_YEAR: u16 = 1968;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PmicId {
+    pub hw_cid: u16,
+    pub sw_cid: u16,
+}
+
+impl PmicId {
+    // The high byte of HWCID is the part number, e.g. 0x58xx on MT6358
+    pub fn model(&self) -> String {
+        format!("MT63{:02X}", self.hw_cid >> 8)
+    }
+
+    pub fn revision(&self) -> u8 {
+        (self.hw_cid & 0xFF) as u8
+    }
+}
+
+// RTC base and write trigger register of the PMICs we know the RTC of
+fn rtc_regs(pmic: &PmicId) -> Option<(u16, u16)> {
+    match pmic.hw_cid >> 8 {
+        0x23 => Some((0x8000, 0x003C)),
+        0x97 => Some((0xE000, 0x003C)),
+        0x57..=0x59 => Some((0x0588, 0x003A)),
+        _ => None,
+    }
+}
+
+// Wall clock time as kept by the PMIC RTC. There's no time zone, whatever
+// Android set it to (UTC usually).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RtcTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl RtcTime {
+    // Seconds since the Unix epoch, taking the RTC as UTC
+    pub fn unix_time(&self) -> i64 {
+        // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    pub fn from_unix_time(secs: i64) -> Self {
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+impl fmt::Display for RtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// I2C and PMIC access through the BROM / preloader protocol. Every argument is
+// echoed back big endian and each command ends with a 16 bit status, like the
+// Read32/Write32 commands. Useful for power and battery checks without a DA.
+impl Connection {
+    async fn read_status(&mut self, cmd: &str) -> Result<()> {
+        let mut status = [0u8; 2];
+        self.read_exact(&mut status).await?;
+
+        let status_val = u16::from_be_bytes(status);
+        if status_val != 0 {
+            error!("{} failed with status: {:04X}", cmd, status_val);
+            return Err(std::io::Error::other(format!(
+                "{} failed with status 0x{:04X}",
+                cmd, status_val
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn i2c_init(&mut self, channel: u8) -> Result<()> {
+        self.echo(&[Command::I2cInit as u8], 1).await?;
+        self.echo(&[channel], 1).await?;
+        self.read_status("I2cInit").await
+    }
+
+    pub async fn i2c_deinit(&mut self) -> Result<()> {
+        self.echo(&[Command::I2cDeinit as u8], 1).await?;
+        self.read_status("I2cDeinit").await
+    }
+
+    pub async fn i2c_set_speed(&mut self, khz: u32) -> Result<()> {
+        self.echo(&[Command::I2cSetSpeed as u8], 1).await?;
+        self.echo(&khz.to_be_bytes(), 4).await?;
+        self.read_status("I2cSetSpeed").await
+    }
+
+    // `slave` is the 7 bit address, without the R/W bit
+    pub async fn i2c_read8(&mut self, slave: u8, reg: u8) -> Result<u8> {
+        self.echo(&[Command::I2cRead8 as u8], 1).await?;
+        self.echo(&[slave], 1).await?;
+        self.echo(&[reg], 1).await?;
+        self.read_status("I2cRead8").await?;
+
+        let mut value = [0u8; 1];
+        self.read_exact(&mut value).await?;
+        self.read_status("I2cRead8 data").await?;
+        Ok(value[0])
+    }
+
+    pub async fn i2c_write8(&mut self, slave: u8, reg: u8, value: u8) -> Result<()> {
+        self.echo(&[Command::I2cWrite8 as u8], 1).await?;
+        self.echo(&[slave], 1).await?;
+        self.echo(&[reg], 1).await?;
+        self.echo(&[value], 1).await?;
+        self.read_status("I2cWrite8").await
+    }
+
+    pub async fn pwr_init(&mut self) -> Result<()> {
+        self.echo(&[Command::PwrInit as u8], 1).await?;
+        self.read_status("PwrInit").await
+    }
+
+    pub async fn pwr_deinit(&mut self) -> Result<()> {
+        self.echo(&[Command::PwrDeinit as u8], 1).await?;
+        self.read_status("PwrDeinit").await
+    }
+
+    pub async fn pwr_read16(&mut self, addr: u16) -> Result<u16> {
+        self.echo(&[Command::PwrRead16 as u8], 1).await?;
+        self.echo(&addr.to_be_bytes(), 2).await?;
+        self.read_status("PwrRead16").await?;
+
+        let mut value = [0u8; 2];
+        self.read_exact(&mut value).await?;
+        self.read_status("PwrRead16 data").await?;
+        Ok(u16::from_be_bytes(value))
+    }
+
+    pub async fn pwr_write16(&mut self, addr: u16, value: u16) -> Result<()> {
+        self.echo(&[Command::PwrWrite16 as u8], 1).await?;
+        self.echo(&addr.to_be_bytes(), 2).await?;
+        self.echo(&value.to_be_bytes(), 2).await?;
+        self.read_status("PwrWrite16").await
+    }
+
+    // Brings up the PMIC wrapper just long enough to read the chip id
+    pub async fn read_pmic_id(&mut self) -> Result<PmicId> {
+        self.pwr_init().await?;
+        let ids = async {
+            let hw_cid = self.pwr_read16(PMIC_HWCID).await?;
+            let sw_cid = self.pwr_read16(PMIC_SWCID).await?;
+            Ok::<_, std::io::Error>(PmicId { hw_cid, sw_cid })
+        }
+        .await;
+        // Always try to leave the wrapper as we found it
+        let deinit = self.pwr_deinit().await;
+
+        let id = ids?;
+        deinit?;
+        debug!("PMIC: {} rev {:02X} ({:?})", id.model(), id.revision(), id);
+        Ok(id)
+    }
+
+    // Reads the PMIC RTC. Only works before a DA is running, like everything
+    // else in here.
+    pub async fn read_rtc(&mut self) -> Result<RtcTime> {
+        let pmic = self.read_pmic_id().await?;
+        let (base, _) = rtc_regs(&pmic).ok_or_else(|| unknown_rtc(&pmic))?;
+
+        self.pwr_init().await?;
+        let time = async {
+            // Seconds read last, if they wrapped around meanwhile the rest may
+            // be a minute behind, so read it all again
+            loop {
+                let first = self.pwr_read16(base + RTC_TC_SEC).await? & 0x3F;
+                let minute = self.pwr_read16(base + RTC_TC_MIN).await? & 0x3F;
+                let hour = self.pwr_read16(base + RTC_TC_HOU).await? & 0x1F;
+                let day = self.pwr_read16(base + RTC_TC_DOM).await? & 0x1F;
+                let month = self.pwr_read16(base + RTC_TC_MTH).await? & 0x0F;
+                let year = self.pwr_read16(base + RTC_TC_YEA).await? & 0x7F;
+                let second = self.pwr_read16(base + RTC_TC_SEC).await? & 0x3F;
+                if second < first {
+                    continue;
+                }
+                break Ok::<_, std::io::Error>(RtcTime {
+                    year: RTC_MIN_YEAR + year,
+                    month: month as u8,
+                    day: day as u8,
+                    hour: hour as u8,
+                    minute: minute as u8,
+                    second: second as u8,
+                });
+            }
+        }
+        .await;
+        let deinit = self.pwr_deinit().await;
+
+        let time = time?;
+        deinit?;
+        if !time.is_valid() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("RTC returned garbage ({:?}), is it powered?", time),
+            ));
+        }
+        debug!("RTC: {}", time);
+        Ok(time)
+    }
+
+    pub async fn write_rtc(&mut self, time: RtcTime) -> Result<()> {
+        if !time.is_valid() || !(RTC_MIN_YEAR..RTC_MIN_YEAR + 0x80).contains(&time.year) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} can't be stored in the RTC", time),
+            ));
+        }
+        let pmic = self.read_pmic_id().await?;
+        let (base, wrtgr) = rtc_regs(&pmic).ok_or_else(|| unknown_rtc(&pmic))?;
+
+        self.pwr_init().await?;
+        let written = async {
+            let fields = [
+                (RTC_TC_SEC, time.second as u16),
+                (RTC_TC_MIN, time.minute as u16),
+                (RTC_TC_HOU, time.hour as u16),
+                (RTC_TC_DOM, time.day as u16),
+                (RTC_TC_MTH, time.month as u16),
+                (RTC_TC_YEA, time.year - RTC_MIN_YEAR),
+            ];
+            for (reg, value) in fields {
+                self.pwr_write16(base + reg, value).await?;
+            }
+            // Nothing sticks until triggered
+            self.pwr_write16(base + wrtgr, 1).await?;
+            for _ in 0..50 {
+                if self.pwr_read16(base + RTC_BBPU).await? & RTC_BBPU_CBUSY == 0 {
+                    return Ok(());
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "RTC stayed busy after writing the time",
+            ))
+        }
+        .await;
+        let deinit = self.pwr_deinit().await;
+
+        written?;
+        deinit?;
+        debug!("RTC set to {}", time);
+        Ok(())
+    }
+}
+
+fn unknown_rtc(pmic: &PmicId) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("Don't know where the RTC of the {} is", pmic.model()),
+    )
+}
@@ -3,8 +3,13 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 
+use crate::connection::diagnostics::diagnose;
+use log::{info, warn};
 use std::fmt::Debug;
-use tokio::io::Result;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::io::{Error, ErrorKind, Result};
 
 pub const KNOWN_PORTS: &[(u16, u16)] = &[
     (0x0e8d, 0x0003), // Mediatek USB Port (BROM)
@@ -12,6 +17,9 @@ pub const KNOWN_PORTS: &[(u16, u16)] = &[
     (0x0e8d, 0x2001), // Mediatek USB Port (DA)
 ];
 
+// How often wait_for_boot_port looks for the device again
+const BOOT_PORT_POLL: Duration = Duration::from_millis(250);
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum ConnectionType {
     Brom,
@@ -19,6 +27,97 @@ pub enum ConnectionType {
     Da,
 }
 
+// Some vendors ship BROM/Preloader with their own VID/PID, these get added at
+// runtime on top of KNOWN_PORTS and are honored by both backends.
+static CUSTOM_PORTS: RwLock<Vec<(u16, u16, ConnectionType)>> = RwLock::new(Vec::new());
+
+pub fn add_known_port(vid: u16, pid: u16, connection_type: ConnectionType) {
+    if let Ok(mut ports) = CUSTOM_PORTS.write() {
+        ports.retain(|&(v, p, _)| v != vid || p != pid);
+        ports.push((vid, pid, connection_type));
+    }
+}
+
+pub fn clear_custom_ports() {
+    if let Ok(mut ports) = CUSTOM_PORTS.write() {
+        ports.clear();
+    }
+}
+
+pub fn connection_type_for(vid: u16, pid: u16) -> Option<ConnectionType> {
+    match (vid, pid) {
+        (0x0e8d, 0x0003) => return Some(ConnectionType::Brom),
+        (0x0e8d, 0x2000) => return Some(ConnectionType::Preloader),
+        (0x0e8d, 0x2001) => return Some(ConnectionType::Da),
+        _ => {}
+    }
+
+    let ports = CUSTOM_PORTS.read().ok()?;
+    ports
+        .iter()
+        .find(|&&(v, p, _)| v == vid && p == pid)
+        .map(|&(_, _, connection_type)| connection_type)
+}
+
+pub fn is_known_port(vid: u16, pid: u16) -> bool {
+    connection_type_for(vid, pid).is_some()
+}
+
+// Loads extra VID/PID pairs from a config file, one per line:
+//
+//   # comment
+//   0e8d:2000 preloader
+//   22d9:0006 brom
+//
+// The mode (brom, preloader or da) is optional and defaults to preloader.
+// Returns how many entries were added.
+pub fn load_port_filter(path: &Path) -> Result<usize> {
+    let content = std::fs::read_to_string(path)?;
+    let mut added = 0;
+
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{}:{}: invalid port entry '{}'",
+                    path.display(),
+                    lineno + 1,
+                    line
+                ),
+            )
+        };
+
+        let mut fields = line.split_whitespace();
+        let ids = fields.next().ok_or_else(invalid)?;
+        let (vid, pid) = ids.split_once(':').ok_or_else(invalid)?;
+        let vid = u16::from_str_radix(vid.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+        let pid = u16::from_str_radix(pid.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+
+        let connection_type = match fields.next().map(|m| m.to_ascii_lowercase()).as_deref() {
+            None | Some("preloader") => ConnectionType::Preloader,
+            Some("brom") => ConnectionType::Brom,
+            Some("da") => ConnectionType::Da,
+            Some(_) => return Err(invalid()),
+        };
+
+        add_known_port(vid, pid, connection_type);
+        added += 1;
+    }
+
+    info!(
+        "Loaded {} custom USB port(s) from {}",
+        added,
+        path.display()
+    );
+    Ok(added)
+}
+
 #[async_trait::async_trait]
 pub trait MTKPort: Send + Debug {
     async fn open(&mut self) -> Result<()>;
@@ -34,6 +133,15 @@ pub trait MTKPort: Send + Debug {
 }
 
 pub async fn find_mtk_port() -> Option<Box<dyn MTKPort>> {
+    try_find_mtk_port().await.ok().flatten()
+}
+
+// Same as find_mtk_port, but keeps "nothing plugged in" (Ok(None)) apart from a
+// port that's there and won't open, which returns the last open error so the
+// caller can diagnose() it.
+pub async fn try_find_mtk_port() -> Result<Option<Box<dyn MTKPort>>> {
+    let mut last_error = None;
+
     #[cfg(not(feature = "libusb"))]
     {
         use crate::connection::backend::serial_backend;
@@ -43,8 +151,12 @@ pub async fn find_mtk_port() -> Option<Box<dyn MTKPort>> {
                 serial_backend::SerialMTKPort::from_port_info(serial_ports[0].clone())
             {
                 let mut boxed_port: Box<dyn MTKPort> = Box::new(port);
-                if boxed_port.open().await.is_ok() {
-                    return Some(boxed_port);
+                match boxed_port.open().await {
+                    Ok(_) => return Ok(Some(boxed_port)),
+                    Err(e) => {
+                        log_open_failure(&boxed_port.get_port_name(), &e);
+                        last_error = Some(e);
+                    }
                 }
             }
         }
@@ -78,12 +190,142 @@ pub async fn find_mtk_port() -> Option<Box<dyn MTKPort>> {
         if let Some(mut ports) = usb_ports {
             for usb_port in ports.drain(..) {
                 let mut boxed_port: Box<dyn MTKPort> = Box::new(usb_port);
-                if boxed_port.open().await.is_ok() {
-                    return Some(boxed_port);
+                match boxed_port.open().await {
+                    Ok(_) => return Ok(Some(boxed_port)),
+                    Err(e) => {
+                        log_open_failure(&boxed_port.get_port_name(), &e);
+                        last_error = Some(e);
+                    }
                 }
             }
         }
     }
 
-    None
+    match last_error {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
+
+// Like find_mtk_port, but opens every MTK port found instead of the first one.
+// Used to drive several devices at once (see core::farm).
+pub async fn find_mtk_ports() -> Vec<Box<dyn MTKPort>> {
+    let mut opened = Vec::new();
+
+    #[cfg(not(feature = "libusb"))]
+    {
+        use crate::connection::backend::serial_backend;
+        for info in serial_backend::find_mtk_serial_ports() {
+            let Some(port) = serial_backend::SerialMTKPort::from_port_info(info) else {
+                continue;
+            };
+            let mut boxed_port: Box<dyn MTKPort> = Box::new(port);
+            match boxed_port.open().await {
+                Ok(_) => opened.push(boxed_port),
+                Err(e) => log_open_failure(&boxed_port.get_port_name(), &e),
+            }
+        }
+    }
+
+    #[cfg(feature = "libusb")]
+    {
+        use crate::connection::backend::libusb_backend::UsbMTKPort;
+        use rusb::{Context, UsbContext};
+        use tokio::task;
+
+        let usb_ports = task::spawn_blocking(|| {
+            let context = Context::new().ok()?;
+            let devices = context.devices().ok()?;
+            Some(
+                devices
+                    .iter()
+                    .filter_map(UsbMTKPort::from_device)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+
+        for usb_port in usb_ports {
+            let mut boxed_port: Box<dyn MTKPort> = Box::new(usb_port);
+            match boxed_port.open().await {
+                Ok(_) => opened.push(boxed_port),
+                Err(e) => log_open_failure(&boxed_port.get_port_name(), &e),
+            }
+        }
+    }
+
+    opened
+}
+
+// After a reset the device drops off the bus and comes back in BROM or
+// preloader mode. Polls for that until `timeout` runs out. A DA port doesn't
+// count, the DA is still running then. Ports are only opened once they're the
+// right kind, the one we still hold doesn't get poked every poll.
+pub async fn wait_for_boot_port(timeout: Duration) -> Option<Box<dyn MTKPort>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        for mut port in boot_port_candidates().await {
+            match port.open().await {
+                Ok(_) => return Some(port),
+                Err(e) => log_open_failure(&port.get_port_name(), &e),
+            }
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::sleep(BOOT_PORT_POLL).await;
+    }
+}
+
+async fn boot_port_candidates() -> Vec<Box<dyn MTKPort>> {
+    let mut ports: Vec<Box<dyn MTKPort>> = Vec::new();
+
+    #[cfg(not(feature = "libusb"))]
+    {
+        use crate::connection::backend::serial_backend;
+        for info in serial_backend::find_mtk_serial_ports() {
+            if let Some(port) = serial_backend::SerialMTKPort::from_port_info(info) {
+                ports.push(Box::new(port));
+            }
+        }
+    }
+
+    #[cfg(feature = "libusb")]
+    {
+        use crate::connection::backend::libusb_backend::UsbMTKPort;
+        use rusb::{Context, UsbContext};
+        use tokio::task;
+
+        let usb_ports = task::spawn_blocking(|| {
+            let context = Context::new().ok()?;
+            let devices = context.devices().ok()?;
+            Some(
+                devices
+                    .iter()
+                    .filter_map(UsbMTKPort::from_device)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+
+        for usb_port in usb_ports {
+            ports.push(Box::new(usb_port));
+        }
+    }
+
+    ports.retain(|port| port.get_connection_type() != ConnectionType::Da);
+    ports
+}
+
+fn log_open_failure(port_name: &str, err: &std::io::Error) {
+    warn!("Failed to open {}: {}", port_name, err);
+    for hint in diagnose(Some(err)) {
+        warn!("Hint: {}", hint);
+    }
 }
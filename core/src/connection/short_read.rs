@@ -0,0 +1,74 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+// How long a read can go without a single byte before the device counts as stalled
+pub const READ_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Empty reads in a row we put up with. CDC devices send zero length packets to
+// end a transfer, and those come out as 0 byte reads, but so does a port whose
+// device went away.
+pub const MAX_EMPTY_READS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortReadCause {
+    // The port is still there, the device just stopped sending.
+    // Worth retrying, or waiting for a reset (see DeviceReset).
+    Stalled(Duration),
+    // The port is gone, the cable was pulled or the device rebooted
+    Disconnected,
+}
+
+// A read that ended before the buffer was full. Maps to TimedOut for a stall
+// and BrokenPipe for a disconnect, so retry and reset handling can go by the
+// ErrorKind. Use `ShortRead::from_error` for the details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortRead {
+    pub port: String,
+    pub expected: usize,
+    pub received: usize,
+    pub cause: ShortReadCause,
+}
+
+impl ShortRead {
+    pub fn from_error(err: &Error) -> Option<&ShortRead> {
+        err.get_ref()?.downcast_ref::<ShortRead>()
+    }
+
+    pub fn is_stall(&self) -> bool {
+        matches!(self.cause, ShortReadCause::Stalled(_))
+    }
+}
+
+impl fmt::Display for ShortRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cause {
+            ShortReadCause::Stalled(idle) => write!(
+                f,
+                "Device stalled on {}: nothing for {:?}, got {} of {} bytes",
+                self.port, idle, self.received, self.expected
+            ),
+            ShortReadCause::Disconnected => write!(
+                f,
+                "{} disconnected after {} of {} bytes",
+                self.port, self.received, self.expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ShortRead {}
+
+impl From<ShortRead> for Error {
+    fn from(err: ShortRead) -> Self {
+        let kind = match err.cause {
+            ShortReadCause::Stalled(_) => ErrorKind::TimedOut,
+            ShortReadCause::Disconnected => ErrorKind::BrokenPipe,
+        };
+        Error::new(kind, err)
+    }
+}
@@ -0,0 +1,62 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Live counters, shared by every clone of a Connection
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    bytes_tx: AtomicU64,
+    bytes_rx: AtomicU64,
+    retries: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn add_tx(&self, bytes: usize) {
+        self.bytes_tx.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_rx(&self, bytes: usize) {
+        self.bytes_rx.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            bytes_tx: self.bytes_tx.load(Ordering::Relaxed),
+            bytes_rx: self.bytes_rx.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Traffic seen on the port since the connection was opened. Take two snapshots
+// and use since() to get the numbers for a single operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    pub retries: u64,
+    pub errors: u64,
+}
+
+impl ConnectionStats {
+    pub fn since(&self, earlier: &ConnectionStats) -> ConnectionStats {
+        ConnectionStats {
+            bytes_tx: self.bytes_tx.saturating_sub(earlier.bytes_tx),
+            bytes_rx: self.bytes_rx.saturating_sub(earlier.bytes_rx),
+            retries: self.retries.saturating_sub(earlier.retries),
+            errors: self.errors.saturating_sub(earlier.errors),
+        }
+    }
+}
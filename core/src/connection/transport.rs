@@ -0,0 +1,148 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::Connection;
+use log::{debug, warn};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::time::{Instant, timeout};
+
+// Tunables for the link itself, shared by every clone of a Connection
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TransportConfig {
+    pub sync: SyncStrategy,
+    pub read: ReadTimeouts,
+}
+
+// How to wait for a sync byte, e.g. the 0xC0 DA1 sends once it's running.
+// DA1 can take a while to come up and some builds print a few log bytes on the
+// same port first, so a single read isn't enough.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStrategy {
+    // Overall time budget, from the first poll
+    pub deadline: Duration,
+    // How long a single read may take before polling again
+    pub poll_timeout: Duration,
+    // Bytes that aren't the sync byte, skipped as stray output before giving up
+    pub max_stray_bytes: usize,
+    // Failed reads (other than timeouts) tolerated before giving up
+    pub retries: u32,
+}
+
+impl Default for SyncStrategy {
+    fn default() -> Self {
+        Self {
+            deadline: Duration::from_secs(10),
+            poll_timeout: Duration::from_millis(500),
+            max_stray_bytes: 0x400,
+            retries: 3,
+        }
+    }
+}
+
+// How long a flash read waits for each chunk. The first one after ReadData can
+// take seconds while the storage wakes up (UFS in power saving especially),
+// the ones after it come in right away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadTimeouts {
+    pub first_chunk: Duration,
+    pub chunk: Duration,
+}
+
+impl Default for ReadTimeouts {
+    fn default() -> Self {
+        Self {
+            first_chunk: Duration::from_secs(30),
+            chunk: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Connection {
+    pub fn transport_config(&self) -> TransportConfig {
+        self.transport.read().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    pub fn set_transport_config(&self, config: TransportConfig) {
+        if let Ok(mut transport) = self.transport.write() {
+            *transport = config;
+        }
+    }
+
+    // Waits for `expected` following the configured SyncStrategy, skipping
+    // anything else that shows up first. `what` is only used for messages.
+    pub async fn wait_sync_byte(&self, expected: u8, what: &str) -> Result<(), Error> {
+        let strategy = self.transport_config().sync;
+        let deadline = Instant::now() + strategy.deadline;
+        let mut stray = Vec::new();
+        let mut failures = 0;
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "Timeout waiting for {} sync byte after {:?}{}",
+                        what,
+                        strategy.deadline,
+                        stray_note(&stray)
+                    ),
+                ));
+            }
+
+            let mut buf = [0u8; 1];
+            let poll = strategy.poll_timeout.min(deadline - now);
+            match timeout(poll, self.read_exact(&mut buf)).await {
+                Ok(Ok(_)) if buf[0] == expected => {
+                    if !stray.is_empty() {
+                        debug!("Skipped {} stray bytes before {} sync", stray.len(), what);
+                    }
+                    return Ok(());
+                }
+                Ok(Ok(_)) => {
+                    stray.push(buf[0]);
+                    if stray.len() > strategy.max_stray_bytes {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Incorrect {} sync byte: expected {:02X}{}",
+                                what,
+                                expected,
+                                stray_note(&stray)
+                            ),
+                        ));
+                    }
+                }
+                // Nothing yet, poll again until the deadline
+                Ok(Err(e)) if e.kind() == ErrorKind::TimedOut => {}
+                Err(_) => {}
+                Ok(Err(e)) => {
+                    failures += 1;
+                    self.record_retry();
+                    warn!(
+                        "Reading {} sync byte failed ({}/{}): {}",
+                        what, failures, strategy.retries, e
+                    );
+                    if failures > strategy.retries {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Stray output is usually DA log text, show it as such
+fn stray_note(stray: &[u8]) -> String {
+    if stray.is_empty() {
+        return String::new();
+    }
+    let tail = &stray[stray.len().saturating_sub(32)..];
+    format!(
+        ", got {} other bytes first (last: {:?})",
+        stray.len(),
+        String::from_utf8_lossy(tail)
+    )
+}
@@ -0,0 +1,301 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::checksums::crc32;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// AOSP's bootloader_control lives in misc, right after the 2K bootloader_message
+pub const BOOT_CONTROL_OFFSET: usize = 0x800;
+const BOOT_CONTROL_SIZE: usize = 32;
+const BOOT_CONTROL_MAGIC: u32 = 0x42414342; // "BCAB"
+// Bytes covered by crc32_le, the rest of the struct
+const BOOT_CONTROL_CRC_LEN: usize = 28;
+const SLOT_INFO_OFFSET: usize = 12;
+
+const MAX_PRIORITY: u8 = 15;
+const MAX_TRIES: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Slot::A => "_a",
+            Slot::B => "_b",
+        }
+    }
+
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    // "boot_b" -> ("boot", Some(B)), "boot" -> ("boot", None)
+    pub fn split_name(name: &str) -> (&str, Option<Slot>) {
+        match name.rsplit_once('_') {
+            Some((base, "a")) => (base, Some(Slot::A)),
+            Some((base, "b")) => (base, Some(Slot::B)),
+            _ => (name, None),
+        }
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.suffix()[1..])
+    }
+}
+
+// One slot_metadata, two bytes packed as
+// priority:4 | tries_remaining:3 | successful_boot:1 | verity_corrupted:1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SlotInfo {
+    pub priority: u8,
+    pub tries_remaining: u8,
+    pub successful_boot: bool,
+    pub verity_corrupted: bool,
+}
+
+impl SlotInfo {
+    fn parse(raw: [u8; 2]) -> Self {
+        SlotInfo {
+            priority: raw[0] & 0x0F,
+            tries_remaining: (raw[0] >> 4) & 0x07,
+            successful_boot: raw[0] & 0x80 != 0,
+            verity_corrupted: raw[1] & 0x01 != 0,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 2] {
+        [
+            (self.priority & 0x0F)
+                | ((self.tries_remaining & 0x07) << 4)
+                | ((self.successful_boot as u8) << 7),
+            self.verity_corrupted as u8,
+        ]
+    }
+
+    // What the bootloader would still try to boot
+    pub fn is_bootable(&self) -> bool {
+        self.priority > 0 && (self.successful_boot || self.tries_remaining > 0)
+    }
+}
+
+// The A/B boot control block, as read from misc at BOOT_CONTROL_OFFSET.
+// Only the fields we touch are decoded, everything else is written back as is.
+#[derive(Debug, Clone)]
+pub struct BootControl {
+    raw: [u8; BOOT_CONTROL_SIZE],
+}
+
+impl BootControl {
+    // None when misc doesn't hold a valid bootloader_control (no A/B, or the
+    // vendor keeps its own format somewhere else)
+    pub fn from_misc(misc: &[u8]) -> Option<Self> {
+        let raw: [u8; BOOT_CONTROL_SIZE] = misc
+            .get(BOOT_CONTROL_OFFSET..BOOT_CONTROL_OFFSET + BOOT_CONTROL_SIZE)?
+            .try_into()
+            .ok()?;
+        let magic = u32::from_le_bytes(raw[4..8].try_into().unwrap());
+        let crc = u32::from_le_bytes(raw[28..32].try_into().unwrap());
+        if magic != BOOT_CONTROL_MAGIC || crc != crc32(&raw[..BOOT_CONTROL_CRC_LEN]) {
+            return None;
+        }
+        let control = BootControl { raw };
+        (control.slot_count() >= 2).then_some(control)
+    }
+
+    pub fn slot_count(&self) -> usize {
+        (self.raw[9] & 0x07) as usize
+    }
+
+    pub fn slot(&self, slot: Slot) -> SlotInfo {
+        let at = SLOT_INFO_OFFSET + slot.index() * 2;
+        SlotInfo::parse([self.raw[at], self.raw[at + 1]])
+    }
+
+    fn set_slot(&mut self, slot: Slot, info: SlotInfo) {
+        let at = SLOT_INFO_OFFSET + slot.index() * 2;
+        self.raw[at..at + 2].copy_from_slice(&info.to_bytes());
+    }
+
+    // The bootable slot with the highest priority, A wins ties like in AOSP
+    pub fn active_slot(&self) -> Option<Slot> {
+        [Slot::A, Slot::B]
+            .into_iter()
+            .filter(|&slot| self.slot(slot).is_bootable())
+            .rev()
+            .max_by_key(|&slot| self.slot(slot).priority)
+    }
+
+    // Same as boot_control's setActiveBootSlot: `slot` gets the top priority and
+    // a fresh set of tries, the other one drops below it but stays bootable as
+    // a fallback if `slot` never boots.
+    pub fn set_active(&mut self, slot: Slot) {
+        let mut other = self.slot(slot.other());
+        if other.priority >= MAX_PRIORITY {
+            other.priority = MAX_PRIORITY - 1;
+        }
+        self.set_slot(slot.other(), other);
+        self.set_slot(
+            slot,
+            SlotInfo {
+                priority: MAX_PRIORITY,
+                tries_remaining: MAX_TRIES,
+                successful_boot: false,
+                verity_corrupted: false,
+            },
+        );
+        self.raw[0..4].fill(0);
+        self.raw[0..2].copy_from_slice(slot.suffix().as_bytes());
+
+        let crc = crc32(&self.raw[..BOOT_CONTROL_CRC_LEN]);
+        self.raw[28..32].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    // `misc` with this block put back in place
+    pub fn apply_to(&self, misc: &[u8]) -> Result<Vec<u8>, Error> {
+        let end = BOOT_CONTROL_OFFSET + BOOT_CONTROL_SIZE;
+        if misc.len() < end {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "misc is too small for a boot control block",
+            ));
+        }
+        let mut misc = misc.to_vec();
+        misc[BOOT_CONTROL_OFFSET..end].copy_from_slice(&self.raw);
+        Ok(misc)
+    }
+}
+
+// What flash_two_phase did: `partition` was written and `active` is the slot
+// the device boots next, `previous` still has the old image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotSwitch {
+    pub partition: String,
+    pub previous: Slot,
+    pub active: Slot,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(priority: u8, tries_remaining: u8, successful_boot: bool) -> SlotInfo {
+        SlotInfo {
+            priority,
+            tries_remaining,
+            successful_boot,
+            verity_corrupted: false,
+        }
+    }
+
+    // A misc image with a valid bootloader_control for two slots
+    fn misc(a: SlotInfo, b: SlotInfo) -> Vec<u8> {
+        let mut misc = vec![0x5A; BOOT_CONTROL_OFFSET + 0x200];
+        let raw = &mut misc[BOOT_CONTROL_OFFSET..BOOT_CONTROL_OFFSET + BOOT_CONTROL_SIZE];
+        raw.fill(0);
+        raw[0..2].copy_from_slice(b"_a");
+        raw[4..8].copy_from_slice(&BOOT_CONTROL_MAGIC.to_le_bytes());
+        raw[8] = 1; // version
+        raw[9] = 2; // nb_slot:3 | recovery_tries_remaining:3
+        raw[12..14].copy_from_slice(&a.to_bytes());
+        raw[14..16].copy_from_slice(&b.to_bytes());
+        let crc = crc32(&raw[..BOOT_CONTROL_CRC_LEN]);
+        raw[28..32].copy_from_slice(&crc.to_le_bytes());
+        misc
+    }
+
+    #[test]
+    fn slot_info_bits() {
+        let slot = SlotInfo {
+            priority: 14,
+            tries_remaining: 3,
+            successful_boot: true,
+            verity_corrupted: true,
+        };
+        assert_eq!(slot.to_bytes(), [0xBE, 0x01]);
+        assert_eq!(SlotInfo::parse(slot.to_bytes()), slot);
+    }
+
+    #[test]
+    fn from_misc_checks_the_block() {
+        let good = misc(info(15, 0, true), info(14, 0, true));
+        assert!(BootControl::from_misc(&good).is_some());
+
+        let mut bad_magic = good.clone();
+        bad_magic[BOOT_CONTROL_OFFSET + 4] ^= 0xFF;
+        assert!(BootControl::from_misc(&bad_magic).is_none());
+
+        // Magic still fine, but a slot byte changed under the CRC
+        let mut bad_crc = good.clone();
+        bad_crc[BOOT_CONTROL_OFFSET + SLOT_INFO_OFFSET] ^= 0x01;
+        assert!(BootControl::from_misc(&bad_crc).is_none());
+
+        assert!(BootControl::from_misc(&good[..BOOT_CONTROL_OFFSET + 16]).is_none());
+    }
+
+    #[test]
+    fn active_slot_tie_break() {
+        let control = |a, b| BootControl::from_misc(&misc(a, b)).unwrap();
+
+        // Same priority, A wins
+        assert_eq!(
+            control(info(15, 0, true), info(15, 0, true)).active_slot(),
+            Some(Slot::A)
+        );
+        assert_eq!(
+            control(info(14, 0, true), info(15, 0, true)).active_slot(),
+            Some(Slot::B)
+        );
+        // Out of tries and never booted, so B even with the lower priority
+        assert_eq!(
+            control(info(15, 0, false), info(14, 7, false)).active_slot(),
+            Some(Slot::B)
+        );
+        assert_eq!(
+            control(info(0, 7, true), info(0, 0, false)).active_slot(),
+            None
+        );
+    }
+
+    #[test]
+    fn set_active_round_trip() {
+        let original = misc(info(15, 0, true), info(14, 0, true));
+        let mut control = BootControl::from_misc(&original).unwrap();
+        control.set_active(Slot::B);
+        let patched = control.apply_to(&original).unwrap();
+
+        // Parses again, so the CRC was recomputed
+        let control = BootControl::from_misc(&patched).unwrap();
+        assert_eq!(control.active_slot(), Some(Slot::B));
+        assert_eq!(control.slot(Slot::B), info(MAX_PRIORITY, MAX_TRIES, false));
+        // A stays behind as the fallback
+        assert_eq!(control.slot(Slot::A), info(MAX_PRIORITY - 1, 0, true));
+        assert_eq!(
+            &patched[BOOT_CONTROL_OFFSET..BOOT_CONTROL_OFFSET + 4],
+            b"_b\0\0"
+        );
+
+        // Nothing outside the block moved
+        let end = BOOT_CONTROL_OFFSET + BOOT_CONTROL_SIZE;
+        assert_eq!(patched.len(), original.len());
+        assert_eq!(
+            patched[..BOOT_CONTROL_OFFSET],
+            original[..BOOT_CONTROL_OFFSET]
+        );
+        assert_eq!(patched[end..], original[end..]);
+    }
+}
@@ -0,0 +1,182 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::chip::ChipIdentity;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Partitions bigger than this don't get read back before being written, hashing
+// them would double the time of the write. Their hash_before is left empty.
+pub const AUDIT_HASH_MAX: u64 = 0x400_0000;
+
+// One destructive operation, as stored in the history file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub chip: ChipIdentity,
+    // "write", "erase", "unlock"...
+    pub operation: String,
+    pub partition: String,
+    // SHA-256 of the partition before and after, when known
+    pub hash_before: Option<String>,
+    pub hash_after: Option<String>,
+    // "ok" or the error message
+    pub result: String,
+}
+
+impl AuditEntry {
+    pub fn succeeded(&self) -> bool {
+        self.result == "ok"
+    }
+
+    // Tab separated, fields can't contain tabs or newlines. The chip versions
+    // share the hw_code column, see ChipIdentity::version_string.
+    fn to_line(&self) -> String {
+        let clean = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+        [
+            self.timestamp.to_string(),
+            self.chip.soc_id_hex(),
+            self.chip.meid_hex(),
+            self.chip.version_string(),
+            clean(&self.operation),
+            clean(&self.partition),
+            self.hash_before.clone().unwrap_or_else(|| "-".to_string()),
+            self.hash_after.clone().unwrap_or_else(|| "-".to_string()),
+            clean(&self.result),
+        ]
+        .join("\t")
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [
+            timestamp,
+            soc_id,
+            meid,
+            hw_code,
+            operation,
+            partition,
+            before,
+            after,
+            result,
+        ] = fields.as_slice()
+        else {
+            return None;
+        };
+        let hash = |h: &str| (h != "-").then(|| h.to_string());
+        let chip = ChipIdentity {
+            soc_id: hex::decode(soc_id).ok()?,
+            meid: hex::decode(meid).ok()?,
+            ..ChipIdentity::from_version_string(hw_code)?
+        };
+
+        Some(Self {
+            timestamp: timestamp.parse().ok()?,
+            chip,
+            operation: operation.to_string(),
+            partition: partition.to_string(),
+            hash_before: hash(before),
+            hash_after: hash(after),
+            result: result.to_string(),
+        })
+    }
+}
+
+// Append only history of destructive operations, for shops that need to trace
+// what was done to which device.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    // $PENUMBRA_HISTORY, or history.log in $XDG_DATA_HOME/penumbra
+    // (~/.local/share/penumbra), %APPDATA%\penumbra on Windows
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("PENUMBRA_HISTORY") {
+            return Some(PathBuf::from(path));
+        }
+
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+        };
+
+        base.map(|dir| dir.join("penumbra").join("history.log"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", entry.to_line())?;
+        file.sync_data()
+    }
+
+    // Oldest first. Lines that can't be parsed are skipped.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(content.lines().filter_map(AuditEntry::from_line).collect())
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// `2025-01-31 13:37:00 UTC`, without pulling a date crate in
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
@@ -0,0 +1,91 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Backups kept per partition unless told otherwise
+pub const DEFAULT_KEEP: usize = 5;
+
+// Dumps every partition right before it gets written or erased, see
+// Device::set_auto_backup. Backups go in `<dir>/<soc id>/<partition>.<ms>.bin`,
+// so those of different devices never get mixed up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoBackup {
+    pub dir: PathBuf,
+    // Newest backups kept per partition, older ones get deleted. 0 keeps everything.
+    pub keep: usize,
+    // Partitions bigger than this are written without a backup (userdata can be
+    // most of the flash), None backs up everything
+    pub max_size: Option<u64>,
+}
+
+impl AutoBackup {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            keep: DEFAULT_KEEP,
+            max_size: None,
+        }
+    }
+
+    pub fn device_dir(&self, soc_id: &str) -> PathBuf {
+        match soc_id {
+            "" => self.dir.join("unknown"),
+            id => self.dir.join(id),
+        }
+    }
+
+    // Where a backup taken now goes. Millisecond timestamps sort the same as
+    // their names, which is what prune() relies on.
+    pub fn backup_path(&self, soc_id: &str, partition: &str) -> PathBuf {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        self.device_dir(soc_id)
+            .join(format!("{}.{:015}.bin", partition, millis))
+    }
+
+    // The backups of `partition`, oldest first
+    pub fn backups(&self, soc_id: &str, partition: &str) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.device_dir(soc_id);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut backups: Vec<PathBuf> = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_backup_of(path, partition))
+            .collect();
+        backups.sort();
+        Ok(backups)
+    }
+
+    // Deletes all but the newest `keep` backups of `partition`, returns what was deleted
+    pub fn prune(&self, soc_id: &str, partition: &str) -> Result<Vec<PathBuf>, Error> {
+        if self.keep == 0 {
+            return Ok(Vec::new());
+        }
+        let backups = self.backups(soc_id, partition)?;
+        let excess = backups.len().saturating_sub(self.keep);
+        let old = backups[..excess].to_vec();
+        for path in &old {
+            std::fs::remove_file(path)?;
+        }
+        Ok(old)
+    }
+}
+
+// `<partition>.<digits>.bin`, partition names can have dots in them too
+fn is_backup_of(path: &Path, partition: &str) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    name.strip_prefix(partition)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|rest| rest.strip_suffix(".bin"))
+        .is_some_and(|stamp| !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit()))
+}
@@ -0,0 +1,82 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+use std::time::Duration;
+
+// Request sizes tried by default, from "lots of round trips" to "few big ones"
+pub const DEFAULT_CHUNK_SIZES: &[usize] = &[0x1_0000, 0x10_0000, 0x40_0000, 0x100_0000];
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkOptions {
+    // Bytes transferred per chunk size
+    pub size: usize,
+    pub chunk_sizes: Vec<usize>,
+    // Where to read from, the start of the user area (GPT) by default
+    pub addr: u64,
+    // Also time writes, by writing back what was just read. The data on the
+    // device doesn't change, but an interrupted write can still leave it broken,
+    // so this is off unless asked for (and skipped in dry-run). The range then
+    // has to sit inside one partition the safety policy doesn't protect.
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    pub chunk_size: usize,
+    pub bytes: usize,
+    pub read_time: Duration,
+    pub write_time: Option<Duration>,
+}
+
+impl BenchmarkOptions {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            chunk_sizes: DEFAULT_CHUNK_SIZES.to_vec(),
+            addr: 0,
+            write: false,
+        }
+    }
+}
+
+impl BenchmarkResult {
+    pub fn read_mbps(&self) -> f64 {
+        mbps(self.bytes, self.read_time)
+    }
+
+    pub fn write_mbps(&self) -> Option<f64> {
+        self.write_time.map(|time| mbps(self.bytes, time))
+    }
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>6} KiB chunks: read {:.2} MB/s",
+            self.chunk_size / 1024,
+            self.read_mbps()
+        )?;
+        if let Some(write) = self.write_mbps() {
+            write!(f, ", write {:.2} MB/s", write)?;
+        }
+        Ok(())
+    }
+}
+
+// The best chunk size in `results`, by read speed
+pub fn fastest(results: &[BenchmarkResult]) -> Option<&BenchmarkResult> {
+    results
+        .iter()
+        .max_by(|a, b| a.read_mbps().total_cmp(&b.read_mbps()))
+}
+
+fn mbps(bytes: usize, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
+    }
+    bytes as f64 / secs / 1_000_000.0
+}
@@ -0,0 +1,160 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use sha2::{Digest, Sha256};
+use std::io::{Read, Result, Write};
+
+// What XFlash wants ahead of every chunk written to flash: all bytes summed up,
+// truncated to 16 bits. Sent as a LE u32, see flash::write_flash.
+pub fn mtk_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
+}
+
+// Checksum BROM/Preloader return after SendDA: XOR of every 16 bit LE word,
+// with a trailing odd byte XORed in as is.
+pub fn da_checksum(data: &[u8]) -> u16 {
+    data.chunks(2).fold(0u16, |sum, word| match word {
+        [lo, hi] => sum ^ u16::from_le_bytes([*lo, *hi]),
+        [last] => sum ^ *last as u16,
+        _ => sum,
+    })
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+// Plain CRC-32 (IEEE 802.3), the one used by GPT headers and entry arrays
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+// Lowercase hex, as found in manifests, the audit log and dry run plans
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// SHA-256 of data that comes in pieces (chunks on the wire, files being copied).
+// Also a Write, so std::io::copy can feed it.
+#[derive(Clone, Default)]
+pub struct Sha256Stream {
+    hasher: Sha256,
+}
+
+impl Sha256Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+
+    pub fn finalize_hex(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl Write for Sha256Stream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Hashes everything left in `reader`, in hex
+pub fn sha256_reader(mut reader: impl Read) -> Result<String> {
+    let mut stream = Sha256Stream::new();
+    std::io::copy(&mut reader, &mut stream)?;
+    Ok(stream.finalize_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hex::encode(sha256(b"abc")), sha256_hex(b"abc"));
+    }
+
+    #[test]
+    fn sha256_stream_matches_one_shot() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 251) as u8).collect();
+
+        let mut stream = Sha256Stream::new();
+        for chunk in data.chunks(1000 - 7) {
+            stream.update(chunk);
+        }
+        assert_eq!(stream.finalize(), sha256(&data));
+
+        assert_eq!(sha256_reader(&data[..]).unwrap(), sha256_hex(&data));
+    }
+
+    #[test]
+    fn mtk_checksum_wraps() {
+        assert_eq!(mtk_checksum(&[]), 0);
+        assert_eq!(mtk_checksum(&[1, 2, 3]), 6);
+        // 0xFF * 258 = 0x100FE, only the low 16 bits are kept
+        assert_eq!(mtk_checksum(&[0xFF; 258]), 0x00FE);
+    }
+
+    #[test]
+    fn da_checksum_words() {
+        assert_eq!(da_checksum(&[]), 0);
+        assert_eq!(da_checksum(&[0x34, 0x12]), 0x1234);
+        // XOR, so the same word twice cancels out
+        assert_eq!(da_checksum(&[0x34, 0x12, 0x34, 0x12]), 0);
+        // A trailing odd byte goes in as is
+        assert_eq!(da_checksum(&[0x34, 0x12, 0xFF]), 0x12CB);
+        assert_eq!(da_checksum(&[0xFF; 4]), 0);
+    }
+}
@@ -0,0 +1,104 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// What the BootROM (or the DA, after attaching) says about the chip: GetHwCode,
+// GetHwSwVer, GetSocId and GetMeId in one place. The IDs are empty when they
+// couldn't be read, e.g. when attaching to a running DA. Serialized with the
+// codes and IDs as hex strings, same as they show up everywhere else.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct ChipIdentity {
+    #[serde(with = "hex_u16")]
+    pub hw_code: u16,
+    #[serde(with = "hex_u16", default)]
+    pub hw_sub_code: u16,
+    #[serde(with = "hex_u16", default)]
+    pub hw_ver: u16,
+    #[serde(with = "hex_u16", default)]
+    pub sw_ver: u16,
+    #[serde(with = "hex_bytes", default)]
+    pub soc_id: Vec<u8>,
+    #[serde(with = "hex_bytes", default)]
+    pub meid: Vec<u8>,
+}
+
+impl ChipIdentity {
+    pub fn soc_id_hex(&self) -> String {
+        hex::encode(&self.soc_id)
+    }
+
+    pub fn meid_hex(&self) -> String {
+        hex::encode(&self.meid)
+    }
+
+    // "hw_code:hw_sub_code:hw_ver:sw_ver" in hex, for one line formats
+    // like the history file
+    pub fn version_string(&self) -> String {
+        format!(
+            "{:04x}:{:04x}:{:04x}:{:04x}",
+            self.hw_code, self.hw_sub_code, self.hw_ver, self.sw_ver
+        )
+    }
+
+    // The other way around, without the IDs. A lone hw_code (older history
+    // files) is fine too.
+    pub fn from_version_string(value: &str) -> Option<Self> {
+        let mut codes = value.split(':').map(|code| u16::from_str_radix(code, 16));
+        let mut chip = Self {
+            hw_code: codes.next()?.ok()?,
+            ..Self::default()
+        };
+        for field in [&mut chip.hw_sub_code, &mut chip.hw_ver, &mut chip.sw_ver] {
+            if let Some(code) = codes.next() {
+                *field = code.ok()?;
+            }
+        }
+        Some(chip)
+    }
+}
+
+impl fmt::Display for ChipIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "HW code {:04X} (sub {:04X}, hw {:04X}, sw {:04X})",
+            self.hw_code, self.hw_sub_code, self.hw_ver, self.sw_ver
+        )?;
+        if !self.soc_id.is_empty() {
+            write!(f, ", SoC ID {}", self.soc_id_hex())?;
+        }
+        if !self.meid.is_empty() {
+            write!(f, ", MEID {}", self.meid_hex())?;
+        }
+        Ok(())
+    }
+}
+
+mod hex_u16 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:04x}", value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        u16::from_str_radix(&value, 16).map_err(serde::de::Error::custom)
+    }
+}
+
+mod hex_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        hex::decode(value).map_err(serde::de::Error::custom)
+    }
+}
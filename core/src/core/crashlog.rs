@@ -0,0 +1,260 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::cmp::Reverse;
+use std::fmt;
+
+// Where the bootloader and AEE keep what happened before the last resets.
+// Not every device has both, logdb is missing on most older SoCs.
+pub const CRASH_PARTITIONS: &[&str] = &["expdb", "logdb"];
+
+// Text shorter than this is most likely binary that happens to be printable
+const MIN_TEXT_RUN: usize = 8;
+// Lines kept after the one that matched, usually the PC/LR or the process
+const CONTEXT_LINES: usize = 4;
+// Longest `[...]`, `(...)` or `<...>` taken as part of a line's log prefix
+const MAX_PREFIX_FIELD: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashKind {
+    KernelPanic,
+    KernelOops,
+    // Android Exception Engine record (KE, HWT, HW_REBOOT...)
+    Aee,
+    Watchdog,
+    // dm-verity found a corrupted block, a classic bootloop after a bad flash
+    Verity,
+}
+
+impl fmt::Display for CrashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CrashKind::KernelPanic => "Kernel panic",
+            CrashKind::KernelOops => "Kernel oops",
+            CrashKind::Aee => "AEE exception",
+            CrashKind::Watchdog => "Watchdog",
+            CrashKind::Verity => "dm-verity",
+        })
+    }
+}
+
+// First match wins, so the more specific markers go first
+const MARKERS: &[(&str, CrashKind)] = &[
+    ("Kernel panic - not syncing", CrashKind::KernelPanic),
+    ("Unable to handle kernel", CrashKind::KernelOops),
+    ("kernel BUG at", CrashKind::KernelOops),
+    ("Internal error:", CrashKind::KernelOops),
+    ("SError Interrupt", CrashKind::KernelOops),
+    ("Exception Class:", CrashKind::Aee),
+    ("Exception Type:", CrashKind::Aee),
+    ("Watchdog detected hard LOCKUP", CrashKind::Watchdog),
+    ("hang detect", CrashKind::Watchdog),
+    ("HW_REBOOT", CrashKind::Watchdog),
+    ("dm-verity device corrupted", CrashKind::Verity),
+];
+
+// One panic reason found in a crash partition. The same reason over and over
+// (a bootloop) is a single record with its count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashRecord {
+    pub partition: String,
+    // Of the last occurrence, from the start of the partition
+    pub offset: u64,
+    pub kind: CrashKind,
+    pub reason: String,
+    pub context: Vec<String>,
+    pub count: usize,
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}+{:#X}: {}",
+            self.kind, self.partition, self.offset, self.reason
+        )?;
+        if self.count > 1 {
+            write!(f, " (x{})", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+// Pulls the panic reasons out of an expdb/logdb dump, newest first. Both are
+// mostly plain text appended over time, so newest means last in the partition.
+// They wrap around once full though, so take the order as a hint.
+pub fn parse_crash_log(partition: &str, data: &[u8]) -> Vec<CrashRecord> {
+    let mut records: Vec<CrashRecord> = Vec::new();
+
+    for (start, text) in text_runs(data) {
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        for line in text.split_inclusive('\n') {
+            lines.push((line_start, line.trim()));
+            line_start += line.len();
+        }
+
+        for (i, &(line_offset, line)) in lines.iter().enumerate() {
+            let Some(&(_, kind)) = MARKERS.iter().find(|(marker, _)| line.contains(marker)) else {
+                continue;
+            };
+            let offset = (start + line_offset) as u64;
+            // Without the timestamp, or the same panic on every boot never matches
+            let reason = strip_log_prefix(line).to_string();
+
+            if let Some(record) = records
+                .iter_mut()
+                .find(|r| r.kind == kind && r.reason == reason)
+            {
+                record.count += 1;
+                record.offset = offset;
+                continue;
+            }
+
+            let context = lines[i + 1..]
+                .iter()
+                .map(|&(_, line)| line)
+                .filter(|line| !line.is_empty())
+                .take(CONTEXT_LINES)
+                .map(str::to_string)
+                .collect();
+            records.push(CrashRecord {
+                partition: partition.to_string(),
+                offset,
+                kind,
+                reason,
+                context,
+                count: 1,
+            });
+        }
+    }
+
+    records.sort_by_key(|r| Reverse(r.offset));
+    records
+}
+
+// Drops the kernel's `[  123.456789]` timestamp and what MTK kernels put after
+// it (`(1)[123:kworker/1:2]`, `[C1]`, `<0>`...), they change on every boot
+fn strip_log_prefix(line: &str) -> &str {
+    let mut rest = line;
+    loop {
+        let trimmed = rest.trim_start();
+        let close = match trimmed.chars().next() {
+            Some('[') => ']',
+            Some('(') => ')',
+            Some('<') => '>',
+            _ => return trimmed,
+        };
+        match trimmed[..trimmed.len().min(MAX_PREFIX_FIELD)].find(close) {
+            Some(end) => rest = &trimmed[end + 1..],
+            None => return trimmed,
+        }
+    }
+}
+
+// Runs of printable ASCII (and line breaks), with their offset in `data`
+fn text_runs(data: &[u8]) -> Vec<(usize, String)> {
+    let printable = |b: &u8| matches!(b, 0x20..=0x7E | b'\t' | b'\n' | b'\r');
+    let mut runs = Vec::new();
+    let mut start = None;
+
+    for (i, b) in data.iter().enumerate() {
+        match (printable(b), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                if i - s >= MIN_TEXT_RUN {
+                    runs.push((s, String::from_utf8_lossy(&data[s..i]).into_owned()));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start
+        && data.len() - s >= MIN_TEXT_RUN
+    {
+        runs.push((s, String::from_utf8_lossy(&data[s..]).into_owned()));
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two boots of a bootloop as they end up in expdb, with the padding AEE
+    // leaves between records
+    fn expdb() -> Vec<u8> {
+        let boot = |time: &str, pid: u32| {
+            format!(
+                "[  {time}] (2)[{pid}:init]init: Service 'vold' crashed\n\
+                 [  {time}] (2)[{pid}:init]Kernel panic - not syncing: Attempted to kill init! exitcode=0x00007f00\n\
+                 [  {time}] (2)[{pid}:init]CPU: 2 PID: 1 Comm: init Tainted: G        W  O 4.19.191 #1\n\
+                 [  {time}] (2)[{pid}:init]Hardware name: MT6768 (DT)\n\
+                 [  {time}] (2)[{pid}:init]Call trace:\n\
+                 [  {time}] (2)[{pid}:init] dump_backtrace+0x0/0x1b0\n\
+                 [  {time}] (2)[{pid}:init] show_stack+0x24/0x30\n"
+            )
+        };
+
+        let mut data = vec![0xFFu8; 0x40];
+        data.extend_from_slice(boot("7.123456", 1).as_bytes());
+        data.extend_from_slice(&[0u8; 0x100]);
+        data.extend_from_slice(b"<0>[    0.000000] Booting Linux on physical CPU 0x0\n");
+        data.extend_from_slice(boot("6.987001", 1).as_bytes());
+        data.extend_from_slice(&[0u8; 0x20]);
+        data
+    }
+
+    #[test]
+    fn bootloop_is_one_record() {
+        let data = expdb();
+        let records = parse_crash_log("expdb", &data);
+
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.kind, CrashKind::KernelPanic);
+        assert_eq!(record.count, 2);
+        assert_eq!(
+            record.reason,
+            "Kernel panic - not syncing: Attempted to kill init! exitcode=0x00007f00"
+        );
+        // The last occurrence, in the second boot
+        let reboot = data
+            .windows(13)
+            .position(|w| w == b"Booting Linux")
+            .unwrap();
+        assert!(record.offset as usize > reboot);
+        assert_eq!(record.context.len(), CONTEXT_LINES);
+        assert!(record.context[0].contains("Comm: init"));
+    }
+
+    #[test]
+    fn newest_first() {
+        let mut data = expdb();
+        data.extend_from_slice(
+            b"[   42.000001] (0)[0:swapper/0]Watchdog detected hard LOCKUP on cpu 3\n",
+        );
+        let records = parse_crash_log("expdb", &data);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].kind, CrashKind::Watchdog);
+        assert_eq!(records[0].reason, "Watchdog detected hard LOCKUP on cpu 3");
+        assert!(records[0].offset > records[1].offset);
+    }
+
+    #[test]
+    fn log_prefixes() {
+        assert_eq!(strip_log_prefix("[  123.456789] foo"), "foo");
+        assert_eq!(
+            strip_log_prefix("<0>[ 1.0][C1] (1)[123:kworker/1:2]foo [bar]"),
+            "foo [bar]"
+        );
+        assert_eq!(
+            strip_log_prefix("Exception Class: KE"),
+            "Exception Class: KE"
+        );
+        assert_eq!(strip_log_prefix("[unterminated"), "[unterminated");
+    }
+}
@@ -12,25 +12,61 @@
     the combined work is subject to the networking terms of the AGPL-3.0-or-later,
     as for term 13 of the GPL-3.0-or-later license.
 */
-#[async_trait::async_trait]
+use std::future::Future;
+use std::io::Error;
+
+// Register access for the crypto engines. Every call can fail, a lost link must
+// not turn into a result computed from zeros.
+// The SEJ loops make thousands of these calls, so this isn't an async_trait:
+// CryptoConfig and SEJCrypto are generic over the IO and nothing gets boxed.
 pub trait CryptoIO: Send {
-    async fn read32(&mut self, addr: u32) -> u32;
-    async fn write32(&mut self, addr: u32, val: u32);
+    fn read32(&mut self, addr: u32) -> impl Future<Output = Result<u32, Error>> + Send;
+    fn write32(&mut self, addr: u32, val: u32) -> impl Future<Output = Result<(), Error>> + Send;
+    // Register sequences, override when the backend can batch them
+    fn read32_multi(
+        &mut self,
+        addrs: &[u32],
+    ) -> impl Future<Output = Result<Vec<u32>, Error>> + Send {
+        async move {
+            let mut values = Vec::with_capacity(addrs.len());
+            for &addr in addrs {
+                values.push(self.read32(addr).await?);
+            }
+            Ok(values)
+        }
+    }
+    fn write32_multi(
+        &mut self,
+        writes: &[(u32, u32)],
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async move {
+            for &(addr, val) in writes {
+                self.write32(addr, val).await?;
+            }
+            Ok(())
+        }
+    }
 }
 
-pub struct CryptoConfig<'a> {
+pub struct CryptoConfig<'a, IO: CryptoIO> {
     pub sej_base: u32,
-    pub io: &'a mut dyn CryptoIO,
+    pub io: &'a mut IO,
 }
 
-impl<'a> CryptoConfig<'a> {
-    pub fn new(sej_base: u32, io: &'a mut dyn CryptoIO) -> Self {
+impl<'a, IO: CryptoIO> CryptoConfig<'a, IO> {
+    pub fn new(sej_base: u32, io: &'a mut IO) -> Self {
         Self { sej_base, io }
     }
-    pub async fn read32(&mut self, addr: u32) -> u32 {
+    pub async fn read32(&mut self, addr: u32) -> Result<u32, Error> {
         self.io.read32(addr).await
     }
-    pub async fn write32(&mut self, addr: u32, val: u32) {
+    pub async fn write32(&mut self, addr: u32, val: u32) -> Result<(), Error> {
         self.io.write32(addr, val).await
     }
+    pub async fn read32_multi(&mut self, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+        self.io.read32_multi(addrs).await
+    }
+    pub async fn write32_multi(&mut self, writes: &[(u32, u32)]) -> Result<(), Error> {
+        self.io.write32_multi(writes).await
+    }
 }
@@ -12,10 +12,11 @@
     the combined work is subject to the networking terms of the AGPL-3.0-or-later,
     as for term 13 of the GPL-3.0-or-later license.
 */
-use crate::core::crypto::config::CryptoConfig;
+use crate::core::crypto::config::{CryptoConfig, CryptoIO};
 use aes::Aes128;
 use cbc::{Decryptor, Encryptor}; // TODO: Recheck this crate, as it doesn't receive stable updates for 3+ years
 use cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use std::io::Error;
 
 #[repr(u32)]
 #[derive(Copy, Clone, Debug)]
@@ -105,12 +106,30 @@ pub const G_CFG_RANDOM_PATTERN: [u32; 12] = [
 pub const DEFAULT_IV: &[u8] = b"\x57\x32\x5A\x5A\x12\x54\x97\x66\x12\x54\x97\x66\x57\x32\x5A\x5A";
 pub const DEFAULT_KEY: &[u8] = b"\x25\xA1\x76\x3A\x21\xBC\x85\x4C\xD5\x69\xDC\x23\xB4\x78\x2B\x63";
 
-pub struct SEJCrypto<'a> {
-    pub config: &'a mut CryptoConfig<'a>,
+// Known plaintext used by self_test(), same size as a seccfg hash
+const SELFTEST_PLAINTEXT: [u8; 32] = *b"Penumbra SEJ self-test vector!!!";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SejMode {
+    Hw,
+    HwV3,
+    HwV4,
+}
+
+#[derive(Debug, Clone)]
+pub struct SejSelfTestResult {
+    pub mode: SejMode,
+    pub ciphertext: Vec<u8>,
+    pub decrypted: Vec<u8>,
+    pub passed: bool,
 }
 
-impl<'a> SEJCrypto<'a> {
-    pub fn new(config: &'a mut CryptoConfig<'a>) -> Self {
+pub struct SEJCrypto<'a, IO: CryptoIO> {
+    pub config: &'a mut CryptoConfig<'a, IO>,
+}
+
+impl<'a, IO: CryptoIO> SEJCrypto<'a, IO> {
+    pub fn new(config: &'a mut CryptoConfig<'a, IO>) -> Self {
         Self { config }
     }
 
@@ -118,16 +137,30 @@ impl<'a> SEJCrypto<'a> {
         self.config.sej_base + reg.offset()
     }
 
-    async fn wreg(&mut self, reg: SejReg, val: u32) {
+    async fn wreg(&mut self, reg: SejReg, val: u32) -> Result<(), Error> {
         let addr = self.reg_addr(reg);
-        self.config.write32(addr, val).await;
+        self.config.write32(addr, val).await
     }
 
-    async fn rreg(&mut self, reg: SejReg) -> u32 {
+    async fn rreg(&mut self, reg: SejReg) -> Result<u32, Error> {
         let addr = self.reg_addr(reg);
         self.config.read32(addr).await
     }
 
+    // Same as wreg/rreg, but in one round trip when the DA extensions allow it
+    async fn wregs(&mut self, writes: &[(SejReg, u32)]) -> Result<(), Error> {
+        let writes: Vec<(u32, u32)> = writes
+            .iter()
+            .map(|&(reg, val)| (self.reg_addr(reg), val))
+            .collect();
+        self.config.write32_multi(&writes).await
+    }
+
+    async fn rregs(&mut self, regs: &[SejReg]) -> Result<Vec<u32>, Error> {
+        let addrs: Vec<u32> = regs.iter().map(|&reg| self.reg_addr(reg)).collect();
+        self.config.read32_multi(&addrs).await
+    }
+
     // Note: This modifies the data directly, it does not return a new Vec
     fn xor(&self, data: &mut [u8]) {
         for i in 0..4 {
@@ -159,171 +192,203 @@ impl<'a> SEJCrypto<'a> {
         }
     }
 
-    pub async fn sej_seccfg_hw(&mut self, data: &[u8], encrypt: bool, noxor: bool) -> Vec<u8> {
+    pub async fn sej_seccfg_hw(
+        &mut self,
+        data: &[u8],
+        encrypt: bool,
+        noxor: bool,
+    ) -> Result<Vec<u8>, Error> {
         let mut working = data.to_vec();
         if encrypt && !noxor {
             self.xor(&mut working);
         }
 
-        self.sej_v3_init(encrypt, &HACC_CFG_1, true).await;
-        let mut result = self.sej_run(&working).await;
-        self.sej_terminate().await;
+        self.sej_v3_init(encrypt, &HACC_CFG_1, true).await?;
+        let mut result = self.sej_run(&working).await?;
+        self.sej_terminate().await?;
 
         if !encrypt && !noxor {
             self.xor(&mut result);
         }
 
-        result
+        Ok(result)
     }
 
-    pub async fn sej_seccfg_hw_v3(&mut self, data: &[u8], encrypt: bool) -> Vec<u8> {
+    pub async fn sej_seccfg_hw_v3(&mut self, data: &[u8], encrypt: bool) -> Result<Vec<u8>, Error> {
         self.hw_aes128_cbc_encrypt(data, encrypt, false).await
     }
 
-    pub async fn sej_seccfg_hw_v4(&mut self, data: &[u8], encrypt: bool) -> Vec<u8> {
+    pub async fn sej_seccfg_hw_v4(&mut self, data: &[u8], encrypt: bool) -> Result<Vec<u8>, Error> {
         self.hw_aes128_cbc_encrypt(data, encrypt, true).await
     }
 
-    async fn hw_aes128_cbc_encrypt(&mut self, data: &[u8], encrypt: bool, legacy: bool) -> Vec<u8> {
-        self.sej_v3_init(encrypt, &HACC_CFG_1, legacy).await;
-        let ret = self.sej_run(&data).await;
-        self.sej_terminate().await;
-        ret
+    // The HW key is unique per device, so there are no fixed ciphertexts to compare
+    // against. Instead we check that each mode actually transforms the data and that
+    // decrypting gives back the original plaintext. A wrong sej_base usually shows up
+    // as all zeroes (or the input echoed back) since nothing is there to do the work.
+    // A register access that fails is an error, not a failed test.
+    pub async fn self_test(&mut self) -> Result<Vec<SejSelfTestResult>, Error> {
+        let mut results = Vec::new();
+
+        for mode in [SejMode::Hw, SejMode::HwV3, SejMode::HwV4] {
+            let ciphertext = self.crypt(mode, &SELFTEST_PLAINTEXT, true).await?;
+            let decrypted = self.crypt(mode, &ciphertext, false).await?;
+
+            let transformed = ciphertext.len() == SELFTEST_PLAINTEXT.len()
+                && ciphertext != SELFTEST_PLAINTEXT
+                && ciphertext.iter().any(|&b| b != ciphertext[0]);
+            let passed = transformed && decrypted == SELFTEST_PLAINTEXT;
+
+            results.push(SejSelfTestResult {
+                mode,
+                ciphertext,
+                decrypted,
+                passed,
+            });
+        }
+
+        Ok(results)
     }
 
-    async fn sej_run(&mut self, data: &[u8]) -> Vec<u8> {
+    // Runs `data` through the hardware-bound key in the given mode. Only whole
+    // 16 byte blocks are processed, a trailing partial block is dropped.
+    pub async fn crypt(
+        &mut self,
+        mode: SejMode,
+        data: &[u8],
+        encrypt: bool,
+    ) -> Result<Vec<u8>, Error> {
+        match mode {
+            SejMode::Hw => self.sej_seccfg_hw(data, encrypt, false).await,
+            SejMode::HwV3 => self.sej_seccfg_hw_v3(data, encrypt).await,
+            SejMode::HwV4 => self.sej_seccfg_hw_v4(data, encrypt).await,
+        }
+    }
+
+    async fn hw_aes128_cbc_encrypt(
+        &mut self,
+        data: &[u8],
+        encrypt: bool,
+        legacy: bool,
+    ) -> Result<Vec<u8>, Error> {
+        self.sej_v3_init(encrypt, &HACC_CFG_1, legacy).await?;
+        let ret = self.sej_run(data).await?;
+        self.sej_terminate().await?;
+        Ok(ret)
+    }
+
+    async fn sej_run(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
         let num_blocks = data.len() / 16; // I'm using u8, mtkclient uses u32
         let mut output = Vec::with_capacity(data.len());
 
         for block in 0..num_blocks {
-            for word in 0..4 {
-                let offset = block * 16 + word * 4;
-                let val = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
-                self.wreg(
-                    match word {
-                        0 => SejReg::ASRC0,
-                        1 => SejReg::ASRC1,
-                        2 => SejReg::ASRC2,
-                        _ => SejReg::ASRC3,
-                    },
-                    val,
-                )
-                .await;
-            }
-            self.wreg(SejReg::ACON2, SEJ_AES_START).await;
+            let word = |i: usize| {
+                let offset = block * 16 + i * 4;
+                u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+            };
+            self.wregs(&[
+                (SejReg::ASRC0, word(0)),
+                (SejReg::ASRC1, word(1)),
+                (SejReg::ASRC2, word(2)),
+                (SejReg::ASRC3, word(3)),
+                (SejReg::ACON2, SEJ_AES_START),
+            ])
+            .await?;
 
             for _ in 0..20 {
-                if self.rreg(SejReg::ACON2).await & SEJ_AES_RDY != 0 {
+                if self.rreg(SejReg::ACON2).await? & SEJ_AES_RDY != 0 {
                     break;
                 }
             }
 
-            for word in 0..4 {
-                let out_val = self
-                    .rreg(match word {
-                        0 => SejReg::AOUT0,
-                        1 => SejReg::AOUT1,
-                        2 => SejReg::AOUT2,
-                        _ => SejReg::AOUT3,
-                    })
-                    .await;
+            let out = self
+                .rregs(&[SejReg::AOUT0, SejReg::AOUT1, SejReg::AOUT2, SejReg::AOUT3])
+                .await?;
+            for out_val in out {
                 output.extend_from_slice(&out_val.to_le_bytes());
             }
         }
-        output
+        Ok(output)
     }
 
-    async fn sej_v3_init(&mut self, encrypt: bool, iv: &[u32], legacy: bool) {
+    async fn sej_v3_init(&mut self, encrypt: bool, iv: &[u32], legacy: bool) -> Result<(), Error> {
         let acon_settings = SEJ_AES_CHG_BO_OFF
             | SEJ_AES_TYPE_128
             | if !iv.is_empty() { SEJ_AES_MODE_CBC } else { 0 }
             | if encrypt { SEJ_AES_ENC } else { SEJ_AES_DEC };
 
-        for reg in [
-            SejReg::AKEY0,
-            SejReg::AKEY1,
-            SejReg::AKEY2,
-            SejReg::AKEY3,
-            SejReg::AKEY4,
-            SejReg::AKEY5,
-            SejReg::AKEY6,
-            SejReg::AKEY7,
-        ] {
-            self.wreg(reg, 0).await;
-        }
-
-        self.wreg(
-            SejReg::ACON,
-            SEJ_AES_CHG_BO_OFF | SEJ_AES_MODE_CBC | SEJ_AES_TYPE_128 | SEJ_AES_DEC,
-        )
-        .await;
-        self.wreg(SejReg::ACONK, SEJ_AES_BK2C | SEJ_AES_R2K).await;
-        self.wreg(SejReg::ACON2, SEJ_AES_CLR).await;
-
-        for (i, &val) in iv.iter().enumerate().take(4) {
-            self.wreg(
-                match i {
-                    0 => SejReg::ACFG0,
-                    1 => SejReg::ACFG1,
-                    2 => SejReg::ACFG2,
-                    _ => SejReg::ACFG3,
-                },
-                val,
-            )
-            .await;
-        }
+        let mut writes = vec![
+            (SejReg::AKEY0, 0),
+            (SejReg::AKEY1, 0),
+            (SejReg::AKEY2, 0),
+            (SejReg::AKEY3, 0),
+            (SejReg::AKEY4, 0),
+            (SejReg::AKEY5, 0),
+            (SejReg::AKEY6, 0),
+            (SejReg::AKEY7, 0),
+            (
+                SejReg::ACON,
+                SEJ_AES_CHG_BO_OFF | SEJ_AES_MODE_CBC | SEJ_AES_TYPE_128 | SEJ_AES_DEC,
+            ),
+            (SejReg::ACONK, SEJ_AES_BK2C | SEJ_AES_R2K),
+            (SejReg::ACON2, SEJ_AES_CLR),
+        ];
+        let acfg = [SejReg::ACFG0, SejReg::ACFG1, SejReg::ACFG2, SejReg::ACFG3];
+        writes.extend(acfg.into_iter().zip(iv.iter().copied()));
+        self.wregs(&writes).await?;
 
         if legacy {
-            let mut val = self.rreg(SejReg::UNK).await | 2;
-            self.wreg(SejReg::UNK, val).await;
-            val = self.rreg(SejReg::ACON2).await | 0x40000000;
-            self.wreg(SejReg::ACON2, val).await;
+            let mut val = self.rreg(SejReg::UNK).await? | 2;
+            self.wreg(SejReg::UNK, val).await?;
+            val = self.rreg(SejReg::ACON2).await? | 0x40000000;
+            self.wreg(SejReg::ACON2, val).await?;
 
             for _ in 0..20 {
-                if self.rreg(SejReg::ACON2).await > 0x80000000 {
+                if self.rreg(SejReg::ACON2).await? > 0x80000000 {
                     break;
                 }
             }
 
-            val = self.rreg(SejReg::UNK).await & 0xFFFFFFFE;
-            self.wreg(SejReg::UNK, val).await;
-            self.wreg(SejReg::ACONK, SEJ_AES_BK2C).await;
-            self.wreg(SejReg::ACON, acon_settings).await;
+            val = self.rreg(SejReg::UNK).await? & 0xFFFFFFFE;
+            self.wreg(SejReg::UNK, val).await?;
+            self.wreg(SejReg::ACONK, SEJ_AES_BK2C).await?;
+            self.wreg(SejReg::ACON, acon_settings).await?;
         } else {
-            self.wreg(SejReg::UNK, 1).await;
+            self.wreg(SejReg::UNK, 1).await?;
 
             for i in 0..3 {
                 let pos = i * 4;
-                self.wreg(SejReg::ASRC0, G_CFG_RANDOM_PATTERN[pos]).await;
+                self.wreg(SejReg::ASRC0, G_CFG_RANDOM_PATTERN[pos]).await?;
                 self.wreg(SejReg::ASRC1, G_CFG_RANDOM_PATTERN[pos + 1])
-                    .await;
+                    .await?;
                 self.wreg(SejReg::ASRC2, G_CFG_RANDOM_PATTERN[pos + 2])
-                    .await;
+                    .await?;
                 self.wreg(SejReg::ASRC3, G_CFG_RANDOM_PATTERN[pos + 3])
-                    .await;
-                self.wreg(SejReg::ACON2, SEJ_AES_START).await;
+                    .await?;
+                self.wreg(SejReg::ACON2, SEJ_AES_START).await?;
                 for _ in 0..20 {
-                    if self.rreg(SejReg::ACON2).await & SEJ_AES_RDY != 0 {
+                    if self.rreg(SejReg::ACON2).await? & SEJ_AES_RDY != 0 {
                         break;
                     }
                 }
             }
 
-            self.wreg(SejReg::ACON2, SEJ_AES_CLR).await;
+            self.wreg(SejReg::ACON2, SEJ_AES_CLR).await?;
 
-            self.wreg(SejReg::ACFG0, iv[0]).await;
-            self.wreg(SejReg::ACFG1, iv[1]).await;
-            self.wreg(SejReg::ACFG2, iv[2]).await;
-            self.wreg(SejReg::ACFG3, iv[3]).await;
+            self.wreg(SejReg::ACFG0, iv[0]).await?;
+            self.wreg(SejReg::ACFG1, iv[1]).await?;
+            self.wreg(SejReg::ACFG2, iv[2]).await?;
+            self.wreg(SejReg::ACFG3, iv[3]).await?;
 
-            self.wreg(SejReg::ACON, acon_settings).await;
-            self.wreg(SejReg::ACONK, 0).await;
+            self.wreg(SejReg::ACON, acon_settings).await?;
+            self.wreg(SejReg::ACONK, 0).await?;
         }
+        Ok(())
     }
 
     // Just clears the registers after use, nothing fancy
-    async fn sej_terminate(&mut self) {
-        self.wreg(SejReg::ACON2, SEJ_AES_CLR).await;
+    async fn sej_terminate(&mut self) -> Result<(), Error> {
+        self.wreg(SejReg::ACON2, SEJ_AES_CLR).await?;
 
         for reg in [
             SejReg::AKEY0,
@@ -335,7 +400,60 @@ impl<'a> SEJCrypto<'a> {
             SejReg::AKEY6,
             SejReg::AKEY7,
         ] {
-            self.wreg(reg, 0).await;
+            self.wreg(reg, 0).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    // Registers that always read as ready, until the link drops after `ok` accesses
+    struct FlakyIO {
+        ok: usize,
+    }
+
+    impl FlakyIO {
+        fn access(&mut self) -> Result<(), Error> {
+            if self.ok == 0 {
+                return Err(Error::new(ErrorKind::BrokenPipe, "link lost"));
+            }
+            self.ok -= 1;
+            Ok(())
+        }
+    }
+
+    impl CryptoIO for FlakyIO {
+        async fn read32(&mut self, _addr: u32) -> Result<u32, Error> {
+            self.access().map(|_| SEJ_AES_RDY)
+        }
+        async fn write32(&mut self, _addr: u32, _val: u32) -> Result<(), Error> {
+            self.access()
+        }
+    }
+
+    #[tokio::test]
+    async fn crypt_fails_with_the_link() {
+        let mut io = FlakyIO { ok: usize::MAX };
+        let mut config = CryptoConfig::new(0x1000A000, &mut io);
+        let mut sej = SEJCrypto::new(&mut config);
+        let out = sej.crypt(SejMode::HwV3, &[0u8; 32], true).await.unwrap();
+        assert_eq!(out.len(), 32);
+        let accesses = usize::MAX - io.ok;
+
+        // Dropping anywhere, init, the blocks or the cleanup, has to fail it
+        for ok in [0, accesses / 2, accesses - 1] {
+            let mut io = FlakyIO { ok };
+            let mut config = CryptoConfig::new(0x1000A000, &mut io);
+            let mut sej = SEJCrypto::new(&mut config);
+            let err = sej
+                .crypt(SejMode::HwV3, &[0u8; 32], true)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::BrokenPipe);
         }
     }
 }
@@ -2,84 +2,337 @@
 SPDX-License-Identifier: AGPL-3.0-or-later
 SPDX-FileCopyrightText: 2025 Shomy
 */
-use crate::connection::port::MTKPort;
-use crate::connection::{Connection, port::ConnectionType};
+use crate::connection::latency::LatencyReport;
+use crate::connection::pmic::RtcTime;
+use crate::connection::port::{MTKPort, wait_for_boot_port};
+use crate::connection::stats::ConnectionStats;
+use crate::connection::transport::TransportConfig;
+use crate::connection::{
+    Connection, HandshakeOptions, LEGACY_MAX_CHUNK, TargetConfig, port::ConnectionType,
+};
+use crate::core::abslot::{BootControl, Slot, SlotSwitch};
+use crate::core::audit::{self, AUDIT_HASH_MAX, AuditEntry, AuditLog};
+use crate::core::autobackup::AutoBackup;
+use crate::core::benchmark::{BenchmarkOptions, BenchmarkResult};
+use crate::core::checksums::{Sha256Stream, sha256_hex};
+use crate::core::chip::ChipIdentity;
+use crate::core::crashlog::{CRASH_PARTITIONS, CrashRecord, parse_crash_log};
 use crate::core::crypto::config::{CryptoConfig, CryptoIO};
-use crate::core::crypto::sej::SEJCrypto;
-use crate::core::seccfg::LockFlag;
-use crate::core::seccfg::SecCfgV4;
-use crate::core::storage::{Partition, StorageType, parse_gpt};
-use crate::da::{DAFile, DAProtocol, DAType, XFlash};
-use log::{error, info, warn};
-use std::io::{Error, ErrorKind};
+use crate::core::crypto::sej::{SEJCrypto, SejMode, SejSelfTestResult};
+use crate::core::dump::{DumpLayout, SegmentedFile, dump_exists, pgpt_sectors, sgpt_sectors};
+use crate::core::events::{
+    Event, EventSink, OperationResult, forward_named_progress, forward_progress,
+    named_progress_events,
+};
+use crate::core::fastboot::{self, FastbootDevice};
+use crate::core::fileio::{self, ChunkWriter};
+use crate::core::flashall::{FormatAllOptions, Journal, JournalStep};
+use crate::core::fsprobe::{self, FsProbe, PROBE_SIZE};
+use crate::core::gpt::{
+    GPT_SIGNATURE, GptData, GptHeader, GptReport, check_against_storage, check_gpt,
+};
+use crate::core::identity::{DeviceIdentity, IdentityMismatch};
+use crate::core::operation::{OperationHook, OperationSummary};
+use crate::core::pipeline::Pipeline;
+use crate::core::policy::SafetyPolicy;
+use crate::core::power::{PowerLimits, PowerProfile};
+use crate::core::preflight::{LockPreflightError, LockReport, LockState, oem_unlock_allowed};
+use crate::core::profile::{DeviceProfile, ProfileStore};
+use crate::core::ptable::{PartitionTableFormat, export_partitions};
+use crate::core::recovery::{self, RecoveryOptions, RecoveryReport, fill_pattern};
+use crate::core::reset::{DeviceReset, MAX_RESET_RECOVERIES, RESET_DETECT_WINDOW};
+use crate::core::seccfg::{LockFlag, SecCfgV4, SecCfgV4Algo, cache_algo, cached_algo};
+use crate::core::storage::{
+    Partition, StorageType, gpt_alternate_lba, gpt_entries_end, gpt_sector_size, parse_gpt,
+};
+use crate::core::throttle::WriteThrottle;
+use crate::core::utilities::saturating_usize;
+use crate::core::vbmeta::{self, BootPatch, VerityReport};
+use crate::da::write_protect::WriteProtectKind;
+use crate::da::xflash::UploadProgress;
+use crate::da::{
+    Capabilities, DA, DAData, DAFile, DAProtocol, DAStatusError, DAType, DaEnvConfig,
+    DaStorageView, EmmcCid, EmmcCsd, LoaderCatalog, LoaderMismatch, ProtocolKind, ShutdownMode,
+    SignatureHandling, StorageHealth, StorageOps, WriteProtectStatus, WriteProtected, XFlash,
+};
+use crate::exploit::ExploitPolicy;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+// Entry arrays bigger than this are treated as a corrupted header rather than read
+const GPT_MAX_ENTRIES_LEN: usize = 0x100000;
+
+// Small partitions read over and over (e.g. seccfg by every lock state change).
+// Their contents are kept after the first read, until something writes to them.
+const CACHED_PARTITIONS: &[&str] = &["seccfg", "misc", "frp"];
+
+// Without a DA, everything goes through LegacyRead/LegacyWrite one small chunk
+// at a time. Enough for seccfg or the preloader, hopeless for anything bigger.
+const LEGACY_MAX_SIZE: usize = 0x100000;
+
+// Enough to hold the protective MBR and the primary GPT header with 4K sectors
+const GPT_HEAD_SIZE: usize = 0x2000;
+
+// Fixed amount of GPT read by the legacy fallback, where every byte is slow
+const GPT_READ_SIZE: usize = 0x8000;
+
+const SEJ_BASE: u32 = 0x1000A000; // TODO: Dynamically determine SEJ base (maybe through preloader)
+
+// Where the BootROM is mapped, same on every SoC we've seen so far
+const BROM_BASE: u32 = 0x0;
+const BROM_SIZE: usize = 0x20000;
+// Read in small steps, so progress moves even without the DA extensions
+const BROM_CHUNK_SIZE: usize = 0x1000;
 
 #[derive(Clone, Debug)]
 pub struct DeviceInfo {
     pub chipset: String,
-    pub soc_id: Vec<u8>,
-    pub meid: Vec<u8>,
-    pub hw_code: u16,
+    pub chip: ChipIdentity,
+    pub target_config: Option<TargetConfig>,
+    // None for pl_ver means the device is in BROM mode, see Connection::get_pl_ver
+    pub pl_ver: Option<u8>,
+    pub br_ver: Option<u8>,
     pub storage: StorageType,
     pub partitions: Vec<Partition>,
+    // Why the partition table couldn't be read, if it couldn't. The device is in
+    // raw mode then: no partitions, only address based access (read_flash,
+    // write_flash), which is enough to put a working GPT back.
+    pub gpt_error: Option<String>,
+    // Flash wear, read once in DA mode if the DA can tell (see StorageHealth)
+    pub storage_health: Option<StorageHealth>,
+}
+
+// Shared between the device and its protocol. Whoever learns something new
+// (storage type, partitions...) updates it, frontends just subscribe.
+pub type SharedDeviceInfo = Arc<watch::Sender<DeviceInfo>>;
+
+// A write that was skipped because the device is in dry-run mode
+#[derive(Clone, Debug)]
+pub struct PlannedWrite {
+    pub partition: String,
+    pub offset: u64,
+    pub size: usize,
+    pub sha256: String,
 }
 
 pub struct Device<'a> {
-    pub dev_info: Option<Arc<Mutex<DeviceInfo>>>,
-    connection: Option<Connection>,
-    protocol: Option<Box<dyn DAProtocol + 'a + Send>>,
+    pub dev_info: Option<SharedDeviceInfo>,
+    connection: Connection,
+    protocol: Option<ProtocolKind<'a>>,
     connected: bool,
+    seccfg_algo: Option<SecCfgV4Algo>,
+    dry_run: bool,
+    planned_writes: Vec<PlannedWrite>,
+    op_hook: Option<OperationHook>,
+    events: Option<EventSink>,
+    op_name: String,
+    op_depth: usize,
+    op_bytes: u64,
+    op_stats: ConnectionStats,
+    partition_cache: HashMap<String, Vec<u8>>,
+    audit: Option<AuditLog>,
+    split_size: Option<u64>,
+    auto_backup: Option<AutoBackup>,
+    power: PowerLimits,
+    power_profile: PowerProfile,
+    write_throttle: WriteThrottle,
+    // Catalog loader the device was initialized with, for its profile
+    loader: Option<String>,
+    profiles: Option<ProfileStore>,
+    exploit: ExploitPolicy,
+    // Bytes per LBA, found out on the first LBA based access
+    sector_size: Option<usize>,
+    // Bring the device back when it resets mid-operation, see handle_reset
+    reset_recovery: bool,
+    safety: SafetyPolicy,
 }
 
-#[async_trait::async_trait]
 impl<'a> CryptoIO for Device<'a> {
-    async fn read32(&mut self, addr: u32) -> u32 {
-        if let Some(protocol) = &mut self.protocol {
-            match protocol.read32(addr).await {
-                Ok(val) => val,
-                Err(e) => {
-                    error!("Failed to read32 from protocol at 0x{:08X}: {}", addr, e);
-                    0
-                }
+    async fn read32(&mut self, addr: u32) -> Result<u32, Error> {
+        self.crypto_protocol()?.read32(addr).await
+    }
+    async fn write32(&mut self, addr: u32, val: u32) -> Result<(), Error> {
+        self.crypto_protocol()?.write32(addr, val).await
+    }
+    async fn read32_multi(&mut self, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+        self.crypto_protocol()?.read32_multi(addrs).await
+    }
+    async fn write32_multi(&mut self, writes: &[(u32, u32)]) -> Result<(), Error> {
+        self.crypto_protocol()?.write32_multi(writes).await
+    }
+}
+
+impl<'a> Device<'a> {
+    pub async fn init(
+        mtk_port: Box<dyn MTKPort>,
+        da_data: impl Into<DAData>,
+    ) -> Result<Self, Error> {
+        Self::init_with(mtk_port, da_data, HandshakeOptions::default()).await
+    }
+
+    pub async fn init_with(
+        mtk_port: Box<dyn MTKPort>,
+        da_data: impl Into<DAData>,
+        handshake: HandshakeOptions,
+    ) -> Result<Self, Error> {
+        let da_data = da_data.into();
+        let connection = Connection::new(mtk_port);
+
+        if connection.connection_type() == ConnectionType::Da {
+            if da_data.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The device is already in DA mode, a DA file is needed to talk to it",
+                ));
             }
-        } else {
-            error!("No protocol available for read32 at 0x{:08X}!", addr);
-            0
+            let da_file = DAFile::parse(da_data)?;
+            let probe = attach_probe(&da_file)?;
+            return Self::attach(connection, probe, |hw_code| {
+                da_file.check_supports(hw_code)?;
+                Ok(da_file)
+            })
+            .await;
         }
-    }
-    async fn write32(&mut self, addr: u32, val: u32) {
-        if let Some(protocol) = &mut self.protocol {
-            if let Err(e) = protocol.write32(addr, val).await {
-                error!("Failed to write32 to protocol at 0x{:08X}: {}", addr, e);
+
+        Self::init_connected(connection, &handshake, |hw_code, _soc_id| {
+            if da_data.is_empty() {
+                return Ok(None);
             }
-        } else {
-            error!("No protocol available for write32 at 0x{:08X}!", addr);
+            // Check the loader against the device before anything else is sent,
+            // a wrong DA is the most common reason for a failed init
+            let da_file = DAFile::parse(da_data)?;
+            da_file.check_supports(hw_code)?;
+            Ok(Some(da_file))
+        })
+        .await
+    }
+
+    // Like init_with, but the DA is picked from `catalog` by the device's hw_code
+    pub async fn init_from_catalog(
+        mtk_port: Box<dyn MTKPort>,
+        catalog: &LoaderCatalog,
+        handshake: HandshakeOptions,
+    ) -> Result<Self, Error> {
+        Self::init_from_catalog_with(mtk_port, catalog, handshake, None).await
+    }
+
+    // Same as init_from_catalog, but the loader this device's profile says
+    // worked last time goes first, if it's still in the catalog
+    pub async fn init_from_catalog_with(
+        mtk_port: Box<dyn MTKPort>,
+        catalog: &LoaderCatalog,
+        handshake: HandshakeOptions,
+        profiles: Option<&ProfileStore>,
+    ) -> Result<Self, Error> {
+        if catalog.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "No DA selected and no fallback loaders available",
+            ));
         }
+        let connection = Connection::new(mtk_port);
+
+        // Can't ask the BootROM anymore, the DA tells us the hw code after
+        // attaching and the loader gets picked then
+        if connection.connection_type() == ConnectionType::Da {
+            let probe = attach_probe(&catalog.entries()[0].da)?;
+            let mut picked = None;
+            let mut device = Self::attach(connection, probe, |hw_code| {
+                let Some(entry) = catalog.find(hw_code) else {
+                    return Err(LoaderMismatch {
+                        hw_code,
+                        supported: catalog.socs(),
+                    }
+                    .into());
+                };
+                info!("Picked loader {} for HW code {:04X}", entry.name, hw_code);
+                picked = Some(entry.name.clone());
+                Ok(entry.da.clone())
+            })
+            .await?;
+            device.loader = picked;
+            return Ok(device);
+        }
+
+        let mut picked = None;
+        let mut device = Self::init_connected(connection, &handshake, |hw_code, soc_id| {
+            let known = profiles
+                .and_then(|store| store.get(soc_id).ok().flatten())
+                .and_then(|profile| profile.loader);
+            let entry = known
+                .as_deref()
+                .and_then(|name| catalog.find_named(name, hw_code))
+                .or_else(|| catalog.find(hw_code));
+            let Some(entry) = entry else {
+                return Err(LoaderMismatch {
+                    hw_code,
+                    supported: catalog.socs(),
+                }
+                .into());
+            };
+            info!("Picked loader {} for HW code {:04X}", entry.name, hw_code);
+            picked = Some(entry.name.clone());
+            Ok(Some(entry.da.clone()))
+        })
+        .await?;
+        device.loader = picked;
+        Ok(device)
     }
-}
 
-impl<'a> Device<'a> {
-    pub async fn init(mtk_port: Box<dyn MTKPort>, da_data: Vec<u8>) -> Result<Self, Error> {
-        let mut connection = Connection::new(mtk_port);
+    // Everything after the port was opened: handshake, BROM info, then the DA
+    // returned by `select` for the device's hw code and SoC ID (None for
+    // preloader only)
+    async fn init_connected(
+        mut connection: Connection,
+        handshake: &HandshakeOptions,
+        select: impl FnOnce(u16, &[u8]) -> Result<Option<DAFile>, Error>,
+    ) -> Result<Self, Error> {
+        connection.handshake_with(handshake).await?;
 
-        connection.handshake().await?;
+        let chip = connection.get_chip_identity().await?;
+        let hw_code = chip.hw_code;
+        let da_file = select(hw_code, &chip.soc_id)?;
 
-        let soc_id = connection.get_soc_id().await?;
-        let meid = connection.get_meid().await?;
-        let hw_code = connection.get_hw_code().await? as u16;
+        let target_config = match connection.get_target_config().await {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Could not read target config: {}", e);
+                None
+            }
+        };
+        let pl_ver = match connection.get_pl_ver().await {
+            Ok(ver) => ver,
+            Err(e) => {
+                warn!("Could not read preloader version: {}", e);
+                None
+            }
+        };
+        let br_ver = match connection.get_br_ver().await {
+            Ok(ver) => Some(ver),
+            Err(e) => {
+                warn!("Could not read BootROM version: {}", e);
+                None
+            }
+        };
 
-        let device_info = Arc::new(Mutex::new(DeviceInfo {
-            soc_id,
-            meid,
-            hw_code,
+        let device_info = Arc::new(watch::Sender::new(DeviceInfo {
+            chip,
+            target_config,
+            pl_ver,
+            br_ver,
             chipset: String::from("Unknown"),
             storage: StorageType::Unknown,
             partitions: vec![],
+            gpt_error: None,
+            storage_health: None,
         }));
 
-        if !da_data.is_empty() {
-            let da_file = DAFile::parse_da(&da_data)?;
+        if let Some(da_file) = da_file {
             let da = match da_file.get_da_from_hw_code(hw_code) {
                 Some(da) => da,
                 None => {
@@ -92,28 +345,104 @@ impl<'a> Device<'a> {
 
             info!("Using DA for HW code {:02X}", da.hw_code);
 
-            let protocol: Box<dyn DAProtocol> = match da.da_type {
-                DAType::V5 => Box::new(XFlash::new(connection, da, Arc::clone(&device_info))),
+            // The protocol gets its own handle, the port itself is shared
+            let protocol = match da.da_type {
+                DAType::V5 => ProtocolKind::XFlash(XFlash::new(
+                    connection.clone(),
+                    da,
+                    Arc::clone(&device_info),
+                )),
                 _ => return Err(Error::new(ErrorKind::Other, "Unsupported DA type!")),
             };
 
-            let device = Device {
-                dev_info: Some(device_info),
-                protocol: Some(protocol),
-                connection: None,
-                connected: true,
-            };
-
-            Ok(device)
+            Ok(Device::from_parts(connection, device_info, Some(protocol)))
         } else {
             warn!("No Download Agent was provided, only preloader commands will be available.");
+            Ok(Device::from_parts(connection, device_info, None))
+        }
+    }
+
+    // The device enumerated as a DA port, so a DA from an earlier session is still
+    // running. The BootROM is gone by now, so instead of a handshake we check that
+    // the DA answers and pick up from there, without uploading anything.
+    // `probe` is only used to talk to the DA until it told us the hw code, the DA
+    // for the rest comes from what `select` returns for it.
+    async fn attach(
+        connection: Connection,
+        probe: DA,
+        select: impl FnOnce(u16) -> Result<DAFile, Error>,
+    ) -> Result<Self, Error> {
+        // No BROM commands anymore, the DA knows the hw code though
+        let device_info = Arc::new(watch::Sender::new(DeviceInfo {
+            chip: ChipIdentity::default(),
+            target_config: None,
+            pl_ver: None,
+            br_ver: None,
+            chipset: String::from("Unknown"),
+            storage: StorageType::Unknown,
+            partitions: vec![],
+            gpt_error: None,
+            storage_health: None,
+        }));
+
+        let mut xflash = XFlash::new(connection.clone(), probe, Arc::clone(&device_info));
+        xflash.attach().await?;
+        let chip = xflash.get_chip_id().await?;
+        let hw_code = chip.hw_code;
+        device_info.send_modify(|info| info.chip = chip);
 
-            Ok(Device {
-                dev_info: Some(device_info),
-                protocol: None,
-                connection: Some(connection),
-                connected: true,
+        // Only matters for whatever gets uploaded later (e.g. the extensions)
+        let da_file = select(hw_code)?;
+        if da_file.da_type != DAType::V5 {
+            return Err(Error::new(ErrorKind::Other, "Unsupported DA type!"));
+        }
+        xflash.da = da_file.get_da_from_hw_code(hw_code).ok_or_else(|| {
+            Error::from(LoaderMismatch {
+                hw_code,
+                supported: da_file.socs(),
             })
+        })?;
+        info!("Attached to the running DA (HW code {:02X})", hw_code);
+
+        Ok(Device::from_parts(
+            connection,
+            device_info,
+            Some(ProtocolKind::XFlash(xflash)),
+        ))
+    }
+
+    fn from_parts(
+        connection: Connection,
+        device_info: SharedDeviceInfo,
+        protocol: Option<ProtocolKind<'a>>,
+    ) -> Self {
+        Device {
+            dev_info: Some(device_info),
+            protocol,
+            connection,
+            connected: true,
+            seccfg_algo: None,
+            dry_run: false,
+            planned_writes: Vec::new(),
+            op_hook: None,
+            events: None,
+            op_name: String::new(),
+            op_depth: 0,
+            op_bytes: 0,
+            op_stats: ConnectionStats::default(),
+            partition_cache: HashMap::new(),
+            audit: None,
+            split_size: None,
+            auto_backup: None,
+            power: PowerLimits::default(),
+            power_profile: PowerProfile::default(),
+            write_throttle: WriteThrottle::default(),
+            loader: None,
+            profiles: None,
+            exploit: ExploitPolicy::default(),
+            sector_size: None,
+            reset_recovery: true,
+            safety: SafetyPolicy::default(),
         }
     }
 
@@ -126,160 +455,3269 @@ impl<'a> Device<'a> {
             return Err(Error::new(ErrorKind::Other, "No DA protocol available"));
         }
 
-        let protocol = self.protocol.as_mut().unwrap();
-        match protocol.upload_da().await {
-            Ok(_) => info!("Successfully entered DA mode"),
+        // Attached to a DA that was already running, nothing to upload
+        if self.connection.connection_type() != ConnectionType::Da {
+            // DA1 + DA2 take a few seconds, frontends get to show how far along we are
+            let started = self.begin_operation("Upload DA");
+            let progress = self.events.clone().map(|sink| {
+                Box::new(named_progress_events(sink, "Upload DA".to_string())) as UploadProgress
+            });
+            if let Some(ProtocolKind::XFlash(xflash)) = self.protocol.as_mut() {
+                xflash.set_upload_progress(progress);
+            }
+
+            let protocol = self.protocol.as_mut().unwrap();
+            let result = protocol.upload_da().await.map(|_| ());
+            if let ProtocolKind::XFlash(xflash) = protocol {
+                xflash.set_upload_progress(None);
+            }
+            self.finish_operation(started, result.as_ref().err());
+            match result {
+                Ok(()) => info!("Successfully entered DA mode"),
+                Err(e) => {
+                    error!("Failed to enter DA mode: {}", e);
+                    return Err(e);
+                }
+            }
+
+            let protocol = self.protocol.as_mut().unwrap();
+            protocol.set_connection_type(ConnectionType::Da)?;
+            self.remember_profile();
+        }
+        self.partition_cache.clear();
+
+        // A broken GPT is exactly when people need the device the most, so it
+        // doesn't fail DA mode, it only leaves us without partitions
+        if let Err(e) = self.reload_partitions().await {
+            warn!("Could not read the partition table, raw mode only: {}", e);
+        }
+        self.refresh_storage_health().await;
+
+        Ok(())
+    }
+
+    // Flash wear as the storage reports it, e.g. to tell if a device is worth
+    // refurbishing before putting time into it
+    pub async fn storage_health(&mut self) -> Result<StorageHealth, Error> {
+        self.ensure_da_mode().await?;
+        self.require(|caps| caps.storage_health, "Reading storage health")?;
+        self.protocol.as_mut().unwrap().storage_health().await
+    }
+
+    // The eMMC's CID, which chip it is (see EmmcCid). The same across reflashes,
+    // handy to tell boards apart. Goes through the DA extensions when the DA
+    // doesn't report it by itself.
+    pub async fn emmc_cid(&mut self) -> Result<EmmcCid, Error> {
+        self.ensure_da_mode().await?;
+        self.protocol.as_mut().unwrap().emmc_cid().await
+    }
+
+    pub async fn emmc_csd(&mut self) -> Result<EmmcCsd, Error> {
+        self.ensure_da_mode().await?;
+        self.require(|caps| caps.extensions, "Reading the eMMC CSD")?;
+        self.protocol.as_mut().unwrap().emmc_csd().await
+    }
+
+    // How many authenticated writes RPMB has seen, e.g. to tell whether
+    // something rewrote it since the last check
+    pub async fn rpmb_write_counter(&mut self) -> Result<u32, Error> {
+        self.ensure_da_mode().await?;
+        self.require(|caps| caps.rpmb, "Reading the RPMB write counter")?;
+        self.protocol.as_mut().unwrap().rpmb_write_counter().await
+    }
+
+    // Puts the storage health in DeviceInfo, for frontends. Not worth failing
+    // DA mode over, most DAs can't tell anyway.
+    async fn refresh_storage_health(&mut self) {
+        let Some(protocol) = self.protocol.as_mut() else {
+            return;
+        };
+        if !protocol.capabilities().storage_health {
+            return;
+        }
+        let health = match protocol.storage_health().await {
+            Ok(health) => health,
             Err(e) => {
-                error!("Failed to enter DA mode: {}", e);
-                return Err(e);
+                debug!("Could not read storage health: {}", e);
+                return;
             }
+        };
+        if health.is_worn() {
+            warn!("The storage is worn out: {}", health);
+        } else {
+            info!("Storage health: {}", health);
+        }
+        if let Some(dev_info) = &self.dev_info {
+            dev_info.send_modify(|info| info.storage_health = Some(health));
         }
-        protocol.set_connection_type(ConnectionType::Da)?;
+    }
 
-        // We don't care about progress here ;D
-        let mut progress = |_read: usize, _total: usize| {};
-        let pgpt_data = protocol.read_flash(0x0, 0x8000, &mut progress).await?;
-        let partitions = parse_gpt(&pgpt_data, StorageType::Emmc)?;
+    // Reads the GPT again, e.g. after repairing it in raw mode.
+    // On failure the partitions are cleared and the reason is kept in DeviceInfo::gpt_error.
+    pub async fn reload_partitions(&mut self) -> Result<(), Error> {
+        self.partition_cache.clear();
+        let result = self.read_partition_table().await;
 
-        if let Some(dev_info_rc) = &self.dev_info {
-            let mut dev_info = dev_info_rc.lock().await;
-            dev_info.partitions = partitions;
-            dev_info.storage = StorageType::Emmc; // Assuming eMMC for now
+        if let Some(dev_info) = &self.dev_info {
+            dev_info.send_modify(|info| {
+                info.storage = StorageType::Emmc; // Assuming eMMC for now
+                match &result {
+                    Ok(partitions) => {
+                        info.partitions = partitions.clone();
+                        info.gpt_error = None;
+                    }
+                    Err(e) => {
+                        info.partitions.clear();
+                        info.gpt_error = Some(e.to_string());
+                    }
+                }
+            });
         }
 
-        Ok(())
+        if let Ok(partitions) = &result {
+            self.cross_check_partitions(partitions).await;
+        }
+        result.map(|_| ())
     }
 
-    pub async fn read_partition(
+    // The storage layout as the DA sees it, see DaStorageView
+    pub async fn da_storage_view(&mut self) -> Result<DaStorageView, Error> {
+        self.ensure_da_mode().await?;
+        self.protocol.as_mut().unwrap().storage_view().await
+    }
+
+    // Warns when the GPT doesn't fit the storage the DA reports, typical after
+    // flashing another device's GPT or a botched repartition. Only informative,
+    // check_gpt() has the same findings.
+    async fn cross_check_partitions(&mut self, partitions: &[Partition]) {
+        let Some(protocol) = self.protocol.as_mut() else {
+            return;
+        };
+        let view = match protocol.storage_view().await {
+            Ok(view) => view,
+            Err(e) => {
+                debug!("DA storage view not available: {}", e);
+                return;
+            }
+        };
+        if let Some(category) = view.table_category {
+            debug!("DA partition table category: {:#X}", category);
+        }
+        let Some(storage) = view.user_size else {
+            return;
+        };
+        for issue in check_against_storage(partitions, None, storage) {
+            warn!("Partition table doesn't match the storage: {}", issue);
+        }
+    }
+
+    // None when the partition table was read fine
+    pub fn gpt_error(&self) -> Option<String> {
+        self.dev_info.as_ref()?.borrow().gpt_error.clone()
+    }
+
+    // Flash access on the storage the device has, through whatever protocol is
+    // up. Partition logic goes through this instead of the protocol's own calls.
+    fn storage(&mut self) -> Result<Box<dyn StorageOps + '_>, Error> {
+        let storage = self
+            .dev_info
+            .as_ref()
+            .map_or(StorageType::Unknown, |info| info.borrow().storage);
+        let protocol = self
+            .protocol
+            .as_mut()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "No DA protocol available"))?;
+        Ok(protocol.storage(storage))
+    }
+
+    // Register access for the SEJ, see CryptoIO
+    fn crypto_protocol(&mut self) -> Result<&mut ProtocolKind<'a>, Error> {
+        self.protocol.as_mut().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotConnected,
+                "No DA protocol available for register access",
+            )
+        })
+    }
+
+    async fn read_partition_table(&mut self) -> Result<Vec<Partition>, Error> {
+        let mut storage = self.storage()?;
+
+        // We don't care about progress here ;D
+        let mut progress = |_read: usize, _total: usize| {};
+        // Read the header first, then exactly as much as the entry array needs
+        let mut pgpt_data = storage.read_vec(0x0, GPT_HEAD_SIZE, &mut progress).await?;
+        let needed = gpt_entries_end(&pgpt_data)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "No valid GPT header found"))?;
+        if needed > GPT_HEAD_SIZE + GPT_MAX_ENTRIES_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("GPT entry array too large ({:#X} bytes)", needed),
+            ));
+        }
+        if needed > pgpt_data.len() {
+            pgpt_data = storage.read_vec(0x0, needed, &mut progress).await?;
+        }
+        parse_gpt(&pgpt_data, StorageType::Emmc)
+    }
+
+    // Address based access, for when there's no partition table to go by
+    pub async fn read_flash(
         &mut self,
-        name: &str,
+        addr: u64,
+        size: usize,
         progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<Vec<u8>, Error> {
-        if self.protocol.is_none() {
-            return Err(Error::new(ErrorKind::Other, "No DA protocol available"));
+        let started = self.begin_operation(format!("Read {:#X}+{:#X}", addr, size));
+        let mut progress = self.event_progress(progress);
+        let result = async {
+            self.ensure_da_mode().await?;
+            self.storage()?.read_vec(addr, size, &mut progress).await
         }
+        .await;
+        if let Ok(data) = &result {
+            self.op_bytes += data.len() as u64;
+        }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
 
-        let conn = self.get_connection()?;
-        if conn.connection_type != ConnectionType::Da {
-            info!("Not in DA mode, entering now");
-            self.enter_da_mode().await?;
+    pub async fn write_flash(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        // No idea which partitions this touches
+        self.partition_cache.clear();
+
+        let started = self.begin_operation(format!("Write {:#X}+{:#X}", addr, data.len()));
+        let mut progress = self.event_progress(progress);
+        let result = self.write_flash_inner(addr, data, &mut progress).await;
+        if result.is_ok() {
+            self.op_bytes += data.len() as u64;
         }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
 
-        let dev_info_rc = match &self.dev_info {
-            Some(info) => Arc::clone(info),
-            None => return Err(Error::new(ErrorKind::Other, "Device info not available")),
-        };
+    async fn write_flash_inner(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        self.ensure_da_mode().await?;
 
-        let dev_info = dev_info_rc.lock().await;
-        let partition = match dev_info.partitions.iter().find(|p| p.name == name) {
-            Some(part) => part,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Partition '{}' not found", name),
-                ));
-            }
-        };
+        if self.dry_run {
+            let planned = PlannedWrite {
+                partition: format!("{:#X}", addr),
+                offset: addr,
+                size: data.len(),
+                sha256: sha256_hex(data),
+            };
+            info!(
+                "[Dry run] Would write {} bytes at {:#X} (sha256 {})",
+                planned.size, planned.offset, planned.sha256
+            );
+            self.planned_writes.push(planned);
+            progress(data.len(), data.len());
+            return Ok(());
+        }
 
-        let protocol = self.protocol.as_mut().unwrap();
-        protocol
-            .read_flash(partition.address, partition.size as usize, progress)
-            .await
+        let result = self.write_recovering(addr, data, progress).await;
+        self.explain_write_error(addr, data.len(), result).await
     }
 
-    pub async fn write_partition(
+    // write_range, done over once the device is back if it resets
+    // halfway. Rewriting what already made it is harmless, picking up at the
+    // last reported offset would trust the DA to have committed it.
+    async fn write_recovering(
         &mut self,
-        name: &str,
+        addr: u64,
         data: &[u8],
         progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<(), Error> {
-        if self.protocol.is_none() {
-            return Err(Error::new(ErrorKind::Other, "No DA protocol available"));
+        let mut recoveries = 0;
+        loop {
+            let mut reached = 0;
+            let mut tracked = |done: usize, total: usize| {
+                reached = done;
+                progress(done, total)
+            };
+            let Err(e) = self.storage()?.write_range(addr, data, &mut tracked).await else {
+                return Ok(());
+            };
+            let operation = format!("Write {:#X}+{:#X}", addr, data.len());
+            self.handle_reset(e, &operation, addr + reached as u64, &mut recoveries)
+                .await?;
         }
+    }
 
-        let conn = self.get_connection()?;
-        if conn.connection_type != ConnectionType::Da {
-            info!("Not in DA mode, entering now");
-            self.enter_da_mode().await?;
+    // Sector based access, for forensics and GPT repair where everything is in
+    // LBAs anyway. `count` is in sectors, see sector_size().
+    pub async fn read_lba(
+        &mut self,
+        start_lba: u64,
+        count: usize,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        let started = self.begin_operation(format!("Read LBA {}+{}", start_lba, count));
+        let mut progress = self.event_progress(progress);
+        let result = async {
+            let sector_size = self.sector_size().await?;
+            let addr = lba_addr(start_lba, sector_size)?;
+            let size = count.checked_mul(sector_size).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} sectors is too much to read at once", count),
+                )
+            })?;
+            self.storage()?.read_vec(addr, size, &mut progress).await
+        }
+        .await;
+        if let Ok(data) = &result {
+            self.op_bytes += data.len() as u64;
         }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
 
-        let dev_info_rc = match &self.dev_info {
-            Some(info) => Arc::clone(info),
-            None => return Err(Error::new(ErrorKind::Other, "Device info not available")),
-        };
+    // `data` has to be whole sectors, a partial one would leave the rest of it
+    // up to the DA
+    pub async fn write_lba(
+        &mut self,
+        start_lba: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        // No idea which partitions this touches
+        self.partition_cache.clear();
 
-        let dev_info = dev_info_rc.lock().await;
-        let partition = match dev_info.partitions.iter().find(|p| p.name == name) {
-            Some(part) => part,
-            None => {
+        let started = self.begin_operation(format!("Write LBA {}+{:#X}", start_lba, data.len()));
+        let mut progress = self.event_progress(progress);
+        let result = async {
+            let sector_size = self.sector_size().await?;
+            if !data.len().is_multiple_of(sector_size) {
                 return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Partition '{}' not found", name),
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{} bytes is not a whole number of {} byte sectors",
+                        data.len(),
+                        sector_size
+                    ),
                 ));
             }
+            let addr = lba_addr(start_lba, sector_size)?;
+            self.write_flash_inner(addr, data, &mut progress).await
+        }
+        .await;
+        if result.is_ok() {
+            self.op_bytes += data.len() as u64;
+        }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    // Bytes per LBA: where the primary GPT header is tells, 512 for eMMC and 4096
+    // for most UFS. Without a GPT it goes by the storage type.
+    pub async fn sector_size(&mut self) -> Result<usize, Error> {
+        if let Some(sector_size) = self.sector_size {
+            return Ok(sector_size);
+        }
+        self.ensure_da_mode().await?;
+
+        let mut no_progress = |_read: usize, _total: usize| {};
+        let head = self
+            .storage()?
+            .read_vec(0x0, GPT_HEAD_SIZE, &mut no_progress)
+            .await?;
+        let sector_size = match gpt_sector_size(&head) {
+            Some(sector_size) => sector_size,
+            None => {
+                let storage = self.dev_info.as_ref().map(|info| info.borrow().storage);
+                let sector_size = match storage {
+                    Some(StorageType::Ufs) => 4096,
+                    _ => 512,
+                };
+                warn!(
+                    "No GPT to tell the sector size, assuming {} bytes",
+                    sector_size
+                );
+                sector_size
+            }
         };
+        debug!("Sector size: {} bytes", sector_size);
+        self.sector_size = Some(sector_size);
+        Ok(sector_size)
+    }
+
+    // Measures how fast the link really is with the current settings, by reading
+    // `size` bytes from the start of the user area in several chunk sizes.
+    // See benchmark_with() for timing writes too.
+    pub async fn benchmark(&mut self, size: usize) -> Result<Vec<BenchmarkResult>, Error> {
+        self.benchmark_with(&BenchmarkOptions::new(size)).await
+    }
+
+    pub async fn benchmark_with(
+        &mut self,
+        options: &BenchmarkOptions,
+    ) -> Result<Vec<BenchmarkResult>, Error> {
+        let started = self.begin_operation("Benchmark");
+        let result = self.benchmark_inner(options).await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn benchmark_inner(
+        &mut self,
+        options: &BenchmarkOptions,
+    ) -> Result<Vec<BenchmarkResult>, Error> {
+        if options.size == 0 || options.chunk_sizes.contains(&0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Benchmark size and chunk sizes must not be 0",
+            ));
+        }
+        self.ensure_da_mode().await?;
+
+        let write = options.write && !self.dry_run;
+        if options.write && !write {
+            info!("[Dry run] Skipping the write benchmark");
+        }
+        // Writing back what was just read still rewrites the range, a reset halfway
+        // through must not be able to take the GPT or a protected partition with it
+        if write {
+            let scratch = self.benchmark_scratch(options.addr, options.size as u64)?;
+            info!("Write benchmark on {}", scratch);
+        }
+
+        let mut progress = |_done: usize, _total: usize| {};
+        let mut results = Vec::with_capacity(options.chunk_sizes.len());
+        let mut transferred = 0;
+        for &chunk_size in &options.chunk_sizes {
+            let chunk_size = chunk_size.min(options.size);
+
+            let start = Instant::now();
+            let mut data = Vec::with_capacity(options.size);
+            while data.len() < options.size {
+                let addr = options.addr + data.len() as u64;
+                let len = chunk_size.min(options.size - data.len());
+                let chunk = self.storage()?.read_vec(addr, len, &mut progress).await?;
+                if chunk.len() != len {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!(
+                            "Short read at {:#X}: got {} of {} bytes",
+                            addr,
+                            chunk.len(),
+                            len
+                        ),
+                    ));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            let read_time = start.elapsed();
+
+            let write_time = if write {
+                let start = Instant::now();
+                for (i, chunk) in data.chunks(chunk_size).enumerate() {
+                    let addr = options.addr + (i * chunk_size) as u64;
+                    self.storage()?
+                        .write_range(addr, chunk, &mut progress)
+                        .await?;
+                }
+                Some(start.elapsed())
+            } else {
+                None
+            };
+
+            let result = BenchmarkResult {
+                chunk_size,
+                bytes: data.len(),
+                read_time,
+                write_time,
+            };
+            info!("Benchmark: {}", result);
+            transferred += data.len() * if write { 2 } else { 1 };
+            results.push(result);
+        }
+
+        self.op_bytes += transferred as u64;
+        Ok(results)
+    }
 
-        if data.len() > partition.size {
+    // The partition holding addr..addr + size, if it's one we may write to
+    fn benchmark_scratch(&self, addr: u64, size: u64) -> Result<String, Error> {
+        let info = match &self.dev_info {
+            Some(info) => info.borrow(),
+            None => return Err(Error::other("Device info not available")),
+        };
+        let end = addr.saturating_add(size);
+        let Some(partition) = info
+            .partitions
+            .iter()
+            .find(|p| p.address <= addr && end <= p.address.saturating_add(p.size))
+        else {
             return Err(Error::new(
                 ErrorKind::InvalidInput,
                 format!(
-                    "Data size {} exceeds partition size {}",
-                    data.len(),
-                    partition.size
+                    "{:#X}..{:#X} isn't inside a single partition, pick a scratch partition for the write benchmark",
+                    addr, end
+                ),
+            ));
+        };
+        if self.safety.is_protected(&partition.name) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "{} is protected, pick a scratch partition for the write benchmark",
+                    partition.name
                 ),
             ));
         }
+        Ok(partition.name.clone())
+    }
 
+    pub async fn write_protect_status(
+        &mut self,
+        addr: u64,
+        size: u64,
+    ) -> Result<WriteProtectStatus, Error> {
+        self.ensure_da_mode().await?;
+        self.require(|caps| caps.write_protect, "Write protection queries")?;
         let protocol = self.protocol.as_mut().unwrap();
-        protocol
-            .write_flash(partition.address, data.len(), data, progress)
-            .await
+        protocol.write_protect_status(addr, size).await
     }
 
-    pub fn get_connection(&mut self) -> Result<&mut Connection, std::io::Error> {
-        if let Some(conn) = &mut self.connection {
-            Ok(conn)
-        } else if let Some(protocol) = &mut self.protocol {
-            Ok(protocol.get_connection())
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotConnected,
-                "No connection available",
-            ))
+    // Only temporary (group) protection can be cleared, see WriteProtectKind
+    pub async fn clear_write_protect(&mut self, addr: u64, size: u64) -> Result<(), Error> {
+        self.ensure_da_mode().await?;
+        self.require(|caps| caps.write_protect, "Clearing write protection")?;
+        let protocol = self.protocol.as_mut().unwrap();
+        let status = protocol.write_protect_status(addr, size).await?;
+        match status.user_protection() {
+            None => Ok(()),
+            Some(WriteProtectKind::Temporary) => {
+                info!(
+                    "Clearing write protection on {} groups at {:#X}",
+                    status.protected_groups, addr
+                );
+                protocol.clear_write_protect(addr, size).await
+            }
+            Some(kind) => Err(WriteProtected {
+                addr,
+                size,
+                kind,
+                status: None,
+            }
+            .into()),
         }
     }
 
-    pub fn get_protocol(&mut self) -> Option<&mut Box<dyn DAProtocol + 'a + Send>> {
-        self.protocol.as_mut()
+    // A failed write only says "status 0x...", if the range turns out to be
+    // write-protected that's the error worth showing instead
+    async fn explain_write_error(
+        &mut self,
+        addr: u64,
+        size: usize,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        let Err(e) = result else {
+            return Ok(());
+        };
+        let Some(status) = DAStatusError::from_error(&e).map(|err| err.status) else {
+            return Err(e);
+        };
+        let protocol = self.protocol.as_mut().unwrap();
+        match protocol.write_protect_status(addr, size as u64).await {
+            Ok(wp) => match wp.user_protection() {
+                Some(kind) => Err(WriteProtected {
+                    addr,
+                    size: size as u64,
+                    kind,
+                    status: Some(status),
+                }
+                .into()),
+                None => Err(e),
+            },
+            // Can't tell, keep the original error
+            Err(_) => Err(e),
+        }
+    }
+
+    // What the protocol can do right now, see Capabilities. Some of it (the
+    // extensions) is only known once in DA mode. Nothing without a protocol.
+    pub fn capabilities(&self) -> Capabilities {
+        self.protocol
+            .as_ref()
+            .map(|protocol| protocol.capabilities())
+            .unwrap_or_default()
+    }
+
+    fn require(&self, check: fn(&Capabilities) -> bool, what: &str) -> Result<(), Error> {
+        if check(&self.capabilities()) {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{} not supported by this DA", what),
+        ))
     }
 
-    pub async fn set_seccfg_lock_state(&mut self, lock_state: LockFlag) -> Option<Vec<u8>> {
+    async fn ensure_da_mode(&mut self) -> Result<(), Error> {
         if self.protocol.is_none() {
-            return None;
+            return Err(Error::new(ErrorKind::Other, "No DA protocol available"));
         }
 
-        let conn = self.get_connection().ok()?;
-        if conn.connection_type != ConnectionType::Da {
+        if self.connection.connection_type() != ConnectionType::Da {
             info!("Not in DA mode, entering now");
-            self.enter_da_mode().await.ok()?;
+            self.enter_da_mode().await?;
         }
 
-        let mut progress = |_read: usize, _total: usize| {};
-
-        let sej_base = 0x1000A000; // TODO: Dynamically determine SEJ base (maybe through preloader)
-        let seccfg_raw = self.read_partition("seccfg", &mut progress).await.ok()?;
+        Ok(())
+    }
 
-        let new_seccfg = {
-            let mut crypto_config = CryptoConfig::new(sej_base, self);
-            let mut sej = SEJCrypto::new(&mut crypto_config);
-            let mut seccfg = SecCfgV4::parse(&seccfg_raw, &mut sej).await.ok()?;
+    pub async fn read_partition(
+        &mut self,
+        name: &str,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        if let Some(data) = self.partition_cache.get(name) {
+            progress(data.len(), data.len());
+            return Ok(data.clone());
+        }
+
+        let started = self.begin_operation(format!("Read {}", name));
+        let mut progress = self.event_progress(progress);
+        let result = self.read_partition_inner(name, &mut progress).await;
+        if let Ok(data) = &result {
+            self.op_bytes += data.len() as u64;
+            if CACHED_PARTITIONS.contains(&name) {
+                self.partition_cache.insert(name.to_string(), data.clone());
+            }
+        }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn read_partition_inner(
+        &mut self,
+        name: &str,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        if self.protocol.is_none() {
+            let partition = self.legacy_partition(name).await?;
+            let size = in_memory_len(&partition)?;
+            return self.legacy_read(partition.address, size, progress).await;
+        }
+
+        self.ensure_da_mode().await?;
+
+        let partition = self.find_partition(name).await?;
+        let size = in_memory_len(&partition)?;
+
+        self.storage()?
+            .read_vec(partition.address, size, progress)
+            .await
+    }
+
+    // Reads `size` bytes starting `offset` bytes into the partition, e.g. to look
+    // at a header without pulling the whole partition.
+    pub async fn read_partition_range(
+        &mut self,
+        name: &str,
+        offset: u64,
+        size: usize,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        let started = self.begin_operation(format!("Read {}", name));
+        let mut progress = self.event_progress(progress);
+        let result = self
+            .read_partition_range_inner(name, offset, size, &mut progress)
+            .await;
+        if let Ok(data) = &result {
+            self.op_bytes += data.len() as u64;
+        }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn read_partition_range_inner(
+        &mut self,
+        name: &str,
+        offset: u64,
+        size: usize,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        self.ensure_da_mode().await?;
+
+        let partition = self.find_partition(name).await?;
+        if offset
+            .checked_add(size as u64)
+            .is_none_or(|end| end > partition.size)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Range {:#X}+{:#X} is outside of '{}' ({:#X} bytes)",
+                    offset, size, name, partition.size
+                ),
+            ));
+        }
+
+        self.storage()?
+            .read_vec(partition.address + offset, size, progress)
+            .await
+    }
+
+    // Reads the start of a partition and identifies the filesystem in it, handy to
+    // check what a partition holds before dumping or wiping it.
+    pub async fn probe_partition(&mut self, name: &str) -> Result<FsProbe, Error> {
+        let size = self.find_partition(name).await?.size;
+        let len = size.min(PROBE_SIZE as u64) as usize;
+        let head = self
+            .read_partition_range(name, 0, len, &mut |_, _| {})
+            .await?;
+        Ok(fsprobe::probe(&head, Some(size)))
+    }
+
+    // Reads `size` bytes at `addr` straight into the file at `path`.
+    // Progress is saved in a `<path>.resume` sidecar after every chunk, so if the
+    // read gets interrupted (cable pulled, device reset...), calling this again
+    // with the same arguments picks up from the last completed chunk.
+    // Returns the SHA-256 of the whole file, hashed on the side while reading.
+    // See set_dump_split() for writing it in several pieces.
+    pub async fn read_flash_to(
+        &mut self,
+        addr: u64,
+        size: u64,
+        path: &Path,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<String, Error> {
+        self.ensure_da_mode().await?;
+
+        // Too big for the target filesystem, write it in segments instead
+        let segment_size = self.split_size.filter(|&segment| size > segment);
+        let marker_path = resume_marker_path(path);
+
+        // Picking up where the marker says and hashing what's already there
+        // both touch the disk, so off the runtime with them
+        let (file, hasher, mut offset) = {
+            let path = path.to_path_buf();
+            let marker_path = marker_path.clone();
+            fileio::blocking(move || {
+                let mut offset = match read_resume_marker(&marker_path) {
+                    Some((m_addr, m_size, m_offset)) if m_addr == addr && m_size == size => {
+                        info!(
+                            "Resuming read of {} at offset {:#X}",
+                            path.display(),
+                            m_offset
+                        );
+                        m_offset
+                    }
+                    Some(_) => {
+                        warn!(
+                            "Resume marker for {} doesn't match, starting over",
+                            path.display()
+                        );
+                        0
+                    }
+                    None => 0,
+                };
+
+                let mut file = SegmentedFile::new(&path, segment_size);
+
+                // Anything past the marker is from a chunk that didn't complete
+                if file.on_disk_len() < offset {
+                    warn!(
+                        "{} is shorter than its resume marker, starting over",
+                        path.display()
+                    );
+                    offset = 0;
+                }
+                file.set_len(offset)?;
+                file.seek(offset);
+
+                // When resuming, hashing starts with what's already on disk
+                let mut hasher = Sha256Stream::new();
+                if offset > 0 {
+                    std::io::copy(&mut file.reader()?.take(offset), &mut hasher)?;
+                }
+                Ok((file, hasher, offset))
+            })
+            .await?
+        };
+
+        // Writing, hashing and the resume marker all happen on a blocking thread,
+        // overlapping with the next USB read. The marker only moves once a chunk
+        // is synced to disk.
+        let resumed_at = offset;
+        let mut writer = ChunkWriter::spawn(
+            (file, hasher, resumed_at),
+            move |(file, hasher, written), chunk: &[u8]| {
+                file.write_all(chunk)?;
+                file.sync_data()?;
+                hasher.update(chunk);
+                *written += chunk.len() as u64;
+                write_resume_marker(&marker_path, addr, size, *written)
+            },
+        );
+
+        // Resumable reads are split in chunks, a marker is saved after each one
+        let power = self.power;
+        let total = saturating_usize(size);
+        let mut recoveries = 0;
+        while offset < size {
+            // Chunks fit in memory, the whole read might not
+            let chunk_len = (power.read_chunk as u64).min(size - offset) as usize;
+            let base = offset;
+            let mut chunk_progress =
+                |read: usize, _total: usize| progress(saturating_usize(base + read as u64), total);
+            // Read straight into a buffer the writer is done with
+            let mut chunk = writer.buffer(chunk_len);
+            let result = self
+                .storage()?
+                .read_range(addr + offset, &mut chunk, &mut chunk_progress)
+                .await;
+            let len = match result {
+                Ok(len) => len,
+                Err(e) => {
+                    // Back in DA mode, this chunk gets read again
+                    let operation = format!("Read {:#X}+{:#X}", addr, size);
+                    self.handle_reset(e, &operation, addr + offset, &mut recoveries)
+                        .await?;
+                    continue;
+                }
+            };
+
+            if len != chunk_len {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!(
+                        "Short read at {:#X}: got {} of {} bytes",
+                        addr + offset,
+                        len,
+                        chunk_len
+                    ),
+                ));
+            }
+
+            writer.write(chunk).await?;
+            offset += chunk_len as u64;
+            progress(saturating_usize(offset), total);
+
+            if !power.pause.is_zero() && offset < size {
+                tokio::time::sleep(power.pause).await;
+            }
+        }
+
+        let (file, hasher, _) = writer.finish().await?;
+        let hash = hasher.finalize_hex();
+        let marker_path = resume_marker_path(path);
+        let manifest_hash = hash.clone();
+        fileio::blocking(move || {
+            file.write_manifest(size, &manifest_hash)?;
+            // Done, nothing left to resume
+            if marker_path.exists() {
+                std::fs::remove_file(&marker_path)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        Ok(hash)
+    }
+
+    // Reads `size` bytes at `addr` into `path` for recovering data off failing
+    // storage: ranges that keep failing are filled with `options.fill` and listed
+    // in the returned report (also saved next to the dump, see report_path)
+    // instead of aborting the whole read.
+    pub async fn read_flash_tolerant(
+        &mut self,
+        addr: u64,
+        size: u64,
+        path: &Path,
+        options: &RecoveryOptions,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<RecoveryReport, Error> {
+        let started = self.begin_operation(format!("Recover {:#X}+{:#X}", addr, size));
+        let mut progress = self.event_progress(progress);
+        let result = self
+            .read_flash_tolerant_inner(addr, size, path, options, &mut progress)
+            .await;
+        if result.is_ok() {
+            self.op_bytes += size;
+        }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    pub async fn read_partition_tolerant(
+        &mut self,
+        name: &str,
+        path: &Path,
+        options: &RecoveryOptions,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<RecoveryReport, Error> {
+        self.ensure_da_mode().await?;
+        let partition = self.find_partition(name).await?;
+        self.read_flash_tolerant(partition.address, partition.size, path, options, progress)
+            .await
+    }
+
+    async fn read_flash_tolerant_inner(
+        &mut self,
+        addr: u64,
+        size: u64,
+        path: &Path,
+        options: &RecoveryOptions,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<RecoveryReport, Error> {
+        if options.chunk_size == 0 || options.block_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Chunk and block size must not be 0",
+            ));
+        }
+        self.ensure_da_mode().await?;
+
+        let file = {
+            let path = path.to_path_buf();
+            fileio::blocking(move || std::fs::File::create(path)).await?
+        };
+        let mut writer = ChunkWriter::spawn(file, |file, chunk: &[u8]| file.write_all(chunk));
+        let mut report = RecoveryReport::new(addr, size);
+        let total = saturating_usize(size);
+        let mut offset = 0;
+        while offset < size {
+            let len = (options.chunk_size as u64).min(size - offset) as usize;
+            let chunk_addr = addr + offset;
+            match self
+                .read_with_retries(chunk_addr, len, options.retries)
+                .await
+            {
+                Ok(data) => writer.write(data).await?,
+                Err(e) if link_lost(&e) => return Err(e),
+                Err(e) => {
+                    warn!(
+                        "Reading {:#X}+{:#X} failed ({}), retrying block by block",
+                        chunk_addr, len, e
+                    );
+                    let mut block = 0;
+                    while block < len {
+                        let block_len = options.block_size.min(len - block);
+                        let block_addr = chunk_addr + block as u64;
+                        match self
+                            .read_with_retries(block_addr, block_len, options.retries)
+                            .await
+                        {
+                            Ok(data) => writer.write(data).await?,
+                            Err(e) if link_lost(&e) => return Err(e),
+                            Err(e) => {
+                                warn!("Unreadable: {:#X}+{:#X}: {}", block_addr, block_len, e);
+                                report.record(block_addr, block_len, e.to_string());
+                                writer
+                                    .write(fill_pattern(
+                                        &options.fill,
+                                        offset + block as u64,
+                                        block_len,
+                                    ))
+                                    .await?;
+                            }
+                        }
+                        block += block_len;
+                        progress(saturating_usize(offset + block as u64), total);
+                    }
+                }
+            }
+            offset += len as u64;
+            progress(saturating_usize(offset), total);
+        }
+        let file = writer.finish().await?;
+
+        let report_path = recovery::report_path(path);
+        let report = {
+            let report_path = report_path.clone();
+            fileio::blocking(move || {
+                file.sync_all()?;
+                if report.is_clean() {
+                    // Left over from an earlier, worse attempt
+                    if report_path.exists() {
+                        std::fs::remove_file(&report_path)?;
+                    }
+                } else {
+                    report.save(&report_path)?;
+                }
+                Ok(report)
+            })
+            .await?
+        };
+        if !report.is_clean() {
+            warn!(
+                "{} bytes in {} ranges could not be read, see {}",
+                report.bad_bytes(),
+                report.bad.len(),
+                report_path.display()
+            );
+        }
+        Ok(report)
+    }
+
+    async fn read_with_retries(
+        &mut self,
+        addr: u64,
+        len: usize,
+        retries: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let mut attempt = 0;
+        loop {
+            let mut progress = |_read: usize, _total: usize| {};
+            let result = match self.storage()?.read_vec(addr, len, &mut progress).await {
+                Ok(data) if data.len() == len => Ok(data),
+                Ok(data) => Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("Short read: got {} of {} bytes", data.len(), len),
+                )),
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if attempt < retries && !link_lost(&e) => {
+                    attempt += 1;
+                    self.connection.record_retry();
+                    debug!("Retrying {:#X}+{:#X} ({}): {}", addr, len, attempt, e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub async fn read_partition_to(
+        &mut self,
+        name: &str,
+        path: &Path,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<String, Error> {
+        let started = self.begin_operation(format!("Read {}", name));
+        let mut progress = self.event_progress(progress);
+        let result = self
+            .read_partition_to_inner(name, path, &mut progress)
+            .await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn read_partition_to_inner(
+        &mut self,
+        name: &str,
+        path: &Path,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<String, Error> {
+        self.ensure_da_mode().await?;
+
+        let partition = self.find_partition(name).await?;
+        let hash = self
+            .read_flash_to(partition.address, partition.size, path, progress)
+            .await?;
+        self.op_bytes += partition.size;
+        Ok(hash)
+    }
+
+    // Takes &mut self so callers' futures stay Send (Device isn't Sync)
+    async fn find_partition(&mut self, name: &str) -> Result<Partition, Error> {
+        let dev_info = match &self.dev_info {
+            Some(info) => info.borrow(),
+            None => return Err(Error::new(ErrorKind::Other, "Device info not available")),
+        };
+
+        if let Some(gpt_error) = &dev_info.gpt_error {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Partition '{}' not found, no partition table ({}). Only address based access works",
+                    name, gpt_error
+                ),
+            ));
+        }
+
+        dev_info
+            .partitions
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("Partition '{}' not found", name),
+                )
+            })
+    }
+
+    pub async fn write_partition(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        // Even a failed write may have changed part of it
+        self.partition_cache.remove(name);
+
+        let started = self.begin_operation(format!("Write {}", name));
+        let mut progress = self.event_progress(progress);
+        let before = self.audit_hash_before(name).await;
+        let mut result = self.backup_before_write(name).await.map(|_| ());
+        if result.is_ok() {
+            result = self.write_partition_inner(name, data, &mut progress).await;
+        }
+        if result.is_ok() {
+            self.op_bytes += data.len() as u64;
+        }
+        self.audit_record("write", name, before, data, result.as_ref().err())
+            .await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn write_partition_inner(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        if self.protocol.is_none() {
+            let partition = self.legacy_partition(name).await?;
+            if data.len() as u64 > partition.size {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Data size {} exceeds partition size {}",
+                        data.len(),
+                        partition.size
+                    ),
+                ));
+            }
+            return self
+                .legacy_write(&partition.name, partition.address, data, progress)
+                .await;
+        }
+
+        self.ensure_da_mode().await?;
+
+        let partition = self.find_partition(name).await?;
+
+        if data.len() as u64 > partition.size {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Data size {} exceeds partition size {}",
+                    data.len(),
+                    partition.size
+                ),
+            ));
+        }
+
+        if self.dry_run {
+            let planned = PlannedWrite {
+                partition: partition.name.clone(),
+                offset: partition.address,
+                size: data.len(),
+                sha256: sha256_hex(data),
+            };
+            info!(
+                "[Dry run] Would write {} bytes to {} at {:#X} (sha256 {})",
+                planned.size, planned.partition, planned.offset, planned.sha256
+            );
+            self.planned_writes.push(planned);
+            progress(data.len(), data.len());
+            return Ok(());
+        }
+
+        let result = self
+            .write_recovering(partition.address, data, progress)
+            .await;
+        self.explain_write_error(partition.address, data.len(), result)
+            .await
+    }
+
+    // Flashes `name` (e.g. "boot" or "lk") into the inactive slot, reads it back
+    // and only then marks that slot active in misc. Until the switch the device
+    // still boots the old slot, and if the new one never boots the bootloader
+    // falls back to it by itself. Needs A/B partitions and AOSP boot control in
+    // misc, anything else is Unsupported.
+    pub async fn flash_two_phase(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<SlotSwitch, Error> {
+        let started = self.begin_operation(format!("Two-phase flash {}", name));
+        let result = self.flash_two_phase_inner(name, data, progress).await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn flash_two_phase_inner(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<SlotSwitch, Error> {
+        self.ensure_da_mode().await?;
+
+        // The slot might have changed since misc was cached
+        self.partition_cache.remove("misc");
+        let misc = self.read_partition("misc", &mut |_, _| {}).await?;
+        let mut control = BootControl::from_misc(&misc).ok_or_else(|| {
+            Error::new(
+                ErrorKind::Unsupported,
+                "No A/B boot control found in misc, can't flash in two phases",
+            )
+        })?;
+        let active = control
+            .active_slot()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "No bootable slot in misc"))?;
+
+        let (base, _) = Slot::split_name(name);
+        let target = active.other();
+        let partition = format!("{}{}", base, target.suffix());
+        self.find_partition(&partition).await?;
+        info!(
+            "Active slot is {}, flashing {} before switching",
+            active, partition
+        );
+
+        self.write_partition(&partition, data, progress).await?;
+        if self.dry_run {
+            info!("[Dry run] Would switch the active slot to {}", target);
+        } else {
+            let written = self.read_partition(&partition, &mut |_, _| {}).await?;
+            if written.get(..data.len()) != Some(data) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} doesn't read back as written, staying on slot {}",
+                        partition, active
+                    ),
+                ));
+            }
+        }
+
+        control.set_active(target);
+        let misc = control.apply_to(&misc)?;
+        self.write_partition("misc", &misc, &mut |_, _| {}).await?;
+        info!("Slot {} is now active", target);
+
+        Ok(SlotSwitch {
+            partition,
+            previous: active,
+            active: target,
+        })
+    }
+
+    // The usual first thing after unlocking: reads boot, patches it (or takes an
+    // already patched image), turns off dm-verity and verification in vbmeta,
+    // flashes both and reads them back. On A/B devices the active slot's copies
+    // are used. Progress is reported per step ("Read boot", "Write vbmeta"...).
+    // The bootloader has to be unlocked, the patched vbmeta no longer verifies.
+    // Refused when seccfg says it's locked, a seccfg we can't read gets a warning.
+    pub async fn flash_boot_and_disable_verity(
+        &mut self,
+        patch: BootPatch,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<VerityReport, Error> {
+        let started = self.begin_operation("Flash boot and disable verity");
+        let mut progress = self.event_named_progress(progress);
+        let result = self
+            .flash_boot_and_disable_verity_inner(patch, &mut progress)
+            .await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn flash_boot_and_disable_verity_inner(
+        &mut self,
+        patch: BootPatch,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<VerityReport, Error> {
+        self.ensure_da_mode().await?;
+
+        // Nothing checks the lock state for us, and a locked device won't boot
+        // with either image
+        match self.read_partition("seccfg", &mut |_, _| {}).await {
+            Ok(seccfg) => match SecCfgV4::parse_unverified(&seccfg) {
+                Ok(seccfg) if !LockState::of(&seccfg).is_unlocked() => {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!(
+                            "The bootloader is {}, unlock it before disabling verity",
+                            LockState::of(&seccfg)
+                        ),
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Could not parse seccfg, assuming unlocked: {}", e),
+            },
+            Err(e) => warn!("Could not read seccfg, assuming unlocked: {}", e),
+        }
+
+        let boot_partition = self.active_partition("boot").await?;
+        let vbmeta_partition = self.active_partition("vbmeta").await?;
+        info!("Patching {} and {}", boot_partition, vbmeta_partition);
+
+        let original_boot = self
+            .read_partition(&boot_partition, &mut |read, total| {
+                progress("Read boot", read, total)
+            })
+            .await?;
+        let vbmeta = self
+            .read_partition(&vbmeta_partition, &mut |read, total| {
+                progress("Read vbmeta", read, total)
+            })
+            .await?;
+        // Checked before anything is written, a bad vbmeta shouldn't leave boot patched
+        let vbmeta_flags_before = vbmeta::flags(&vbmeta)?;
+        let new_vbmeta = vbmeta::disable_verity(&vbmeta)?;
+
+        let boot = match patch {
+            BootPatch::Image(image) => image,
+            BootPatch::With(patch) => patch(original_boot.clone())?,
+        };
+        if boot.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Patched boot image is empty",
+            ));
+        }
+
+        self.write_partition(&boot_partition, &boot, &mut |written, total| {
+            progress("Write boot", written, total)
+        })
+        .await?;
+        self.write_partition(&vbmeta_partition, &new_vbmeta, &mut |written, total| {
+            progress("Write vbmeta", written, total)
+        })
+        .await?;
+
+        if self.dry_run {
+            info!("[Dry run] Skipping the readback");
+        } else {
+            for (name, expected) in [(&boot_partition, &boot), (&vbmeta_partition, &new_vbmeta)] {
+                let data = self
+                    .read_partition(name, &mut |read, total| progress("Verify", read, total))
+                    .await?;
+                if data.get(..expected.len()) != Some(&expected[..]) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} doesn't read back as written", name),
+                    ));
+                }
+            }
+        }
+
+        Ok(VerityReport {
+            boot_partition,
+            vbmeta_partition,
+            original_boot_sha256: sha256_hex(&original_boot),
+            original_boot,
+            boot_sha256: sha256_hex(&boot),
+            vbmeta_flags_before,
+            vbmeta_flags_after: vbmeta::flags(&new_vbmeta)?,
+        })
+    }
+
+    // `base` itself, or its copy in the active slot on A/B devices
+    async fn active_partition(&mut self, base: &str) -> Result<String, Error> {
+        if self.find_partition(base).await.is_ok() {
+            return Ok(base.to_string());
+        }
+        self.partition_cache.remove("misc");
+        let misc = self.read_partition("misc", &mut |_, _| {}).await?;
+        let slot = BootControl::from_misc(&misc)
+            .and_then(|control| control.active_slot())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("No {} partition and no active A/B slot", base),
+                )
+            })?;
+        let name = format!("{}{}", base, slot.suffix());
+        self.find_partition(&name).await?;
+        Ok(name)
+    }
+
+    // Writes the image at `path` to a partition, decoded through `pipeline` on the
+    // way (see Pipeline::for_file to pick one from the image itself).
+    pub async fn write_partition_from(
+        &mut self,
+        name: &str,
+        path: &Path,
+        pipeline: &Pipeline,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        // Anything past the partition size is an error anyway, no need to decode it all
+        let limit = self
+            .find_partition(name)
+            .await
+            .map_or(u64::MAX, |p| p.size.saturating_add(1));
+        let (path, pipeline) = (path.to_path_buf(), pipeline.clone());
+        let data = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, Error> {
+            let mut data = Vec::new();
+            pipeline
+                .reader(std::fs::File::open(&path)?)?
+                .take(limit)
+                .read_to_end(&mut data)?;
+            Ok(data)
+        })
+        .await
+        .map_err(Error::other)??;
+
+        self.write_partition(name, &data, progress).await
+    }
+
+    // Reads a partition into the file at `path`, encoded through `pipeline` on the
+    // way, e.g. compressed.
+    pub async fn read_partition_with(
+        &mut self,
+        name: &str,
+        path: &Path,
+        pipeline: &Pipeline,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        let data = self.read_partition(name, progress).await?;
+        let (path, pipeline) = (path.to_path_buf(), pipeline.clone());
+        tokio::task::spawn_blocking(move || {
+            let mut sink = pipeline.writer(std::fs::File::create(&path)?)?;
+            sink.write_all(&data)?;
+            sink.finish()
+        })
+        .await
+        .map_err(Error::other)?
+    }
+
+    // Dumps the partition tables and every partition into `dir`, named after `layout`,
+    // along with a sha256sums.txt manifest. Returns the paths of the files written.
+    pub async fn dump_all(
+        &mut self,
+        dir: &Path,
+        layout: DumpLayout,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<PathBuf>, Error> {
+        let started = self.begin_operation("Dump all partitions");
+        let mut progress = self.event_named_progress(progress);
+        let result = self.dump_all_inner(dir, layout, &mut progress).await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn dump_all_inner(
+        &mut self,
+        dir: &Path,
+        layout: DumpLayout,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<PathBuf>, Error> {
+        self.ensure_da_mode().await?;
+
+        fileio::create_dir_all(dir.to_path_buf()).await?;
+        let mut written = Vec::new();
+
+        // 4K sectors on most UFS, the GPT takes fewer of them but more bytes
+        let sector_size = self.sector_size().await?;
+        let mut no_progress = |_read: usize, _total: usize| {};
+        let pgpt_len = pgpt_sectors(sector_size as u64) as usize * sector_size;
+        let pgpt = self
+            .storage()?
+            .read_vec(0x0, pgpt_len, &mut no_progress)
+            .await?;
+        let pgpt_path = dir.join(layout.pgpt_file_name());
+        let pgpt_hash = sha256_hex(&pgpt);
+        let alt_lba = gpt_alternate_lba(&pgpt);
+        fileio::write(pgpt_path.clone(), pgpt).await?;
+        written.push((pgpt_path, pgpt_hash));
+
+        // The backup GPT lives at the very end of the user area, with the header
+        // in the last sector and the entries right before it.
+        if let Some(alt_lba) = alt_lba {
+            let sgpt_sectors = sgpt_sectors(sector_size as u64);
+            let start = lba_addr(
+                alt_lba.saturating_add(1).saturating_sub(sgpt_sectors),
+                sector_size,
+            )?;
+            let sgpt = self
+                .storage()?
+                .read_vec(start, sgpt_sectors as usize * sector_size, &mut no_progress)
+                .await?;
+            let sgpt_path = dir.join(layout.sgpt_file_name());
+            let sgpt_hash = sha256_hex(&sgpt);
+            fileio::write(sgpt_path.clone(), sgpt).await?;
+            written.push((sgpt_path, sgpt_hash));
+        } else {
+            warn!("Could not locate the backup GPT, skipping it");
+        }
+
+        let names: Vec<String> = match &self.dev_info {
+            Some(info) => info
+                .borrow()
+                .partitions
+                .iter()
+                .map(|p| p.name.clone())
+                .collect(),
+            None => return Err(Error::other("Device info not available")),
+        };
+
+        written.extend(
+            self.dump_partitions_inner(dir, layout, &names, progress)
+                .await?,
+        );
+        fileio::write_manifest(dir.to_path_buf(), written.clone()).await?;
+        Ok(written.into_iter().map(|(path, _)| path).collect())
+    }
+
+    // Same as dump_all, but only for the given partitions and without the GPT.
+    // The manifest only lists the partitions dumped by this call.
+    // Pair with load_partition_table() to back up a list of partitions from a file.
+    pub async fn dump_partitions(
+        &mut self,
+        dir: &Path,
+        layout: DumpLayout,
+        names: &[String],
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<PathBuf>, Error> {
+        let started = self.begin_operation("Dump partitions");
+        let mut progress = self.event_named_progress(progress);
+        let result = match self
+            .dump_partitions_inner(dir, layout, names, &mut progress)
+            .await
+        {
+            Ok(written) => fileio::write_manifest(dir.to_path_buf(), written.clone())
+                .await
+                .map(|_| written.into_iter().map(|(path, _)| path).collect()),
+            Err(e) => Err(e),
+        };
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn dump_partitions_inner(
+        &mut self,
+        dir: &Path,
+        layout: DumpLayout,
+        names: &[String],
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<(PathBuf, String)>, Error> {
+        fileio::create_dir_all(dir.to_path_buf()).await?;
+        let mut written = Vec::new();
+
+        for name in names {
+            info!("Dumping partition {}", name);
+            let mut part_progress = |read: usize, total: usize| progress(name, read, total);
+            let path = layout.partition_path(dir, name);
+            let hash = self
+                .read_partition_to(name, &path, &mut part_progress)
+                .await?;
+            written.push((path, hash));
+        }
+
+        Ok(written)
+    }
+
+    // expdb and logdb, whichever this device has
+    fn crash_partitions(&self) -> Result<Vec<String>, Error> {
+        let names: Vec<String> = match &self.dev_info {
+            Some(info) => info
+                .borrow()
+                .partitions
+                .iter()
+                .filter(|p| CRASH_PARTITIONS.contains(&p.name.as_str()))
+                .map(|p| p.name.clone())
+                .collect(),
+            None => return Err(Error::other("Device info not available")),
+        };
+        if names.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No crash log partition ({})", CRASH_PARTITIONS.join(", ")),
+            ));
+        }
+        Ok(names)
+    }
+
+    // Dumps expdb/logdb into `dir` like dump_partitions, to keep the crash logs
+    // before flashing anything that might wipe them.
+    pub async fn dump_crash_logs(
+        &mut self,
+        dir: &Path,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<PathBuf>, Error> {
+        self.ensure_da_mode().await?;
+        let names = self.crash_partitions()?;
+        self.dump_partitions(dir, DumpLayout::Penumbra, &names, progress)
+            .await
+    }
+
+    // Writes back the crash logs dump_crash_logs saved in `dir`, e.g. to hand the
+    // device to someone else with its history intact. Returns what was restored.
+    pub async fn restore_crash_logs(
+        &mut self,
+        dir: &Path,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        self.ensure_da_mode().await?;
+        let mut restored = Vec::new();
+        for name in self.crash_partitions()? {
+            let path = DumpLayout::Penumbra.partition_path(dir, &name);
+            if !dump_exists(&path) {
+                continue;
+            }
+            info!("Restoring {} from {}", name, path.display());
+            let data = fileio::read_dump(path).await?;
+            let mut part_progress = |written: usize, total: usize| progress(&name, written, total);
+            self.write_partition(&name, &data, &mut part_progress)
+                .await?;
+            restored.push(name);
+        }
+        Ok(restored)
+    }
+
+    // Reads expdb/logdb and picks out the panic reasons, newest first within
+    // each partition. Often the quickest way to see why a device bootloops.
+    pub async fn crash_reasons(&mut self) -> Result<Vec<CrashRecord>, Error> {
+        self.ensure_da_mode().await?;
+        let mut records = Vec::new();
+        for name in self.crash_partitions()? {
+            let data = self.read_partition(&name, &mut |_, _| {}).await?;
+            records.extend(parse_crash_log(&name, &data));
+        }
+        Ok(records)
+    }
+
+    // Reads both GPT copies from the device and validates them, see gpt::check_gpt.
+    pub async fn check_gpt(&mut self) -> Result<GptReport, Error> {
+        self.ensure_da_mode().await?;
+        let mut storage = self.storage()?;
+        let mut no_progress = |_read: usize, _total: usize| {};
+
+        // Header is at LBA 1, try both 512 and 4K sectors (UFS)
+        let head = storage
+            .read_vec(0x0, GPT_HEAD_SIZE, &mut no_progress)
+            .await?;
+        // A short read can end right after the signature, that's no header either
+        let found = [512usize, 4096]
+            .into_iter()
+            .find(|&ss| head.get(ss..ss + 8) == Some(GPT_SIGNATURE))
+            .and_then(|ss| {
+                let sector = head.get(ss..ss * 2)?.to_vec();
+                let header = GptHeader::parse(&sector)?;
+                Some((ss, sector, header))
+            });
+        let Some((sector_size, primary_header, primary)) = found else {
+            return Ok(check_gpt(
+                &GptData {
+                    header: &[],
+                    entries: &[],
+                },
+                None,
+            ));
+        };
+        if primary.entries_len() > GPT_MAX_ENTRIES_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "GPT entry array too large ({:#X} bytes)",
+                    primary.entries_len()
+                ),
+            ));
+        }
+
+        let primary_entries = storage
+            .read_vec(
+                lba_addr(primary.entries_lba, sector_size)?,
+                primary.entries_len(),
+                &mut no_progress,
+            )
+            .await?;
+
+        // A backup we can't read is a finding like any other, not a reason to
+        // stop checking
+        let backup_header = match lba_addr(primary.alternate_lba, sector_size) {
+            Ok(addr) => storage.read_vec(addr, sector_size, &mut no_progress).await,
+            Err(e) => Err(e),
+        };
+        let backup_header = match backup_header {
+            Ok(header) => Some(header),
+            Err(e) => {
+                warn!("Failed to read the backup GPT header: {}", e);
+                None
+            }
+        };
+        let backup_entries = match backup_header.as_deref().and_then(GptHeader::parse) {
+            Some(backup) if backup.entries_len() <= GPT_MAX_ENTRIES_LEN => {
+                let entries = match lba_addr(backup.entries_lba, sector_size) {
+                    Ok(addr) => {
+                        storage
+                            .read_vec(addr, backup.entries_len(), &mut no_progress)
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                // Leaving them empty reports them as truncated
+                entries.unwrap_or_else(|e| {
+                    warn!("Failed to read the backup GPT entries: {}", e);
+                    Vec::new()
+                })
+            }
+            _ => Vec::new(),
+        };
+
+        let mut report = check_gpt(
+            &GptData {
+                header: &primary_header,
+                entries: &primary_entries,
+            },
+            backup_header
+                .as_deref()
+                .map(|header| GptData {
+                    header,
+                    entries: &backup_entries,
+                })
+                .as_ref(),
+        );
+
+        // Cross-check with the DA, when it tells how big the storage is
+        let user_size = storage.info().await.ok().and_then(|info| info.user_size);
+        drop(storage);
+        if let Some(storage) = user_size {
+            let partitions = self
+                .dev_info
+                .as_ref()
+                .map(|info| info.borrow().partitions.clone())
+                .unwrap_or_default();
+            report.issues.extend(check_against_storage(
+                &partitions,
+                Some((primary.alternate_lba, sector_size as u64)),
+                storage,
+            ));
+        }
+        Ok(report)
+    }
+
+    pub async fn export_partition_table(
+        &mut self,
+        format: PartitionTableFormat,
+    ) -> Result<String, Error> {
+        let dev_info = match &self.dev_info {
+            Some(info) => info.borrow(),
+            None => return Err(Error::other("Device info not available")),
+        };
+        Ok(export_partitions(&dev_info.partitions, format))
+    }
+
+    // Flashes back every partition that has a matching file in `dir`.
+    // GPT files are never written back, the partition table on the device is the
+    // one used to locate partitions. If `layout` is None, it gets detected from
+    // the files in the directory. Returns the names of the restored partitions.
+    pub async fn restore_all(
+        &mut self,
+        dir: &Path,
+        layout: Option<DumpLayout>,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        let started = self.begin_operation("Restore partitions");
+        let mut progress = self.event_named_progress(progress);
+        let result = self.restore_all_inner(dir, layout, &mut progress).await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn restore_all_inner(
+        &mut self,
+        dir: &Path,
+        layout: Option<DumpLayout>,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        let layout = match layout.or_else(|| DumpLayout::detect(dir)) {
+            Some(layout) => layout,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Could not detect dump layout in {}", dir.display()),
+                ));
+            }
+        };
+
+        self.ensure_da_mode().await?;
+
+        let names: Vec<String> = match &self.dev_info {
+            Some(info) => info
+                .borrow()
+                .partitions
+                .iter()
+                .map(|p| p.name.clone())
+                .collect(),
+            None => return Err(Error::other("Device info not available")),
+        };
+
+        let mut restored = Vec::new();
+        for name in names {
+            let path = layout.partition_path(dir, &name);
+            if !dump_exists(&path) {
+                continue;
+            }
+
+            info!("Restoring partition {} from {}", name, path.display());
+            let data = fileio::read_dump(path).await?;
+            let mut part_progress = |written: usize, total: usize| progress(&name, written, total);
+            self.write_partition(&name, &data, &mut part_progress)
+                .await?;
+            restored.push(name);
+        }
+
+        Ok(restored)
+    }
+
+    // Erases every partition not in `options.protected`, then flashes the images
+    // found in `dir`. Progress is reported over the whole run, not per partition.
+    // With a backup dir, each partition gets dumped before being erased and every
+    // step is journaled there, so a failed run can be undone with rollback().
+    // Returns the names of the flashed partitions.
+    pub async fn format_and_download(
+        &mut self,
+        dir: &Path,
+        layout: Option<DumpLayout>,
+        options: &FormatAllOptions,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        let started = self.begin_operation("Format and download");
+        let mut progress = self.event_named_progress(progress);
+        let result = self
+            .format_and_download_inner(dir, layout, options, &mut progress)
+            .await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn format_and_download_inner(
+        &mut self,
+        dir: &Path,
+        layout: Option<DumpLayout>,
+        options: &FormatAllOptions,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        let layout = match layout.or_else(|| DumpLayout::detect(dir)) {
+            Some(layout) => layout,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Could not detect dump layout in {}", dir.display()),
+                ));
+            }
+        };
+
+        self.ensure_da_mode().await?;
+
+        let partitions: Vec<Partition> = match &self.dev_info {
+            Some(info) => info
+                .borrow()
+                .partitions
+                .iter()
+                .filter(|p| !options.is_protected(&p.name) && !self.safety.is_protected(&p.name))
+                .cloned()
+                .collect(),
+            None => return Err(Error::other("Device info not available")),
+        };
+
+        // Finding the images and their sizes stats every one of them
+        let candidates: Vec<(Partition, PathBuf)> = partitions
+            .iter()
+            .map(|p| (p.clone(), layout.partition_path(dir, &p.name)))
+            .collect();
+        let (images, image_bytes) = fileio::blocking(move || {
+            let images: Vec<(Partition, PathBuf)> = candidates
+                .into_iter()
+                .filter(|(_, path)| dump_exists(path))
+                .collect();
+            let mut bytes = 0u64;
+            for (_, path) in &images {
+                bytes += std::fs::metadata(path)?.len();
+            }
+            Ok((images, bytes))
+        })
+        .await?;
+
+        let mut total = partitions.iter().map(|p| p.size).sum::<u64>() + image_bytes;
+        if options.backup_dir.is_some() {
+            total += partitions.iter().map(|p| p.size).sum::<u64>();
+        }
+        let total = saturating_usize(total);
+        let mut done = 0u64;
+
+        let journal = match &options.backup_dir {
+            Some(backup_dir) => Some(Journal::create(backup_dir)?),
+            None => None,
+        };
+
+        if let (Some(backup_dir), Some(journal)) = (&options.backup_dir, &journal) {
+            for part in &partitions {
+                info!("Backing up partition {}", part.name);
+                let path = DumpLayout::Penumbra.partition_path(backup_dir, &part.name);
+                let mut part_progress = |read: usize, _: usize| {
+                    progress(&part.name, saturating_usize(done + read as u64), total)
+                };
+                self.read_partition_to(&part.name, &path, &mut part_progress)
+                    .await?;
+                journal.record(JournalStep::BackedUp, &part.name)?;
+                done += part.size;
+            }
+        }
+
+        for part in &partitions {
+            if self.dry_run {
+                info!(
+                    "[Dry run] Would erase {} ({:#X} bytes)",
+                    part.name, part.size
+                );
+                done += part.size;
+                progress(&part.name, saturating_usize(done), total);
+                continue;
+            }
+
+            // A backup dir already has all of them
+            if options.backup_dir.is_none() {
+                self.backup_before_write(&part.name).await?;
+            }
+
+            info!("Erasing partition {}", part.name);
+            let mut part_progress = |erased: usize, _: usize| {
+                progress(&part.name, saturating_usize(done + erased as u64), total)
+            };
+            let result = self
+                .storage()?
+                .erase_range(part.address, part.size, &mut part_progress)
+                .await;
+            self.audit_record("erase", &part.name, None, &[], result.as_ref().err())
+                .await;
+            result?;
+            self.partition_cache.remove(&part.name);
+            if let Some(journal) = &journal {
+                journal.record(JournalStep::Erased, &part.name)?;
+            }
+            done += part.size;
+        }
+
+        let mut flashed = Vec::new();
+        for (part, path) in images {
+            info!("Flashing partition {} from {}", part.name, path.display());
+            let data = fileio::read_dump(path).await?;
+            let mut part_progress = |written: usize, _: usize| {
+                progress(&part.name, saturating_usize(done + written as u64), total)
+            };
+            let result = self
+                .write_partition_inner(&part.name, &data, &mut part_progress)
+                .await;
+            self.audit_record("write", &part.name, None, &data, result.as_ref().err())
+                .await;
+            result?;
+            self.partition_cache.remove(&part.name);
+            self.op_bytes += data.len() as u64;
+            if let Some(journal) = &journal {
+                journal.record(JournalStep::Written, &part.name)?;
+            }
+            done += data.len() as u64;
+            flashed.push(part.name);
+        }
+
+        Ok(flashed)
+    }
+
+    // Undoes an interrupted format_and_download, writing back the backup of every
+    // partition the journal in `backup_dir` marks as erased or written.
+    // Returns the names of the restored partitions.
+    pub async fn rollback(
+        &mut self,
+        backup_dir: &Path,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        let started = self.begin_operation("Rollback");
+        let mut progress = self.event_named_progress(progress);
+        let result = self.rollback_inner(backup_dir, &mut progress).await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn rollback_inner(
+        &mut self,
+        backup_dir: &Path,
+        progress: &mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> Result<Vec<String>, Error> {
+        let entries = Journal::load(backup_dir)?;
+
+        let mut touched: Vec<String> = Vec::new();
+        for (step, name) in &entries {
+            if *step != JournalStep::BackedUp && !touched.contains(name) {
+                touched.push(name.clone());
+            }
+        }
+
+        let mut restored = Vec::new();
+        for name in touched {
+            let backed_up = entries
+                .iter()
+                .any(|(step, n)| *step == JournalStep::BackedUp && *n == name);
+            let path = DumpLayout::Penumbra.partition_path(backup_dir, &name);
+            if !backed_up || !dump_exists(&path) {
+                warn!("No backup for partition {}, can't roll it back", name);
+                continue;
+            }
+
+            info!("Rolling back partition {}", name);
+            let data = fileio::read_dump(path).await?;
+            let mut part_progress = |written: usize, total: usize| progress(&name, written, total);
+            self.write_partition(&name, &data, &mut part_progress)
+                .await?;
+            restored.push(name);
+        }
+
+        Ok(restored)
+    }
+
+    // Degraded mode for when no DA was given: reads `size` bytes at `addr` through
+    // the preloader. Limited to LEGACY_MAX_SIZE, see read_partition for the
+    // usual (and much faster) path.
+    pub async fn legacy_read(
+        &mut self,
+        addr: u64,
+        size: usize,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        check_legacy_size(size)?;
+
+        let mut data = Vec::with_capacity(size);
+        while data.len() < size {
+            let chunk_len = std::cmp::min(LEGACY_MAX_CHUNK, size - data.len());
+            let chunk = self
+                .connection
+                .legacy_read(addr + data.len() as u64, chunk_len)
+                .await?;
+            data.extend_from_slice(&chunk);
+            progress(data.len(), size);
+        }
+
+        Ok(data)
+    }
+
+    async fn legacy_write(
+        &mut self,
+        name: &str,
+        addr: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        check_legacy_size(data.len())?;
+
+        if self.dry_run {
+            let planned = PlannedWrite {
+                partition: name.to_string(),
+                offset: addr,
+                size: data.len(),
+                sha256: sha256_hex(data),
+            };
+            info!(
+                "[Dry run] Would write {} bytes to {} at {:#X} through the preloader",
+                planned.size, planned.partition, planned.offset
+            );
+            self.planned_writes.push(planned);
+            progress(data.len(), data.len());
+            return Ok(());
+        }
+
+        let mut written = 0;
+        for chunk in data.chunks(LEGACY_MAX_CHUNK) {
+            self.connection
+                .legacy_write(addr + written as u64, chunk)
+                .await?;
+            written += chunk.len();
+            progress(written, data.len());
+        }
+
+        Ok(())
+    }
+
+    // Looks up a partition for the legacy path, reading the GPT through the
+    // preloader the first time since enter_da_mode never ran.
+    async fn legacy_partition(&mut self, name: &str) -> Result<Partition, Error> {
+        let loaded = match &self.dev_info {
+            Some(info) => !info.borrow().partitions.is_empty(),
+            None => return Err(Error::other("Device info not available")),
+        };
+
+        if !loaded {
+            info!("No DA loaded, reading the partition table through the preloader");
+            let mut no_progress = |_read: usize, _total: usize| {};
+            let pgpt = self
+                .legacy_read(0x0, GPT_READ_SIZE, &mut no_progress)
+                .await?;
+            let partitions = parse_gpt(&pgpt, StorageType::Emmc)?;
+            if let Some(info) = &self.dev_info {
+                info.send_modify(|info| info.partitions = partitions);
+            }
+        }
+
+        self.find_partition(name).await
+    }
+
+    // Drops every cached partition, e.g. after writing to flash through get_protocol()
+    pub fn clear_partition_cache(&mut self) {
+        self.partition_cache.clear();
+    }
+
+    pub fn get_connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+
+    // Bytes sent/received, retries and errors since the device was opened
+    pub fn stats(&self) -> ConnectionStats {
+        self.connection.stats()
+    }
+
+    // Applies to the protocol too, it shares the connection settings
+    pub fn set_transport_config(&mut self, config: TransportConfig) {
+        self.connection.set_transport_config(config);
+    }
+
+    // Trades speed for a lower power draw, see PowerProfile
+    pub fn set_power_profile(&mut self, profile: PowerProfile) {
+        self.power_profile = profile;
+        self.power = profile.limits();
+        if let Some(ProtocolKind::XFlash(xflash)) = self.protocol.as_mut() {
+            xflash.set_power_limits(self.power);
+        }
+    }
+
+    // Slows down every write from now on, until it's set back to the default.
+    // Set it right before the operation that needs it, e.g. flashing a big
+    // partition on a device known to corrupt data at full speed.
+    pub fn set_write_throttle(&mut self, throttle: WriteThrottle) {
+        self.write_throttle = throttle;
+        if let Some(ProtocolKind::XFlash(xflash)) = self.protocol.as_mut() {
+            xflash.set_write_throttle(throttle);
+        }
+    }
+
+    pub fn write_throttle(&self) -> WriteThrottle {
+        self.write_throttle
+    }
+
+    // How the DA1 signature is sent on the next upload, e.g. Strip for a DA1 that
+    // was modified and would fail its own signature. Only XFlash has a DA1 for now.
+    pub fn set_da1_signature(&mut self, handling: SignatureHandling) {
+        match self.protocol.as_mut() {
+            Some(ProtocolKind::XFlash(xflash)) => xflash.set_da1_signature(handling),
+            _ => warn!("DA1 signature handling only applies to XFlash, ignoring"),
+        }
+    }
+
+    // Whether exploits run on the next DA upload, e.g. Skip for a signed DA on a
+    // device without DAA. Only XFlash patches DA2 for now.
+    pub fn set_exploit_policy(&mut self, policy: ExploitPolicy) {
+        match self.protocol.as_mut() {
+            Some(ProtocolKind::XFlash(xflash)) => xflash.set_exploit_policy(policy.clone()),
+            _ => warn!("Exploit policy only applies to XFlash, ignoring"),
+        }
+        self.exploit = policy;
+    }
+
+    // DA log level, log channel and the other SetupEnvironment flags, e.g. the
+    // log on USB to debug a DA that dies early. Set it before enter_da_mode().
+    pub fn set_da_env(&mut self, env: DaEnvConfig) {
+        match self.protocol.as_mut() {
+            Some(ProtocolKind::XFlash(xflash)) => xflash.set_env_config(env),
+            _ => warn!("DA environment only applies to XFlash, ignoring"),
+        }
+    }
+
+    // Whether a device that resets mid-operation (the watchdog, usually) gets
+    // its handshake and DA upload redone so the operation can carry on. On by
+    // default, off makes those fail right away with DeviceReset.
+    pub fn set_reset_recovery(&mut self, enabled: bool) {
+        self.reset_recovery = enabled;
+    }
+
+    // Brings the device back after it reset: waits up to `timeout` for it to
+    // show up in BROM or preloader mode, then the same as reconnect().
+    pub async fn recover_from_reset(&mut self, timeout: Duration) -> Result<(), Error> {
+        let port = wait_for_boot_port(timeout).await.ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotConnected,
+                "The device didn't come back in BROM or preloader mode",
+            )
+        })?;
+        self.reconnect(port).await
+    }
+
+    // Handshake and DA upload again on `port`, where the device came back after
+    // a reset. It has to be the same device, and gets the settings this session
+    // already had (DA, signature handling, power, throttle, exploits...).
+    async fn reconnect(&mut self, port: Box<dyn MTKPort>) -> Result<(), Error> {
+        if !matches!(self.protocol, Some(ProtocolKind::XFlash(_))) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Only XFlash sessions can be brought back after a reset",
+            ));
+        }
+
+        let mut connection = Connection::new(port);
+        connection.set_transport_config(self.connection.transport_config());
+        connection.set_latency_tracking(self.connection.latency_tracking());
+        connection.handshake().await?;
+
+        let chip = connection.get_chip_identity().await?;
+        let found = DeviceIdentity::of_chip(&chip);
+        // Attached sessions never saw the IDs, nothing to compare against
+        let expected = self.identity();
+        if expected.is_known() && !expected.matches(&found) {
+            return Err(IdentityMismatch { expected, found }.into());
+        }
+        if let Err(e) = connection.get_target_config().await {
+            warn!("Could not read target config: {}", e);
+        }
+        if let Some(info) = &self.dev_info {
+            let target_config = connection.target_config();
+            info.send_modify(|info| {
+                info.chip = chip;
+                info.target_config = target_config;
+            });
+        }
+
+        if let Some(ProtocolKind::XFlash(xflash)) = self.protocol.as_mut() {
+            xflash.reconnect(connection.clone());
+        }
+        self.connection = connection;
+        self.connected = true;
+        self.partition_cache.clear();
+        self.enter_da_mode().await
+    }
+
+    // Whether `error` was the device resetting under us: it dropped off and
+    // showed up again in BROM or preloader mode. Ok(()) once it's back in DA
+    // mode and `operation` can carry on from `offset`. Otherwise the error to
+    // give up with, DeviceReset if it did reset.
+    async fn handle_reset(
+        &mut self,
+        error: Error,
+        operation: &str,
+        offset: u64,
+        recoveries: &mut usize,
+    ) -> Result<(), Error> {
+        let lost = link_lost(&error) || error.kind() == ErrorKind::TimedOut;
+        if !lost || DeviceReset::from_error(&error).is_some() {
+            return Err(error);
+        }
+        let Some(port) = wait_for_boot_port(RESET_DETECT_WINDOW).await else {
+            return Err(error);
+        };
+        warn!("Device reset at {:#X} during {}", offset, operation);
+
+        let reset = |recovered| DeviceReset {
+            operation: operation.to_string(),
+            offset,
+            recovered,
+        };
+        if !self.reset_recovery {
+            return Err(reset(false).into());
+        }
+        if let Err(e) = self.reconnect(port).await {
+            error!("Could not bring the device back: {}", e);
+            return Err(reset(false).into());
+        }
+        // Usable again, but it keeps resetting, leave it to the user
+        if *recoveries >= MAX_RESET_RECOVERIES {
+            return Err(reset(true).into());
+        }
+        *recoveries += 1;
+        info!("Device is back, resuming {} at {:#X}", operation, offset);
+        Ok(())
+    }
+
+    // Times every send/get_status/read_data and logs percentiles and outliers
+    // when an operation ends, also passed to the operation hook. For finding out
+    // where the time goes on a slow device, off by default.
+    pub fn set_latency_tracking(&mut self, enabled: bool) {
+        self.connection.set_latency_tracking(enabled);
+    }
+
+    // Where profiles get saved after a successful session and looked up by
+    // apply_known_profile, see DeviceProfile. None (the default) keeps nothing.
+    pub fn set_profile_store(&mut self, store: Option<ProfileStore>) {
+        self.profiles = store;
+    }
+
+    // This device's settings as they are now. None when the SoC ID isn't known
+    // (attached to a running DA), there would be nothing to key it by.
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        let info = self.dev_info.as_ref()?.borrow();
+        if info.chip.soc_id.is_empty() {
+            return None;
+        }
+        let mut profile = DeviceProfile::new(&info.chip);
+        profile.loader = self.loader.clone();
+        if let Some(ProtocolKind::XFlash(xflash)) = self.protocol.as_ref()
+            && xflash.da2_patched()
+        {
+            profile.exploit = Some("carbonara".to_string());
+        }
+        profile.set_seccfg_algo(self.seccfg_algo.or_else(|| cached_algo(&info.chip.soc_id)));
+        profile.set_power_profile(self.power_profile);
+        profile.set_write_throttle(self.write_throttle);
+        profile.set_transport_config(&self.connection.transport_config());
+        Some(profile)
+    }
+
+    // Puts a saved profile's settings in place. The seccfg algorithm only seeds
+    // the detection cache, it's still checked against the hash before it's used.
+    pub fn apply_profile(&mut self, profile: &DeviceProfile) {
+        if let Some(algo) = profile.seccfg_algo()
+            && let Ok(soc_id) = hex::decode(&profile.soc_id)
+        {
+            cache_algo(&soc_id, algo);
+        }
+        self.set_power_profile(profile.power_profile());
+        self.set_write_throttle(profile.write_throttle());
+        self.set_transport_config(profile.transport_config());
+        // The stock DA2 was enough last time, no point in trying the exploit again.
+        // Only when nothing else was asked for, a forced exploit stays forced.
+        if profile.exploit.is_none()
+            && self.exploit == ExploitPolicy::Auto
+            && matches!(self.protocol, Some(ProtocolKind::XFlash(_)))
+        {
+            self.set_exploit_policy(ExploitPolicy::Skip);
+        }
+    }
+
+    // Looks this device up in the profile store and applies what worked last
+    // time. Best called right after init, before entering DA mode.
+    pub fn apply_known_profile(&mut self) -> Option<DeviceProfile> {
+        let store = self.profiles.as_ref()?;
+        let soc_id = self.dev_info.as_ref()?.borrow().chip.soc_id.clone();
+        if soc_id.is_empty() {
+            return None;
+        }
+        let profile = match store.get(&soc_id) {
+            Ok(profile) => profile?,
+            Err(e) => {
+                warn!(
+                    "Could not read profiles from {}: {}",
+                    store.path().display(),
+                    e
+                );
+                return None;
+            }
+        };
+        info!("Applying the saved profile for this device");
+        self.apply_profile(&profile);
+        Some(profile)
+    }
+
+    fn remember_profile(&self) {
+        let (Some(store), Some(profile)) = (self.profiles.as_ref(), self.profile()) else {
+            return;
+        };
+        if let Err(e) = store.save(&profile) {
+            warn!(
+                "Could not save the device profile to {}: {}",
+                store.path().display(),
+                e
+            );
+        }
+    }
+
+    // A clone of the connection handle, sharing the port with the protocol.
+    // Lets monitoring tasks talk to the device without borrowing the Device.
+    pub fn connection_handle(&self) -> Connection {
+        self.connection.clone()
+    }
+
+    // Follows DeviceInfo as it gets filled in (partitions after entering DA mode,
+    // hw code when attaching...), without locking the Device.
+    pub fn watch_info(&self) -> Option<watch::Receiver<DeviceInfo>> {
+        self.dev_info.as_ref().map(|info| info.subscribe())
+    }
+
+    // SoC ID and MEID as read at init, see DeviceIdentity
+    pub fn identity(&self) -> DeviceIdentity {
+        match &self.dev_info {
+            Some(info) => DeviceIdentity::of(&info.borrow()),
+            None => DeviceIdentity {
+                soc_id: Vec::new(),
+                meid: Vec::new(),
+            },
+        }
+    }
+
+    // Refuses (IdentityMismatch) unless this is the device `expected` was
+    // captured from. A device whose IDs can't be read never matches.
+    pub fn verify_identity(&self, expected: &DeviceIdentity) -> Result<(), Error> {
+        let found = self.identity();
+        if expected.matches(&found) {
+            return Ok(());
+        }
+        Err(IdentityMismatch {
+            expected: expected.clone(),
+            found,
+        }
+        .into())
+    }
+
+    // The PMIC RTC, for timestamping service work on devices that don't boot.
+    // XFlash DAs have no devctrl for it, so this goes through the BROM/preloader
+    // PMIC commands and has to happen before entering DA mode.
+    pub async fn rtc_time(&mut self) -> Result<RtcTime, Error> {
+        self.ensure_pre_da("Reading the RTC")?;
+        self.connection.read_rtc().await
+    }
+
+    pub async fn set_rtc_time(&mut self, time: RtcTime) -> Result<(), Error> {
+        self.ensure_pre_da("Setting the RTC")?;
+        if self.dry_run {
+            info!("[Dry run] Would set the RTC to {}", time);
+            return Ok(());
+        }
+        self.connection.write_rtc(time).await
+    }
+
+    fn ensure_pre_da(&self, what: &str) -> Result<(), Error> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Device not connected"));
+        }
+        if self.connection.connection_type() == ConnectionType::Da {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} needs BROM or preloader mode, the DA is already running",
+                    what
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    // Cheap keepalive for frontends sitting idle in DA mode: sends a harmless
+    // devctrl and returns how long the device took to answer. Unlike the other
+    // helpers this never tries to enter DA mode, a dead link should just fail.
+    pub async fn ping(&mut self, timeout: Duration) -> Result<Duration, Error> {
+        if self.connection.connection_type() != ConnectionType::Da {
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                "Device is not in DA mode",
+            ));
+        }
+        let protocol = self
+            .protocol
+            .as_mut()
+            .ok_or_else(|| Error::other("No DA protocol available"))?;
+
+        let started = Instant::now();
+        match tokio::time::timeout(timeout, protocol.get_usb_speed()).await {
+            Ok(result) => result.map(|_| started.elapsed()),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "Device stopped responding")),
+        }
+    }
+
+    // Lets the device boot normally after gathering info in BROM/preloader mode,
+    // no battery pull needed. Once a DA is running there's no way back, the device
+    // has to be rebooted instead. The device can't be used anymore after this.
+    pub async fn release(&mut self) -> Result<(), Error> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Device not connected"));
+        }
+        if self.connection.connection_type() == ConnectionType::Da {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "The DA is already running, reboot the device instead",
+            ));
+        }
+
+        self.connection.jump_bl().await?;
+        info!("Device released, continuing normal boot");
+        self.connected = false;
+        self.partition_cache.clear();
+        Ok(())
+    }
+
+    // Reboots out of DA mode, into Android, fastboot or powered off. Like
+    // release(), the device can't be used anymore after this.
+    pub async fn reboot(&mut self, mode: ShutdownMode) -> Result<(), Error> {
+        self.ensure_da_mode().await?;
+        self.require(|caps| caps.shutdown, "Rebooting")?;
+        let protocol = self.protocol.as_mut().unwrap();
+        protocol.shutdown(mode).await?;
+        self.connected = false;
+        self.partition_cache.clear();
+        Ok(())
+    }
+
+    // Reboots into fastboot and waits for it to show up on USB, for tooling that
+    // carries on with fastboot (after unlocking, mostly). DAs that can't boot
+    // into fastboot themselves get a bootonce-bootloader message in misc instead,
+    // which the bootloader clears once it has acted on it.
+    pub async fn reboot_to_fastboot(&mut self, timeout: Duration) -> Result<FastbootDevice, Error> {
+        let known = fastboot::find_fastboot_devices().await;
+
+        match self.reboot(ShutdownMode::Fastboot).await {
+            Ok(()) => {}
+            Err(e)
+                if DAStatusError::from_error(&e).is_some()
+                    || e.kind() == ErrorKind::Unsupported =>
+            {
+                info!(
+                    "DA can't reboot to fastboot ({}), using the misc bootloader message",
+                    e
+                );
+                let misc = self.read_partition("misc", &mut |_, _| {}).await?;
+                let misc = fastboot::with_bootloader_command(&misc, fastboot::BOOTLOADER_COMMAND)?;
+                self.write_partition("misc", &misc, &mut |_, _| {}).await?;
+                self.reboot(ShutdownMode::Normal).await?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        info!("Waiting for fastboot...");
+        let device = fastboot::wait_for_fastboot(&known, timeout).await?;
+        info!(
+            "Fastboot device {} found ({})",
+            device.serial, device.location
+        );
+        Ok(device)
+    }
+
+    // In dry-run mode every write to flash (partition writes, restores, seccfg
+    // lock state changes) is skipped and recorded in planned_writes() instead.
+    // Note that raw access through get_protocol() is not covered.
+    pub fn set_dry_run(&mut self, enabled: bool) {
+        self.dry_run = enabled;
+    }
+
+    // Dumps bigger than `segment_size` get written as numbered pieces plus a
+    // `.split` manifest, e.g. FAT32_SEGMENT_SIZE for FAT32 drives. Restores read
+    // split dumps transparently, dump::join_split puts one back together.
+    pub fn set_dump_split(&mut self, segment_size: Option<u64>) {
+        self.split_size = segment_size.filter(|&size| size > 0);
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn planned_writes(&self) -> &[PlannedWrite] {
+        &self.planned_writes
+    }
+
+    pub fn take_planned_writes(&mut self) -> Vec<PlannedWrite> {
+        std::mem::take(&mut self.planned_writes)
+    }
+
+    // Dumps every partition right before write_partition or an erase touches it,
+    // keeping the newest few per partition (see AutoBackup). Nothing gets written
+    // if the backup fails. Skipped in dry-run.
+    pub fn set_auto_backup(&mut self, backup: Option<AutoBackup>) {
+        self.auto_backup = backup;
+    }
+
+    // House rules for protected partitions and forced backups, see SafetyPolicy.
+    // format_and_download skips what it protects on top of its own options, and
+    // what it wants backed up gets backed up even with auto backups off.
+    pub fn set_safety_policy(&mut self, policy: SafetyPolicy) {
+        self.safety = policy;
+    }
+
+    pub fn safety_policy(&self) -> &SafetyPolicy {
+        &self.safety
+    }
+
+    // Returns where the backup went, None when there's no need for one
+    async fn backup_before_write(&mut self, name: &str) -> Result<Option<PathBuf>, Error> {
+        let required = self.safety.needs_backup(name);
+        let backup = match (self.auto_backup.clone(), &self.safety.backup_dir) {
+            (Some(backup), _) => backup,
+            (None, Some(dir)) if required => AutoBackup::new(dir),
+            (None, None) if required => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!(
+                        "The safety policy wants {} backed up first, but auto backups are off and it has no backup_dir",
+                        name
+                    ),
+                ));
+            }
+            (None, _) => return Ok(None),
+        };
+        if self.dry_run {
+            return Ok(None);
+        }
+
+        let legacy = self.protocol.is_none();
+        let partition = if legacy {
+            self.legacy_partition(name).await?
+        } else {
+            self.ensure_da_mode().await?;
+            self.find_partition(name).await?
+        };
+        if !required && backup.max_size.is_some_and(|max| partition.size > max) {
+            warn!(
+                "{} is {:#X} bytes, too big to back up automatically",
+                name, partition.size
+            );
+            return Ok(None);
+        }
+
+        let soc_id = match &self.dev_info {
+            Some(info) => info.borrow().chip.soc_id_hex(),
+            None => String::new(),
+        };
+        let path = backup.backup_path(&soc_id, name);
+        let failed = |e: Error| {
+            Error::new(
+                e.kind(),
+                format!("Backing up {} failed, not touching it: {}", name, e),
+            )
+        };
+        if let Some(dir) = path.parent() {
+            fileio::create_dir_all(dir.to_path_buf())
+                .await
+                .map_err(failed)?;
+        }
+
+        info!("Backing up {} to {}", name, path.display());
+        let mut no_progress = |_read: usize, _total: usize| {};
+        if legacy {
+            let data = self
+                .read_partition_inner(name, &mut no_progress)
+                .await
+                .map_err(failed)?;
+            fileio::write(path.clone(), data).await.map_err(failed)?;
+        } else {
+            self.read_flash_to(partition.address, partition.size, &path, &mut no_progress)
+                .await
+                .map_err(failed)?;
+        }
 
-            seccfg.create(&mut sej, lock_state).await
+        let pruned = {
+            let name = name.to_string();
+            fileio::blocking(move || backup.prune(&soc_id, &name)).await
         };
+        match pruned {
+            Ok(old) => {
+                for old in old {
+                    debug!("Deleted old backup {}", old.display());
+                }
+            }
+            Err(e) => warn!("Failed to delete old backups of {}: {}", name, e),
+        }
+        Ok(Some(path))
+    }
 
-        self.write_partition("seccfg", &new_seccfg, &mut progress)
+    // Every write, erase and lock state change from now on gets appended to `log`,
+    // with the device identifiers and the partition hashes. Dry runs aren't logged.
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        self.audit = log;
+    }
+
+    // SHA-256 of the partition as it is now, if it's worth reading back for the audit log
+    async fn audit_hash_before(&mut self, name: &str) -> Option<String> {
+        if self.audit.is_none() || self.dry_run {
+            return None;
+        }
+        let size = self.find_partition(name).await.ok()?.size;
+        if size > AUDIT_HASH_MAX {
+            return None;
+        }
+        let mut no_progress = |_read: usize, _total: usize| {};
+        let data = self
+            .read_partition_inner(name, &mut no_progress)
             .await
             .ok()?;
-        Some(new_seccfg)
+        Some(sha256_hex(&data))
+    }
+
+    // `written` is what was sent to the device, its hash is logged as hash_after
+    async fn audit_record(
+        &mut self,
+        operation: &str,
+        partition: &str,
+        hash_before: Option<String>,
+        written: &[u8],
+        error: Option<&Error>,
+    ) {
+        let Some(log) = &self.audit else {
+            return;
+        };
+        if self.dry_run {
+            return;
+        }
+
+        let chip = match &self.dev_info {
+            Some(info) => info.borrow().chip.clone(),
+            None => ChipIdentity::default(),
+        };
+
+        let entry = AuditEntry {
+            timestamp: audit::now(),
+            chip,
+            operation: operation.to_string(),
+            partition: partition.to_string(),
+            hash_before,
+            hash_after: (error.is_none() && !written.is_empty()).then(|| sha256_hex(written)),
+            result: error.map_or_else(|| "ok".to_string(), |e| e.to_string()),
+        };
+        if let Err(e) = log.append(&entry) {
+            warn!(
+                "Failed to write to the audit log {}: {}",
+                log.path().display(),
+                e
+            );
+        }
+    }
+
+    // Called once every top level operation completes or fails, e.g. to send a
+    // desktop notification when a long flash is over. Operations started by other
+    // operations (like the reads done by dump_all) are folded into their parent.
+    pub fn set_operation_hook(&mut self, hook: Option<OperationHook>) {
+        self.op_hook = hook;
+    }
+
+    // Every top level operation reports OperationStarted, Progress and
+    // OperationFinished events to `sink`, see core::events.
+    pub fn set_event_sink(&mut self, sink: Option<EventSink>) {
+        self.events = sink;
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(sink) = &self.events {
+            sink(&event);
+        }
+    }
+
+    fn begin_operation(&mut self, name: impl Into<String>) -> Instant {
+        if self.op_depth == 0 {
+            self.op_bytes = 0;
+            self.op_stats = self.connection.stats();
+            if self.connection.latency_tracking() {
+                // Whatever ran between operations isn't part of this one
+                self.connection.take_latency_report();
+            }
+            self.op_name = name.into();
+            self.emit(Event::OperationStarted {
+                operation: self.op_name.clone(),
+            });
+        }
+        self.op_depth += 1;
+        Instant::now()
+    }
+
+    fn finish_operation(&mut self, started: Instant, error: Option<&Error>) {
+        self.op_depth = self.op_depth.saturating_sub(1);
+        if self.op_depth > 0 {
+            return;
+        }
+
+        let latency = self.take_latency();
+        let summary = OperationSummary {
+            name: std::mem::take(&mut self.op_name),
+            duration: started.elapsed(),
+            bytes: self.op_bytes,
+            verified: None,
+            error: error.map(|e| e.to_string()),
+            link: self.connection.stats().since(&self.op_stats),
+            latency,
+        };
+        self.emit(Event::OperationFinished {
+            operation: summary.name.clone(),
+            duration_ms: summary.duration.as_millis() as u64,
+            bytes: summary.bytes,
+            result: match &summary.error {
+                Some(error) => OperationResult::Failed {
+                    error: error.clone(),
+                },
+                None => OperationResult::Ok,
+            },
+        });
+        if let Some(hook) = &self.op_hook {
+            hook(&summary);
+        }
+    }
+
+    // Dumps the call timings of the operation that just ended, outliers and
+    // failures on top since those are what make an operation slow
+    fn take_latency(&self) -> Option<LatencyReport> {
+        if !self.connection.latency_tracking() {
+            return None;
+        }
+        let report = self.connection.take_latency_report();
+        if report.is_empty() {
+            return None;
+        }
+        info!("Protocol call latency for {}:\n{}", self.op_name, report);
+        for call in report.suspicious() {
+            warn!(
+                "{}: {} outliers, {} failed, slowest took {:?} (median {:?})",
+                call.call, call.outliers, call.failed, call.max, call.p50
+            );
+        }
+        Some(report)
+    }
+
+    // Progress callbacks of top level operations also go to the event sink. Nested
+    // operations stay quiet, their parent already reports the same progress.
+    fn op_events(&self) -> Option<EventSink> {
+        self.events.clone().filter(|_| self.op_depth == 1)
+    }
+
+    fn event_progress<'p>(
+        &self,
+        progress: &'p mut (dyn FnMut(usize, usize) + Send),
+    ) -> impl FnMut(usize, usize) + Send + use<'p> {
+        forward_progress(self.op_events(), self.op_name.clone(), progress)
+    }
+
+    fn event_named_progress<'p>(
+        &self,
+        progress: &'p mut (dyn FnMut(&str, usize, usize) + Send),
+    ) -> impl FnMut(&str, usize, usize) + Send + use<'p> {
+        forward_named_progress(self.op_events(), self.op_name.clone(), progress)
+    }
+
+    pub fn get_protocol(&mut self) -> Option<&mut ProtocolKind<'a>> {
+        self.protocol.as_mut()
+    }
+
+    // Copies the BootROM out through the DA (the extensions' ExtReadMem when they're
+    // loaded, register reads otherwise) into `dir`, as brom_<hw code>.bin, next to a
+    // brom_<hw code>.txt with the SoC info. Returns the path of the dump.
+    pub async fn dump_brom(
+        &mut self,
+        dir: &Path,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<PathBuf, Error> {
+        let started = self.begin_operation("Dump BootROM");
+        let mut progress = self.event_progress(progress);
+        let result = self.dump_brom_inner(dir, &mut progress).await;
+        if result.is_ok() {
+            self.op_bytes += BROM_SIZE as u64;
+        }
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn dump_brom_inner(
+        &mut self,
+        dir: &Path,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<PathBuf, Error> {
+        self.ensure_da_mode().await?;
+
+        let info = match &self.dev_info {
+            Some(info) => info.borrow().clone(),
+            None => return Err(Error::other("Device info not available")),
+        };
+
+        let protocol = self.protocol.as_mut().unwrap();
+        let mut brom = Vec::with_capacity(BROM_SIZE);
+        while brom.len() < BROM_SIZE {
+            let addr = BROM_BASE + brom.len() as u32;
+            let size = (BROM_SIZE - brom.len()).min(BROM_CHUNK_SIZE);
+            brom.extend(protocol.read_mem(addr, size).await?);
+            progress(brom.len(), BROM_SIZE);
+        }
+
+        // A DA that can't see the BootROM (or a wrong base) reads back as all the same byte
+        if brom.iter().all(|&b| b == brom[0]) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "BootROM at {:#X} reads back as {:#04X} only, it's probably not accessible from the DA",
+                    BROM_BASE, brom[0]
+                ),
+            ));
+        }
+
+        let name = format!("brom_{:04x}", info.chip.hw_code);
+        let path = dir.join(format!("{}.bin", name));
+        let metadata = [
+            format!("hw_code = {:04x}", info.chip.hw_code),
+            format!("hw_sub_code = {:04x}", info.chip.hw_sub_code),
+            format!("hw_ver = {:04x}", info.chip.hw_ver),
+            format!("sw_ver = {:04x}", info.chip.sw_ver),
+            format!("chipset = {}", info.chipset),
+            format!("soc_id = {}", info.chip.soc_id_hex()),
+            format!("meid = {}", info.chip.meid_hex()),
+            format!("base = {:#x}", BROM_BASE),
+            format!("size = {:#x}", BROM_SIZE),
+            format!("sha256 = {}", sha256_hex(&brom)),
+        ];
+
+        let dir = dir.to_path_buf();
+        let bin_path = path.clone();
+        fileio::blocking(move || {
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&bin_path, &brom)?;
+            std::fs::write(
+                dir.join(format!("{}.txt", name)),
+                metadata.join("\n") + "\n",
+            )
+        })
+        .await?;
+
+        info!("BootROM dumped to {}", path.display());
+        Ok(path)
+    }
+
+    // Runs a known plaintext through every SEJ mode on the device and checks that
+    // it round trips. Worth running before trusting a lock state change.
+    pub async fn crypto_selftest(&mut self) -> Result<Vec<SejSelfTestResult>, Error> {
+        self.ensure_da_mode().await?;
+
+        let results = {
+            let mut crypto_config = CryptoConfig::new(SEJ_BASE, self);
+            let mut sej = SEJCrypto::new(&mut crypto_config);
+            sej.self_test().await?
+        };
+
+        for result in &results {
+            if result.passed {
+                info!("SEJ self-test {:?}: passed", result.mode);
+            } else {
+                warn!(
+                    "SEJ self-test {:?}: FAILED (ciphertext {:02X?}, decrypted {:02X?})",
+                    result.mode, result.ciphertext, result.decrypted
+                );
+            }
+        }
+
+        Ok(results)
+    }
+
+    // Encrypts `data` with the device's hardware-bound key, e.g. to rebuild a blob
+    // the device encrypted itself. The result only decrypts on this very device.
+    pub async fn sej_encrypt(&mut self, data: &[u8], mode: SejMode) -> Result<Vec<u8>, Error> {
+        self.sej_crypt(data, mode, true).await
+    }
+
+    // Decrypts hw-encrypted blobs (nvdata items, seccfg hashes...) read from this device
+    pub async fn sej_decrypt(&mut self, data: &[u8], mode: SejMode) -> Result<Vec<u8>, Error> {
+        self.sej_crypt(data, mode, false).await
+    }
+
+    async fn sej_crypt(
+        &mut self,
+        data: &[u8],
+        mode: SejMode,
+        encrypt: bool,
+    ) -> Result<Vec<u8>, Error> {
+        // SEJ works on whole AES blocks and we don't pick a padding for the caller
+        if data.is_empty() || !data.len().is_multiple_of(16) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "SEJ data must be a non-empty multiple of 16 bytes, got {}",
+                    data.len()
+                ),
+            ));
+        }
+
+        self.ensure_da_mode().await?;
+
+        let mut crypto_config = CryptoConfig::new(SEJ_BASE, self);
+        let mut sej = SEJCrypto::new(&mut crypto_config);
+        sej.crypt(mode, data, encrypt).await
+    }
+
+    // Skips seccfg hash algorithm detection and always uses `algo`. Pass None to go
+    // back to detecting it (using the per SoC cache when possible).
+    pub fn set_seccfg_algo(&mut self, algo: Option<SecCfgV4Algo>) {
+        self.seccfg_algo = algo;
+    }
+
+    // Rewrites seccfg with the new lock state. Nothing gets written unless the
+    // pre-flight checks pass, see LockPreflightError for what can stop it.
+    // Returns the new seccfg image.
+    pub async fn set_seccfg_lock_state(&mut self, lock_state: LockFlag) -> Result<Vec<u8>, Error> {
+        let name = match lock_state {
+            LockFlag::Lock => "Lock bootloader",
+            LockFlag::Unlock => "Unlock bootloader",
+        };
+
+        let started = self.begin_operation(name);
+        let result = self.set_seccfg_lock_state_inner(lock_state, None).await;
+        self.finish_operation(started, result.as_ref().err());
+        result.map(|report| report.new_seccfg)
+    }
+
+    // Same as set_seccfg_lock_state, but saves the current seccfg to `backup`
+    // (if given) before writing and reports the lock state before and after.
+    // The after state is read back from the device, not taken from what was sent.
+    pub async fn change_lock_state(
+        &mut self,
+        lock_state: LockFlag,
+        backup: Option<&Path>,
+    ) -> Result<LockReport, Error> {
+        let name = match lock_state {
+            LockFlag::Lock => "Lock bootloader",
+            LockFlag::Unlock => "Unlock bootloader",
+        };
+
+        let started = self.begin_operation(name);
+        let result = self.set_seccfg_lock_state_inner(lock_state, backup).await;
+        self.finish_operation(started, result.as_ref().err());
+        result
+    }
+
+    async fn set_seccfg_lock_state_inner(
+        &mut self,
+        lock_state: LockFlag,
+        backup: Option<&Path>,
+    ) -> Result<LockReport, Error> {
+        if self.protocol.is_none() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Changing the lock state needs a DA",
+            ));
+        }
+
+        if self.connection.connection_type() != ConnectionType::Da {
+            info!("Not in DA mode, entering now");
+            self.enter_da_mode().await?;
+        }
+
+        let mut progress = |_read: usize, _total: usize| {};
+
+        // Not every device has the flag (or an frp partition), only refuse on an explicit "no"
+        if matches!(lock_state, LockFlag::Unlock) {
+            match self.read_partition("frp", &mut progress).await {
+                Ok(frp) => {
+                    if oem_unlock_allowed(&frp) == Some(false) {
+                        return Err(LockPreflightError::OemUnlockDisabled.into());
+                    }
+                }
+                Err(e) => warn!("Could not read the OEM unlock flag, skipping check: {}", e),
+            }
+        }
+
+        let operation = match lock_state {
+            LockFlag::Lock => "lock",
+            LockFlag::Unlock => "unlock",
+        };
+        let sej_base = SEJ_BASE;
+        let seccfg_raw = self.read_partition("seccfg", &mut progress).await?;
+
+        let soc_id = match &self.dev_info {
+            Some(info) => info.borrow().chip.soc_id.clone(),
+            None => return Err(Error::other("Device info not available")),
+        };
+        let forced_algo = self.seccfg_algo;
+
+        let (before, after, algo, new_seccfg) = {
+            let mut crypto_config = CryptoConfig::new(sej_base, self);
+            let mut sej = SEJCrypto::new(&mut crypto_config);
+            let mut seccfg = match forced_algo {
+                Some(algo) => {
+                    let mut seccfg = SecCfgV4::parse_unverified(&seccfg_raw)?;
+                    seccfg.set_algo(algo);
+                    if !seccfg.verify(&seccfg_raw, &mut sej).await? {
+                        return Err(LockPreflightError::HashMismatch(algo).into());
+                    }
+                    seccfg
+                }
+                None => {
+                    let hint = cached_algo(&soc_id);
+                    let seccfg = SecCfgV4::parse_with_hint(&seccfg_raw, &mut sej, hint).await?;
+                    match seccfg.algo() {
+                        Some(algo) => cache_algo(&soc_id, algo),
+                        None => return Err(LockPreflightError::UnknownAlgo.into()),
+                    }
+                    seccfg
+                }
+            };
+
+            let before = LockState::of(&seccfg);
+            let algo = seccfg.algo().unwrap_or(SecCfgV4Algo::None);
+            let new_seccfg = seccfg.create(&mut sej, lock_state).await?;
+            if !seccfg.verify(&new_seccfg, &mut sej).await? {
+                return Err(LockPreflightError::RoundTripFailed(algo).into());
+            }
+            (before, LockState::of(&seccfg), algo, new_seccfg)
+        };
+        info!("seccfg is {}, hash algorithm {:?}", before, algo);
+
+        if let Some(path) = backup {
+            fileio::write(path.to_path_buf(), seccfg_raw.clone())
+                .await
+                .map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!("Failed to back up seccfg to {}: {}", path.display(), e),
+                    )
+                })?;
+            info!("Saved the current seccfg to {}", path.display());
+        }
+
+        // Not write_partition, that would log a second audit entry for this write
+        self.partition_cache.remove("seccfg");
+        let mut result = self.backup_before_write("seccfg").await.map(|_| ());
+        if result.is_ok() {
+            result = self
+                .write_partition_inner("seccfg", &new_seccfg, &mut progress)
+                .await;
+        }
+        if result.is_ok() {
+            self.op_bytes += new_seccfg.len() as u64;
+        }
+        let hash_before = Some(sha256_hex(&seccfg_raw));
+        self.audit_record(
+            operation,
+            "seccfg",
+            hash_before,
+            &new_seccfg,
+            result.as_ref().err(),
+        )
+        .await;
+        result?;
+
+        let readback = if self.dry_run {
+            None
+        } else if !self.power.readback {
+            info!("Skipping the seccfg readback to save power");
+            None
+        } else {
+            let data = self.read_partition("seccfg", &mut progress).await?;
+            let landed = data.get(..new_seccfg.len()) == Some(&new_seccfg[..]);
+            let state = SecCfgV4::parse_unverified(&data).map(|seccfg| LockState::of(&seccfg));
+            match state {
+                Ok(state) if landed && state == after => Some(state),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "seccfg read back after writing doesn't match what was written",
+                    ));
+                }
+            }
+        };
+
+        // The algorithm is known to work now, no need to detect it next time
+        self.remember_profile();
+
+        Ok(LockReport {
+            before,
+            after: readback,
+            algo,
+            backup: backup.map(Path::to_path_buf),
+            new_seccfg,
+        })
+    }
+}
+
+fn check_legacy_size(size: usize) -> Result<(), Error> {
+    if size > LEGACY_MAX_SIZE {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{:#X} bytes is too much to transfer without a DA (limit is {:#X})",
+                size, LEGACY_MAX_SIZE
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Any V5 DA will do to attach, see Device::attach
+fn attach_probe(da_file: &DAFile) -> Result<DA, Error> {
+    if da_file.da_type != DAType::V5 {
+        return Err(Error::new(ErrorKind::Other, "Unsupported DA type!"));
+    }
+    da_file
+        .das
+        .first()
+        .cloned()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "DA file has no entries"))
+}
+
+fn lba_addr(lba: u64, sector_size: usize) -> Result<u64, Error> {
+    lba.checked_mul(sector_size as u64).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("LBA {} is out of range", lba),
+        )
+    })
+}
+
+// Whole partition reads go through memory, past 4 GiB that only works on 64-bit hosts
+fn in_memory_len(partition: &Partition) -> Result<usize, Error> {
+    usize::try_from(partition.size).map_err(|_| {
+        Error::new(
+            ErrorKind::OutOfMemory,
+            format!(
+                "'{}' is too big to read into memory on this host ({:#X} bytes), read it to a file instead",
+                partition.name, partition.size
+            ),
+        )
+    })
+}
+
+// Nothing more to recover once the device itself is gone
+fn link_lost(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::NotConnected | ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+    )
+}
+
+fn resume_marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(".resume");
+    PathBuf::from(marker)
+}
+
+// Marker format is three hex numbers, one per line: address, size and offset reached
+fn read_resume_marker(path: &Path) -> Option<(u64, u64, u64)> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let mut values = content
+        .lines()
+        .map(|line| u64::from_str_radix(line.trim().trim_start_matches("0x"), 16));
+
+    let addr = values.next()?.ok()?;
+    let size = values.next()?.ok()?;
+    let offset = values.next()?.ok()?;
+    Some((addr, size, offset))
+}
+
+fn write_resume_marker(path: &Path, addr: u64, size: u64, offset: u64) -> Result<(), Error> {
+    std::fs::write(path, format!("{:#X}\n{:#X}\n{:#X}\n", addr, size, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::PartitionKind;
+
+    #[test]
+    fn lba_addr_past_4gib() {
+        assert_eq!(lba_addr(0x80_0000, 512).unwrap(), 0x1_0000_0000);
+        assert_eq!(lba_addr(0x10_0000, 4096).unwrap(), 0x1_0000_0000);
+        assert_eq!(
+            lba_addr(u64::MAX / 2, 512).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn in_memory_len_past_4gib() {
+        let small = Partition::new("boot", 0x400_0000, 0, PartitionKind::Unknown);
+        assert_eq!(in_memory_len(&small).unwrap(), 0x400_0000);
+
+        let userdata = Partition::new("userdata", 0x1_4000_0000, 0, PartitionKind::Unknown);
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(in_memory_len(&userdata).unwrap(), 0x1_4000_0000);
+        #[cfg(not(target_pointer_width = "64"))]
+        assert_eq!(
+            in_memory_len(&userdata).unwrap_err().kind(),
+            ErrorKind::OutOfMemory
+        );
     }
 }
@@ -0,0 +1,298 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// 128 entries of 128 bytes
+const GPT_ENTRIES_LEN: u64 = 128 * 128;
+
+// Protective MBR + GPT header + the entries, in `sector_size` sectors.
+// 34 with 512 bytes sectors, 6 with the 4K ones of UFS.
+pub fn pgpt_sectors(sector_size: u64) -> u64 {
+    2 + GPT_ENTRIES_LEN.div_ceil(sector_size)
+}
+
+// Same as above, minus the protective MBR
+pub fn sgpt_sectors(sector_size: u64) -> u64 {
+    pgpt_sectors(sector_size) - 1
+}
+
+// `sha256sum` format, so a dump can be checked with `sha256sum -c sha256sums.txt`
+pub const MANIFEST_FILE: &str = "sha256sums.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpLayout {
+    // <name>.img for partitions, pgpt.bin / sgpt.bin for the partition tables
+    #[default]
+    Penumbra,
+    // Same names `mtk rl` uses, so dumps can be cross checked or restored with either tool
+    MtkClient,
+}
+
+impl DumpLayout {
+    pub fn partition_file_name(&self, name: &str) -> String {
+        match self {
+            DumpLayout::Penumbra => format!("{}.img", name),
+            DumpLayout::MtkClient => format!("{}.bin", name),
+        }
+    }
+
+    pub fn pgpt_file_name(&self) -> &'static str {
+        match self {
+            DumpLayout::Penumbra => "pgpt.bin",
+            DumpLayout::MtkClient => "gpt_main.bin",
+        }
+    }
+
+    pub fn sgpt_file_name(&self) -> &'static str {
+        match self {
+            DumpLayout::Penumbra => "sgpt.bin",
+            DumpLayout::MtkClient => "gpt_backup.bin",
+        }
+    }
+
+    pub fn partition_path(&self, dir: &Path, name: &str) -> PathBuf {
+        dir.join(self.partition_file_name(name))
+    }
+
+    // Guesses the layout of an existing dump directory by looking at the GPT files
+    pub fn detect(dir: &Path) -> Option<DumpLayout> {
+        [DumpLayout::MtkClient, DumpLayout::Penumbra]
+            .into_iter()
+            .find(|layout| dir.join(layout.pgpt_file_name()).is_file())
+    }
+}
+
+// Writes the manifest for the given (file, sha256) pairs in `dir`, replacing any old one
+pub fn write_manifest(dir: &Path, entries: &[(PathBuf, String)]) -> std::io::Result<()> {
+    let mut text = String::new();
+    for (path, hash) in entries {
+        let name = path.strip_prefix(dir).unwrap_or(path);
+        text.push_str(&format!("{}  {}\n", hash, name.display()));
+    }
+    std::fs::write(dir.join(MANIFEST_FILE), text)
+}
+
+// Largest file FAT32 can hold, for dumps going straight to a USB stick
+pub const FAT32_SEGMENT_SIZE: u64 = 0xFFFF_FFFF;
+
+// `<path>.000`, `<path>.001`... for split dumps
+pub fn segment_path(path: &Path, index: u64) -> PathBuf {
+    let mut segment = path.as_os_str().to_owned();
+    segment.push(format!(".{:03}", index));
+    PathBuf::from(segment)
+}
+
+// Sits next to the segments, tells how to put them back together
+pub fn split_manifest_path(path: &Path) -> PathBuf {
+    let mut manifest = path.as_os_str().to_owned();
+    manifest.push(".split");
+    PathBuf::from(manifest)
+}
+
+// A dump written either as one file, or as `segment_size` pieces next to each
+// other. Only supports what read_flash_to needs: truncating, then appending.
+pub struct SegmentedFile {
+    path: PathBuf,
+    segment_size: Option<u64>,
+    open: Option<(u64, File)>,
+    pos: u64,
+}
+
+impl SegmentedFile {
+    pub fn new(path: &Path, segment_size: Option<u64>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            segment_size,
+            open: None,
+            pos: 0,
+        }
+    }
+
+    fn file_path(&self, index: u64) -> PathBuf {
+        match self.segment_size {
+            Some(_) => segment_path(&self.path, index),
+            None => self.path.clone(),
+        }
+    }
+
+    fn file_len(path: &Path) -> u64 {
+        std::fs::metadata(path).map_or(0, |meta| meta.len())
+    }
+
+    // Bytes already on disk, only counting segments that follow each other
+    pub fn on_disk_len(&self) -> u64 {
+        let Some(segment_size) = self.segment_size else {
+            return Self::file_len(&self.path);
+        };
+        let mut total = 0;
+        for index in 0.. {
+            let len = Self::file_len(&self.file_path(index));
+            total += len;
+            if len != segment_size {
+                break;
+            }
+        }
+        total
+    }
+
+    pub fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+        self.open = None;
+        let segment_size = self.segment_size.unwrap_or(u64::MAX);
+        for index in 0.. {
+            let path = self.file_path(index);
+            let start = index * segment_size;
+            if start >= len && index > 0 {
+                // Leftovers of a longer dump
+                if !path.exists() {
+                    break;
+                }
+                std::fs::remove_file(&path)?;
+                continue;
+            }
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?
+                .set_len((len - start).min(segment_size))?;
+            if self.segment_size.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn seek(&mut self, pos: u64) {
+        self.open = None;
+        self.pos = pos;
+    }
+
+    pub fn write_all(&mut self, mut data: &[u8]) -> std::io::Result<()> {
+        let segment_size = self.segment_size.unwrap_or(u64::MAX);
+        while !data.is_empty() {
+            let index = self.pos / segment_size;
+            let offset = self.pos % segment_size;
+            if self.open.as_ref().is_none_or(|(open, _)| *open != index) {
+                // The previous segment is complete, make sure it's on disk
+                self.sync_data()?;
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(self.file_path(index))?;
+                file.seek(SeekFrom::Start(offset))?;
+                self.open = Some((index, file));
+            }
+
+            let len = ((segment_size - offset) as usize).min(data.len());
+            let (_, file) = self.open.as_mut().unwrap();
+            file.write_all(&data[..len])?;
+            self.pos += len as u64;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    pub fn sync_data(&mut self) -> std::io::Result<()> {
+        match &self.open {
+            Some((_, file)) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    // Reads the dump back as one stream, whether it's split or not
+    pub fn reader(&self) -> std::io::Result<Box<dyn Read + Send>> {
+        let Some(segment_size) = self.segment_size else {
+            return Ok(Box::new(File::open(&self.path)?));
+        };
+        let mut reader: Box<dyn Read + Send> = Box::new(std::io::empty());
+        for index in 0.. {
+            let path = self.file_path(index);
+            if !path.exists() {
+                break;
+            }
+            let last = Self::file_len(&path) != segment_size;
+            reader = Box::new(reader.chain(File::open(path)?));
+            if last {
+                break;
+            }
+        }
+        Ok(reader)
+    }
+
+    // Records how to join the segments, `sha256` being the hash of the joined file
+    pub fn write_manifest(&self, size: u64, sha256: &str) -> std::io::Result<()> {
+        let Some(segment_size) = self.segment_size else {
+            return Ok(());
+        };
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let segments = size.div_ceil(segment_size).max(1);
+        let text = format!(
+            "# {name} split in {segments} pieces, join them in order to get it back,\n\
+             # e.g. cat {name}.000 {name}.001 ... > {name}\n\
+             size = {size}\n\
+             segment_size = {segment_size}\n\
+             segments = {segments}\n\
+             sha256 = {sha256}\n"
+        );
+        std::fs::write(split_manifest_path(&self.path), text)
+    }
+
+    // Opens what `path` was dumped as: the file itself, or its segments if there's a manifest
+    pub fn open_dump(path: &Path) -> std::io::Result<Self> {
+        if path.is_file() {
+            return Ok(Self::new(path, None));
+        }
+        let manifest = std::fs::read_to_string(split_manifest_path(path))?;
+        let segment_size = manifest
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(key, _)| key.trim() == "segment_size")
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "No segment_size in the split manifest of {}",
+                        path.display()
+                    ),
+                )
+            })?;
+        Ok(Self::new(path, Some(segment_size)))
+    }
+}
+
+// True if `path` was dumped, as one file or split
+pub fn dump_exists(path: &Path) -> bool {
+    path.is_file() || split_manifest_path(path).is_file()
+}
+
+pub fn read_dump(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    SegmentedFile::open_dump(path)?
+        .reader()?
+        .read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// Puts a split dump back together as `path`, the segments are left alone
+pub fn join_split(path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    let mut reader = SegmentedFile::open_dump(path)?.reader()?;
+    let mut out = File::create(path)?;
+    std::io::copy(&mut reader, &mut out)?;
+    out.sync_all()
+}
@@ -0,0 +1,165 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use log::{Level, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Progress events are only sent every 1/PROGRESS_STEPS of the total, plus the last one
+const PROGRESS_STEPS: usize = 200;
+
+// What core reports to frontends while it works. Serialized as one JSON object
+// per event, tagged by "event", so a daemon or GUI can just forward them as-is.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    OperationStarted {
+        operation: String,
+    },
+    Progress {
+        operation: String,
+        // Partition being worked on, for operations that go through several
+        #[serde(skip_serializing_if = "Option::is_none")]
+        item: Option<String>,
+        done: u64,
+        total: u64,
+    },
+    LogLine {
+        level: String,
+        target: String,
+        message: String,
+    },
+    OperationFinished {
+        operation: String,
+        duration_ms: u64,
+        bytes: u64,
+        result: OperationResult,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OperationResult {
+    Ok,
+    Failed { error: String },
+}
+
+pub type EventSink = Arc<dyn Fn(&Event) + Send + Sync>;
+
+impl Event {
+    pub fn to_json(&self) -> String {
+        // Nothing in here can fail to serialize
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(line: &str) -> serde_json::Result<Event> {
+        serde_json::from_str(line)
+    }
+}
+
+// Turns progress updates into Progress events, throttled, then passes them on.
+fn progress_event(
+    sink: &EventSink,
+    operation: &str,
+    item: Option<&str>,
+    last: &mut usize,
+    done: usize,
+    total: usize,
+) {
+    // Going backwards means a new item (or a retry) started, always report that
+    if done > *last && done != total && done - *last < total / PROGRESS_STEPS {
+        return;
+    }
+    *last = done;
+    sink(&Event::Progress {
+        operation: operation.to_string(),
+        item: item.map(str::to_string),
+        done: done as u64,
+        total: total as u64,
+    });
+}
+
+pub fn forward_progress<'p>(
+    sink: Option<EventSink>,
+    operation: String,
+    progress: &'p mut (dyn FnMut(usize, usize) + Send),
+) -> impl FnMut(usize, usize) + Send + 'p {
+    let mut last = 0;
+    move |done, total| {
+        if let Some(sink) = &sink {
+            progress_event(sink, &operation, None, &mut last, done, total);
+        }
+        progress(done, total)
+    }
+}
+
+// Same as forward_progress, for the callbacks that name the partition
+pub fn forward_named_progress<'p>(
+    sink: Option<EventSink>,
+    operation: String,
+    progress: &'p mut (dyn FnMut(&str, usize, usize) + Send),
+) -> impl FnMut(&str, usize, usize) + Send + 'p {
+    let mut last = 0;
+    move |item, done, total| {
+        if let Some(sink) = &sink {
+            progress_event(sink, &operation, Some(item), &mut last, done, total);
+        }
+        progress(item, done, total)
+    }
+}
+
+// Throttled Progress events for `operation`, owning everything it needs so it
+// can be handed to code that outlives the caller's borrows (e.g. the DA upload)
+pub fn named_progress_events(
+    sink: EventSink,
+    operation: String,
+) -> impl FnMut(&str, usize, usize) + Send + 'static {
+    let mut last = 0;
+    move |item, done, total| progress_event(&sink, &operation, Some(item), &mut last, done, total)
+}
+
+// Logger that sends records at or above `level` to a sink as LogLine events,
+// and everything to the wrapped logger (e.g. env_logger writing to a file).
+pub struct EventLogger {
+    inner: Box<dyn Log>,
+    sink: EventSink,
+    level: Level,
+    // Set while inside the sink, so a sink that logs doesn't recurse forever
+    busy: AtomicBool,
+}
+
+impl EventLogger {
+    pub fn new(inner: Box<dyn Log>, sink: EventSink, level: Level) -> Self {
+        Self {
+            inner,
+            sink,
+            level,
+            busy: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+
+        if record.level() <= self.level && !self.busy.swap(true, Ordering::Acquire) {
+            (self.sink)(&Event::LogLine {
+                level: record.level().to_string(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            });
+            self.busy.store(false, Ordering::Release);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
@@ -0,0 +1,326 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::port::MTKPort;
+use crate::core::device::Device;
+use crate::core::dump::DumpLayout;
+use crate::core::flashall::FormatAllOptions;
+use crate::core::identity::DeviceIdentity;
+use crate::core::pipeline::Pipeline;
+use crate::core::seccfg::LockFlag;
+use crate::da::DAData;
+use log::{error, info};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Replaced by the port name in job paths, so each device gets its own files
+const DEVICE_PLACEHOLDER: &str = "{device}";
+
+#[derive(Debug, Clone)]
+pub enum JobStep {
+    Read { partition: String, path: PathBuf },
+    Write { partition: String, path: PathBuf },
+    DumpAll { dir: PathBuf },
+    Restore { dir: PathBuf },
+    FormatAll { dir: PathBuf },
+    Unlock,
+    Lock,
+}
+
+impl JobStep {
+    fn name(&self) -> String {
+        match self {
+            JobStep::Read { partition, .. } => format!("read {}", partition),
+            JobStep::Write { partition, .. } => format!("write {}", partition),
+            JobStep::DumpAll { .. } => "dump_all".to_string(),
+            JobStep::Restore { .. } => "restore".to_string(),
+            JobStep::FormatAll { .. } => "format_all".to_string(),
+            JobStep::Unlock => "unlock".to_string(),
+            JobStep::Lock => "lock".to_string(),
+        }
+    }
+}
+
+// What every device in the farm goes through, in order. Loaded from a file
+// with one step per line:
+//
+//   # comment
+//   dump_all backups/{device}
+//   write boot images/boot.img
+//   read seccfg out/{device}/seccfg.bin
+//   restore images/
+//   format_all images/
+//   unlock
+//   lock
+#[derive(Debug, Clone, Default)]
+pub struct Job {
+    pub steps: Vec<JobStep>,
+}
+
+impl Job {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: invalid job step '{}'", lineno + 1, line),
+                )
+            };
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let step = match fields.as_slice() {
+                ["read", partition, path] => JobStep::Read {
+                    partition: partition.to_string(),
+                    path: PathBuf::from(path),
+                },
+                ["write", partition, path] => JobStep::Write {
+                    partition: partition.to_string(),
+                    path: PathBuf::from(path),
+                },
+                ["dump_all", dir] => JobStep::DumpAll {
+                    dir: PathBuf::from(dir),
+                },
+                ["restore", dir] => JobStep::Restore {
+                    dir: PathBuf::from(dir),
+                },
+                ["format_all", dir] => JobStep::FormatAll {
+                    dir: PathBuf::from(dir),
+                },
+                ["unlock"] => JobStep::Unlock,
+                ["lock"] => JobStep::Lock,
+                _ => return Err(invalid()),
+            };
+            steps.push(step);
+        }
+
+        Ok(Self { steps })
+    }
+}
+
+// A port to run the job on. With `expected` set, the job only runs if the
+// device found there is that one (see DeviceIdentity), e.g. when the job was
+// queued from an earlier run's FarmResult and phones may have been swapped since.
+pub struct FarmTarget {
+    pub port: Box<dyn MTKPort>,
+    pub expected: Option<DeviceIdentity>,
+}
+
+impl From<Box<dyn MTKPort>> for FarmTarget {
+    fn from(port: Box<dyn MTKPort>) -> Self {
+        Self {
+            port,
+            expected: None,
+        }
+    }
+}
+
+// Called with (port, step, done, total) from every device task
+pub type FarmProgress = Arc<dyn Fn(&str, &str, usize, usize) + Send + Sync>;
+
+#[derive(Debug)]
+pub struct FarmResult {
+    pub port: String,
+    // Who was on the port, None if the device never got that far
+    pub identity: Option<DeviceIdentity>,
+    pub duration: Duration,
+    // Steps that completed before the first failure (or all of them)
+    pub steps_done: usize,
+    pub result: Result<()>,
+}
+
+impl FarmResult {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+// Runs `job` on every port at once, one task per device. A failing device
+// doesn't stop the others, each gets its own FarmResult (same order as `ports`).
+pub async fn run_farm(
+    ports: Vec<Box<dyn MTKPort>>,
+    da_data: Vec<u8>,
+    job: Job,
+    progress: FarmProgress,
+) -> Vec<FarmResult> {
+    let targets = ports.into_iter().map(FarmTarget::from).collect();
+    run_farm_bound(targets, da_data, job, progress).await
+}
+
+// Like run_farm, but each device is checked against its target's identity
+// first. A mismatch fails that device (IdentityMismatch) before anything runs.
+pub async fn run_farm_bound(
+    targets: Vec<FarmTarget>,
+    da_data: Vec<u8>,
+    job: Job,
+    progress: FarmProgress,
+) -> Vec<FarmResult> {
+    let job = Arc::new(job);
+    // Shared by every device task, not copied
+    let da_data = DAData::from(da_data);
+
+    let tasks: Vec<_> = targets
+        .into_iter()
+        .map(|target| {
+            let name = target.port.get_port_name();
+            let task = tokio::spawn(run_device(
+                target,
+                da_data.clone(),
+                Arc::clone(&job),
+                Arc::clone(&progress),
+            ));
+            (name, task)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for (port, task) in tasks {
+        let result = match task.await {
+            Ok(result) => result,
+            Err(e) => FarmResult {
+                port,
+                identity: None,
+                duration: Duration::ZERO,
+                steps_done: 0,
+                result: Err(Error::other(format!("Device task failed: {}", e))),
+            },
+        };
+        match &result.result {
+            Ok(()) => info!("[{}] Job done in {:.1?}", result.port, result.duration),
+            Err(e) => error!(
+                "[{}] Job failed after {} step(s): {}",
+                result.port, result.steps_done, e
+            ),
+        }
+        results.push(result);
+    }
+
+    results
+}
+
+async fn run_device(
+    target: FarmTarget,
+    da_data: DAData,
+    job: Arc<Job>,
+    progress: FarmProgress,
+) -> FarmResult {
+    let started = Instant::now();
+    let port_name = target.port.get_port_name();
+    let mut steps_done = 0;
+    let mut identity = None;
+
+    let result = async {
+        let mut dev = Device::init(target.port, da_data).await?;
+        identity = Some(dev.identity());
+        // Before the DA goes up, nothing has touched the device yet
+        if let Some(expected) = &target.expected {
+            dev.verify_identity(expected)?;
+        }
+        dev.enter_da_mode().await?;
+
+        for step in &job.steps {
+            info!("[{}] {}", port_name, step.name());
+            run_step(&mut dev, &port_name, step, &progress).await?;
+            steps_done += 1;
+        }
+        Ok::<(), Error>(())
+    }
+    .await;
+
+    FarmResult {
+        port: port_name,
+        identity,
+        duration: started.elapsed(),
+        steps_done,
+        result,
+    }
+}
+
+async fn run_step(
+    dev: &mut Device<'_>,
+    port_name: &str,
+    step: &JobStep,
+    progress: &FarmProgress,
+) -> Result<()> {
+    let step_name = step.name();
+    let mut step_progress =
+        |done: usize, total: usize| progress(port_name, &step_name, done, total);
+    let mut named_progress =
+        |_: &str, done: usize, total: usize| progress(port_name, &step_name, done, total);
+
+    match step {
+        JobStep::Read { partition, path } => {
+            let path = device_path(path, port_name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            dev.read_partition_to(partition, &path, &mut step_progress)
+                .await
+                .map(|_| ())
+        }
+        JobStep::Write { partition, path } => {
+            // Sparse and zstd images are unpacked on the way
+            let path = device_path(path, port_name);
+            let pipeline = Pipeline::for_file(&path)?;
+            dev.write_partition_from(partition, &path, &pipeline, &mut step_progress)
+                .await
+        }
+        JobStep::DumpAll { dir } => dev
+            .dump_all(
+                &device_path(dir, port_name),
+                DumpLayout::Penumbra,
+                &mut named_progress,
+            )
+            .await
+            .map(|_| ()),
+        JobStep::Restore { dir } => dev
+            .restore_all(&device_path(dir, port_name), None, &mut named_progress)
+            .await
+            .map(|_| ()),
+        JobStep::FormatAll { dir } => dev
+            .format_and_download(
+                &device_path(dir, port_name),
+                None,
+                &FormatAllOptions::default(),
+                &mut named_progress,
+            )
+            .await
+            .map(|_| ()),
+        JobStep::Unlock | JobStep::Lock => {
+            let flag = match step {
+                JobStep::Unlock => LockFlag::Unlock,
+                _ => LockFlag::Lock,
+            };
+            dev.set_seccfg_lock_state(flag).await.map(|_| ())
+        }
+    }
+}
+
+// Port names are things like /dev/ttyACM0 or COM3, keep only what's safe in a path
+fn device_path(path: &Path, port_name: &str) -> PathBuf {
+    let path = path.to_string_lossy();
+    if !path.contains(DEVICE_PLACEHOLDER) {
+        return PathBuf::from(path.as_ref());
+    }
+
+    let device: String = port_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    PathBuf::from(path.replace(DEVICE_PLACEHOLDER, device.trim_matches('_')))
+}
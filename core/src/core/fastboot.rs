@@ -0,0 +1,149 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use log::debug;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+// The fastboot USB interface, as defined by AOSP
+#[cfg(feature = "libusb")]
+const FASTBOOT_CLASS: (u8, u8, u8) = (0xFF, 0x42, 0x03);
+
+// Android's bootloader_message lives at the start of misc, `command` is its
+// first field. The bootloader stops in fastboot once when it finds this there.
+pub const BOOTLOADER_COMMAND: &str = "bootonce-bootloader";
+pub const COMMAND_LEN: usize = 32;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FastbootDevice {
+    pub serial: String,
+    // Where it was found, e.g. "usb 003:012" or "fastboot devices"
+    pub location: String,
+}
+
+// `misc` with its bootloader_message command replaced, the rest stays as is
+pub fn with_bootloader_command(misc: &[u8], command: &str) -> Result<Vec<u8>, Error> {
+    if misc.len() < COMMAND_LEN || command.len() >= COMMAND_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "misc is too small for a bootloader message",
+        ));
+    }
+    let mut misc = misc.to_vec();
+    misc[..COMMAND_LEN].fill(0);
+    misc[..command.len()].copy_from_slice(command.as_bytes());
+    Ok(misc)
+}
+
+// Fastboot devices connected right now
+pub async fn find_fastboot_devices() -> Vec<FastbootDevice> {
+    #[cfg(feature = "libusb")]
+    {
+        tokio::task::spawn_blocking(find_usb_devices)
+            .await
+            .unwrap_or_default()
+    }
+
+    // No USB access of our own, the fastboot tool has it
+    #[cfg(not(feature = "libusb"))]
+    {
+        let output = tokio::process::Command::new("fastboot")
+            .arg("devices")
+            .output()
+            .await;
+        match output {
+            Ok(output) => parse_devices(&String::from_utf8_lossy(&output.stdout)),
+            Err(e) => {
+                debug!("Could not run fastboot: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+// Waits until a fastboot device not in `known` shows up. `known` is what was
+// already there before the reboot, so another phone on the same host doesn't
+// get mistaken for this one.
+pub async fn wait_for_fastboot(
+    known: &[FastbootDevice],
+    timeout: Duration,
+) -> Result<FastbootDevice, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(device) = find_fastboot_devices()
+            .await
+            .into_iter()
+            .find(|device| !known.contains(device))
+        {
+            return Ok(device);
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("No fastboot device showed up within {:?}", timeout),
+            ));
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+// `fastboot devices` prints `<serial>\tfastboot` per device
+#[cfg(not(feature = "libusb"))]
+fn parse_devices(output: &str) -> Vec<FastbootDevice> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (serial, state) = line.split_once('\t')?;
+            (state.trim() == "fastboot").then(|| FastbootDevice {
+                serial: serial.trim().to_string(),
+                location: "fastboot devices".to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "libusb")]
+fn find_usb_devices() -> Vec<FastbootDevice> {
+    use rusb::{Context, UsbContext};
+
+    let Ok(devices) = Context::new().and_then(|context| context.devices()) else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    for device in devices.iter() {
+        let Ok(config) = device.active_config_descriptor() else {
+            continue;
+        };
+        let is_fastboot = config.interfaces().any(|interface| {
+            interface.descriptors().any(|desc| {
+                (
+                    desc.class_code(),
+                    desc.sub_class_code(),
+                    desc.protocol_code(),
+                ) == FASTBOOT_CLASS
+            })
+        });
+        if !is_fastboot {
+            continue;
+        }
+
+        let serial = device
+            .device_descriptor()
+            .ok()
+            .zip(device.open().ok())
+            .and_then(|(descriptor, handle)| {
+                handle.read_serial_number_string_ascii(&descriptor).ok()
+            })
+            .unwrap_or_default();
+        found.push(FastbootDevice {
+            serial,
+            location: format!("usb {:03}:{:03}", device.bus_number(), device.address()),
+        });
+    }
+    found
+}
@@ -0,0 +1,117 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::dump;
+use std::io::{Error, Result};
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Chunks a ChunkWriter can fall behind before write() starts waiting on it.
+// Reads are at most a few MiB per chunk, so this stays well under 64 MiB.
+const QUEUE_CHUNKS: usize = 8;
+
+// Runs `f` on tokio's blocking pool. For disk IO of any size, so frontends
+// sharing the runtime (the TUI) keep redrawing while a dump is being read in.
+pub async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(Error::other)?
+}
+
+// dump::read_dump, off the runtime
+pub async fn read_dump(path: PathBuf) -> Result<Vec<u8>> {
+    blocking(move || dump::read_dump(&path)).await
+}
+
+// std::fs::write, off the runtime
+pub async fn write(path: PathBuf, data: Vec<u8>) -> Result<()> {
+    blocking(move || std::fs::write(path, data)).await
+}
+
+// std::fs::create_dir_all, off the runtime
+pub async fn create_dir_all(path: PathBuf) -> Result<()> {
+    blocking(move || std::fs::create_dir_all(path)).await
+}
+
+// dump::write_manifest, off the runtime
+pub async fn write_manifest(dir: PathBuf, entries: Vec<(PathBuf, String)>) -> Result<()> {
+    blocking(move || dump::write_manifest(&dir, &entries)).await
+}
+
+// Writes chunks from a blocking thread while the caller goes on reading the
+// next ones off the device. `S` is whatever the writing needs (a file, a hasher,
+// a resume marker...), it's handed back by finish().
+// Written chunks come back to be read into again, see buffer().
+pub struct ChunkWriter<S> {
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<S>>>,
+    recycled: std::sync::mpsc::Receiver<Vec<u8>>,
+}
+
+impl<S: Send + 'static> ChunkWriter<S> {
+    pub fn spawn<F>(state: S, mut write: F) -> Self
+    where
+        F: FnMut(&mut S, &[u8]) -> Result<()> + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(QUEUE_CHUNKS);
+        let (recycle, recycled) = std::sync::mpsc::channel();
+        let worker = tokio::task::spawn_blocking(move || {
+            let mut state = state;
+            while let Some(chunk) = rx.blocking_recv() {
+                write(&mut state, &chunk)?;
+                // Nobody asking for buffers anymore is fine
+                let _ = recycle.send(chunk);
+            }
+            Ok(state)
+        });
+        Self {
+            tx: Some(tx),
+            worker: Some(worker),
+            recycled,
+        }
+    }
+
+    // A `len` bytes buffer to read the next chunk into, one that was already
+    // written when there is one. Multi-GB dumps end up cycling through the same
+    // few buffers instead of allocating one per chunk.
+    pub fn buffer(&self, len: usize) -> Vec<u8> {
+        let mut buffer = self.recycled.try_recv().unwrap_or_default();
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    // Queues `chunk`, only waits when the queue is full. Errors from earlier
+    // chunks show up here (or in finish()), the writer is unusable afterwards.
+    pub async fn write(&mut self, chunk: Vec<u8>) -> Result<()> {
+        let Some(tx) = &self.tx else {
+            return Err(Error::other("Writer already stopped"));
+        };
+        if tx.send(chunk).await.is_ok() {
+            return Ok(());
+        }
+
+        // The worker only hangs up when writing failed
+        self.tx = None;
+        match self.join().await {
+            Ok(_) => Err(Error::other("Writer stopped unexpectedly")),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Waits for everything queued to be written
+    pub async fn finish(mut self) -> Result<S> {
+        self.tx = None;
+        self.join().await
+    }
+
+    async fn join(&mut self) -> Result<S> {
+        match self.worker.take() {
+            Some(worker) => worker.await.map_err(Error::other)?,
+            None => Err(Error::other("Writer already stopped")),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fs::OpenOptions;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+
+// Partitions holding per device data (calibration, IMEI, keys...) that can't be
+// recovered from a firmware package. Never erased or written by default.
+pub const DEFAULT_PROTECTED: &[&str] = &[
+    "preloader",
+    "preloader_a",
+    "preloader_b",
+    "nvram",
+    "nvdata",
+    "nvcfg",
+    "protect1",
+    "protect2",
+    "persist",
+    "proinfo",
+    "seccfg",
+    "frp",
+];
+
+pub const JOURNAL_FILE: &str = "journal.txt";
+
+#[derive(Debug, Clone)]
+pub struct FormatAllOptions {
+    pub protected: Vec<String>,
+    // Every partition gets dumped here before being touched, so the whole run
+    // can be undone with Device::rollback(). Needs as much space as the flash.
+    pub backup_dir: Option<PathBuf>,
+}
+
+impl Default for FormatAllOptions {
+    fn default() -> Self {
+        Self {
+            protected: DEFAULT_PROTECTED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            backup_dir: None,
+        }
+    }
+}
+
+impl FormatAllOptions {
+    pub fn is_protected(&self, name: &str) -> bool {
+        self.protected.iter().any(|p| p == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalStep {
+    BackedUp,
+    Erased,
+    Written,
+}
+
+impl JournalStep {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalStep::BackedUp => "backed_up",
+            JournalStep::Erased => "erased",
+            JournalStep::Written => "written",
+        }
+    }
+
+    fn parse(step: &str) -> Option<Self> {
+        match step {
+            "backed_up" => Some(JournalStep::BackedUp),
+            "erased" => Some(JournalStep::Erased),
+            "written" => Some(JournalStep::Written),
+            _ => None,
+        }
+    }
+}
+
+// Append only log of what format_and_download did, one `<step> <partition>` per
+// line. Synced after every entry, so it survives the run being cut short.
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(JOURNAL_FILE);
+        std::fs::write(&path, "")?;
+        Ok(Self { path })
+    }
+
+    pub fn record(&self, step: JournalStep, name: &str) -> Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{} {}", step.as_str(), name)?;
+        file.sync_data()
+    }
+
+    pub fn load(dir: &Path) -> Result<Vec<(JournalStep, String)>> {
+        let content = std::fs::read_to_string(dir.join(JOURNAL_FILE))?;
+        Ok(content
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter_map(|(step, name)| Some((JournalStep::parse(step)?, name.to_string())))
+            .collect())
+    }
+}
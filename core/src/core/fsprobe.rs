@@ -0,0 +1,374 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::dump::SegmentedFile;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+// Every superblock we look at sits in the first 4K, the rest is headroom
+pub const PROBE_SIZE: usize = 0x10000;
+
+// All three keep their superblock 1024 bytes in
+const SUPERBLOCK_OFFSET: usize = 0x400;
+
+const EXT4_MAGIC: u16 = 0xEF53;
+const EROFS_MAGIC: u32 = 0xE0F5_E1E2;
+const F2FS_MAGIC: u32 = 0xF2F5_2010;
+const SPARSE_MAGIC: u32 = 0xED26_FF3A;
+
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x80;
+const EXT4_STATE_CLEAN: u16 = 0x1;
+
+// Block count times block size past u64, only a corrupt superblock does that
+const SIZE_OVERFLOW_HINT: &str = "The superblock claims a size past 16 EiB, it looks corrupt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsKind {
+    Ext4,
+    Erofs,
+    F2fs,
+    // Not a filesystem, but what fastboot images usually are
+    AndroidSparse,
+    // Only zeros (or only 0xFF) where the superblock should be
+    Blank,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsProbe {
+    pub kind: FsKind,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub block_size: u64,
+    // Size of the filesystem according to its superblock
+    pub total_bytes: Option<u64>,
+    // Only ext4 keeps this in the superblock
+    pub free_bytes: Option<u64>,
+    pub hints: Vec<String>,
+}
+
+impl fmt::Display for FsKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FsKind::Ext4 => "ext4",
+            FsKind::Erofs => "erofs",
+            FsKind::F2fs => "f2fs",
+            FsKind::AndroidSparse => "Android sparse image",
+            FsKind::Blank => "blank",
+            FsKind::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FsProbe {
+    fn new(kind: FsKind) -> Self {
+        Self {
+            kind,
+            label: None,
+            uuid: None,
+            block_size: 0,
+            total_bytes: None,
+            free_bytes: None,
+            hints: Vec::new(),
+        }
+    }
+}
+
+// Identifies the filesystem in the first bytes of a partition (PROBE_SIZE is plenty).
+// `image_size` is the size of the whole dump or partition, if known, and is used
+// to spot truncated dumps.
+pub fn probe(head: &[u8], image_size: Option<u64>) -> FsProbe {
+    let mut result = if le32(head, 0) == Some(SPARSE_MAGIC) {
+        let mut result = FsProbe::new(FsKind::AndroidSparse);
+        result
+            .hints
+            .push("Sparse image, convert it with simg2img to inspect it".to_string());
+        return result;
+    } else if let Some(result) = probe_ext4(head) {
+        result
+    } else if let Some(result) = probe_erofs(head) {
+        result
+    } else if let Some(result) = probe_f2fs(head) {
+        result
+    } else {
+        let blank = head.iter().all(|&b| b == 0) || head.iter().all(|&b| b == 0xFF);
+        let mut result = FsProbe::new(if blank {
+            FsKind::Blank
+        } else {
+            FsKind::Unknown
+        });
+        if blank {
+            result
+                .hints
+                .push("Looks erased, nothing to mount here".to_string());
+        }
+        return result;
+    };
+
+    if let (Some(total), Some(size)) = (result.total_bytes, image_size)
+        && size < total
+    {
+        result.hints.push(format!(
+            "Image is {} bytes but the filesystem spans {}, the dump looks truncated",
+            size, total
+        ));
+    }
+    // In u128, a few EiB times 100 doesn't fit a u64
+    if let (Some(total), Some(free)) = (result.total_bytes, result.free_bytes)
+        && let Some(used) = ((total - free.min(total)) as u128 * 100).checked_div(total as u128)
+    {
+        result.hints.push(format!("{}% used", used));
+    }
+    result
+}
+
+// Same as probe(), on a dump file (split or not)
+pub fn probe_file(path: &Path) -> std::io::Result<FsProbe> {
+    let file = SegmentedFile::open_dump(path)?;
+    let mut head = Vec::with_capacity(PROBE_SIZE);
+    file.reader()?
+        .take(PROBE_SIZE as u64)
+        .read_to_end(&mut head)?;
+    Ok(probe(&head, Some(file.on_disk_len())))
+}
+
+fn probe_ext4(head: &[u8]) -> Option<FsProbe> {
+    let sb = head.get(SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 0x400)?;
+    if le16(sb, 0x38)? != EXT4_MAGIC {
+        return None;
+    }
+
+    let mut result = FsProbe::new(FsKind::Ext4);
+    result.block_size = 1024u64 << le32(sb, 0x18)?.min(16);
+    let (mut blocks, mut free) = (le32(sb, 0x04)? as u64, le32(sb, 0x0C)? as u64);
+    if le32(sb, 0x60)? & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+        blocks |= (le32(sb, 0x150)? as u64) << 32;
+        free |= (le32(sb, 0x158)? as u64) << 32;
+    }
+    result.total_bytes = blocks.checked_mul(result.block_size);
+    result.free_bytes = free.checked_mul(result.block_size);
+    if result.total_bytes.is_none() || result.free_bytes.is_none() {
+        result.hints.push(SIZE_OVERFLOW_HINT.to_string());
+    }
+    result.uuid = uuid(&sb[0x68..0x78]);
+    result.label = c_string(&sb[0x78..0x88]);
+
+    if le16(sb, 0x3A)? & EXT4_STATE_CLEAN == 0 {
+        result.hints.push(
+            "Not cleanly unmounted, normal for a partition in use (e.g. userdata)".to_string(),
+        );
+    }
+    if let Some(mounted) = c_string(&sb[0x88..0xC8]) {
+        result.hints.push(format!("Last mounted on {}", mounted));
+    }
+    Some(result)
+}
+
+fn probe_erofs(head: &[u8]) -> Option<FsProbe> {
+    let sb = head.get(SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 0x80)?;
+    if le32(sb, 0x00)? != EROFS_MAGIC {
+        return None;
+    }
+
+    let mut result = FsProbe::new(FsKind::Erofs);
+    result.block_size = 1u64 << sb[0x0C].min(16);
+    // 32-bit block count and at most 64K blocks, this one can't overflow
+    result.total_bytes = Some(le32(sb, 0x24)? as u64 * result.block_size);
+    result.uuid = uuid(&sb[0x30..0x40]);
+    result.label = c_string(&sb[0x40..0x50]);
+    result
+        .hints
+        .push("Read-only filesystem, it's always as full as it needs to be".to_string());
+    Some(result)
+}
+
+fn probe_f2fs(head: &[u8]) -> Option<FsProbe> {
+    let sb = head.get(SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 0x47C)?;
+    if le32(sb, 0x00)? != F2FS_MAGIC {
+        return None;
+    }
+
+    let mut result = FsProbe::new(FsKind::F2fs);
+    result.block_size = 1u64 << le32(sb, 0x10)?.min(16);
+    result.total_bytes = le64(sb, 0x24)?.checked_mul(result.block_size);
+    if result.total_bytes.is_none() {
+        result.hints.push(SIZE_OVERFLOW_HINT.to_string());
+    }
+    result.uuid = uuid(&sb[0x6C..0x7C]);
+    // UTF-16LE, up to 512 characters
+    let name: Vec<u16> = sb[0x7C..0x47C]
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    result.label = Some(String::from_utf16_lossy(&name)).filter(|name| !name.is_empty());
+    Some(result)
+}
+
+fn le16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn le32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn le64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn c_string(data: &[u8]) -> Option<String> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let s = String::from_utf8_lossy(&data[..end]).trim().to_string();
+    (!s.is_empty()).then_some(s)
+}
+
+fn uuid(data: &[u8]) -> Option<String> {
+    if data.iter().all(|&b| b == 0) {
+        return None;
+    }
+    let hex = hex::encode(data);
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SB: usize = SUPERBLOCK_OFFSET;
+
+    fn put32(data: &mut [u8], at: usize, value: u32) {
+        data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn ext4(blocks: u64, free: u64) -> Vec<u8> {
+        let mut head = vec![0u8; PROBE_SIZE];
+        // 4K blocks
+        put32(&mut head, SB + 0x18, 2);
+        put32(&mut head, SB + 0x04, blocks as u32);
+        put32(&mut head, SB + 0x0C, free as u32);
+        head[SB + 0x38..SB + 0x3A].copy_from_slice(&EXT4_MAGIC.to_le_bytes());
+        head[SB + 0x3A..SB + 0x3C].copy_from_slice(&EXT4_STATE_CLEAN.to_le_bytes());
+        if blocks > u32::MAX as u64 || free > u32::MAX as u64 {
+            put32(&mut head, SB + 0x60, EXT4_FEATURE_INCOMPAT_64BIT);
+            put32(&mut head, SB + 0x150, (blocks >> 32) as u32);
+            put32(&mut head, SB + 0x158, (free >> 32) as u32);
+        }
+        head[SB + 0x68..SB + 0x78].fill(0x11);
+        head[SB + 0x78..SB + 0x7E].copy_from_slice(b"system");
+        head
+    }
+
+    #[test]
+    fn ext4_sizes() {
+        let result = probe(&ext4(0x1000, 0x400), Some(0x100_0000));
+        assert_eq!(result.kind, FsKind::Ext4);
+        assert_eq!(result.block_size, 4096);
+        assert_eq!(result.total_bytes, Some(0x100_0000));
+        assert_eq!(result.free_bytes, Some(0x40_0000));
+        assert_eq!(result.label.as_deref(), Some("system"));
+        assert_eq!(
+            result.uuid.as_deref(),
+            Some("11111111-1111-1111-1111-111111111111")
+        );
+        assert_eq!(result.hints, ["75% used"]);
+    }
+
+    #[test]
+    fn ext4_truncated_dump() {
+        let result = probe(&ext4(0x1000, 0x400), Some(0x1000));
+        assert!(result.hints[0].contains("looks truncated"));
+    }
+
+    #[test]
+    fn ext4_size_overflow() {
+        // 64-bit block counts near the top, times 4K
+        let result = probe(&ext4(u64::MAX - 1, u64::MAX / 2), None);
+        assert_eq!(result.kind, FsKind::Ext4);
+        assert_eq!(result.total_bytes, None);
+        assert_eq!(result.free_bytes, None);
+        assert_eq!(result.hints, [SIZE_OVERFLOW_HINT]);
+
+        // Fits, but a percentage in u64 wouldn't
+        let result = probe(&ext4(u64::MAX >> 12, 0), None);
+        assert_eq!(result.total_bytes, Some((u64::MAX >> 12) << 12));
+        assert_eq!(result.hints, ["100% used"]);
+    }
+
+    #[test]
+    fn erofs_sizes() {
+        let mut head = vec![0u8; PROBE_SIZE];
+        put32(&mut head, SB, EROFS_MAGIC);
+        head[SB + 0x0C] = 12;
+        put32(&mut head, SB + 0x24, 0x800);
+        head[SB + 0x40..SB + 0x46].copy_from_slice(b"vendor");
+
+        let result = probe(&head, None);
+        assert_eq!(result.kind, FsKind::Erofs);
+        assert_eq!(result.block_size, 4096);
+        assert_eq!(result.total_bytes, Some(0x80_0000));
+        assert_eq!(result.label.as_deref(), Some("vendor"));
+        assert_eq!(result.uuid, None);
+    }
+
+    #[test]
+    fn f2fs_sizes() {
+        let mut head = vec![0u8; PROBE_SIZE];
+        put32(&mut head, SB, F2FS_MAGIC);
+        put32(&mut head, SB + 0x10, 12);
+        head[SB + 0x24..SB + 0x2C].copy_from_slice(&0x10_0000u64.to_le_bytes());
+        for (i, c) in "data".encode_utf16().enumerate() {
+            head[SB + 0x7C + i * 2..SB + 0x7E + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        let result = probe(&head, None);
+        assert_eq!(result.kind, FsKind::F2fs);
+        assert_eq!(result.total_bytes, Some(0x1_0000_0000));
+        assert_eq!(result.label.as_deref(), Some("data"));
+
+        head[SB + 0x24..SB + 0x2C].copy_from_slice(&u64::MAX.to_le_bytes());
+        let result = probe(&head, None);
+        assert_eq!(result.total_bytes, None);
+        assert_eq!(result.hints, [SIZE_OVERFLOW_HINT]);
+    }
+
+    #[test]
+    fn sparse_and_blank() {
+        let mut head = vec![0u8; PROBE_SIZE];
+        assert_eq!(probe(&head, None).kind, FsKind::Blank);
+        assert_eq!(probe(&vec![0xFF; PROBE_SIZE], None).kind, FsKind::Blank);
+
+        put32(&mut head, 0, SPARSE_MAGIC);
+        assert_eq!(probe(&head, None).kind, FsKind::AndroidSparse);
+    }
+
+    #[test]
+    fn truncated_or_garbage() {
+        // Magic there, but the superblock is cut off right after it
+        let head = ext4(0x1000, 0x400);
+        let result = probe(&head[..SB + 0x3A], None);
+        assert_eq!(result.kind, FsKind::Unknown);
+        assert_eq!(probe(&[], None).kind, FsKind::Blank);
+
+        let garbage: Vec<u8> = (0..PROBE_SIZE).map(|i| (i * 7 + 3) as u8).collect();
+        let result = probe(&garbage, None);
+        assert_eq!(result.kind, FsKind::Unknown);
+        assert_eq!(result.total_bytes, None);
+    }
+}
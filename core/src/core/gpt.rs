@@ -0,0 +1,609 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::checksums::crc32;
+use crate::core::storage::Partition;
+use std::fmt;
+
+pub const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+// Header fields are only defined up to here, the rest of the sector is reserved
+const GPT_HEADER_MIN_SIZE: usize = 92;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GptCopy {
+    Primary,
+    Backup,
+}
+
+impl fmt::Display for GptCopy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GptCopy::Primary => f.write_str("primary"),
+            GptCopy::Backup => f.write_str("backup"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptHeader {
+    pub header_size: u32,
+    pub header_crc: u32,
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: [u8; 16],
+    pub entries_lba: u64,
+    pub num_entries: u32,
+    pub entry_size: u32,
+    pub entries_crc: u32,
+    // CRC of the header as found on disk, with the CRC field zeroed
+    computed_crc: Option<u32>,
+}
+
+impl GptHeader {
+    // `sector` is the whole sector holding the header
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        if sector.len() < GPT_HEADER_MIN_SIZE || &sector[0..8] != GPT_SIGNATURE {
+            return None;
+        }
+
+        let le32 = |at: usize| u32::from_le_bytes(sector[at..at + 4].try_into().unwrap());
+        let le64 = |at: usize| u64::from_le_bytes(sector[at..at + 8].try_into().unwrap());
+
+        let header_size = le32(12);
+        // A header_size too small to even hold the CRC field gets no CRC, see
+        // InvalidHeaderSize
+        let computed_crc = if header_size as usize >= GPT_HEADER_MIN_SIZE {
+            sector.get(..header_size as usize).map(|hdr| {
+                let mut hdr = hdr.to_vec();
+                hdr[16..20].fill(0);
+                crc32(&hdr)
+            })
+        } else {
+            None
+        };
+
+        Some(Self {
+            header_size,
+            header_crc: le32(16),
+            my_lba: le64(24),
+            alternate_lba: le64(32),
+            first_usable_lba: le64(40),
+            last_usable_lba: le64(48),
+            disk_guid: sector[56..72].try_into().unwrap(),
+            entries_lba: le64(72),
+            num_entries: le32(80),
+            entry_size: le32(84),
+            entries_crc: le32(88),
+            computed_crc,
+        })
+    }
+
+    // Size in bytes of the partition entry array this header describes
+    pub fn entries_len(&self) -> usize {
+        self.num_entries as usize * self.entry_size as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GptIssue {
+    MissingHeader(GptCopy),
+    InvalidHeaderSize {
+        copy: GptCopy,
+        size: u32,
+    },
+    HeaderCrcMismatch {
+        copy: GptCopy,
+        stored: u32,
+        computed: u32,
+    },
+    EntriesTruncated {
+        copy: GptCopy,
+    },
+    EntriesCrcMismatch {
+        copy: GptCopy,
+        stored: u32,
+        computed: u32,
+    },
+    // A header field that should match between the two copies doesn't
+    BackupMismatch {
+        field: &'static str,
+    },
+    EntriesDiffer,
+    InvalidRange {
+        name: String,
+    },
+    OutOfBounds {
+        name: String,
+    },
+    Overlap {
+        first: String,
+        second: String,
+    },
+    // Ends past the user area the DA reports, the table was made for a bigger
+    // chip (or another device altogether)
+    BeyondStorage {
+        name: String,
+        end: u64,
+        storage: u64,
+    },
+    // The backup GPT isn't in the last sector, so the storage is bigger than
+    // the table was made for
+    BackupNotAtEnd {
+        backup_end: u64,
+        storage: u64,
+    },
+}
+
+impl fmt::Display for GptIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GptIssue::MissingHeader(copy) => write!(f, "No {} GPT header found", copy),
+            GptIssue::InvalidHeaderSize { copy, size } => {
+                write!(f, "The {} GPT header has an invalid size ({})", copy, size)
+            }
+            GptIssue::HeaderCrcMismatch {
+                copy,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "The {} GPT header CRC is wrong (stored {:08X}, computed {:08X})",
+                copy, stored, computed
+            ),
+            GptIssue::EntriesTruncated { copy } => {
+                write!(f, "The {} GPT partition entries could not be read", copy)
+            }
+            GptIssue::EntriesCrcMismatch {
+                copy,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "The {} GPT partition entries CRC is wrong (stored {:08X}, computed {:08X})",
+                copy, stored, computed
+            ),
+            GptIssue::BackupMismatch { field } => {
+                write!(f, "Primary and backup GPT disagree on {}", field)
+            }
+            GptIssue::EntriesDiffer => {
+                f.write_str("Primary and backup GPT have different partition entries")
+            }
+            GptIssue::InvalidRange { name } => {
+                write!(f, "Partition '{}' ends before it starts", name)
+            }
+            GptIssue::OutOfBounds { name } => {
+                write!(f, "Partition '{}' is outside of the usable area", name)
+            }
+            GptIssue::Overlap { first, second } => {
+                write!(f, "Partitions '{}' and '{}' overlap", first, second)
+            }
+            GptIssue::BeyondStorage { name, end, storage } => write!(
+                f,
+                "Partition '{}' ends at {:#X}, past the end of the storage ({:#X})",
+                name, end, storage
+            ),
+            GptIssue::BackupNotAtEnd {
+                backup_end,
+                storage,
+            } => write!(
+                f,
+                "The backup GPT ends at {:#X}, but the storage goes on to {:#X}",
+                backup_end, storage
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GptReport {
+    pub primary: Option<GptHeader>,
+    pub backup: Option<GptHeader>,
+    pub issues: Vec<GptIssue>,
+}
+
+impl GptReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+// Raw data of one GPT copy: the sector holding the header and the entry array
+// it points to. `entries` may be empty if the header couldn't be located.
+pub struct GptData<'a> {
+    pub header: &'a [u8],
+    pub entries: &'a [u8],
+}
+
+fn check_copy(copy: GptCopy, data: &GptData, issues: &mut Vec<GptIssue>) -> Option<GptHeader> {
+    let Some(header) = GptHeader::parse(data.header) else {
+        issues.push(GptIssue::MissingHeader(copy));
+        return None;
+    };
+
+    match header.computed_crc {
+        None => issues.push(GptIssue::InvalidHeaderSize {
+            copy,
+            size: header.header_size,
+        }),
+        Some(computed) if computed != header.header_crc => {
+            issues.push(GptIssue::HeaderCrcMismatch {
+                copy,
+                stored: header.header_crc,
+                computed,
+            })
+        }
+        _ => {}
+    }
+
+    match data.entries.get(..header.entries_len()) {
+        Some(entries) => {
+            let computed = crc32(entries);
+            if computed != header.entries_crc {
+                issues.push(GptIssue::EntriesCrcMismatch {
+                    copy,
+                    stored: header.entries_crc,
+                    computed,
+                });
+            }
+        }
+        None => issues.push(GptIssue::EntriesTruncated { copy }),
+    }
+
+    Some(header)
+}
+
+struct Extent {
+    name: String,
+    first: u64,
+    last: u64,
+}
+
+fn extents(header: &GptHeader, entries: &[u8]) -> Vec<Extent> {
+    let entry_size = header.entry_size as usize;
+    if entry_size < 128 {
+        return Vec::new();
+    }
+
+    entries
+        .chunks_exact(entry_size)
+        .take(header.num_entries as usize)
+        .filter(|entry| entry[0..16].iter().any(|&b| b != 0))
+        .map(|entry| Extent {
+            name: String::from_utf16_lossy(
+                &entry[56..128]
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect::<Vec<u16>>(),
+            ),
+            first: u64::from_le_bytes(entry[32..40].try_into().unwrap()),
+            last: u64::from_le_bytes(entry[40..48].try_into().unwrap()),
+        })
+        .collect()
+}
+
+// Validates both GPT copies: header and entry CRCs, that the backup mirrors the
+// primary, and that partitions stay in the usable area without overlapping.
+pub fn check_gpt(primary: &GptData, backup: Option<&GptData>) -> GptReport {
+    let mut issues = Vec::new();
+
+    let primary_hdr = check_copy(GptCopy::Primary, primary, &mut issues);
+    let backup_hdr = match backup {
+        Some(backup) => check_copy(GptCopy::Backup, backup, &mut issues),
+        None => {
+            issues.push(GptIssue::MissingHeader(GptCopy::Backup));
+            None
+        }
+    };
+
+    if let (Some(p), Some(b), Some(backup)) = (&primary_hdr, &backup_hdr, backup) {
+        let mismatches = [
+            (
+                "the header location",
+                p.my_lba != b.alternate_lba || p.alternate_lba != b.my_lba,
+            ),
+            ("the usable area", {
+                p.first_usable_lba != b.first_usable_lba || p.last_usable_lba != b.last_usable_lba
+            }),
+            ("the disk GUID", p.disk_guid != b.disk_guid),
+            ("the entry count", p.num_entries != b.num_entries),
+            ("the entry size", p.entry_size != b.entry_size),
+        ];
+        issues.extend(
+            mismatches
+                .into_iter()
+                .filter(|&(_, differs)| differs)
+                .map(|(field, _)| GptIssue::BackupMismatch { field }),
+        );
+
+        let len = p.entries_len();
+        if p.entries_len() == b.entries_len()
+            && let (Some(pe), Some(be)) = (primary.entries.get(..len), backup.entries.get(..len))
+            && pe != be
+        {
+            issues.push(GptIssue::EntriesDiffer);
+        }
+    }
+
+    if let Some(header) = &primary_hdr {
+        let mut parts = extents(header, primary.entries);
+
+        for part in &parts {
+            if part.last < part.first {
+                issues.push(GptIssue::InvalidRange {
+                    name: part.name.clone(),
+                });
+            } else if part.first < header.first_usable_lba || part.last > header.last_usable_lba {
+                issues.push(GptIssue::OutOfBounds {
+                    name: part.name.clone(),
+                });
+            }
+        }
+
+        parts.retain(|part| part.last >= part.first);
+        parts.sort_by_key(|part| part.first);
+        for pair in parts.windows(2) {
+            if pair[1].first <= pair[0].last {
+                issues.push(GptIssue::Overlap {
+                    first: pair[0].name.clone(),
+                    second: pair[1].name.clone(),
+                });
+            }
+        }
+    }
+
+    GptReport {
+        primary: primary_hdr,
+        backup: backup_hdr,
+        issues,
+    }
+}
+
+// Checks a parsed table against the user area size the DA reports (see
+// DAProtocol::storage_view). `backup` is the alternate LBA and sector size
+// from the primary header, when known.
+pub fn check_against_storage(
+    partitions: &[Partition],
+    backup: Option<(u64, u64)>,
+    storage: u64,
+) -> Vec<GptIssue> {
+    let mut issues: Vec<GptIssue> = partitions
+        .iter()
+        .filter_map(|p| {
+            let end = p.address.saturating_add(p.size);
+            (end > storage).then(|| GptIssue::BeyondStorage {
+                name: p.name.clone(),
+                end,
+                storage,
+            })
+        })
+        .collect();
+
+    if let Some((alternate_lba, sector_size)) = backup {
+        let backup_end = alternate_lba.saturating_add(1).saturating_mul(sector_size);
+        if backup_end > storage {
+            issues.push(GptIssue::BeyondStorage {
+                name: "backup GPT".to_string(),
+                end: backup_end,
+                storage,
+            });
+        } else if backup_end < storage {
+            issues.push(GptIssue::BackupNotAtEnd {
+                backup_end,
+                storage,
+            });
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUM_ENTRIES: usize = 4;
+    const LAST_LBA: u64 = 0x1FFF;
+
+    fn entries(parts: &[(&str, u64, u64)]) -> Vec<u8> {
+        let mut data = vec![0u8; NUM_ENTRIES * 128];
+        for (i, &(name, first, last)) in parts.iter().enumerate() {
+            let entry = &mut data[i * 128..(i + 1) * 128];
+            entry[0..16].fill(0xAA);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (j, c) in name.encode_utf16().enumerate() {
+                entry[56 + j * 2..58 + j * 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    // Header CRC over the first 92 bytes, with the CRC field zeroed
+    fn seal(header: &mut [u8]) {
+        header[16..20].fill(0);
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn header(my_lba: u64, alternate_lba: u64, entries_lba: u64, entries: &[u8]) -> Vec<u8> {
+        let mut hdr = vec![0u8; 512];
+        hdr[0..8].copy_from_slice(GPT_SIGNATURE);
+        hdr[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        hdr[12..16].copy_from_slice(&92u32.to_le_bytes());
+        hdr[24..32].copy_from_slice(&my_lba.to_le_bytes());
+        hdr[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        hdr[40..48].copy_from_slice(&34u64.to_le_bytes());
+        hdr[48..56].copy_from_slice(&(LAST_LBA - 33).to_le_bytes());
+        hdr[56..72].fill(0x42);
+        hdr[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        hdr[80..84].copy_from_slice(&(NUM_ENTRIES as u32).to_le_bytes());
+        hdr[84..88].copy_from_slice(&128u32.to_le_bytes());
+        hdr[88..92].copy_from_slice(&crc32(entries).to_le_bytes());
+        seal(&mut hdr);
+        hdr
+    }
+
+    // Primary and backup header over the same entries
+    fn table(parts: &[(&str, u64, u64)]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let entries = entries(parts);
+        let primary = header(1, LAST_LBA, 2, &entries);
+        let backup = header(LAST_LBA, 1, LAST_LBA - 32, &entries);
+        (primary, backup, entries)
+    }
+
+    fn check(primary: &[u8], backup: &[u8], entries: &[u8]) -> GptReport {
+        check_gpt(
+            &GptData {
+                header: primary,
+                entries,
+            },
+            Some(&GptData {
+                header: backup,
+                entries,
+            }),
+        )
+    }
+
+    const PARTS: &[(&str, u64, u64)] = &[("boot", 0x40, 0x7F), ("userdata", 0x80, 0x1000)];
+
+    #[test]
+    fn clean_table() {
+        let (primary, backup, entries) = table(PARTS);
+        let report = check(&primary, &backup, &entries);
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.primary.unwrap().alternate_lba, LAST_LBA);
+    }
+
+    #[test]
+    fn missing_headers() {
+        let report = check_gpt(
+            &GptData {
+                header: &[],
+                entries: &[],
+            },
+            None,
+        );
+        assert_eq!(
+            report.issues,
+            [
+                GptIssue::MissingHeader(GptCopy::Primary),
+                GptIssue::MissingHeader(GptCopy::Backup)
+            ]
+        );
+    }
+
+    #[test]
+    fn header_crc_mismatch() {
+        let (mut primary, backup, entries) = table(PARTS);
+        // Flip a byte in the reserved part the CRC covers, nothing else changes
+        primary[20] ^= 0xFF;
+        let report = check(&primary, &backup, &entries);
+        assert!(matches!(
+            report.issues.as_slice(),
+            [GptIssue::HeaderCrcMismatch {
+                copy: GptCopy::Primary,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn invalid_header_size() {
+        let (mut primary, backup, entries) = table(PARTS);
+        primary[12..16].copy_from_slice(&16u32.to_le_bytes());
+        let report = check(&primary, &backup, &entries);
+        assert_eq!(
+            report.issues,
+            [GptIssue::InvalidHeaderSize {
+                copy: GptCopy::Primary,
+                size: 16
+            }]
+        );
+    }
+
+    #[test]
+    fn entries_crc_mismatch() {
+        let (primary, backup, mut entries) = table(PARTS);
+        entries[32] ^= 0x01;
+        let report = check(&primary, &backup, &entries);
+        assert!(report.issues.iter().any(|issue| matches!(
+            issue,
+            GptIssue::EntriesCrcMismatch {
+                copy: GptCopy::Primary,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn entries_truncated() {
+        let (primary, backup, entries) = table(PARTS);
+        let report = check_gpt(
+            &GptData {
+                header: &primary,
+                entries: &entries[..128],
+            },
+            Some(&GptData {
+                header: &backup,
+                entries: &entries,
+            }),
+        );
+        assert!(report.issues.contains(&GptIssue::EntriesTruncated {
+            copy: GptCopy::Primary
+        }));
+    }
+
+    #[test]
+    fn overlap_and_range() {
+        let (primary, backup, entries) = table(&[
+            ("boot", 0x40, 0x7F),
+            ("vendor", 0x70, 0x90),
+            ("broken", 0x200, 0x100),
+            ("outside", 0x10, 0x20),
+        ]);
+        let issues = check(&primary, &backup, &entries).issues;
+        assert!(issues.contains(&GptIssue::Overlap {
+            first: "boot".to_string(),
+            second: "vendor".to_string()
+        }));
+        assert!(issues.contains(&GptIssue::InvalidRange {
+            name: "broken".to_string()
+        }));
+        assert!(issues.contains(&GptIssue::OutOfBounds {
+            name: "outside".to_string()
+        }));
+    }
+
+    #[test]
+    fn backup_mismatch() {
+        let (primary, mut backup, entries) = table(PARTS);
+        // Another disk's GUID, CRC fixed up so only the mismatch shows
+        backup[56..72].fill(0x43);
+        seal(&mut backup);
+        let report = check(&primary, &backup, &entries);
+        assert_eq!(
+            report.issues,
+            [GptIssue::BackupMismatch {
+                field: "the disk GUID"
+            }]
+        );
+
+        // Same header, other entries
+        let (primary, backup, entries) = table(PARTS);
+        let other = self::entries(&[("boot", 0x40, 0x7F)]);
+        let report = check_gpt(
+            &GptData {
+                header: &primary,
+                entries: &entries,
+            },
+            Some(&GptData {
+                header: &backup,
+                entries: &other,
+            }),
+        );
+        assert!(report.issues.contains(&GptIssue::EntriesDiffer));
+    }
+}
@@ -0,0 +1,94 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::chip::ChipIdentity;
+use crate::core::device::DeviceInfo;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// Who a device is, as far as the BootROM tells us. Captured when a job is
+// queued and checked again before it runs, so swapping phones on a port
+// between the two doesn't get the wrong one flashed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceIdentity {
+    pub soc_id: Vec<u8>,
+    pub meid: Vec<u8>,
+}
+
+impl DeviceIdentity {
+    pub fn of(info: &DeviceInfo) -> Self {
+        Self::of_chip(&info.chip)
+    }
+
+    pub fn of_chip(chip: &ChipIdentity) -> Self {
+        Self {
+            soc_id: chip.soc_id.clone(),
+            meid: chip.meid.clone(),
+        }
+    }
+
+    // Attaching to a running DA skips the BROM, so there's nothing to go on
+    pub fn is_known(&self) -> bool {
+        !self.soc_id.is_empty() || !self.meid.is_empty()
+    }
+
+    // Every ID both sides have has to agree, and there has to be at least one
+    pub fn matches(&self, other: &DeviceIdentity) -> bool {
+        let same = |a: &[u8], b: &[u8]| a.is_empty() || b.is_empty() || a == b;
+        let comparable = (!self.soc_id.is_empty() && !other.soc_id.is_empty())
+            || (!self.meid.is_empty() && !other.meid.is_empty());
+        comparable && same(&self.soc_id, &other.soc_id) && same(&self.meid, &other.meid)
+    }
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_known() {
+            return f.write_str("unknown device");
+        }
+        // Enough to tell devices apart in a log line
+        let short = |id: &[u8]| match id {
+            [] => "-".to_string(),
+            _ => hex::encode(&id[..id.len().min(8)]),
+        };
+        write!(
+            f,
+            "SoC {} / MEID {}",
+            short(&self.soc_id),
+            short(&self.meid)
+        )
+    }
+}
+
+// The device on the port isn't the one the operation was queued for.
+// Wrapped in an io::Error, use `IdentityMismatch::from_error` to get it back.
+#[derive(Debug, Clone)]
+pub struct IdentityMismatch {
+    pub expected: DeviceIdentity,
+    pub found: DeviceIdentity,
+}
+
+impl IdentityMismatch {
+    pub fn from_error(err: &Error) -> Option<&IdentityMismatch> {
+        err.get_ref()?.downcast_ref::<IdentityMismatch>()
+    }
+}
+
+impl fmt::Display for IdentityMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected {}, but found {}. Refusing to run on another device",
+            self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for IdentityMismatch {}
+
+impl From<IdentityMismatch> for Error {
+    fn from(err: IdentityMismatch) -> Self {
+        Error::new(ErrorKind::PermissionDenied, err)
+    }
+}
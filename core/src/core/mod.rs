@@ -2,8 +2,36 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
+pub mod abslot;
+pub mod audit;
+pub mod autobackup;
+pub mod benchmark;
+pub mod checksums;
+pub mod chip;
+pub mod crashlog;
 pub mod crypto;
 pub mod device;
+pub mod dump;
+pub mod events;
+pub mod farm;
+pub mod fastboot;
+pub mod fileio;
+pub mod flashall;
+pub mod fsprobe;
+pub mod gpt;
+pub mod identity;
+pub mod operation;
+pub mod pipeline;
+pub mod policy;
+pub mod power;
+pub mod preflight;
+pub mod profile;
+pub mod ptable;
+pub mod recovery;
+pub mod report;
+pub mod reset;
 pub mod seccfg;
 pub mod storage;
+pub mod throttle;
 pub mod utilities;
+pub mod vbmeta;
@@ -0,0 +1,32 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::latency::LatencyReport;
+use crate::connection::stats::ConnectionStats;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Passed to the operation hook once a top level Device operation (read, write,
+// dump, restore, lock state change...) is over, successful or not.
+#[derive(Clone, Debug)]
+pub struct OperationSummary {
+    pub name: String,
+    pub duration: Duration,
+    pub bytes: u64,
+    // None when the operation doesn't verify what it did
+    pub verified: Option<bool>,
+    pub error: Option<String>,
+    // Traffic on the port during the operation
+    pub link: ConnectionStats,
+    // Protocol call timings, only with Device::set_latency_tracking on
+    pub latency: Option<LatencyReport>,
+}
+
+impl OperationSummary {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+pub type OperationHook = Arc<dyn Fn(&OperationSummary) + Send + Sync>;
@@ -0,0 +1,481 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::checksums::Sha256Stream;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const ZSTD_MAGIC: &[u8; 4] = b"\x28\xB5\x2F\xFD";
+const ZSTD_LEVEL: i32 = 3;
+
+const SPARSE_MAGIC: u32 = 0xED26_FF3A;
+const SPARSE_HEADER_LEN: usize = 28;
+const SPARSE_CHUNK_HEADER_LEN: usize = 12;
+const CHUNK_TYPE_RAW: u16 = 0xCAC1;
+const CHUNK_TYPE_FILL: u16 = 0xCAC2;
+const CHUNK_TYPE_DONT_CARE: u16 = 0xCAC3;
+const CHUNK_TYPE_CRC32: u16 = 0xCAC4;
+
+pub type Source<'a> = Box<dyn Read + Send + 'a>;
+pub type Sink<'a> = Box<dyn FinishWrite + Send + 'a>;
+
+// A Write that has to be told when the stream is over, e.g. to write the zstd
+// epilogue. Errors there would be lost if it was left to Drop.
+pub trait FinishWrite: Write {
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+impl FinishWrite for File {
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.sync_all()
+    }
+}
+
+// One layer of a Pipeline. `decode` goes from the image on disk towards what ends
+// up on the device (decompressing, unsparsing, decrypting...), `encode` goes the
+// other way when dumping. Not everything can go both ways, encode() defaults to
+// Unsupported.
+pub trait Transform: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn decode<'a>(&self, inner: Source<'a>) -> Result<Source<'a>>;
+
+    fn encode<'a>(&self, _inner: Sink<'a>) -> Result<Sink<'a>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} can only be used when writing to the device",
+                self.name()
+            ),
+        ))
+    }
+}
+
+// Layers of transforms between an image file and the device. The first transform
+// is the one closest to the file, so the same pipeline reads back what it wrote:
+//
+//   Pipeline::new().then(Zstd).then(Sha256Tap::new())
+//
+// decompresses an image and hashes what goes to the device, or hashes a dump
+// and compresses it on the way to disk.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    transforms: Vec<Arc<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, transform: impl Transform + 'static) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    pub fn push(&mut self, transform: Arc<dyn Transform>) {
+        self.transforms.push(transform);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.transforms.iter().map(|t| t.name()).collect()
+    }
+
+    // Picks the transforms needed to turn an image into raw partition data,
+    // from its first bytes. Plain images get an empty pipeline.
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(ZSTD_MAGIC) {
+            Pipeline::new().then(Zstd)
+        } else if head.len() >= 4
+            && u32::from_le_bytes(head[0..4].try_into().unwrap()) == SPARSE_MAGIC
+        {
+            Pipeline::new().then(Sparse)
+        } else {
+            Pipeline::new()
+        }
+    }
+
+    pub fn for_file(path: &Path) -> Result<Self> {
+        let mut head = Vec::with_capacity(4);
+        File::open(path)?.take(4).read_to_end(&mut head)?;
+        Ok(Self::detect(&head))
+    }
+
+    pub fn reader<'a>(&self, source: impl Read + Send + 'a) -> Result<Source<'a>> {
+        self.transforms
+            .iter()
+            .try_fold(Box::new(source) as Source<'a>, |inner, t| t.decode(inner))
+    }
+
+    pub fn writer<'a>(&self, sink: impl FinishWrite + Send + 'a) -> Result<Sink<'a>> {
+        self.transforms
+            .iter()
+            .try_fold(Box::new(sink) as Sink<'a>, |inner, t| t.encode(inner))
+    }
+}
+
+pub struct Zstd;
+
+impl Transform for Zstd {
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn decode<'a>(&self, inner: Source<'a>) -> Result<Source<'a>> {
+        Ok(Box::new(zstd::Decoder::new(inner)?))
+    }
+
+    fn encode<'a>(&self, inner: Sink<'a>) -> Result<Sink<'a>> {
+        Ok(Box::new(ZstdSink(zstd::Encoder::new(inner, ZSTD_LEVEL)?)))
+    }
+}
+
+struct ZstdSink<'a>(zstd::Encoder<'static, Sink<'a>>);
+
+impl Write for ZstdSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush()
+    }
+}
+
+impl FinishWrite for ZstdSink<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.0.finish()?.finish()
+    }
+}
+
+// Android sparse images (what fastboot and most firmware packages use), expanded
+// to the raw partition contents. "Don't care" chunks come out as zeros.
+pub struct Sparse;
+
+impl Transform for Sparse {
+    fn name(&self) -> &str {
+        "sparse"
+    }
+
+    fn decode<'a>(&self, inner: Source<'a>) -> Result<Source<'a>> {
+        Ok(Box::new(SparseReader::new(inner)?))
+    }
+}
+
+enum SparseChunk {
+    Raw(u64),
+    Fill([u8; 4], u64, u64),
+    Zero(u64),
+}
+
+struct SparseReader<'a> {
+    inner: Source<'a>,
+    block_size: u64,
+    chunk_header_extra: u64,
+    chunks_left: u32,
+    chunk: SparseChunk,
+}
+
+impl<'a> SparseReader<'a> {
+    fn new(mut inner: Source<'a>) -> Result<Self> {
+        let mut header = [0u8; SPARSE_HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        let le16 = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+        let le32 = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+
+        if le32(0) != SPARSE_MAGIC {
+            return Err(invalid("Not a sparse image".to_string()));
+        }
+        if le16(4) != 1 {
+            return Err(invalid(format!("Unsupported sparse version {}", le16(4))));
+        }
+        let (file_header_len, chunk_header_len) = (le16(8) as usize, le16(10) as usize);
+        if file_header_len < SPARSE_HEADER_LEN || chunk_header_len < SPARSE_CHUNK_HEADER_LEN {
+            return Err(invalid("Sparse header sizes are too small".to_string()));
+        }
+        skip(&mut inner, (file_header_len - SPARSE_HEADER_LEN) as u64)?;
+
+        Ok(Self {
+            inner,
+            block_size: le32(12) as u64,
+            chunk_header_extra: (chunk_header_len - SPARSE_CHUNK_HEADER_LEN) as u64,
+            chunks_left: le32(20),
+            chunk: SparseChunk::Zero(0),
+        })
+    }
+
+    // Returns false once every chunk has been read
+    fn next_chunk(&mut self) -> Result<bool> {
+        loop {
+            if self.chunks_left == 0 {
+                return Ok(false);
+            }
+            self.chunks_left -= 1;
+
+            let mut header = [0u8; SPARSE_CHUNK_HEADER_LEN];
+            self.inner.read_exact(&mut header)?;
+            skip(&mut self.inner, self.chunk_header_extra)?;
+            let kind = u16::from_le_bytes([header[0], header[1]]);
+            let blocks = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+            let total = u32::from_le_bytes(header[8..12].try_into().unwrap()) as u64;
+            let len = blocks * self.block_size;
+
+            self.chunk = match kind {
+                CHUNK_TYPE_RAW => SparseChunk::Raw(len),
+                CHUNK_TYPE_FILL => {
+                    let mut pattern = [0u8; 4];
+                    self.inner.read_exact(&mut pattern)?;
+                    SparseChunk::Fill(pattern, len, 0)
+                }
+                CHUNK_TYPE_DONT_CARE => SparseChunk::Zero(len),
+                CHUNK_TYPE_CRC32 => {
+                    let header_len = SPARSE_CHUNK_HEADER_LEN as u64 + self.chunk_header_extra;
+                    skip(&mut self.inner, total.saturating_sub(header_len))?;
+                    continue;
+                }
+                _ => return Err(invalid(format!("Unknown sparse chunk type {:#X}", kind))),
+            };
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for SparseReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let n = match &mut self.chunk {
+                SparseChunk::Raw(left) if *left > 0 => {
+                    let want = buf.len().min(*left as usize);
+                    let n = self.inner.read(&mut buf[..want])?;
+                    if n == 0 {
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "Sparse image ends in the middle of a chunk",
+                        ));
+                    }
+                    *left -= n as u64;
+                    n
+                }
+                SparseChunk::Fill(pattern, left, pos) if *left > 0 => {
+                    let n = buf.len().min(*left as usize);
+                    for (i, b) in buf[..n].iter_mut().enumerate() {
+                        *b = pattern[((*pos as usize) + i) % 4];
+                    }
+                    *left -= n as u64;
+                    *pos += n as u64;
+                    n
+                }
+                SparseChunk::Zero(left) if *left > 0 => {
+                    let n = buf.len().min(*left as usize);
+                    buf[..n].fill(0);
+                    *left -= n as u64;
+                    n
+                }
+                _ => {
+                    if buf.is_empty() || !self.next_chunk()? {
+                        return Ok(0);
+                    }
+                    continue;
+                }
+            };
+            return Ok(n);
+        }
+    }
+}
+
+// Hashes everything going through it. The digest is available from any clone of
+// the tap once the stream has been read to the end (or the sink finished).
+#[derive(Clone, Default)]
+pub struct Sha256Tap {
+    digest: Arc<Mutex<Option<String>>>,
+}
+
+impl Sha256Tap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn digest(&self) -> Option<String> {
+        self.digest.lock().unwrap().clone()
+    }
+}
+
+impl Transform for Sha256Tap {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+
+    fn decode<'a>(&self, inner: Source<'a>) -> Result<Source<'a>> {
+        Ok(Box::new(HashReader {
+            inner,
+            hasher: Some(Sha256Stream::new()),
+            digest: self.digest.clone(),
+        }))
+    }
+
+    fn encode<'a>(&self, inner: Sink<'a>) -> Result<Sink<'a>> {
+        Ok(Box::new(HashSink {
+            inner,
+            hasher: Sha256Stream::new(),
+            digest: self.digest.clone(),
+        }))
+    }
+}
+
+struct HashReader<'a> {
+    inner: Source<'a>,
+    hasher: Option<Sha256Stream>,
+    digest: Arc<Mutex<Option<String>>>,
+}
+
+impl Read for HashReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&buf[..n]);
+            }
+        } else if !buf.is_empty()
+            && let Some(hasher) = self.hasher.take()
+        {
+            *self.digest.lock().unwrap() = Some(hasher.finalize_hex());
+        }
+        Ok(n)
+    }
+}
+
+struct HashSink<'a> {
+    inner: Sink<'a>,
+    hasher: Sha256Stream,
+    digest: Arc<Mutex<Option<String>>>,
+}
+
+impl Write for HashSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl FinishWrite for HashSink<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        let this = *self;
+        this.inner.finish()?;
+        *this.digest.lock().unwrap() = Some(this.hasher.finalize_hex());
+        Ok(())
+    }
+}
+
+// XORs the stream with a keystream, for stream ciphers (AES-CTR and the like)
+// and vendor containers that boil down to one. `apply` gets the offset of the
+// buffer in the stream and transforms it in place; it's used both ways.
+pub type KeystreamFn = dyn Fn(u64, &mut [u8]) + Send + Sync;
+
+#[derive(Clone)]
+pub struct Keystream {
+    name: String,
+    apply: Arc<KeystreamFn>,
+}
+
+impl Keystream {
+    pub fn new(name: &str, apply: impl Fn(u64, &mut [u8]) + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.to_string(),
+            apply: Arc::new(apply),
+        }
+    }
+}
+
+impl Transform for Keystream {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn decode<'a>(&self, inner: Source<'a>) -> Result<Source<'a>> {
+        Ok(Box::new(KeystreamReader {
+            inner,
+            apply: self.apply.clone(),
+            offset: 0,
+        }))
+    }
+
+    fn encode<'a>(&self, inner: Sink<'a>) -> Result<Sink<'a>> {
+        Ok(Box::new(KeystreamSink {
+            inner,
+            apply: self.apply.clone(),
+            offset: 0,
+            scratch: Vec::new(),
+        }))
+    }
+}
+
+struct KeystreamReader<'a> {
+    inner: Source<'a>,
+    apply: Arc<KeystreamFn>,
+    offset: u64,
+}
+
+impl Read for KeystreamReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        (self.apply)(self.offset, &mut buf[..n]);
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+struct KeystreamSink<'a> {
+    inner: Sink<'a>,
+    apply: Arc<KeystreamFn>,
+    offset: u64,
+    scratch: Vec<u8>,
+}
+
+impl Write for KeystreamSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // All of it or nothing, a short write would leave the offset out of sync
+        self.scratch.clear();
+        self.scratch.extend_from_slice(buf);
+        (self.apply)(self.offset, &mut self.scratch);
+        self.inner.write_all(&self.scratch)?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl FinishWrite for KeystreamSink<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+fn skip(reader: &mut Source<'_>, len: u64) -> Result<()> {
+    let skipped = std::io::copy(&mut reader.take(len), &mut std::io::sink())?;
+    if skipped != len {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Sparse image is truncated",
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
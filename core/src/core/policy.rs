@@ -0,0 +1,187 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::flashall::{DEFAULT_PROTECTED, FormatAllOptions};
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// Looked for in the working directory and its parents, so every job run from a
+// shop's workspace picks up the same house rules
+pub const POLICY_FILE: &str = ".penumbra.conf";
+
+// Which partitions are off limits and which always get backed up before being
+// touched. Defaults to DEFAULT_PROTECTED and no forced backups, a policy file
+// changes that:
+//
+//   protected = nvram, nvdata, persist   # replaces the default list
+//   protect = proinfo_b                  # added to it
+//   unprotect = frp                      # taken off it
+//   always_backup = persist, nvram
+//   backup_dir = /srv/backups            # used when auto backups are off
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyPolicy {
+    // Skipped by format_and_download, frontends ask for extra confirmation
+    pub protected: Vec<String>,
+    // Backed up before every write or erase, even with auto backups off
+    pub always_backup: Vec<String>,
+    pub backup_dir: Option<PathBuf>,
+    // The file it came from, None for the defaults
+    pub source: Option<PathBuf>,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self {
+            protected: DEFAULT_PROTECTED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            always_backup: Vec::new(),
+            backup_dir: None,
+            source: None,
+        }
+    }
+}
+
+impl SafetyPolicy {
+    // Same `key = value` lines as the TUI settings, unknown keys are skipped so
+    // the policy can share a file with them. Keys apply in order, a line that
+    // isn't `key = value` fails the whole file rather than being guessed at.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        let names = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "line {}: expected `key = value`, got '{}'",
+                        number + 1,
+                        line
+                    ),
+                ));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "protected" => policy.protected = names(value),
+                "protect" => {
+                    for name in names(value) {
+                        if !policy.is_protected(&name) {
+                            policy.protected.push(name);
+                        }
+                    }
+                }
+                "unprotect" => {
+                    let removed = names(value);
+                    policy.protected.retain(|name| !removed.contains(name));
+                }
+                "always_backup" => policy.always_backup = names(value),
+                "backup_dir" if !value.is_empty() => policy.backup_dir = Some(value.into()),
+                _ => {}
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut policy = std::fs::read_to_string(path)
+            .and_then(|content| Self::parse(&content))
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        policy.source = Some(path.to_path_buf());
+        Ok(policy)
+    }
+
+    // $PENUMBRA_POLICY, or the closest POLICY_FILE from `dir` up
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        if let Some(path) = env::var_os("PENUMBRA_POLICY") {
+            return Some(PathBuf::from(path));
+        }
+        dir.ancestors()
+            .map(|dir| dir.join(POLICY_FILE))
+            .find(|path| path.is_file())
+    }
+
+    // The policy that applies to a job run from `dir`, the defaults when there's
+    // no policy file. One that can't be read or parsed is an error: running with
+    // the defaults could touch partitions the shop meant to protect.
+    pub fn for_workspace(dir: &Path) -> Result<Self> {
+        match Self::find(dir) {
+            Some(path) => Self::load(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn is_protected(&self, name: &str) -> bool {
+        self.protected.iter().any(|p| p == name)
+    }
+
+    pub fn needs_backup(&self, name: &str) -> bool {
+        self.always_backup.iter().any(|p| p == name)
+    }
+
+    // FormatAllOptions that skip what this policy protects
+    pub fn format_options(&self, backup_dir: Option<PathBuf>) -> FormatAllOptions {
+        FormatAllOptions {
+            protected: self.protected.clone(),
+            backup_dir,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keys_in_order() {
+        let policy = SafetyPolicy::parse(
+            "# shop rules\n\
+             protected = nvram, persist\n\
+             protect = proinfo\n\
+             unprotect = persist\n\
+             theme = dark   # a TUI setting\n\
+             always_backup = nvram\n",
+        )
+        .unwrap();
+        assert_eq!(policy.protected, ["nvram", "proinfo"]);
+        assert_eq!(policy.always_backup, ["nvram"]);
+        assert!(policy.backup_dir.is_none());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        let err = SafetyPolicy::parse("protected = nvram\nprotect nvdata\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn load_fails_closed() {
+        let dir = env::temp_dir().join(format!("penumbra-policy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(POLICY_FILE);
+
+        std::fs::write(&path, "protected nvram\n").unwrap();
+        assert!(SafetyPolicy::load(&path).is_err());
+
+        std::fs::write(&path, [0xff, 0xfe, 0x00]).unwrap();
+        assert!(SafetyPolicy::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(SafetyPolicy::load(&path).is_err());
+    }
+}
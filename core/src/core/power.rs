@@ -0,0 +1,54 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::time::Duration;
+
+// How hard to push the device. LowPower is for boards running off USB power
+// alone (dead or missing battery), where long full speed bursts make the PMIC
+// brown out and reset the SoC halfway through a transfer.
+// The USB speed isn't part of this: we never ask the DA to switch to high
+// speed, it stays at whatever the BROM came up with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerProfile {
+    #[default]
+    Normal,
+    LowPower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerLimits {
+    // Cap on the XFlash write packet, None sends what the DA asks for
+    pub write_packet: Option<usize>,
+    // Bytes per read_flash call when dumping to a file
+    pub read_chunk: usize,
+    // Idle time after every packet or chunk, lets the supply recover
+    pub pause: Duration,
+    // Read back what was just written to check it landed (seccfg and the like)
+    pub readback: bool,
+}
+
+impl PowerProfile {
+    pub fn limits(self) -> PowerLimits {
+        match self {
+            PowerProfile::Normal => PowerLimits {
+                write_packet: None,
+                read_chunk: 0x400_0000,
+                pause: Duration::ZERO,
+                readback: true,
+            },
+            PowerProfile::LowPower => PowerLimits {
+                write_packet: Some(0x1_0000),
+                read_chunk: 0x10_0000,
+                pause: Duration::from_millis(20),
+                readback: false,
+            },
+        }
+    }
+}
+
+impl Default for PowerLimits {
+    fn default() -> Self {
+        PowerProfile::default().limits()
+    }
+}
@@ -0,0 +1,125 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::seccfg::{SecCfgV4, SecCfgV4Algo};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+// Why a lock state change was refused before anything got written.
+// Wrapped in an io::Error, use `LockPreflightError::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockPreflightError {
+    // "OEM unlocking" is off in the developer options (FRP flag)
+    OemUnlockDisabled,
+    // The seccfg hash didn't match any known algorithm, so we can't sign a new one
+    UnknownAlgo,
+    // A forced algorithm doesn't verify the hash already on the device
+    HashMismatch(SecCfgV4Algo),
+    // The image we built doesn't decrypt back to its own hash
+    RoundTripFailed(SecCfgV4Algo),
+}
+
+impl LockPreflightError {
+    pub fn from_error(err: &Error) -> Option<&LockPreflightError> {
+        err.get_ref()?.downcast_ref::<LockPreflightError>()
+    }
+}
+
+impl fmt::Display for LockPreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockPreflightError::OemUnlockDisabled => write!(
+                f,
+                "OEM unlocking is disabled on this device, enable it in the developer options first"
+            ),
+            LockPreflightError::UnknownAlgo => write!(
+                f,
+                "Could not match the seccfg hash with any known algorithm, refusing to write a new one"
+            ),
+            LockPreflightError::HashMismatch(algo) => write!(
+                f,
+                "The seccfg on the device doesn't verify with the {:?} algorithm",
+                algo
+            ),
+            LockPreflightError::RoundTripFailed(algo) => write!(
+                f,
+                "The new seccfg doesn't verify with the {:?} algorithm, not writing it",
+                algo
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LockPreflightError {}
+
+impl From<LockPreflightError> for Error {
+    fn from(err: LockPreflightError) -> Self {
+        let kind = match err {
+            LockPreflightError::OemUnlockDisabled => ErrorKind::PermissionDenied,
+            _ => ErrorKind::InvalidData,
+        };
+        Error::new(kind, err)
+    }
+}
+
+// Android keeps the "OEM unlocking" switch in the last byte of the FRP partition
+// (persistent data block), 1 when unlocking is allowed.
+// None when the partition doesn't look like it holds the flag.
+pub fn oem_unlock_allowed(frp: &[u8]) -> Option<bool> {
+    match frp.last()? {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+// The lock fields of a seccfg image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockState {
+    pub lock_state: u32,
+    pub critical_lock_state: u32,
+}
+
+impl LockState {
+    pub fn of(seccfg: &SecCfgV4) -> Self {
+        Self {
+            lock_state: seccfg.lock_state,
+            critical_lock_state: seccfg.critical_lock_state,
+        }
+    }
+
+    // 1 is what devices ship with, 3 is what SecCfgV4::create writes for unlock
+    pub fn is_unlocked(&self) -> bool {
+        self.lock_state == 3
+    }
+}
+
+impl fmt::Display for LockState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.lock_state {
+            1 => "locked",
+            3 => "unlocked",
+            _ => "unknown",
+        };
+        write!(
+            f,
+            "{} (lock_state {}, critical {})",
+            name, self.lock_state, self.critical_lock_state
+        )
+    }
+}
+
+// What a lock state change did, see Device::change_lock_state
+#[derive(Debug, Clone)]
+pub struct LockReport {
+    pub before: LockState,
+    // Read back from the device after writing, None in dry-run or when the
+    // power profile skips readbacks
+    pub after: Option<LockState>,
+    pub algo: SecCfgV4Algo,
+    // Where the original seccfg was saved, if asked to
+    pub backup: Option<PathBuf>,
+    pub new_seccfg: Vec<u8>,
+}
@@ -0,0 +1,273 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::connection::transport::{ReadTimeouts, SyncStrategy, TransportConfig};
+use crate::core::chip::ChipIdentity;
+use crate::core::power::PowerProfile;
+use crate::core::seccfg::SecCfgV4Algo;
+use crate::core::throttle::WriteThrottle;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// What worked last time on one device, so the next session with it doesn't
+// have to find out again. Saved after every session that got into DA mode.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    // Hex, the key in the profile file
+    pub soc_id: String,
+    pub hw_code: u16,
+    // The whole identity as last seen, None in profiles saved before it was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chip: Option<ChipIdentity>,
+    // Catalog loader that got the device into DA mode, None for a DA the user
+    // picked by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loader: Option<String>,
+    // "carbonara" when DA2 had to be patched, None when it ran as is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exploit: Option<String>,
+    // "sw", "hw", "hwv3" or "hwv4"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seccfg_algo: Option<String>,
+    // "normal" or "low"
+    #[serde(default)]
+    pub power_profile: String,
+    // Bytes per second, None for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_rate: Option<u64>,
+    #[serde(default)]
+    pub write_delay_ms: u64,
+    // TransportConfig, only what differs from the defaults. A slow DA1 sync or
+    // storage that takes ages to wake up won't change between sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_deadline_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_poll_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_max_stray_bytes: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_first_chunk_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_chunk_ms: Option<u64>,
+    // Seconds since the Unix epoch
+    #[serde(default)]
+    pub last_seen: u64,
+}
+
+impl DeviceProfile {
+    pub fn new(chip: &ChipIdentity) -> Self {
+        Self {
+            soc_id: chip.soc_id_hex(),
+            hw_code: chip.hw_code,
+            chip: Some(chip.clone()),
+            last_seen: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            ..Self::default()
+        }
+    }
+
+    pub fn seccfg_algo(&self) -> Option<SecCfgV4Algo> {
+        match self.seccfg_algo.as_deref()? {
+            "sw" => Some(SecCfgV4Algo::SW),
+            "hw" => Some(SecCfgV4Algo::HW),
+            "hwv3" => Some(SecCfgV4Algo::HWv3),
+            "hwv4" => Some(SecCfgV4Algo::HWv4),
+            _ => None,
+        }
+    }
+
+    pub fn set_seccfg_algo(&mut self, algo: Option<SecCfgV4Algo>) {
+        self.seccfg_algo = algo.and_then(|algo| match algo {
+            SecCfgV4Algo::SW => Some("sw".to_string()),
+            SecCfgV4Algo::HW => Some("hw".to_string()),
+            SecCfgV4Algo::HWv3 => Some("hwv3".to_string()),
+            SecCfgV4Algo::HWv4 => Some("hwv4".to_string()),
+            SecCfgV4Algo::None => None,
+        });
+    }
+
+    pub fn power_profile(&self) -> PowerProfile {
+        match self.power_profile.as_str() {
+            "low" => PowerProfile::LowPower,
+            _ => PowerProfile::Normal,
+        }
+    }
+
+    pub fn set_power_profile(&mut self, profile: PowerProfile) {
+        self.power_profile = match profile {
+            PowerProfile::Normal => "normal",
+            PowerProfile::LowPower => "low",
+        }
+        .to_string();
+    }
+
+    pub fn write_throttle(&self) -> WriteThrottle {
+        WriteThrottle {
+            rate: self.write_rate,
+            delay: Duration::from_millis(self.write_delay_ms),
+        }
+    }
+
+    pub fn set_write_throttle(&mut self, throttle: WriteThrottle) {
+        self.write_rate = throttle.rate;
+        self.write_delay_ms = throttle.delay.as_millis() as u64;
+    }
+
+    pub fn transport_config(&self) -> TransportConfig {
+        let sync = SyncStrategy::default();
+        let read = ReadTimeouts::default();
+        let ms = |value: Option<u64>, default| value.map_or(default, Duration::from_millis);
+        TransportConfig {
+            sync: SyncStrategy {
+                deadline: ms(self.sync_deadline_ms, sync.deadline),
+                poll_timeout: ms(self.sync_poll_ms, sync.poll_timeout),
+                max_stray_bytes: self.sync_max_stray_bytes.unwrap_or(sync.max_stray_bytes),
+                retries: self.sync_retries.unwrap_or(sync.retries),
+            },
+            read: ReadTimeouts {
+                first_chunk: ms(self.read_first_chunk_ms, read.first_chunk),
+                chunk: ms(self.read_chunk_ms, read.chunk),
+            },
+        }
+    }
+
+    pub fn set_transport_config(&mut self, config: &TransportConfig) {
+        let defaults = TransportConfig::default();
+        let ms = |value: Duration, default| (value != default).then_some(value.as_millis() as u64);
+        self.sync_deadline_ms = ms(config.sync.deadline, defaults.sync.deadline);
+        self.sync_poll_ms = ms(config.sync.poll_timeout, defaults.sync.poll_timeout);
+        self.sync_max_stray_bytes =
+            Some(config.sync.max_stray_bytes).filter(|&n| n != defaults.sync.max_stray_bytes);
+        self.sync_retries = Some(config.sync.retries).filter(|&n| n != defaults.sync.retries);
+        self.read_first_chunk_ms = ms(config.read.first_chunk, defaults.read.first_chunk);
+        self.read_chunk_ms = ms(config.read.chunk, defaults.read.chunk);
+    }
+}
+
+// All known profiles, one JSON file keyed by SoC ID. Small enough to be read
+// and rewritten as a whole every time.
+#[derive(Debug, Clone)]
+pub struct ProfileStore {
+    path: PathBuf,
+}
+
+impl ProfileStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    // $PENUMBRA_PROFILES, or profiles.json next to the history log
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("PENUMBRA_PROFILES") {
+            return Some(PathBuf::from(path));
+        }
+
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+        };
+
+        base.map(|dir| dir.join("penumbra").join("profiles.json"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // A missing file is just an empty store
+    pub fn load(&self) -> Result<BTreeMap<String, DeviceProfile>> {
+        let data = match std::fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn get(&self, soc_id: &[u8]) -> Result<Option<DeviceProfile>> {
+        Ok(self.load()?.remove(&hex::encode(soc_id)))
+    }
+
+    // The `limit` devices seen last, newest first
+    pub fn recent(&self, limit: usize) -> Result<Vec<DeviceProfile>> {
+        let mut profiles: Vec<DeviceProfile> = self.load()?.into_values().collect();
+        profiles.sort_by_key(|p| Reverse(p.last_seen));
+        profiles.truncate(limit);
+        Ok(profiles)
+    }
+
+    // Replaces whatever was stored for the same SoC ID
+    pub fn save(&self, profile: &DeviceProfile) -> Result<()> {
+        let mut profiles = self.load()?;
+        profiles.insert(profile.soc_id.clone(), profile.clone());
+        self.write(&profiles)
+    }
+
+    // Forget a device, e.g. after its settings stopped working
+    pub fn remove(&self, soc_id: &[u8]) -> Result<bool> {
+        let mut profiles = self.load()?;
+        if profiles.remove(&hex::encode(soc_id)).is_none() {
+            return Ok(false);
+        }
+        self.write(&profiles)?;
+        Ok(true)
+    }
+
+    fn write(&self, profiles: &BTreeMap<String, DeviceProfile>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_string_pretty(profiles)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        // Written next to it and renamed, a crash halfway can't lose every profile
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_round_trip() {
+        let mut config = TransportConfig::default();
+        config.sync.deadline = Duration::from_secs(25);
+        config.sync.retries = 6;
+        config.read.first_chunk = Duration::from_secs(90);
+
+        let mut profile = DeviceProfile::default();
+        profile.set_transport_config(&config);
+        assert_eq!(profile.sync_deadline_ms, Some(25_000));
+        assert_eq!(profile.sync_retries, Some(6));
+        assert_eq!(profile.read_first_chunk_ms, Some(90_000));
+        // Defaults aren't pinned, so they can still change later
+        assert_eq!(profile.sync_poll_ms, None);
+        assert_eq!(profile.read_chunk_ms, None);
+
+        let json = serde_json::to_string(&profile).unwrap();
+        let loaded: DeviceProfile = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.transport_config(), config);
+    }
+
+    #[test]
+    fn old_profiles_get_the_default_transport() {
+        let profile: DeviceProfile =
+            serde_json::from_str(r#"{"soc_id": "00", "hw_code": 1894}"#).unwrap();
+        assert_eq!(profile.transport_config(), TransportConfig::default());
+    }
+}
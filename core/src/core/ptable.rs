@@ -0,0 +1,369 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::storage::{EmmcPartition, Partition, PartitionKind};
+use std::fmt::Write;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+const CSV_HEADER: &str = "name,start,size,kind,slot";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTableFormat {
+    Json,
+    Csv,
+}
+
+impl PartitionTableFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(PartitionTableFormat::Json),
+            "csv" => Some(PartitionTableFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+// One row of an exported partition table. `kind` is the physical area the
+// partition lives in (e.g. "emmc-user", "ufs-lu2"), `slot` is "a"/"b" for A/B
+// partitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionEntry {
+    pub name: String,
+    pub start: u64,
+    pub size: u64,
+    pub kind: String,
+    pub slot: Option<String>,
+}
+
+impl From<&Partition> for PartitionEntry {
+    fn from(part: &Partition) -> Self {
+        let slot = part
+            .name
+            .rsplit_once('_')
+            .filter(|(_, suffix)| matches!(*suffix, "a" | "b"))
+            .map(|(_, suffix)| suffix.to_string());
+
+        Self {
+            name: part.name.clone(),
+            start: part.address,
+            size: part.size,
+            kind: kind_name(&part.kind),
+            slot,
+        }
+    }
+}
+
+fn kind_name(kind: &PartitionKind) -> String {
+    match kind {
+        PartitionKind::Emmc(part) => {
+            let name = match part {
+                EmmcPartition::Boot1 => "boot1",
+                EmmcPartition::Boot2 => "boot2",
+                EmmcPartition::Rpmb => "rpmb",
+                EmmcPartition::Gp1 => "gp1",
+                EmmcPartition::Gp2 => "gp2",
+                EmmcPartition::Gp3 => "gp3",
+                EmmcPartition::Gp4 => "gp4",
+                EmmcPartition::User => "user",
+                EmmcPartition::End => "end",
+                EmmcPartition::Boot1Boot2 => "boot1boot2",
+            };
+            format!("emmc-{}", name)
+        }
+        PartitionKind::Ufs(lu) => format!("ufs-lu{}", *lu as u32),
+        PartitionKind::Unknown => "unknown".to_string(),
+    }
+}
+
+pub fn export_partitions(partitions: &[Partition], format: PartitionTableFormat) -> String {
+    let entries: Vec<PartitionEntry> = partitions.iter().map(PartitionEntry::from).collect();
+    export_entries(&entries, format)
+}
+
+pub fn export_entries(entries: &[PartitionEntry], format: PartitionTableFormat) -> String {
+    let mut out = String::new();
+
+    match format {
+        PartitionTableFormat::Json => {
+            out.push_str("[\n");
+            for (i, e) in entries.iter().enumerate() {
+                let slot = match &e.slot {
+                    Some(slot) => json_string(slot),
+                    None => "null".to_string(),
+                };
+                let _ = write!(
+                    out,
+                    "  {{\"name\": {}, \"start\": {}, \"size\": {}, \"kind\": {}, \"slot\": {}}}",
+                    json_string(&e.name),
+                    e.start,
+                    e.size,
+                    json_string(&e.kind),
+                    slot
+                );
+                out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+            }
+            out.push_str("]\n");
+        }
+        PartitionTableFormat::Csv => {
+            out.push_str(CSV_HEADER);
+            out.push('\n');
+            for e in entries {
+                let _ = writeln!(
+                    out,
+                    "{},{:#x},{:#x},{},{}",
+                    e.name,
+                    e.start,
+                    e.size,
+                    e.kind,
+                    e.slot.as_deref().unwrap_or("")
+                );
+            }
+        }
+    }
+
+    out
+}
+
+pub fn import_entries(content: &str, format: PartitionTableFormat) -> Result<Vec<PartitionEntry>> {
+    match format {
+        PartitionTableFormat::Json => JsonReader::new(content).entries(),
+        PartitionTableFormat::Csv => import_csv(content),
+    }
+}
+
+// Loads a table written by export_partitions(), e.g. a hand trimmed list of
+// partitions to back up. The format is picked from the file extension.
+pub fn load_partition_table(path: &Path) -> Result<Vec<PartitionEntry>> {
+    let format = PartitionTableFormat::from_path(path).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Unknown partition table format, expected a .json or .csv file",
+        )
+    })?;
+    import_entries(&std::fs::read_to_string(path)?, format)
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn import_csv(content: &str) -> Result<Vec<PartitionEntry>> {
+    let mut entries = Vec::new();
+
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == CSV_HEADER {
+            continue;
+        }
+
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid partition table entry on line {}", lineno + 1),
+            )
+        };
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() < 3 || fields[0].is_empty() {
+            return Err(invalid());
+        }
+
+        entries.push(PartitionEntry {
+            name: fields[0].to_string(),
+            start: parse_number(fields[1]).ok_or_else(invalid)?,
+            size: parse_number(fields[2]).ok_or_else(invalid)?,
+            kind: fields.get(3).unwrap_or(&"unknown").to_string(),
+            slot: fields
+                .get(4)
+                .filter(|slot| !slot.is_empty())
+                .map(|slot| slot.to_string()),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+enum JsonValue {
+    Str(String),
+    Num(u64),
+    Null,
+}
+
+// Just enough JSON to read back what export_entries() writes: an array of flat
+// objects with string, unsigned integer or null values.
+struct JsonReader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> JsonReader<'a> {
+    fn new(content: &'a str) -> Self {
+        Self {
+            chars: content.chars().peekable(),
+        }
+    }
+
+    fn error(msg: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid partition table JSON: {}", msg),
+        )
+    }
+
+    fn skip_ws(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_ws();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(Self::error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    // Consumes `c` if it's the next non whitespace char
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        self.chars.next_if_eq(&c).is_some()
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self
+                .chars
+                .next()
+                .ok_or_else(|| Self::error("unterminated string"))?
+            {
+                '"' => return Ok(out),
+                '\\' => match self.chars.next() {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| Self::error("bad unicode escape"))?;
+                        out.push(c);
+                    }
+                    Some(c) => out.push(c),
+                    None => return Err(Self::error("unterminated string")),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue> {
+        self.skip_ws();
+        match self.chars.peek() {
+            Some('"') => Ok(JsonValue::Str(self.string()?)),
+            Some('n') => {
+                let word: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                if word == "null" {
+                    Ok(JsonValue::Null)
+                } else {
+                    Err(Self::error("unexpected token"))
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                }
+                digits
+                    .parse()
+                    .map(JsonValue::Num)
+                    .map_err(|_| Self::error("number out of range"))
+            }
+            _ => Err(Self::error("unsupported value")),
+        }
+    }
+
+    fn entry(&mut self) -> Result<PartitionEntry> {
+        let mut name = None;
+        let mut start = None;
+        let mut size = None;
+        let mut kind = None;
+        let mut slot = None;
+
+        self.expect('{')?;
+        if !self.eat('}') {
+            loop {
+                let key = self.string()?;
+                self.expect(':')?;
+                match (key.as_str(), self.value()?) {
+                    ("name", JsonValue::Str(v)) => name = Some(v),
+                    ("start", JsonValue::Num(v)) => start = Some(v),
+                    ("size", JsonValue::Num(v)) => size = Some(v),
+                    ("kind", JsonValue::Str(v)) => kind = Some(v),
+                    ("slot", JsonValue::Str(v)) => slot = Some(v),
+                    ("slot", JsonValue::Null) => slot = None,
+                    // Unknown keys are fine, so the file can carry extra notes
+                    (k, _) if !matches!(k, "name" | "start" | "size" | "kind" | "slot") => {}
+                    (k, _) => return Err(Self::error(&format!("wrong type for '{}'", k))),
+                }
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            self.expect('}')?;
+        }
+
+        Ok(PartitionEntry {
+            name: name.ok_or_else(|| Self::error("entry without a name"))?,
+            start: start.ok_or_else(|| Self::error("entry without a start"))?,
+            size: size.ok_or_else(|| Self::error("entry without a size"))?,
+            kind: kind.unwrap_or_else(|| "unknown".to_string()),
+            slot,
+        })
+    }
+
+    fn entries(mut self) -> Result<Vec<PartitionEntry>> {
+        let mut entries = Vec::new();
+
+        self.expect('[')?;
+        if !self.eat(']') {
+            loop {
+                entries.push(self.entry()?);
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            self.expect(']')?;
+        }
+
+        self.skip_ws();
+        if self.chars.next().is_some() {
+            return Err(Self::error("trailing data"));
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,127 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt::Write as _;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+// What unreadable ranges are filled with in the dump, easy to spot in a hex editor
+pub const DEFAULT_FILL: &[u8] = b"BADBLOCK";
+
+// Settings for reading failing storage, see Device::read_flash_tolerant
+#[derive(Debug, Clone)]
+pub struct RecoveryOptions {
+    // Bytes asked for at once while everything reads fine
+    pub chunk_size: usize,
+    // Smallest range a failed chunk gets split into, anything that still fails
+    // at this size is given up on
+    pub block_size: usize,
+    // Extra attempts before a range counts as unreadable
+    pub retries: u32,
+    // Repeated over the unreadable ranges in the output
+    pub fill: Vec<u8>,
+}
+
+impl Default for RecoveryOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 0x10_0000,
+            block_size: 0x1000,
+            retries: 2,
+            fill: DEFAULT_FILL.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadRange {
+    // Absolute flash address
+    pub addr: u64,
+    pub len: usize,
+    // Last error the range failed with
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub addr: u64,
+    pub size: u64,
+    // Sorted and merged, adjacent ranges end up as one
+    pub bad: Vec<BadRange>,
+}
+
+impl RecoveryReport {
+    pub fn new(addr: u64, size: u64) -> Self {
+        Self {
+            addr,
+            size,
+            bad: Vec::new(),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.bad.is_empty()
+    }
+
+    pub fn bad_bytes(&self) -> u64 {
+        self.bad.iter().map(|range| range.len as u64).sum()
+    }
+
+    // Ranges come in address order, so only the last one can be extended
+    pub fn record(&mut self, addr: u64, len: usize, error: String) {
+        if let Some(last) = self.bad.last_mut()
+            && last.addr + last.len as u64 == addr
+        {
+            last.len += len;
+            last.error = error;
+            return;
+        }
+        self.bad.push(BadRange { addr, len, error });
+    }
+
+    // One range per line: start, length (hex) and the error, after a short summary.
+    // Offsets are relative to the dump as well, that's what people look at.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "# {:#X}+{:#X}: {} unreadable bytes in {} ranges\n# addr offset length error\n",
+            self.addr,
+            self.size,
+            self.bad_bytes(),
+            self.bad.len()
+        );
+        for range in &self.bad {
+            let _ = writeln!(
+                text,
+                "{:#X} {:#X} {:#X} {}",
+                range.addr,
+                range.addr - self.addr,
+                range.len,
+                range.error
+            );
+        }
+        text
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_text())
+    }
+}
+
+// Where the report for a dump goes, next to it
+pub fn report_path(dump: &Path) -> PathBuf {
+    let mut path = dump.as_os_str().to_owned();
+    path.push(".badblocks.txt");
+    PathBuf::from(path)
+}
+
+// `len` bytes of `fill` repeated, starting at the right phase for `offset`
+// so neighbouring filled ranges line up
+pub fn fill_pattern(fill: &[u8], offset: u64, len: usize) -> Vec<u8> {
+    if fill.is_empty() {
+        return vec![0; len];
+    }
+    (offset..offset + len as u64)
+        .map(|i| fill[(i % fill.len() as u64) as usize])
+        .collect()
+}
@@ -0,0 +1,279 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::checksums::sha256_hex;
+use crate::core::device::DeviceInfo;
+use crate::core::events::{Event, EventSink, OperationResult};
+use crate::da::DAFile;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::env;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Progress events are throttled already, this is a few long operations' worth
+pub const DEFAULT_TRACE_EVENTS: usize = 5000;
+
+// The last `capacity` events core reported, kept around for a bug report.
+// Put it in front of the frontend's sink with `sink()`.
+pub struct SessionTrace {
+    events: Mutex<VecDeque<Event>>,
+    capacity: usize,
+}
+
+impl SessionTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new(VecDeque::with_capacity(capacity.min(DEFAULT_TRACE_EVENTS))),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, event: &Event) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Records every event, then passes it on to `next`
+    pub fn sink(self: &Arc<Self>, next: Option<EventSink>) -> EventSink {
+        let trace = self.clone();
+        Arc::new(move |event: &Event| {
+            trace.record(event);
+            if let Some(next) = &next {
+                next(event);
+            }
+        })
+    }
+
+    // One JSON event per line, same as what a daemon would forward
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut data = String::new();
+        for event in self.events.lock().unwrap().iter() {
+            data.push_str(&event.to_json());
+            data.push('\n');
+        }
+        std::fs::write(path, data)
+    }
+
+    // Lines that aren't events (cut short by a crash, from a newer version...)
+    // are skipped, the rest is still worth having
+    pub fn load(path: &Path) -> Result<Vec<Event>> {
+        let file = std::fs::File::open(path)?;
+        let mut events = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(event) = Event::from_json(&line?) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    // $PENUMBRA_TRACE, or trace.jsonl next to the history log
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("PENUMBRA_TRACE") {
+            return Some(PathBuf::from(path));
+        }
+
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+        };
+
+        base.map(|dir| dir.join("penumbra").join("trace.jsonl"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceSummary {
+    pub chipset: String,
+    pub hw_code: String,
+    // "hw_code:hw_sub_code:hw_ver:sw_ver", see ChipIdentity::version_string
+    #[serde(default)]
+    pub chip_version: String,
+    // Hex, or a hash of it once redacted
+    pub soc_id: String,
+    pub meid: String,
+    pub pl_ver: Option<u8>,
+    pub br_ver: Option<u8>,
+    pub storage: String,
+    pub target_config: Option<String>,
+    pub partitions: usize,
+    pub gpt_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaSummary {
+    pub da_type: String,
+    pub size: usize,
+    pub sha256: String,
+    // HW codes the DA has an entry for
+    pub hw_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub operation: String,
+    pub error: String,
+    // The DA status code, when the error came with one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u32>,
+}
+
+// Everything a bug report needs in one JSON file: what happened (the trace),
+// on what (device and DA) and how it went wrong.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionReport {
+    pub version: String,
+    // Seconds since the Unix epoch
+    pub created: u64,
+    pub os: String,
+    pub arch: String,
+    pub redacted: bool,
+    pub device: Option<DeviceSummary>,
+    pub da: Option<DaSummary>,
+    pub failures: Vec<Failure>,
+    pub events: Vec<Event>,
+}
+
+impl SessionReport {
+    pub fn new(events: Vec<Event>) -> Self {
+        let failures = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::OperationFinished {
+                    operation,
+                    result: OperationResult::Failed { error },
+                    ..
+                } => Some(Failure {
+                    operation: operation.clone(),
+                    error: error.clone(),
+                    status: status_code(error),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            os: env::consts::OS.to_string(),
+            arch: env::consts::ARCH.to_string(),
+            redacted: false,
+            device: None,
+            da: None,
+            failures,
+            events,
+        }
+    }
+
+    pub fn with_device(mut self, info: &DeviceInfo) -> Self {
+        self.device = Some(DeviceSummary {
+            chipset: info.chipset.clone(),
+            hw_code: format!("{:04x}", info.chip.hw_code),
+            chip_version: info.chip.version_string(),
+            soc_id: info.chip.soc_id_hex(),
+            meid: info.chip.meid_hex(),
+            pl_ver: info.pl_ver,
+            br_ver: info.br_ver,
+            storage: format!("{:?}", info.storage),
+            target_config: info.target_config.map(|config| format!("{:?}", config)),
+            partitions: info.partitions.len(),
+            gpt_error: info.gpt_error.clone(),
+        });
+        self
+    }
+
+    pub fn with_da(mut self, da: &DAFile, raw: &[u8]) -> Self {
+        self.da = Some(DaSummary {
+            da_type: format!("{:?}", da.da_type),
+            size: raw.len(),
+            sha256: sha256_hex(raw),
+            hw_codes: da
+                .socs()
+                .iter()
+                .map(|code| format!("{:04x}", code))
+                .collect(),
+        });
+        self
+    }
+
+    // Swaps the SoC ID and MEID for a short hash of them, everywhere they show up
+    // (log lines print them too). Same device, same hash, so reports can still be
+    // told apart without giving the IDs away.
+    pub fn redact(&mut self) {
+        self.redacted = true;
+        let Some(device) = &mut self.device else {
+            return;
+        };
+
+        let mut ids = Vec::new();
+        for id in [&mut device.soc_id, &mut device.meid] {
+            if id.is_empty() {
+                continue;
+            }
+            let hashed = format!("redacted:{}", &sha256_hex(id.as_bytes())[..12]);
+            ids.push((std::mem::replace(id, hashed.clone()), hashed));
+        }
+
+        let scrub = |text: &mut String| {
+            for (id, hashed) in &ids {
+                *text = text
+                    .replace(id.as_str(), hashed)
+                    .replace(&id.to_uppercase(), hashed);
+            }
+        };
+        for failure in &mut self.failures {
+            scrub(&mut failure.error);
+        }
+        for event in &mut self.events {
+            match event {
+                Event::LogLine { message, .. } => scrub(message),
+                Event::OperationFinished {
+                    result: OperationResult::Failed { error },
+                    ..
+                } => scrub(error),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Nothing in here can fail to serialize
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        serde_json::from_str(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+// DAStatusError reads "... failed with status 0x%08X"
+fn status_code(error: &str) -> Option<u32> {
+    let (_, rest) = error.split_once("status 0x")?;
+    let hex: String = rest.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
@@ -0,0 +1,56 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+// How long a device that dropped off gets to show up again in BROM/preloader
+// mode before we call it gone instead of reset
+pub const RESET_DETECT_WINDOW: Duration = Duration::from_secs(5);
+
+// Resets a single operation gets to recover from. A device that keeps resetting
+// at the same spot won't get any better by trying forever.
+pub const MAX_RESET_RECOVERIES: usize = 3;
+
+// The device reset (the watchdog, usually) while an operation was running.
+// `recovered` tells whether it's back in DA mode, in which case running the
+// operation again picks up where it was (see read_flash_to and flash_all).
+// Wrapped in an io::Error, use `DeviceReset::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceReset {
+    pub operation: String,
+    // Absolute flash address the operation had reached
+    pub offset: u64,
+    pub recovered: bool,
+}
+
+impl DeviceReset {
+    pub fn from_error(err: &Error) -> Option<&DeviceReset> {
+        err.get_ref()?.downcast_ref::<DeviceReset>()
+    }
+}
+
+impl fmt::Display for DeviceReset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Device reset at offset {:#X} during {}",
+            self.offset, self.operation
+        )?;
+        if self.recovered {
+            f.write_str(", it's back in DA mode, run it again to resume")
+        } else {
+            f.write_str(", could not bring it back")
+        }
+    }
+}
+
+impl std::error::Error for DeviceReset {}
+
+impl From<DeviceReset> for Error {
+    fn from(err: DeviceReset) -> Self {
+        Error::new(ErrorKind::ConnectionReset, err)
+    }
+}
@@ -12,9 +12,12 @@
     the combined work is subject to the networking terms of the AGPL-3.0-or-later,
     as for term 13 of the GPL-3.0-or-later license.
 */
+use crate::core::crypto::config::CryptoIO;
 use crate::core::crypto::sej::SEJCrypto;
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
+use std::sync::{Mutex, OnceLock};
 
 const V4_MAGIC_BEGIN: u32 = 0x4D4D4D4D;
 const V4_MAGIC_END: u32 = 0x45454545;
@@ -24,7 +27,8 @@ pub enum LockFlag {
     Unlock,
 }
 
-enum SecCfgV4Algo {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecCfgV4Algo {
     SW,
     HW,
     HWv3,
@@ -41,6 +45,21 @@ pub struct SecCfgV4 {
     algo: Option<SecCfgV4Algo>,
 }
 
+// Matching the hash means running up to four SEJ operations over the wire,
+// so remember which algorithm worked for each SoC for the rest of the session.
+static ALGO_CACHE: OnceLock<Mutex<HashMap<Vec<u8>, SecCfgV4Algo>>> = OnceLock::new();
+
+pub fn cached_algo(soc_id: &[u8]) -> Option<SecCfgV4Algo> {
+    let cache = ALGO_CACHE.get_or_init(Default::default).lock().ok()?;
+    cache.get(soc_id).copied()
+}
+
+pub fn cache_algo(soc_id: &[u8], algo: SecCfgV4Algo) {
+    if let Ok(mut cache) = ALGO_CACHE.get_or_init(Default::default).lock() {
+        cache.insert(soc_id.to_vec(), algo);
+    }
+}
+
 impl SecCfgV4 {
     pub fn new() -> Self {
         SecCfgV4 {
@@ -53,7 +72,57 @@ impl SecCfgV4 {
         }
     }
 
-    pub async fn parse<'a>(data: &[u8], sej: &mut SEJCrypto<'a>) -> Result<SecCfgV4, Error> {
+    pub async fn parse<'a, IO: CryptoIO>(
+        data: &[u8],
+        sej: &mut SEJCrypto<'a, IO>,
+    ) -> Result<SecCfgV4, Error> {
+        Self::parse_with_hint(data, sej, None).await
+    }
+
+    // Same as parse(), but tries `hint` before brute forcing the other algorithms
+    pub async fn parse_with_hint<'a, IO: CryptoIO>(
+        data: &[u8],
+        sej: &mut SEJCrypto<'a, IO>,
+        hint: Option<SecCfgV4Algo>,
+    ) -> Result<SecCfgV4, Error> {
+        let mut seccfg = Self::parse_unverified(data)?;
+        let hash_start = seccfg.seccfg_size as usize - 32;
+        let hash = &data[hash_start..hash_start + 32];
+        let calculated_hash = Sha256::digest(seccfg.header());
+
+        let mut matched_algo: Option<SecCfgV4Algo> = None;
+
+        // This is unlikely to happen, but hey
+        if hash == calculated_hash.as_slice() {
+            matched_algo = Some(SecCfgV4Algo::None);
+        } else {
+            let mut candidates = vec![
+                SecCfgV4Algo::SW,
+                SecCfgV4Algo::HW,
+                SecCfgV4Algo::HWv3,
+                SecCfgV4Algo::HWv4,
+            ];
+            if let Some(hint) = hint {
+                candidates.retain(|&algo| algo != hint);
+                candidates.insert(0, hint);
+            }
+
+            for algo in candidates {
+                let dec_hash = decrypt_hash(algo, hash, sej).await?;
+                if calculated_hash.as_slice() == dec_hash.as_slice() {
+                    matched_algo = Some(algo);
+                    break;
+                }
+            }
+        }
+
+        seccfg.algo = matched_algo;
+        Ok(seccfg)
+    }
+
+    // Parses the header without checking the hash, so no algorithm is set.
+    // Meant for when the algorithm is already known and gets forced with set_algo().
+    pub fn parse_unverified(data: &[u8]) -> Result<SecCfgV4, Error> {
         if data.len() < 0x20 + 32 {
             return Err(Error::new(ErrorKind::InvalidData, "Data too short"));
         }
@@ -70,53 +139,12 @@ impl SecCfgV4 {
             return Err(Error::new(ErrorKind::InvalidData, "Invalid magic values"));
         }
 
-        let hash_start = seccfg_size as usize - 32;
-        if data.len() < hash_start + 32 {
+        if (seccfg_size as usize) < 32 || data.len() < seccfg_size as usize {
             return Err(Error::new(
                 ErrorKind::InvalidData,
                 "Data too short for hash",
             ));
         }
-        let hash = &data[hash_start..hash_start + 32];
-
-        let header_data = [
-            magic.to_le_bytes(),
-            seccfg_ver.to_le_bytes(),
-            seccfg_size.to_le_bytes(),
-            lock_state.to_le_bytes(),
-            critical_lock_state.to_le_bytes(),
-            sboot_runtime.to_le_bytes(),
-            V4_MAGIC_END.to_le_bytes(),
-        ]
-        .concat();
-
-        let calculated_hash = Sha256::digest(&header_data);
-
-        let mut matched_algo: Option<SecCfgV4Algo> = None;
-
-        // This is unlikely to happen, but hey
-        if hash == calculated_hash.as_slice() {
-            matched_algo = Some(SecCfgV4Algo::None);
-        } else {
-            for algo in [
-                SecCfgV4Algo::SW,
-                SecCfgV4Algo::HW,
-                SecCfgV4Algo::HWv3,
-                SecCfgV4Algo::HWv4,
-            ] {
-                let dec_hash = match algo {
-                    SecCfgV4Algo::SW => sej.sej_seccfg_sw(hash, false),
-                    SecCfgV4Algo::HW => sej.sej_seccfg_hw(hash, false, false).await,
-                    SecCfgV4Algo::HWv3 => sej.sej_seccfg_hw_v3(hash, false).await,
-                    SecCfgV4Algo::HWv4 => sej.sej_seccfg_hw_v4(hash, false).await,
-                    SecCfgV4Algo::None => continue,
-                };
-                if calculated_hash.as_slice() == dec_hash.as_slice() {
-                    matched_algo = Some(algo);
-                    break;
-                }
-            }
-        }
 
         Ok(SecCfgV4 {
             seccfg_ver,
@@ -124,11 +152,54 @@ impl SecCfgV4 {
             lock_state,
             critical_lock_state,
             sboot_runtime,
-            algo: matched_algo,
+            algo: None,
         })
     }
 
-    pub async fn create<'a>(&mut self, sej: &mut SEJCrypto<'a>, lock_flag: LockFlag) -> Vec<u8> {
+    pub fn algo(&self) -> Option<SecCfgV4Algo> {
+        self.algo
+    }
+
+    pub fn set_algo(&mut self, algo: SecCfgV4Algo) {
+        self.algo = Some(algo);
+    }
+
+    // Checks that the hash stored in `data` decrypts, with this seccfg's algorithm,
+    // to the hash of the header in `data`. False when no algorithm is set.
+    pub async fn verify<'a, IO: CryptoIO>(
+        &self,
+        data: &[u8],
+        sej: &mut SEJCrypto<'a, IO>,
+    ) -> Result<bool, Error> {
+        let Some(algo) = self.algo else {
+            return Ok(false);
+        };
+
+        let parsed = Self::parse_unverified(data)?;
+        let hash_start = parsed.seccfg_size as usize - 32;
+        let hash = &data[hash_start..hash_start + 32];
+        let dec_hash = decrypt_hash(algo, hash, sej).await?;
+        Ok(Sha256::digest(parsed.header()).as_slice() == dec_hash.as_slice())
+    }
+
+    fn header(&self) -> Vec<u8> {
+        [
+            V4_MAGIC_BEGIN.to_le_bytes(),
+            self.seccfg_ver.to_le_bytes(),
+            self.seccfg_size.to_le_bytes(),
+            self.lock_state.to_le_bytes(),
+            self.critical_lock_state.to_le_bytes(),
+            self.sboot_runtime.to_le_bytes(),
+            V4_MAGIC_END.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    pub async fn create<'a, IO: CryptoIO>(
+        &mut self,
+        sej: &mut SEJCrypto<'a, IO>,
+        lock_flag: LockFlag,
+    ) -> Result<Vec<u8>, Error> {
         // TODO: Check if critical lock state being 0 is valid. Penangf unlock through lk
         // sets it to 0
         match lock_flag {
@@ -142,31 +213,38 @@ impl SecCfgV4 {
             }
         }
 
-        let mut seccfg_data = Vec::new();
-        seccfg_data.extend(&V4_MAGIC_BEGIN.to_le_bytes());
-        seccfg_data.extend(&self.seccfg_ver.to_le_bytes());
-        seccfg_data.extend(&self.seccfg_size.to_le_bytes());
-        seccfg_data.extend(&self.lock_state.to_le_bytes());
-        seccfg_data.extend(&self.critical_lock_state.to_le_bytes());
-        seccfg_data.extend(&self.sboot_runtime.to_le_bytes());
-        seccfg_data.extend(&V4_MAGIC_END.to_le_bytes());
+        let mut seccfg_data = self.header();
 
         let hash = Sha256::digest(&seccfg_data);
 
         let encrypted_hash = match self.algo {
             Some(SecCfgV4Algo::SW) => sej.sej_seccfg_sw(&hash, true),
-            Some(SecCfgV4Algo::HW) => sej.sej_seccfg_hw(&hash, true, false).await,
-            Some(SecCfgV4Algo::HWv3) => sej.sej_seccfg_hw_v3(&hash, true).await,
-            Some(SecCfgV4Algo::HWv4) => sej.sej_seccfg_hw_v4(&hash, true).await,
+            Some(SecCfgV4Algo::HW) => sej.sej_seccfg_hw(&hash, true, false).await?,
+            Some(SecCfgV4Algo::HWv3) => sej.sej_seccfg_hw_v3(&hash, true).await?,
+            Some(SecCfgV4Algo::HWv4) => sej.sej_seccfg_hw_v4(&hash, true).await?,
             _ => hash.to_vec(),
         };
 
         seccfg_data.extend_from_slice(&encrypted_hash);
 
-        while seccfg_data.len() % 0x200 != 0 {
+        while !seccfg_data.len().is_multiple_of(0x200) {
             seccfg_data.push(0);
         }
 
-        seccfg_data
+        Ok(seccfg_data)
+    }
+}
+
+async fn decrypt_hash<'a, IO: CryptoIO>(
+    algo: SecCfgV4Algo,
+    hash: &[u8],
+    sej: &mut SEJCrypto<'a, IO>,
+) -> Result<Vec<u8>, Error> {
+    match algo {
+        SecCfgV4Algo::SW => Ok(sej.sej_seccfg_sw(hash, false)),
+        SecCfgV4Algo::HW => sej.sej_seccfg_hw(hash, false, false).await,
+        SecCfgV4Algo::HWv3 => sej.sej_seccfg_hw_v3(hash, false).await,
+        SecCfgV4Algo::HWv4 => sej.sej_seccfg_hw_v4(hash, false).await,
+        SecCfgV4Algo::None => Ok(hash.to_vec()),
     }
 }
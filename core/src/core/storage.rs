@@ -2,8 +2,15 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
+use log::warn;
+use std::fmt;
 use std::io::{Error, ErrorKind, Result};
 
+// Smallest entry the spec allows, bigger entries just have extra vendor data
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+// Header bytes parse_gpt reads, up to the entry size field
+const GPT_HEADER_FIELDS: usize = 88;
+
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StorageType {
@@ -51,13 +58,14 @@ pub enum PartitionKind {
 #[derive(Debug, Clone)]
 pub struct Partition {
     pub name: String,
-    pub size: usize,
+    // u64 even on 32-bit hosts, userdata and super are well past 4 GiB
+    pub size: u64,
     pub address: u64,
     pub kind: PartitionKind,
 }
 
 impl Partition {
-    pub fn new(name: &str, size: usize, address: u64, kind: PartitionKind) -> Self {
+    pub fn new(name: &str, size: u64, address: u64, kind: PartitionKind) -> Self {
         Self {
             name: name.to_string(),
             size,
@@ -67,44 +75,133 @@ impl Partition {
     }
 }
 
+// Why parse_gpt gave up. Wrapped in an io::Error (InvalidData), use
+// `GptParseError::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GptParseError {
+    NoHeader,
+    // The header sector was cut short
+    ShortHeader {
+        len: usize,
+        needed: usize,
+    },
+    EntrySize(usize),
+    // The entry array starts somewhere that can't be addressed
+    EntriesOutOfRange {
+        lba: u64,
+    },
+    InvalidRange {
+        index: usize,
+        first_lba: u64,
+        last_lba: u64,
+    },
+}
+
+impl GptParseError {
+    pub fn from_error(err: &Error) -> Option<&GptParseError> {
+        err.get_ref()?.downcast_ref::<GptParseError>()
+    }
+}
+
+impl fmt::Display for GptParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GptParseError::NoHeader => f.write_str("No valid GPT header found"),
+            GptParseError::ShortHeader { len, needed } => write!(
+                f,
+                "GPT header is cut short ({} of {} bytes read)",
+                len, needed
+            ),
+            GptParseError::EntrySize(size) => {
+                write!(f, "Unsupported partition entry size {}", size)
+            }
+            GptParseError::EntriesOutOfRange { lba } => {
+                write!(f, "GPT entries start at an impossible LBA ({:#X})", lba)
+            }
+            GptParseError::InvalidRange {
+                index,
+                first_lba,
+                last_lba,
+            } => write!(
+                f,
+                "Partition entry {} ends before it starts ({:#X} > {:#X})",
+                index, first_lba, last_lba
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GptParseError {}
+
+impl From<GptParseError> for Error {
+    fn from(err: GptParseError) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+// What parse_gpt_partial got out of `data`. Short reads happen on flaky links,
+// the entries that did make it are still good.
+#[derive(Debug, Clone, Default)]
+pub struct GptParse {
+    pub partitions: Vec<Partition>,
+    // Entries the header lists that weren't (fully) in `data`
+    pub missing_entries: usize,
+}
+
+impl GptParse {
+    pub fn is_truncated(&self) -> bool {
+        self.missing_entries > 0
+    }
+}
+
 // Oh dear Mediatek! Why make me lose 2 hours over this!
 // Why in the scatter file you have reserved partitions prefixed with 0xFFFF,
 // but then I can just dump them with non reserved addresses? <3
 // Over such a simple task, I lost too much time ._.
 pub fn parse_gpt(data: &[u8], storage_type: StorageType) -> Result<Vec<Partition>> {
-    let mut sector_size: Option<usize> = None;
-
-    let sector_sizes = [512, 4096, 0x8000, 0x10000, 0x20000];
-    for &ss in &sector_sizes {
-        if data.len() >= ss + 8 && &data[ss..ss + 8] == b"EFI PART" {
-            sector_size = Some(ss);
-            break;
-        }
+    let parsed = parse_gpt_partial(data, storage_type)?;
+    if parsed.is_truncated() {
+        warn!(
+            "GPT read was short, {} entries missing, ignoring them",
+            parsed.missing_entries
+        );
     }
+    Ok(parsed.partitions)
+}
 
-    let sector_size = match sector_size {
-        Some(size) => 512,
-        None => {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "No valid GPT header found",
-            ));
-        }
-    };
+pub fn parse_gpt_partial(
+    data: &[u8],
+    storage_type: StorageType,
+) -> std::result::Result<GptParse, GptParseError> {
+    let sector_size = gpt_sector_size(data).ok_or(GptParseError::NoHeader)?;
 
-    let hdr = &data[sector_size..sector_size * 2];
+    let hdr = data
+        .get(sector_size..sector_size + GPT_HEADER_FIELDS)
+        .ok_or(GptParseError::ShortHeader {
+            len: data.len().saturating_sub(sector_size),
+            needed: GPT_HEADER_FIELDS,
+        })?;
     let partition_entry_lba = u64::from_le_bytes(hdr[72..80].try_into().unwrap());
-    let num_entries = u32::from_le_bytes(hdr[80..84].try_into().unwrap());
-    let entry_size = u32::from_le_bytes(hdr[84..88].try_into().unwrap());
+    let num_entries = u32::from_le_bytes(hdr[80..84].try_into().unwrap()) as usize;
+    let entry_size = u32::from_le_bytes(hdr[84..88].try_into().unwrap()) as usize;
 
-    if entry_size as usize != 128 {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "Unsupported partition entry size",
-        ));
+    if entry_size < GPT_MIN_ENTRY_SIZE || !entry_size.is_multiple_of(8) {
+        return Err(GptParseError::EntrySize(entry_size));
     }
 
-    let start_offset = (partition_entry_lba as usize) * sector_size;
+    let start_offset = usize::try_from(partition_entry_lba)
+        .ok()
+        .and_then(|lba| lba.checked_mul(sector_size))
+        .ok_or(GptParseError::EntriesOutOfRange {
+            lba: partition_entry_lba,
+        })?;
+
+    // Don't trust num_entries, a corrupted header would have us index way past
+    // the data. Anything not read (see gpt_entries_end) gets dropped.
+    let available = data.len().saturating_sub(start_offset) / entry_size;
+    let missing_entries = num_entries.saturating_sub(available);
+    let num_entries = num_entries.min(available);
+
     let mut partitions: Vec<Partition> = Vec::new();
     let part_kind = match storage_type {
         StorageType::Emmc => PartitionKind::Emmc(EmmcPartition::User),
@@ -112,11 +209,14 @@ pub fn parse_gpt(data: &[u8], storage_type: StorageType) -> Result<Vec<Partition
         _ => PartitionKind::Unknown,
     };
 
-    for i in 0..num_entries {
-        let current_offset = start_offset + (i as usize * entry_size as usize);
-
-        let entry = &data[current_offset..current_offset + entry_size as usize];
-
+    // An entry LBA past the end of the read leaves nothing, not a panic
+    for (i, entry) in data
+        .get(start_offset..)
+        .unwrap_or(&[])
+        .chunks_exact(entry_size)
+        .take(num_entries)
+        .enumerate()
+    {
         // Yeet empty entries
         if entry[0..16].iter().all(|&b| b == 0) {
             continue;
@@ -126,14 +226,17 @@ pub fn parse_gpt(data: &[u8], storage_type: StorageType) -> Result<Vec<Partition
         let last_lba = u64::from_le_bytes(entry[40..48].try_into().unwrap());
 
         if last_lba < first_lba {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Partition last_lba < first_lba",
-            ));
+            return Err(GptParseError::InvalidRange {
+                index: i,
+                first_lba,
+                last_lba,
+            });
         }
 
-        let part_size = (last_lba - first_lba + 1) * sector_size as u64;
-        let part_addr = first_lba * sector_size as u64;
+        let part_size = (last_lba - first_lba)
+            .saturating_add(1)
+            .saturating_mul(sector_size as u64);
+        let part_addr = first_lba.saturating_mul(sector_size as u64);
 
         let part_name = String::from_utf16_lossy(
             &entry[56..128]
@@ -145,11 +248,107 @@ pub fn parse_gpt(data: &[u8], storage_type: StorageType) -> Result<Vec<Partition
 
         partitions.push(Partition::new(
             &part_name,
-            part_size as usize,
+            part_size,
             part_addr,
             part_kind.clone(),
         ));
     }
 
-    Ok(partitions)
+    Ok(GptParse {
+        partitions,
+        missing_entries,
+    })
+}
+
+// The primary header lives at LBA 1, so where it shows up in `data` (read from
+// the start of the disk) tells the sector size: 512 for eMMC, 4096 for most UFS.
+pub fn gpt_sector_size(data: &[u8]) -> Option<usize> {
+    [512, 4096]
+        .into_iter()
+        .find(|&ss| data.get(ss..ss + 8) == Some(b"EFI PART".as_slice()))
+}
+
+// How many bytes from the start of the disk parse_gpt needs to see every entry.
+// Only the first two sectors have to be in `data`, so callers can read the
+// header first and then exactly what the entry array needs.
+pub fn gpt_entries_end(data: &[u8]) -> Option<usize> {
+    let sector_size = gpt_sector_size(data)?;
+    let hdr = data.get(sector_size..sector_size + 92)?;
+    let entries_lba = usize::try_from(u64::from_le_bytes(hdr[72..80].try_into().unwrap())).ok()?;
+    let num_entries = u32::from_le_bytes(hdr[80..84].try_into().unwrap()) as usize;
+    let entry_size = u32::from_le_bytes(hdr[84..88].try_into().unwrap()) as usize;
+
+    entries_lba
+        .checked_mul(sector_size)?
+        .checked_add(num_entries.checked_mul(entry_size)?)
+}
+
+// Returns the LBA of the backup GPT header, as stored in the primary one
+pub fn gpt_alternate_lba(data: &[u8]) -> Option<u64> {
+    let sector_size = gpt_sector_size(data)?;
+    let hdr = data.get(sector_size..sector_size + 92)?;
+    Some(u64::from_le_bytes(hdr[32..40].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR: usize = 512;
+
+    // Protective MBR sector, header at LBA 1 and the entries right after it
+    fn gpt(entries: &[(&str, u64, u64)]) -> Vec<u8> {
+        let num_entries = 4;
+        let mut data = vec![0u8; SECTOR * 2 + num_entries * 128];
+
+        let hdr = &mut data[SECTOR..SECTOR * 2];
+        hdr[0..8].copy_from_slice(b"EFI PART");
+        hdr[72..80].copy_from_slice(&2u64.to_le_bytes());
+        hdr[80..84].copy_from_slice(&(num_entries as u32).to_le_bytes());
+        hdr[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+        for (i, &(name, first_lba, last_lba)) in entries.iter().enumerate() {
+            let entry = &mut data[SECTOR * 2 + i * 128..SECTOR * 2 + (i + 1) * 128];
+            entry[0..16].fill(0xAA);
+            entry[32..40].copy_from_slice(&first_lba.to_le_bytes());
+            entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+            for (j, c) in name.encode_utf16().enumerate() {
+                entry[56 + j * 2..58 + j * 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn parse_gpt_past_4gib() {
+        // 5 GiB userdata, starting right at 4 GiB
+        let first_lba = 0x80_0000;
+        let last_lba = first_lba + 0xA0_0000 - 1;
+        let data = gpt(&[("boot", 0x40, 0x7F), ("userdata", first_lba, last_lba)]);
+
+        let partitions = parse_gpt(&data, StorageType::Emmc).unwrap();
+        assert_eq!(partitions.len(), 2);
+
+        let userdata = &partitions[1];
+        assert_eq!(userdata.name, "userdata");
+        assert_eq!(userdata.address, 0x1_0000_0000);
+        assert_eq!(userdata.size, 0x1_4000_0000);
+    }
+
+    #[test]
+    fn parse_gpt_truncated() {
+        let data = gpt(&[("boot", 0x40, 0x7F), ("userdata", 0x80, 0xFF)]);
+
+        // Only the first entry made it
+        let parsed = parse_gpt_partial(&data[..SECTOR * 2 + 128], StorageType::Emmc).unwrap();
+        assert_eq!(parsed.partitions.len(), 1);
+        assert_eq!(parsed.missing_entries, 3);
+
+        // Entries pointing past the read are all missing, not a panic
+        let mut data = data;
+        data[SECTOR + 72..SECTOR + 80].copy_from_slice(&0x1000u64.to_le_bytes());
+        let parsed = parse_gpt_partial(&data, StorageType::Emmc).unwrap();
+        assert!(parsed.partitions.is_empty());
+        assert_eq!(parsed.missing_entries, 4);
+    }
 }
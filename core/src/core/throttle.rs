@@ -0,0 +1,67 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+// Slows writes down for devices that corrupt data at full speed (flaky eMMC,
+// worn out controllers). Applies on top of the PowerProfile pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteThrottle {
+    // Average bytes per second, None for no limit
+    pub rate: Option<u64>,
+    // Extra idle time after every chunk
+    pub delay: Duration,
+}
+
+impl WriteThrottle {
+    pub fn rate(bytes_per_sec: u64) -> Self {
+        Self {
+            rate: Some(bytes_per_sec).filter(|&rate| rate > 0),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.rate.is_none() && self.delay.is_zero()
+    }
+
+    // One per write, the rate is averaged from when this is called
+    pub fn limiter(&self) -> RateLimiter {
+        RateLimiter {
+            throttle: *self,
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+}
+
+pub struct RateLimiter {
+    throttle: WriteThrottle,
+    started: Instant,
+    sent: u64,
+}
+
+impl RateLimiter {
+    // Call after every chunk that went out, sleeps as long as it takes to get
+    // back under the rate
+    pub async fn wait(&mut self, bytes: usize) {
+        self.sent += bytes as u64;
+        if !self.throttle.delay.is_zero() {
+            sleep(self.throttle.delay).await;
+        }
+        let Some(rate) = self.throttle.rate else {
+            return;
+        };
+        let due = self.started + Duration::from_secs_f64(self.sent as f64 / rate as f64);
+        if due > Instant::now() {
+            sleep(due - Instant::now()).await;
+        }
+    }
+}
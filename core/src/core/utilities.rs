@@ -13,3 +13,9 @@ pub fn find_pattern(data: &[u8], to_find: &[u8], offset: usize) -> Option<usize>
         .position(|chunk| chunk == to_find)
         .map(|index| index + offset)
 }
+
+// Progress callbacks count in usize. Flash sizes are u64, and on a 32-bit host
+// a >4 GiB partition doesn't fit, so the count just tops out there.
+pub fn saturating_usize(value: u64) -> usize {
+    usize::try_from(value).unwrap_or(usize::MAX)
+}
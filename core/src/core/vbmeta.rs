@@ -0,0 +1,58 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::io::{Error, ErrorKind};
+
+// AvbVBMetaImageHeader: "AVB0" | versions | sizes ... | flags (u32 BE) at 120
+const VBMETA_MAGIC: &[u8; 4] = b"AVB0";
+const VBMETA_FLAGS_OFFSET: usize = 120;
+const VBMETA_HEADER_SIZE: usize = 256;
+
+// AVB_VBMETA_IMAGE_FLAGS_*
+pub const FLAG_HASHTREE_DISABLED: u32 = 1 << 0;
+pub const FLAG_VERIFICATION_DISABLED: u32 = 1 << 1;
+
+pub fn flags(vbmeta: &[u8]) -> Result<u32, Error> {
+    if vbmeta.len() < VBMETA_HEADER_SIZE || &vbmeta[..4] != VBMETA_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Not a vbmeta image (no AVB0 header)",
+        ));
+    }
+    let raw = &vbmeta[VBMETA_FLAGS_OFFSET..VBMETA_FLAGS_OFFSET + 4];
+    Ok(u32::from_be_bytes(raw.try_into().unwrap()))
+}
+
+// Same as `avbtool --flags 3` / `fastboot --disable-verity --disable-verification`.
+// The flags are part of the signed header, so this breaks the vbmeta signature:
+// only an unlocked bootloader will boot the result.
+pub fn disable_verity(vbmeta: &[u8]) -> Result<Vec<u8>, Error> {
+    let flags = flags(vbmeta)? | FLAG_HASHTREE_DISABLED | FLAG_VERIFICATION_DISABLED;
+    let mut patched = vbmeta.to_vec();
+    patched[VBMETA_FLAGS_OFFSET..VBMETA_FLAGS_OFFSET + 4].copy_from_slice(&flags.to_be_bytes());
+    Ok(patched)
+}
+
+// What flash_boot_and_disable_verity did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerityReport {
+    pub boot_partition: String,
+    pub vbmeta_partition: String,
+    // The boot image as it was, worth keeping to go back to stock
+    pub original_boot: Vec<u8>,
+    pub original_boot_sha256: String,
+    pub boot_sha256: String,
+    pub vbmeta_flags_before: u32,
+    pub vbmeta_flags_after: u32,
+}
+
+// Gets the boot image read off the device, returns the patched one
+pub type BootPatchFn = Box<dyn FnOnce(Vec<u8>) -> Result<Vec<u8>, Error> + Send>;
+
+// Where the new boot image comes from
+pub enum BootPatch {
+    // Already patched, e.g. by Magisk on another device
+    Image(Vec<u8>),
+    With(BootPatchFn),
+}
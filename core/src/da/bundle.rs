@@ -0,0 +1,212 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::da::DAFile;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+pub const BUNDLE_EXTENSION: &str = "penumbra-loader";
+
+// Layout after decompression:
+//   "PNBL" | version u8 | sections...
+//   section: tag [u8; 4] | length u32 LE | data
+const BUNDLE_MAGIC: &[u8; 4] = b"PNBL";
+const BUNDLE_VERSION: u8 = 1;
+const ZSTD_MAGIC: &[u8; 4] = b"\x28\xB5\x2F\xFD";
+const ZSTD_LEVEL: i32 = 19;
+
+const TAG_META: &[u8; 4] = b"META";
+const TAG_DA: &[u8; 4] = b"DA\0\0";
+const TAG_PRELOADER: &[u8; 4] = b"PRLD";
+const TAG_PATCHES: &[u8; 4] = b"PTCH";
+
+#[derive(Clone, Debug, Default)]
+pub struct BundleMetadata {
+    pub name: String,
+    pub hw_codes: Vec<u16>,
+    pub notes: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LoaderBundle {
+    pub metadata: BundleMetadata,
+    pub da: Vec<u8>,
+    pub preloader: Option<Vec<u8>>,
+    // Patch definitions, kept as text so they can be diffed and reviewed
+    pub patches: Option<String>,
+}
+
+impl BundleMetadata {
+    // Same `key = value` format as settings.conf, hw_codes is comma separated hex
+    fn parse(text: &str) -> Result<BundleMetadata, Error> {
+        let mut meta = BundleMetadata::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(invalid(format!("Malformed metadata line '{}'", line)));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "name" => meta.name = value.to_string(),
+                "hw_codes" => {
+                    meta.hw_codes = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|code| !code.is_empty())
+                        .map(|code| {
+                            u16::from_str_radix(code.trim_start_matches("0x"), 16)
+                                .map_err(|_| invalid(format!("Invalid hw_code '{}'", code)))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                // Multi-line notes are stored one `notes =` line each
+                "notes" => {
+                    if !meta.notes.is_empty() {
+                        meta.notes.push('\n');
+                    }
+                    meta.notes.push_str(value);
+                }
+                // Unknown keys are ignored so newer bundles still load
+                _ => {}
+            }
+        }
+        Ok(meta)
+    }
+
+    fn serialize(&self) -> String {
+        let mut text = format!("name = {}\n", self.name);
+        let codes: Vec<String> = self
+            .hw_codes
+            .iter()
+            .map(|c| format!("0x{:04x}", c))
+            .collect();
+        text.push_str(&format!("hw_codes = {}\n", codes.join(", ")));
+        for line in self.notes.lines() {
+            text.push_str(&format!("notes = {}\n", line));
+        }
+        text
+    }
+}
+
+impl LoaderBundle {
+    pub fn load(path: &Path) -> Result<LoaderBundle, Error> {
+        LoaderBundle::parse(&std::fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_bytes()?)
+    }
+
+    pub fn is_bundle(data: &[u8]) -> bool {
+        data.starts_with(ZSTD_MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<LoaderBundle, Error> {
+        if !LoaderBundle::is_bundle(data) {
+            return Err(invalid(
+                "Not a loader bundle: missing zstd header".to_string(),
+            ));
+        }
+        let raw = zstd::decode_all(data)?;
+
+        if raw.len() < 5 || &raw[0..4] != BUNDLE_MAGIC {
+            return Err(invalid("Not a loader bundle: bad magic".to_string()));
+        }
+        if raw[4] != BUNDLE_VERSION {
+            return Err(invalid(format!("Unsupported bundle version {}", raw[4])));
+        }
+
+        let mut bundle = LoaderBundle::default();
+        let mut have_meta = false;
+        let mut pos = 5;
+        while pos < raw.len() {
+            if raw.len() - pos < 8 {
+                return Err(invalid("Truncated section header".to_string()));
+            }
+            let tag: [u8; 4] = raw[pos..pos + 4].try_into().unwrap();
+            let len = u32::from_le_bytes(raw[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            pos += 8;
+            let body = raw
+                .get(pos..pos + len)
+                .ok_or_else(|| invalid("Truncated section".to_string()))?;
+            pos += len;
+
+            match &tag {
+                TAG_META => {
+                    let text = std::str::from_utf8(body)
+                        .map_err(|_| invalid("Metadata is not UTF-8".to_string()))?;
+                    bundle.metadata = BundleMetadata::parse(text)?;
+                    have_meta = true;
+                }
+                TAG_DA => bundle.da = body.to_vec(),
+                TAG_PRELOADER => bundle.preloader = Some(body.to_vec()),
+                TAG_PATCHES => {
+                    let text = String::from_utf8(body.to_vec())
+                        .map_err(|_| invalid("Patch definitions are not UTF-8".to_string()))?;
+                    bundle.patches = Some(text);
+                }
+                _ => {}
+            }
+        }
+
+        if !have_meta {
+            return Err(invalid("Bundle has no metadata section".to_string()));
+        }
+        if bundle.da.is_empty() {
+            return Err(invalid("Bundle has no DA section".to_string()));
+        }
+        Ok(bundle)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut raw = BUNDLE_MAGIC.to_vec();
+        raw.push(BUNDLE_VERSION);
+
+        let mut section = |tag: &[u8; 4], body: &[u8]| -> Result<(), Error> {
+            let len = u32::try_from(body.len())
+                .map_err(|_| invalid("Section too large for a bundle".to_string()))?;
+            raw.extend_from_slice(tag);
+            raw.extend_from_slice(&len.to_le_bytes());
+            raw.extend_from_slice(body);
+            Ok(())
+        };
+
+        section(TAG_META, self.metadata.serialize().as_bytes())?;
+        section(TAG_DA, &self.da)?;
+        if let Some(preloader) = &self.preloader {
+            section(TAG_PRELOADER, preloader)?;
+        }
+        if let Some(patches) = &self.patches {
+            section(TAG_PATCHES, patches.as_bytes())?;
+        }
+
+        zstd::encode_all(raw.as_slice(), ZSTD_LEVEL)
+    }
+
+    pub fn da_file(&self) -> Result<DAFile, Error> {
+        DAFile::parse_da(&self.da)
+    }
+
+    // An empty list means the bundle didn't restrict itself to any SoC
+    pub fn supports(&self, hw_code: u16) -> bool {
+        self.metadata.hw_codes.is_empty() || self.metadata.hw_codes.contains(&hw_code)
+    }
+}
+
+// Accepts either a plain DA file or a bundle, so callers don't need to care
+pub fn load_loader(path: &Path) -> Result<(DAFile, Option<LoaderBundle>), Error> {
+    let data = std::fs::read(path)?;
+    if LoaderBundle::is_bundle(&data) {
+        let bundle = LoaderBundle::parse(&data)?;
+        Ok((bundle.da_file()?, Some(bundle)))
+    } else {
+        Ok((DAFile::parse(data.into())?, None))
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,150 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+
+// Both registers are 128 bits. The DA keeps them the way the card answered,
+// as four LE u32 words with bits 127:96 first (same as GetEmmcInfo).
+pub const CARD_REG_SIZE: usize = 16;
+
+// Turns the four words back into the register, MSB first
+fn register(data: &[u8]) -> Option<[u8; CARD_REG_SIZE]> {
+    let words = data.get(..CARD_REG_SIZE)?;
+    let mut reg = [0u8; CARD_REG_SIZE];
+    for (i, word) in words.chunks_exact(4).enumerate() {
+        let value = u32::from_le_bytes(word.try_into().unwrap());
+        reg[i * 4..i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    Some(reg)
+}
+
+// `len` bits ending at bit `hi` (as the spec numbers them) of a MSB first register
+fn bits(reg: &[u8; CARD_REG_SIZE], hi: usize, len: usize) -> u32 {
+    let mut value = 0u32;
+    for bit in (hi + 1 - len..=hi).rev() {
+        let byte = reg[CARD_REG_SIZE - 1 - bit / 8];
+        value = (value << 1) | ((byte >> (bit % 8)) & 1) as u32;
+    }
+    value
+}
+
+// eMMC Card IDentification register, who made the chip and which one it is.
+// Stays the same across reflashes, unlike anything on the storage itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmmcCid {
+    pub raw: [u8; CARD_REG_SIZE],
+    // JEDEC manufacturer ID, e.g. 0x15 Samsung, 0x90 SK Hynix
+    pub manufacturer: u8,
+    pub oem: u8,
+    // Up to six ASCII characters
+    pub product: [u8; 6],
+    // BCD, major.minor
+    pub revision: u8,
+    pub serial: u32,
+    pub month: u8,
+    // Years since 2013 on eMMC 4.41 and later, since 1997 before that
+    pub year_code: u8,
+}
+
+impl EmmcCid {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let raw = register(data)?;
+        let mut product = [0u8; 6];
+        product.copy_from_slice(&raw[3..9]);
+        Some(Self {
+            raw,
+            manufacturer: bits(&raw, 127, 8) as u8,
+            oem: bits(&raw, 111, 8) as u8,
+            product,
+            revision: bits(&raw, 55, 8) as u8,
+            serial: bits(&raw, 47, 32),
+            month: bits(&raw, 15, 4) as u8,
+            year_code: bits(&raw, 11, 4) as u8,
+        })
+    }
+
+    pub fn product_name(&self) -> String {
+        String::from_utf8_lossy(&self.product)
+            .trim_end_matches(['\0', ' '])
+            .to_string()
+    }
+
+    // Anything a DA runs on is 4.41 or later, so 2013 based
+    pub fn year(&self) -> u16 {
+        2013 + self.year_code as u16
+    }
+
+    pub fn manufacturer_name(&self) -> Option<&'static str> {
+        Some(match self.manufacturer {
+            0x11 => "Toshiba",
+            0x13 => "Micron",
+            0x15 => "Samsung",
+            0x45 => "SanDisk",
+            0x70 => "Kingston",
+            0x88 => "Foresee",
+            0x90 => "SK Hynix",
+            0x9B => "YMTC",
+            0xFE => "Micron",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for EmmcCid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.manufacturer_name() {
+            Some(name) => write!(f, "{} ({:#04X})", name, self.manufacturer)?,
+            None => write!(f, "{:#04X}", self.manufacturer)?,
+        }
+        write!(
+            f,
+            " {} rev {}.{}, serial {:08X}, made {:02}/{}",
+            self.product_name(),
+            self.revision >> 4,
+            self.revision & 0xF,
+            self.serial,
+            self.month,
+            self.year()
+        )
+    }
+}
+
+// eMMC Card Specific Data, mostly timings. Only the fields worth showing are
+// picked out, the rest is in `raw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmmcCsd {
+    pub raw: [u8; CARD_REG_SIZE],
+    pub structure: u8,
+    // 4 for eMMC 4.x and later
+    pub spec_version: u8,
+    pub read_block_len: u8,
+    pub c_size: u16,
+    pub c_size_mult: u8,
+}
+
+// C_SIZE saying "too big for the CSD, see SEC_COUNT in EXT_CSD"
+const CSD_C_SIZE_EXTENDED: u16 = 0xFFF;
+
+impl EmmcCsd {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let raw = register(data)?;
+        Some(Self {
+            raw,
+            structure: bits(&raw, 127, 2) as u8,
+            spec_version: bits(&raw, 125, 4) as u8,
+            read_block_len: bits(&raw, 83, 4) as u8,
+            c_size: bits(&raw, 73, 12) as u16,
+            c_size_mult: bits(&raw, 49, 3) as u8,
+        })
+    }
+
+    // Only for cards up to 2 GB, None for anything sector addressed
+    pub fn capacity(&self) -> Option<u64> {
+        if self.c_size == CSD_C_SIZE_EXTENDED {
+            return None;
+        }
+        let blocks = (self.c_size as u64 + 1) << (self.c_size_mult + 2);
+        Some(blocks << self.read_block_len)
+    }
+}
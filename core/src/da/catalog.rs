@@ -0,0 +1,146 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::da::DAFile;
+use crate::da::bundle::{BUNDLE_EXTENSION, LoaderBundle, load_loader};
+use log::{debug, warn};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+// Loaders built into the binary with the `embedded-loaders` feature, see build.rs.
+// Only put DAs in there that can actually be redistributed.
+#[cfg(feature = "embedded-loaders")]
+const EMBEDDED_LOADERS: &[(&str, &[u8])] =
+    include!(concat!(env!("OUT_DIR"), "/embedded_loaders.rs"));
+#[cfg(not(feature = "embedded-loaders"))]
+const EMBEDDED_LOADERS: &[(&str, &[u8])] = &[];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoaderOrigin {
+    Embedded,
+    Directory(PathBuf),
+}
+
+#[derive(Clone)]
+pub struct LoaderEntry {
+    // Bundle name, or the file name for plain DAs
+    pub name: String,
+    pub origin: LoaderOrigin,
+    pub da: DAFile,
+}
+
+// Loaders to pick from by hw_code when the user didn't select one themselves.
+// Entries added first win, so a loader directory can override the embedded ones.
+#[derive(Clone, Default)]
+pub struct LoaderCatalog {
+    entries: Vec<LoaderEntry>,
+}
+
+impl LoaderCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The user's loader directory (if any) first, then whatever was embedded
+    pub fn with_defaults(dir: Option<&Path>) -> Self {
+        let mut catalog = Self::new();
+        if let Some(dir) = dir
+            && dir.is_dir()
+            && let Err(e) = catalog.add_dir(dir)
+        {
+            warn!("Could not scan loader directory {}: {}", dir.display(), e);
+        }
+        catalog.add_embedded();
+        catalog
+    }
+
+    pub fn add_embedded(&mut self) {
+        for (name, data) in EMBEDDED_LOADERS {
+            match DAFile::parse_da(data) {
+                Ok(da) => self.entries.push(LoaderEntry {
+                    name: name.to_string(),
+                    origin: LoaderOrigin::Embedded,
+                    da,
+                }),
+                // build.rs doesn't parse them, so this is the first time we find out
+                Err(e) => warn!("Embedded loader {} is not a valid DA: {}", name, e),
+            }
+        }
+    }
+
+    // Adds every DA and loader bundle in `dir`, not recursive. Files that fail to
+    // parse are skipped with a warning, a stray .bin shouldn't hide the others.
+    pub fn add_dir(&mut self, dir: &Path) -> Result<usize, Error> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|ext| ext == "bin" || ext == BUNDLE_EXTENSION)
+            })
+            .collect();
+        // read_dir order is up to the filesystem, keep the pick predictable
+        paths.sort();
+
+        let before = self.entries.len();
+        for path in paths {
+            match load_loader(&path) {
+                Ok((da, bundle)) => {
+                    debug!("Found loader {} for {:04X?}", path.display(), da.socs());
+                    self.entries.push(LoaderEntry {
+                        name: entry_name(&path, bundle.as_ref()),
+                        origin: LoaderOrigin::Directory(path),
+                        da,
+                    });
+                }
+                Err(e) => warn!("Skipping {}: {}", path.display(), e),
+            }
+        }
+        Ok(self.entries.len() - before)
+    }
+
+    pub fn entries(&self) -> &[LoaderEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // First loader with an entry for the device
+    pub fn find(&self, hw_code: u16) -> Option<&LoaderEntry> {
+        self.entries.iter().find(|entry| entry.da.supports(hw_code))
+    }
+
+    // The loader called `name`, only if it supports `hw_code`
+    pub fn find_named(&self, name: &str, hw_code: u16) -> Option<&LoaderEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name && entry.da.supports(hw_code))
+    }
+
+    // Every SoC some loader in here supports, sorted
+    pub fn socs(&self) -> Vec<u16> {
+        let mut socs: Vec<u16> = self
+            .entries
+            .iter()
+            .flat_map(|entry| entry.da.socs())
+            .collect();
+        socs.sort_unstable();
+        socs.dedup();
+        socs
+    }
+}
+
+fn entry_name(path: &Path, bundle: Option<&LoaderBundle>) -> String {
+    bundle
+        .map(|bundle| bundle.metadata.name.clone())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Unnamed DA".to_string())
+        })
+}
@@ -3,7 +3,99 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 use log::debug;
-use std::io::Error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::Arc;
+
+// Fixed part of the file header, the SoC entry table follows
+const DA_HEADER_LEN: usize = 0x6C;
+
+// Why a loader file couldn't be parsed.
+// Wrapped in an io::Error, use `DAParseError::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DAParseError {
+    // The file ends before `what` does
+    TooShort {
+        what: &'static str,
+        needed: usize,
+        len: usize,
+    },
+    MissingSignature,
+    TooManyRegions {
+        soc: usize,
+        count: usize,
+        max: usize,
+    },
+    RegionOutOfBounds {
+        soc: usize,
+        region: usize,
+        offset: u32,
+        length: u32,
+        len: usize,
+    },
+    SignatureTooLong {
+        soc: usize,
+        region: usize,
+        sig_len: u32,
+        length: u32,
+    },
+}
+
+impl DAParseError {
+    pub fn from_error(err: &Error) -> Option<&DAParseError> {
+        err.get_ref()?.downcast_ref::<DAParseError>()
+    }
+}
+
+impl fmt::Display for DAParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid DA file: ")?;
+        match self {
+            DAParseError::TooShort { what, needed, len } => write!(
+                f,
+                "truncated {} (needs {} bytes, file is {})",
+                what, needed, len
+            ),
+            DAParseError::MissingSignature => write!(f, "missing MTK_DOWNLOAD_AGENT signature"),
+            DAParseError::TooManyRegions { soc, count, max } => write!(
+                f,
+                "SoC entry {} claims {} regions, at most {} fit",
+                soc, count, max
+            ),
+            DAParseError::RegionOutOfBounds {
+                soc,
+                region,
+                offset,
+                length,
+                len,
+            } => write!(
+                f,
+                "region {} of SoC entry {} ({:#X}+{:#X}) is past the end of the file ({:#X} bytes)",
+                region, soc, offset, length, len
+            ),
+            DAParseError::SignatureTooLong {
+                soc,
+                region,
+                sig_len,
+                length,
+            } => write!(
+                f,
+                "region {} of SoC entry {} has a {} byte signature but is only {} bytes",
+                region, soc, sig_len, length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DAParseError {}
+
+impl From<DAParseError> for Error {
+    fn from(err: DAParseError) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DAType {
@@ -12,9 +104,59 @@ pub enum DAType {
     V6,
 }
 
+// Cheap to clone view into a loader file. V6 loaders can be over 100 MB, so the
+// regions share the buffer the file was read into instead of each getting a copy.
+#[derive(Clone)]
+pub struct DAData {
+    buf: Arc<Vec<u8>>,
+    range: Range<usize>,
+}
+
+impl DAData {
+    // None if `range` is out of bounds, like slice::get
+    pub fn get(&self, range: Range<usize>) -> Option<DAData> {
+        (**self).get(range.clone())?;
+        Some(DAData {
+            buf: Arc::clone(&self.buf),
+            range: self.range.start + range.start..self.range.start + range.end,
+        })
+    }
+}
+
+impl From<Vec<u8>> for DAData {
+    fn from(buf: Vec<u8>) -> Self {
+        let range = 0..buf.len();
+        DAData {
+            buf: Arc::new(buf),
+            range,
+        }
+    }
+}
+
+impl Deref for DAData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.range.clone()]
+    }
+}
+
+impl AsRef<[u8]> for DAData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+// The contents are way too big to be useful in a log
+impl fmt::Debug for DAData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DAData({} bytes)", self.len())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct DAEntryRegion {
-    pub data: Vec<u8>,      // Raw data of the region, including signature if any
+    pub data: DAData,       // Raw data of the region, including signature if any
     pub offset: u32,        // Offset within the file itself, where the region starts
     pub length: u32,        // Length of the region
     pub addr: u32,          // Address in which the region will be loaded in the device
@@ -31,16 +173,34 @@ pub struct DA {
     pub hw_sub_code: u16,
 }
 
+#[derive(Clone)]
 pub struct DAFile {
     // da_file_path: Path,
-    pub da_raw_data: Vec<u8>,
+    pub da_raw_data: DAData,
     pub da_type: DAType,
     pub das: Vec<DA>,
 }
 
 impl DAFile {
+    pub fn load(path: &Path) -> Result<DAFile, Error> {
+        DAFile::parse(std::fs::read(path)?.into())
+    }
+
     pub fn parse_da(raw_data: &[u8]) -> Result<DAFile, Error> {
-        let hdr = &raw_data[..0x6C];
+        DAFile::parse(raw_data.to_vec().into())
+    }
+
+    // Takes ownership of the buffer, regions end up pointing into it.
+    // Every offset comes from the file, so all of them get checked: a truncated
+    // or corrupt loader gives a DAParseError (wrapped in an io::Error), never a panic.
+    pub fn parse(raw_data: DAData) -> Result<DAFile, Error> {
+        let hdr = raw_data
+            .get(0..DA_HEADER_LEN)
+            .ok_or(DAParseError::TooShort {
+                what: "header",
+                needed: DA_HEADER_LEN,
+                len: raw_data.len(),
+            })?;
 
         let da_type = if &hdr[0..2] == b"\xDA\xDA" {
             DAType::Legacy
@@ -51,18 +211,21 @@ impl DAFile {
         };
 
         if da_type != DAType::Legacy && !hdr.windows(0x12).any(|w| w == b"MTK_DOWNLOAD_AGENT") {
-            return Err(Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid DA file: Missing MTK_DOWNLOAD_AGENT signature",
-            ));
+            return Err(DAParseError::MissingSignature.into());
         }
 
         let da_id = String::from_utf8_lossy(&hdr[0x20..0x60])
             .trim_end_matches('\0')
             .to_string();
-        let version = u32::from_le_bytes(hdr[0x60..0x64].try_into().unwrap());
-        let num_socs = u32::from_le_bytes(hdr[0x68..0x6C].try_into().unwrap());
-        let magic_number = &hdr[0x64..0x68];
+        let version = le32(&hdr, 0x60);
+        let num_socs = le32(&hdr, 0x68);
+        debug!(
+            "DA file: id '{}', version {}, magic {:02X?}, {} SoC entries",
+            da_id,
+            version,
+            &hdr[0x64..0x68],
+            num_socs
+        );
 
         let da_entry_size = match da_type {
             DAType::Legacy => 0xD8,
@@ -70,19 +233,25 @@ impl DAFile {
         };
 
         let mut das = Vec::new();
-        for i in 0..num_socs {
+        for i in 0..num_socs as usize {
             // Each one of this is a DA entry in the header
-            let start = 0x6C + (i as usize * da_entry_size);
-            let end = start + da_entry_size;
-            let da_entry = &raw_data[start..end];
+            let start = DA_HEADER_LEN + i * da_entry_size;
+            let da_entry =
+                raw_data
+                    .get(start..start + da_entry_size)
+                    .ok_or(DAParseError::TooShort {
+                        what: "SoC entry table",
+                        needed: start + da_entry_size,
+                        len: raw_data.len(),
+                    })?;
 
             // For each DA, we parse its header entry
-            let magic = u16::from_le_bytes(da_entry[0x00..0x02].try_into().unwrap());
-            let hw_code = u16::from_le_bytes(da_entry[0x02..0x04].try_into().unwrap());
-            let hw_sub_code = u16::from_le_bytes(da_entry[0x04..0x06].try_into().unwrap());
-            let hw_version = u16::from_le_bytes(da_entry[0x06..0x08].try_into().unwrap());
+            let magic = le16(&da_entry, 0x00);
+            let hw_code = le16(&da_entry, 0x02);
+            let hw_sub_code = le16(&da_entry, 0x04);
+            let hw_version = le16(&da_entry, 0x06);
             let mut regions: Vec<DAEntryRegion> = Vec::new();
-            let region_count = u16::from_le_bytes(da_entry[0x12..0x14].try_into().unwrap());
+            let region_count = le16(&da_entry, 0x12) as usize;
             // Structure of the DA header entry
             // 0x00	magic	u16
             // 0x02	hw_code	u16
@@ -95,8 +264,18 @@ impl DAFile {
             // 0x10	entry_region_index	u16
             // 0x12	entry_region_count	u16
             // 0x14	region table starts
+            let max_regions = (da_entry_size - 0x14) / 20;
+            if region_count > max_regions {
+                return Err(DAParseError::TooManyRegions {
+                    soc: i,
+                    count: region_count,
+                    max: max_regions,
+                }
+                .into());
+            }
+
             let mut current_region_offset = 0x14; // Starting from 0x14 to skip the data we already parsed
-            for _ in 0..region_count {
+            for region in 0..region_count {
                 // Each region entry is 20 bytes
                 // 0x00	offset (m_buf)	u32
                 // 0x04	length (m_len)	u32
@@ -105,23 +284,41 @@ impl DAFile {
                 // 0x10	sig_len (m_sig_len)	u32
                 let region_header_data =
                     &da_entry[current_region_offset..current_region_offset + 20];
-                let offset = u32::from_le_bytes(region_header_data[0x00..0x04].try_into().unwrap());
-                let length = u32::from_le_bytes(region_header_data[0x04..0x08].try_into().unwrap());
-                let addr = u32::from_le_bytes(region_header_data[0x08..0x0C].try_into().unwrap());
-                let sig_len =
-                    u32::from_le_bytes(region_header_data[0x10..0x14].try_into().unwrap());
-                let region_data: Vec<u8> =
-                    raw_data[offset as usize..(offset + length) as usize].to_vec();
+                let offset = le32(region_header_data, 0x00);
+                let length = le32(region_header_data, 0x04);
+                let addr = le32(region_header_data, 0x08);
+                let sig_len = le32(region_header_data, 0x10);
                 debug!(
                     "Region: offset={:08X}, length={:08X}, addr={:08X}, sig_len={:08X}",
                     offset, length, addr, sig_len
                 );
+
+                let region_data = (offset as usize)
+                    .checked_add(length as usize)
+                    .and_then(|end| raw_data.get(offset as usize..end))
+                    .ok_or(DAParseError::RegionOutOfBounds {
+                        soc: i,
+                        region,
+                        offset,
+                        length,
+                        len: raw_data.len(),
+                    })?;
+                if sig_len > length {
+                    return Err(DAParseError::SignatureTooLong {
+                        soc: i,
+                        region,
+                        sig_len,
+                        length,
+                    }
+                    .into());
+                }
+
                 regions.push(DAEntryRegion {
                     data: region_data,
                     offset,
                     length,
                     addr,
-                    region_offset: offset - sig_len,
+                    region_offset: offset.saturating_sub(sig_len),
                     sig_len,
                 });
                 current_region_offset += 20; // Move to the next region header
@@ -135,29 +332,105 @@ impl DAFile {
                 hw_sub_code,
             });
             debug!(
-                "Parsed DA entry: hw_code={:04X}, hw_sub_code={:04X}, regions={}",
-                hw_code, hw_sub_code, region_count
+                "Parsed DA entry: hw_code={:04X}, hw_sub_code={:04X}, hw_version={:04X}, regions={}",
+                hw_code, hw_sub_code, hw_version, region_count
             );
         }
 
         Ok(DAFile {
             // da_file_path: Path::new(da_file_path).to_path_buf(),
-            da_raw_data: raw_data.to_vec(),
+            da_raw_data: raw_data,
             da_type,
             das,
         })
     }
 
-    // TODO: Make an Hashmap, possibly also including other info about a chip
     pub fn get_da_from_hw_code(&self, hw_code: u16) -> Option<DA> {
-        let da_code = match hw_code {
-            0x0707 => 0x6768,
-            _ => return None,
-        };
+        let da_code = soc_for_hw_code(hw_code)?;
 
         // I did the clone, I'm sorry!
         self.das.iter().find(|da| da.hw_code == da_code).cloned()
     }
+
+    // SoCs this loader has an entry for (e.g. 0x6768), sorted
+    pub fn socs(&self) -> Vec<u16> {
+        let mut socs: Vec<u16> = self.das.iter().map(|da| da.hw_code).collect();
+        socs.sort_unstable();
+        socs.dedup();
+        socs
+    }
+
+    pub fn supports(&self, hw_code: u16) -> bool {
+        soc_for_hw_code(hw_code).is_some_and(|soc| self.das.iter().any(|da| da.hw_code == soc))
+    }
+
+    // Fails with a LoaderMismatch when there's no entry for the device, so it
+    // can be reported before anything gets sent to it
+    pub fn check_supports(&self, hw_code: u16) -> Result<(), Error> {
+        if self.supports(hw_code) {
+            return Ok(());
+        }
+        Err(LoaderMismatch {
+            hw_code,
+            supported: self.socs(),
+        }
+        .into())
+    }
+}
+
+// BROM hw codes don't match the SoC number DA entries are keyed by.
+// TODO: Make an Hashmap, possibly also including other info about a chip
+pub fn soc_for_hw_code(hw_code: u16) -> Option<u16> {
+    match hw_code {
+        0x0707 => Some(0x6768),
+        _ => None,
+    }
+}
+
+// The selected loader has no entry for the connected device.
+// Wrapped in an io::Error, use `LoaderMismatch::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoaderMismatch {
+    pub hw_code: u16,
+    // SoCs the loader does support, as in DAFile::socs()
+    pub supported: Vec<u16>,
+}
+
+impl LoaderMismatch {
+    pub fn from_error(err: &Error) -> Option<&LoaderMismatch> {
+        err.get_ref()?.downcast_ref::<LoaderMismatch>()
+    }
+}
+
+impl fmt::Display for LoaderMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match soc_for_hw_code(self.hw_code) {
+            Some(soc) => write!(f, "This DA does not support MT{:04X}", soc)?,
+            None => write!(
+                f,
+                "This DA does not support this device (unknown hw code {:04X})",
+                self.hw_code
+            )?,
+        }
+        let supported: Vec<String> = self
+            .supported
+            .iter()
+            .map(|soc| format!("MT{:04X}", soc))
+            .collect();
+        if supported.is_empty() {
+            write!(f, ", it has no SoC entries at all")
+        } else {
+            write!(f, ". Supported: {}", supported.join(", "))
+        }
+    }
+}
+
+impl std::error::Error for LoaderMismatch {}
+
+impl From<LoaderMismatch> for Error {
+    fn from(err: LoaderMismatch) -> Self {
+        Error::new(ErrorKind::Unsupported, err)
+    }
 }
 
 impl DA {
@@ -177,3 +450,11 @@ impl DA {
         }
     }
 }
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
@@ -0,0 +1,96 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+
+// How chatty DA1/DA2 are. Anything at or above the level gets logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DaLogLevel {
+    Trace = 0,
+    Debug = 1,
+    #[default]
+    Info = 2,
+    Warning = 3,
+    Error = 4,
+}
+
+impl DaLogLevel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "trace" => Ok(DaLogLevel::Trace),
+            "debug" => Ok(DaLogLevel::Debug),
+            "info" => Ok(DaLogLevel::Info),
+            "warning" | "warn" => Ok(DaLogLevel::Warning),
+            "error" => Ok(DaLogLevel::Error),
+            _ => Err(format!(
+                "Unknown DA log level '{}', expected trace, debug, info, warning or error",
+                value
+            )),
+        }
+    }
+}
+
+// Where the DA sends its log. USB logs are kept by the DA and don't get mixed
+// into the protocol traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DaLogChannel {
+    // Nothing, e.g. to keep the UART quiet on boards that share it with something
+    None = 0,
+    #[default]
+    Uart = 1,
+    Usb = 2,
+    UartUsb = 3,
+}
+
+impl DaLogChannel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(DaLogChannel::None),
+            "uart" => Ok(DaLogChannel::Uart),
+            "usb" => Ok(DaLogChannel::Usb),
+            "both" | "uart+usb" => Ok(DaLogChannel::UartUsb),
+            _ => Err(format!(
+                "Unknown DA log channel '{}', expected none, uart, usb or both",
+                value
+            )),
+        }
+    }
+}
+
+// The host OS as the DA wants to know it, only changes a few USB timings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DaSystemOs {
+    Windows = 0,
+    #[default]
+    Linux = 1,
+}
+
+// What DA1 gets with SetupEnvironment, right after it syncs. The defaults are
+// what we always sent: info level on the UART, Linux, no UFS provisioning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DaEnvConfig {
+    pub log_level: DaLogLevel,
+    pub log_channel: DaLogChannel,
+    pub system_os: DaSystemOs,
+    // Lets the DA provision a blank UFS (LU layout). Leave off unless that's
+    // what you're doing, it's not something a configured device needs.
+    pub ufs_provision: bool,
+}
+
+impl DaEnvConfig {
+    // Five LE u32s, the last one is reserved
+    pub fn to_bytes(&self) -> [u8; 20] {
+        let fields = [
+            self.log_level as u32,
+            self.log_channel as u32,
+            self.system_os as u32,
+            self.ufs_provision as u32,
+            0,
+        ];
+        let mut bytes = [0u8; 20];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+}
@@ -2,12 +2,37 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
+pub mod bundle;
+pub mod card_regs;
+pub mod catalog;
 pub mod da;
+pub mod env;
+pub mod patch;
 pub mod protocol;
+pub mod secure_boot;
+pub mod signature;
+pub mod status;
+pub mod storage_health;
+pub mod storage_ops;
+pub mod write_protect;
 pub mod xflash;
+pub use bundle::LoaderBundle;
+pub use card_regs::{EmmcCid, EmmcCsd};
+pub use catalog::LoaderCatalog;
 pub use da::DA;
+pub use da::DAData;
 pub use da::DAEntryRegion;
 pub use da::DAFile;
+pub use da::DAParseError;
 pub use da::DAType;
-pub use protocol::DAProtocol;
+pub use da::LoaderMismatch;
+pub use env::{DaEnvConfig, DaLogChannel, DaLogLevel, DaSystemOs};
+pub use patch::PatchVerifyError;
+pub use protocol::{Capabilities, DAProtocol, DaStorageView, ProtocolKind, ShutdownMode};
+pub use secure_boot::SecureBootRejection;
+pub use signature::{SendDaPayload, SignatureHandling};
+pub use status::DAStatusError;
+pub use storage_health::{PreEol, StorageHealth};
+pub use storage_ops::{ProtocolStorage, StorageInfo, StorageOps};
+pub use write_protect::{WriteProtectStatus, WriteProtected};
 pub use xflash::XFlash;
@@ -0,0 +1,227 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::da::DAEntryRegion;
+use log::debug;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// One change made to DA2 code, e.g. by an exploit disabling a check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodePatch {
+    // Offset from the start of the region
+    pub offset: usize,
+    // What has to be there before patching, so we know we're patching the right thing
+    pub original: Vec<u8>,
+    pub patched: Vec<u8>,
+    // Mnemonic of the first patched instruction (e.g. "movs"), only checked with
+    // the `disasm` feature
+    pub expect: Option<&'static str>,
+}
+
+// Why a patched DA2 looks wrong. Booting it anyway usually means a hung DA.
+// Wrapped in an io::Error, use `PatchVerifyError::from_error` to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchVerifyError {
+    LengthMismatch {
+        expected: usize,
+        got: usize,
+    },
+    // Patch (partly) outside the code, e.g. in the signature
+    OutsideText {
+        offset: usize,
+        len: usize,
+        text_len: usize,
+    },
+    // The bytes before patching aren't what the patch expects, wrong DA build
+    OriginalMismatch {
+        offset: usize,
+    },
+    // The patched bytes aren't what the patch says it writes
+    NotApplied {
+        offset: usize,
+    },
+    // A byte changed that no patch accounts for
+    UndeclaredChange {
+        offset: usize,
+    },
+    // What the patch landed on doesn't disassemble to what it should
+    BadInstruction {
+        offset: usize,
+        found: String,
+        expected: String,
+    },
+}
+
+impl PatchVerifyError {
+    pub fn from_error(err: &Error) -> Option<&PatchVerifyError> {
+        err.get_ref()?.downcast_ref::<PatchVerifyError>()
+    }
+}
+
+impl fmt::Display for PatchVerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Refusing to boot patched DA2: ")?;
+        match self {
+            PatchVerifyError::LengthMismatch { expected, got } => {
+                write!(f, "it is {} bytes, expected {}", got, expected)
+            }
+            PatchVerifyError::OutsideText {
+                offset,
+                len,
+                text_len,
+            } => write!(
+                f,
+                "patch at {:#X} ({} bytes) is outside the code ({:#X} bytes)",
+                offset, len, text_len
+            ),
+            PatchVerifyError::OriginalMismatch { offset } => write!(
+                f,
+                "unexpected original bytes at {:#X}, the patch doesn't fit this DA",
+                offset
+            ),
+            PatchVerifyError::NotApplied { offset } => {
+                write!(f, "patch at {:#X} didn't land", offset)
+            }
+            PatchVerifyError::UndeclaredChange { offset } => {
+                write!(f, "byte at {:#X} changed without a patch for it", offset)
+            }
+            PatchVerifyError::BadInstruction {
+                offset,
+                found,
+                expected,
+            } => write!(
+                f,
+                "patch at {:#X} decodes to '{}', expected '{}'",
+                offset, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchVerifyError {}
+
+impl From<PatchVerifyError> for Error {
+    fn from(err: PatchVerifyError) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+// Sanity checks a patched DA2 against the region it came from before it gets
+// booted. `patched` can be sent with or without the signature, patches have to
+// stay within the code either way.
+pub fn verify_da2_patch(
+    region: &DAEntryRegion,
+    patched: &[u8],
+    patches: &[CodePatch],
+) -> Result<(), PatchVerifyError> {
+    let original: &[u8] = &region.data;
+    let text_len = original.len().saturating_sub(region.sig_len as usize);
+
+    if patched.len() != original.len() && patched.len() != text_len {
+        return Err(PatchVerifyError::LengthMismatch {
+            expected: text_len,
+            got: patched.len(),
+        });
+    }
+
+    for patch in patches {
+        let len = patch.patched.len();
+        let end = patch.offset.checked_add(len).filter(|&end| end <= text_len);
+        if end.is_none() || patch.original.len() != len {
+            return Err(PatchVerifyError::OutsideText {
+                offset: patch.offset,
+                len,
+                text_len,
+            });
+        }
+        let range = patch.offset..patch.offset + len;
+        if original[range.clone()] != patch.original[..] {
+            return Err(PatchVerifyError::OriginalMismatch {
+                offset: patch.offset,
+            });
+        }
+        if patched[range] != patch.patched[..] {
+            return Err(PatchVerifyError::NotApplied {
+                offset: patch.offset,
+            });
+        }
+        check_instruction(patched, patch)?;
+    }
+
+    let declared = |offset: usize| {
+        patches
+            .iter()
+            .any(|p| (p.offset..p.offset + p.patched.len()).contains(&offset))
+    };
+    if let Some(offset) = (0..patched.len()).find(|&i| patched[i] != original[i] && !declared(i)) {
+        return Err(PatchVerifyError::UndeclaredChange { offset });
+    }
+
+    debug!(
+        "[Penumbra] Patched DA2 passed verification ({} patches)",
+        patches.len()
+    );
+    Ok(())
+}
+
+// V5 DA2 is Thumb-2, a patch that doesn't decode (or decodes to something else)
+// landed in the wrong spot
+#[cfg(feature = "disasm")]
+fn check_instruction(patched: &[u8], patch: &CodePatch) -> Result<(), PatchVerifyError> {
+    use capstone::prelude::*;
+
+    let cs = Capstone::new()
+        .arm()
+        .mode(arch::arm::ArchMode::Thumb)
+        .build()
+        .map_err(|e| PatchVerifyError::BadInstruction {
+            offset: patch.offset,
+            found: format!("capstone: {}", e),
+            expected: "a disassembler".to_string(),
+        })?;
+
+    let end = (patch.offset + patch.patched.len() + 8).min(patched.len());
+    let code = &patched[patch.offset..end];
+    let insns = cs.disasm_count(code, patch.offset as u64, 4).map_err(|e| {
+        PatchVerifyError::BadInstruction {
+            offset: patch.offset,
+            found: e.to_string(),
+            expected: patch.expect.unwrap_or("valid code").to_string(),
+        }
+    })?;
+
+    let Some(first) = insns.iter().next() else {
+        return Err(PatchVerifyError::BadInstruction {
+            offset: patch.offset,
+            found: "nothing".to_string(),
+            expected: patch.expect.unwrap_or("valid code").to_string(),
+        });
+    };
+    for insn in insns.iter() {
+        debug!(
+            "[Penumbra] DA2 {:#X}: {} {}",
+            insn.address(),
+            insn.mnemonic().unwrap_or("?"),
+            insn.op_str().unwrap_or("")
+        );
+    }
+
+    let mnemonic = first.mnemonic().unwrap_or("");
+    match patch.expect {
+        Some(expected) if !mnemonic.eq_ignore_ascii_case(expected) => {
+            Err(PatchVerifyError::BadInstruction {
+                offset: patch.offset,
+                found: mnemonic.to_string(),
+                expected: expected.to_string(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "disasm"))]
+fn check_instruction(_patched: &[u8], _patch: &CodePatch) -> Result<(), PatchVerifyError> {
+    Ok(())
+}
@@ -4,8 +4,59 @@
 */
 use crate::connection::Connection;
 use crate::connection::port::ConnectionType;
+use crate::core::storage::StorageType;
+use crate::da::xflash::XFlashStorage;
+use crate::da::{
+    EmmcCid, EmmcCsd, ProtocolStorage, StorageHealth, StorageOps, WriteProtectStatus, XFlash,
+};
+use std::ops::{Deref, DerefMut};
 use tokio::io::Error;
 
+// The storage layout as the DA sees it. V5 DAs don't hand out their partition
+// list, only what kind of table they found and how big the storage is, so
+// that's what the GPT gets checked against. Anything the DA didn't answer is None.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DaStorageView {
+    // Raw GetPartitionTblCata answer
+    pub table_category: Option<u32>,
+    pub block_size: Option<u32>,
+    // User area size in bytes
+    pub user_size: Option<u64>,
+}
+
+// What a protocol (and the DA behind it) can do right now, so callers can
+// refuse or hide an action up front instead of failing halfway through it.
+// Things that depend on the DA extensions only count once they're running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    pub read_flash: bool,
+    pub write_flash: bool,
+    pub format_flash: bool,
+    // read32/write32 and friends, SEJ goes through these too
+    pub registers: bool,
+    // da_x.bin is loaded, see xflash/exts.rs
+    pub extensions: bool,
+    pub rpmb: bool,
+    pub ufs: bool,
+    pub write_protect: bool,
+    pub shutdown: bool,
+    pub attach: bool,
+    pub storage_view: bool,
+    pub storage_health: bool,
+    // Biggest single read_flash worth asking for, None if there's no limit
+    pub max_read_size: Option<usize>,
+}
+
+// What the device does once the DA lets go of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    PowerOff,
+    // Regular boot to Android
+    Normal,
+    // Straight to the bootloader's fastboot mode
+    Fastboot,
+}
+
 #[async_trait::async_trait]
 pub trait DAProtocol: Send {
     // Main helpers
@@ -23,6 +74,21 @@ pub trait DAProtocol: Send {
         progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<Vec<u8>, Error>;
 
+    // Same as read_flash, but into `buf` (buf.len() bytes at `addr`) instead of a
+    // new Vec. Returns how many bytes the DA sent. The default copies, protocols
+    // that can should read straight into `buf`.
+    async fn read_flash_into(
+        &mut self,
+        addr: u64,
+        buf: &mut [u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<usize, Error> {
+        let data = self.read_flash(addr, buf.len(), progress).await?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
     async fn write_flash(
         &mut self,
         addr: u64,
@@ -31,16 +97,208 @@ pub trait DAProtocol: Send {
         progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<(), Error>;
 
+    async fn format_flash(
+        &mut self,
+        addr: u64,
+        size: u64,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error>;
+
     async fn download(&mut self, part_name: String, data: &[u8]) -> Result<(), Error>;
 
     // Memory
     async fn read32(&mut self, addr: u32) -> Result<u32, Error>;
     async fn write32(&mut self, addr: u32, value: u32) -> Result<(), Error>;
+    // Batched variants for register sequences, the defaults just loop over
+    // read32/write32. Values come back in the same order as `addrs`.
+    async fn read32_multi(&mut self, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+        let mut values = Vec::with_capacity(addrs.len());
+        for &addr in addrs {
+            values.push(self.read32(addr).await?);
+        }
+        Ok(values)
+    }
+    async fn write32_multi(&mut self, writes: &[(u32, u32)]) -> Result<(), Error> {
+        for &(addr, value) in writes {
+            self.write32(addr, value).await?;
+        }
+        Ok(())
+    }
+    // Reads `size` bytes of memory (not flash) at `addr`, word by word by default.
+    async fn read_mem(&mut self, addr: u32, size: usize) -> Result<Vec<u8>, Error> {
+        let addrs: Vec<u32> = (0..size.div_ceil(4) as u32).map(|i| addr + i * 4).collect();
+        let mut data: Vec<u8> = self
+            .read32_multi(&addrs)
+            .await?
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        data.truncate(size);
+        Ok(data)
+    }
+
+    // Picks up a DA left running by an earlier session instead of uploading one
+    async fn attach(&mut self) -> Result<(), Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Attaching to a running DA is not supported by this protocol",
+        ))
+    }
+
+    // eMMC write protection on a user area range. Needs DA support, so both
+    // are Unsupported unless the protocol says otherwise.
+    async fn write_protect_status(
+        &mut self,
+        _addr: u64,
+        _size: u64,
+    ) -> Result<WriteProtectStatus, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Querying write protection is not supported by this protocol",
+        ))
+    }
+    async fn clear_write_protect(&mut self, _addr: u64, _size: u64) -> Result<(), Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Clearing write protection is not supported by this protocol",
+        ))
+    }
+
+    // What the DA found on the storage by itself, to cross-check the GPT we parse
+    async fn storage_view(&mut self) -> Result<DaStorageView, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Querying the storage layout is not supported by this protocol",
+        ))
+    }
+
+    // Flash wear (pre-EOL and life time estimates), needs DA support
+    async fn storage_health(&mut self) -> Result<StorageHealth, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Reading storage health is not supported by this protocol",
+        ))
+    }
+
+    // eMMC card registers and the RPMB write counter, to identify the storage
+    async fn emmc_cid(&mut self) -> Result<EmmcCid, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Reading the eMMC CID is not supported by this protocol",
+        ))
+    }
+    async fn emmc_csd(&mut self) -> Result<EmmcCsd, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Reading the eMMC CSD is not supported by this protocol",
+        ))
+    }
+    async fn rpmb_write_counter(&mut self) -> Result<u32, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Reading the RPMB write counter is not supported by this protocol",
+        ))
+    }
+
+    // Ends the DA session, the connection is gone afterwards
+    async fn shutdown(&mut self, _mode: ShutdownMode) -> Result<(), Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Rebooting is not supported by this protocol",
+        ))
+    }
 
     async fn get_usb_speed(&mut self) -> Result<u32, Error>;
+
+    // Claims nothing unless the protocol says otherwise
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
     // fn set_usb_speed(&mut self, speed: u32) -> Result<(), Error>;
 
     // Connection
     fn get_connection(&mut self) -> &mut Connection;
     fn set_connection_type(&mut self, conn_type: ConnectionType) -> Result<(), Error>;
 }
+
+// The protocol a Device talks through. Known protocols are matched on directly,
+// so the small calls that run thousands of times per operation (status, register
+// access) skip async_trait's boxed futures. Everything else goes through the
+// trait via Deref, and `Other` takes any DAProtocol implementation.
+pub enum ProtocolKind<'a> {
+    XFlash(XFlash),
+    Other(Box<dyn DAProtocol + 'a + Send>),
+}
+
+impl ProtocolKind<'_> {
+    // Flash access for `storage`, XFlash needs to be told which one it is
+    pub fn storage(&mut self, storage: StorageType) -> Box<dyn StorageOps + '_> {
+        match self {
+            ProtocolKind::XFlash(xflash) => Box::new(XFlashStorage::new(xflash, storage)),
+            ProtocolKind::Other(protocol) => {
+                Box::new(ProtocolStorage::new(protocol.as_mut(), storage))
+            }
+        }
+    }
+
+    pub async fn get_status(&mut self) -> Result<u32, Error> {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash.get_status().await,
+            ProtocolKind::Other(protocol) => protocol.get_status().await,
+        }
+    }
+
+    pub async fn read32(&mut self, addr: u32) -> Result<u32, Error> {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash.read32(addr).await,
+            ProtocolKind::Other(protocol) => protocol.read32(addr).await,
+        }
+    }
+
+    pub async fn write32(&mut self, addr: u32, value: u32) -> Result<(), Error> {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash.write32(addr, value).await,
+            ProtocolKind::Other(protocol) => protocol.write32(addr, value).await,
+        }
+    }
+
+    pub async fn read32_multi(&mut self, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash.read32_multi(addrs).await,
+            ProtocolKind::Other(protocol) => protocol.read32_multi(addrs).await,
+        }
+    }
+
+    pub async fn write32_multi(&mut self, writes: &[(u32, u32)]) -> Result<(), Error> {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash.write32_multi(writes).await,
+            ProtocolKind::Other(protocol) => protocol.write32_multi(writes).await,
+        }
+    }
+}
+
+impl<'a> Deref for ProtocolKind<'a> {
+    type Target = dyn DAProtocol + 'a + Send;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash,
+            ProtocolKind::Other(protocol) => protocol.as_ref(),
+        }
+    }
+}
+
+impl DerefMut for ProtocolKind<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            ProtocolKind::XFlash(xflash) => xflash,
+            ProtocolKind::Other(protocol) => protocol.as_mut(),
+        }
+    }
+}
+
+impl From<XFlash> for ProtocolKind<'_> {
+    fn from(xflash: XFlash) -> Self {
+        ProtocolKind::XFlash(xflash)
+    }
+}
@@ -0,0 +1,84 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::exploit::{BootStage, find_exploit};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// Returned (wrapped in an io::Error) when the device refuses to run a DA stage
+// because of signature enforcement. Use `SecureBootRejection::from_error` to
+// tell it apart from other failures.
+#[derive(Debug, Clone)]
+pub struct SecureBootRejection {
+    // Stage that got rejected, either Da1 (by BROM/Preloader) or Da2 (by DA1)
+    pub stage: BootStage,
+    pub status: Option<u32>,
+    // Exploit that can get around the check, if we have one
+    pub bypass: Option<String>,
+    pub bypass_attempted: bool,
+}
+
+impl SecureBootRejection {
+    pub fn new(stage: BootStage, status: Option<u32>) -> Self {
+        // DA1 is checked by whatever runs before it, DA2 by DA1 itself
+        let bypass_stage = match stage {
+            BootStage::Da2 => BootStage::Da1,
+            _ => BootStage::Brom,
+        };
+
+        Self {
+            stage,
+            status,
+            bypass: find_exploit(bypass_stage).map(|meta| meta.name),
+            bypass_attempted: false,
+        }
+    }
+
+    pub fn attempted(mut self) -> Self {
+        self.bypass_attempted = true;
+        self
+    }
+
+    pub fn from_error(err: &Error) -> Option<&SecureBootRejection> {
+        err.get_ref()?.downcast_ref::<SecureBootRejection>()
+    }
+}
+
+impl fmt::Display for SecureBootRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self.stage {
+            BootStage::Da2 => "DA2",
+            _ => "DA1",
+        };
+        write!(f, "{} was rejected by secure boot", stage)?;
+        if let Some(status) = self.status {
+            write!(f, " (status 0x{:04X})", status)?;
+        }
+        write!(
+            f,
+            ". The DA is probably unsigned or signed for a different device."
+        )?;
+
+        match (&self.bypass, self.bypass_attempted) {
+            (Some(name), true) => write!(
+                f,
+                " {} was attempted but didn't work, use the DA from this device's firmware.",
+                name
+            ),
+            (Some(name), false) => write!(f, " {} may be able to bypass this check.", name),
+            (None, _) => write!(
+                f,
+                " No known exploit applies here, use the DA from this device's firmware."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SecureBootRejection {}
+
+impl From<SecureBootRejection> for Error {
+    fn from(rejection: SecureBootRejection) -> Self {
+        Error::new(ErrorKind::PermissionDenied, rejection)
+    }
+}
@@ -0,0 +1,204 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::da::{DAData, DAEntryRegion};
+use log::{info, warn};
+use std::io::{Error, ErrorKind};
+
+// What to do with a region's signature when sending it with SendDA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureHandling {
+    // Send the signature when there is one, drop it when it's missing or blank
+    #[default]
+    Auto,
+    // Always send what the header says is the signature
+    Keep,
+    // Never send it, for modified DAs whose signature no longer matches the code.
+    // Only boots with secure boot off (or bypassed), DAA rejects unsigned DAs.
+    Strip,
+}
+
+// The SendDA arguments for a region. The length always matches what's in
+// `data`, whatever the region header said.
+#[derive(Debug, Clone)]
+pub struct SendDaPayload {
+    pub data: DAData,
+    pub length: u32,
+    pub sig_len: u32,
+}
+
+impl SendDaPayload {
+    pub fn is_signed(&self) -> bool {
+        self.sig_len > 0
+    }
+
+    fn unsigned(data: DAData) -> Self {
+        Self {
+            length: data.len() as u32,
+            data,
+            sig_len: 0,
+        }
+    }
+}
+
+pub fn prepare_send_da(
+    region: &DAEntryRegion,
+    handling: SignatureHandling,
+) -> Result<SendDaPayload, Error> {
+    let data = region.data.clone();
+    let sig_len = region.sig_len as usize;
+    // The header still counts a signature that was never appended (or cut off
+    // since), the region is exactly that much short
+    let absent = sig_len > 0 && data.len() as u64 + sig_len as u64 == region.length as u64;
+
+    if data.len() as u64 != region.length as u64 && !absent {
+        warn!(
+            "Region is {:#X} bytes but its header says {:#X}, sending what's there",
+            data.len(),
+            region.length
+        );
+    }
+
+    if sig_len == 0 {
+        return Ok(SendDaPayload::unsigned(data));
+    }
+
+    if absent {
+        if handling == SignatureHandling::Keep {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The DA header has a {} byte signature, but the region doesn't include it",
+                    sig_len
+                ),
+            ));
+        }
+        info!("DA has no signature appended, sending it unsigned");
+        return Ok(SendDaPayload::unsigned(data));
+    }
+
+    let Some(code_len) = data.len().checked_sub(sig_len) else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Signature ({} bytes) is longer than the region ({} bytes)",
+                sig_len,
+                data.len()
+            ),
+        ));
+    };
+
+    let signature = &data[code_len..];
+    let blank = signature.iter().all(|&b| b == 0) || signature.iter().all(|&b| b == 0xFF);
+    let strip = match handling {
+        SignatureHandling::Auto => blank,
+        SignatureHandling::Keep => false,
+        SignatureHandling::Strip => true,
+    };
+    if !strip {
+        return Ok(SendDaPayload {
+            length: data.len() as u32,
+            data,
+            sig_len: sig_len as u32,
+        });
+    }
+
+    if blank {
+        info!("DA signature is blank, sending it unsigned");
+    } else {
+        info!("Stripping the {} byte DA signature", sig_len);
+    }
+    // code_len <= data.len(), can't be out of bounds
+    let code = data.get(0..code_len).unwrap_or(data);
+    Ok(SendDaPayload::unsigned(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &[u8] = &[0xA5; 0x40];
+    const SIG_LEN: usize = 0x10;
+
+    // A region holding CODE followed by `signature`, with a header claiming
+    // SIG_LEN bytes of signature and `length` bytes in total
+    fn region(signature: &[u8], length: usize) -> DAEntryRegion {
+        DAEntryRegion {
+            data: [CODE, signature].concat().into(),
+            offset: 0,
+            length: length as u32,
+            addr: 0x200000,
+            region_offset: 0,
+            sig_len: SIG_LEN as u32,
+        }
+    }
+
+    fn signed() -> DAEntryRegion {
+        let signature: Vec<u8> = (1..=SIG_LEN as u8).collect();
+        region(&signature, CODE.len() + SIG_LEN)
+    }
+
+    fn assert_unsigned(payload: &SendDaPayload) {
+        assert!(!payload.is_signed());
+        assert_eq!(&payload.data[..], CODE);
+        assert_eq!(payload.length as usize, CODE.len());
+    }
+
+    fn assert_signed(payload: &SendDaPayload) {
+        assert!(payload.is_signed());
+        assert_eq!(payload.sig_len as usize, SIG_LEN);
+        assert_eq!(payload.length as usize, CODE.len() + SIG_LEN);
+        assert_eq!(&payload.data[..CODE.len()], CODE);
+    }
+
+    #[test]
+    fn unsigned_region() {
+        let mut region = region(&[], CODE.len());
+        region.sig_len = 0;
+        for handling in [
+            SignatureHandling::Auto,
+            SignatureHandling::Keep,
+            SignatureHandling::Strip,
+        ] {
+            assert_unsigned(&prepare_send_da(&region, handling).unwrap());
+        }
+    }
+
+    #[test]
+    fn real_signature() {
+        let region = signed();
+        assert_signed(&prepare_send_da(&region, SignatureHandling::Auto).unwrap());
+        assert_signed(&prepare_send_da(&region, SignatureHandling::Keep).unwrap());
+        assert_unsigned(&prepare_send_da(&region, SignatureHandling::Strip).unwrap());
+    }
+
+    #[test]
+    fn blank_signature() {
+        for fill in [0x00, 0xFF] {
+            let region = region(&[fill; SIG_LEN], CODE.len() + SIG_LEN);
+            assert_unsigned(&prepare_send_da(&region, SignatureHandling::Auto).unwrap());
+            assert_signed(&prepare_send_da(&region, SignatureHandling::Keep).unwrap());
+            assert_unsigned(&prepare_send_da(&region, SignatureHandling::Strip).unwrap());
+        }
+    }
+
+    #[test]
+    fn absent_signature() {
+        // The header counts the signature, the data stops right before it
+        let region = region(&[], CODE.len() + SIG_LEN);
+        assert_unsigned(&prepare_send_da(&region, SignatureHandling::Auto).unwrap());
+        assert_unsigned(&prepare_send_da(&region, SignatureHandling::Strip).unwrap());
+
+        let err = prepare_send_da(&region, SignatureHandling::Keep).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn signature_longer_than_region() {
+        let mut region = region(&[], CODE.len());
+        region.sig_len = CODE.len() as u32 + 1;
+        let err = prepare_send_da(&region, SignatureHandling::Auto).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
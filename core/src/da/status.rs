@@ -0,0 +1,46 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// Non zero status returned by the DA, wrapped in an io::Error.
+// Use `DAStatusError::from_error` to get the raw status back.
+#[derive(Debug, Clone)]
+pub struct DAStatusError {
+    // What we were doing when the DA complained, e.g. "ReadData"
+    pub context: String,
+    pub status: u32,
+}
+
+impl DAStatusError {
+    pub fn new(context: impl Into<String>, status: u32) -> Self {
+        Self {
+            context: context.into(),
+            status,
+        }
+    }
+
+    pub fn from_error(err: &Error) -> Option<&DAStatusError> {
+        err.get_ref()?.downcast_ref::<DAStatusError>()
+    }
+}
+
+impl fmt::Display for DAStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed with status 0x{:08X}",
+            self.context, self.status
+        )
+    }
+}
+
+impl std::error::Error for DAStatusError {}
+
+impl From<DAStatusError> for Error {
+    fn from(err: DAStatusError) -> Self {
+        Error::new(ErrorKind::Other, err)
+    }
+}
@@ -0,0 +1,116 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+
+// EXT_CSD PRE_EOL_INFO (267), DEVICE_LIFE_TIME_EST_TYP_A (268) and _B (269)
+const EXT_CSD_PRE_EOL_INFO: usize = 267;
+const EXT_CSD_LIFE_TIME_EST_A: usize = 268;
+const EXT_CSD_LIFE_TIME_EST_B: usize = 269;
+const EXT_CSD_SIZE: usize = 512;
+
+// UFS health descriptor: bLength | bDescriptorIDN | bPreEOLInfo | bDeviceLifeTimeEstA | B
+const UFS_HEALTH_DESC_IDN: u8 = 0x09;
+
+// Which register dump ExtGetStorageHealth answered with
+const HEALTH_SOURCE_EMMC: u32 = 1;
+const HEALTH_SOURCE_UFS: u32 = 2;
+
+// Flash wear as the storage reports it, through the DA extensions
+// (ExtGetStorageHealth). eMMC (5.0+) and UFS (2.1+) use the same encoding, so
+// both end up here. Anything the storage left at 0 ("not defined") is None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StorageHealth {
+    pub pre_eol: Option<PreEol>,
+    // Life time used, in 10% steps, for the SLC (A) and MLC/TLC (B) areas.
+    // 0x0B means the estimate was exceeded.
+    pub life_time_a: Option<u8>,
+    pub life_time_b: Option<u8>,
+}
+
+// How much of the reserved blocks is used up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreEol {
+    Normal,
+    // 80% of the reserved blocks are gone
+    Warning,
+    Urgent,
+}
+
+impl StorageHealth {
+    // Layout: source u32 | the raw EXT_CSD (512 bytes) or UFS health descriptor
+    pub fn parse(data: &[u8]) -> Option<StorageHealth> {
+        let source = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+        let regs = &data[4..];
+        let (pre_eol, a, b) = match source {
+            HEALTH_SOURCE_EMMC if regs.len() >= EXT_CSD_SIZE => (
+                regs[EXT_CSD_PRE_EOL_INFO],
+                regs[EXT_CSD_LIFE_TIME_EST_A],
+                regs[EXT_CSD_LIFE_TIME_EST_B],
+            ),
+            HEALTH_SOURCE_UFS if regs.len() >= 5 && regs[1] == UFS_HEALTH_DESC_IDN => {
+                (regs[2], regs[3], regs[4])
+            }
+            _ => return None,
+        };
+        Some(StorageHealth::from_registers(pre_eol, a, b))
+    }
+
+    pub fn from_registers(pre_eol: u8, life_time_a: u8, life_time_b: u8) -> Self {
+        let life_time = |value: u8| (1..=0x0B).contains(&value).then_some(value);
+        StorageHealth {
+            pre_eol: match pre_eol {
+                1 => Some(PreEol::Normal),
+                2 => Some(PreEol::Warning),
+                3 => Some(PreEol::Urgent),
+                _ => None,
+            },
+            life_time_a: life_time(life_time_a),
+            life_time_b: life_time(life_time_b),
+        }
+    }
+
+    // Worst of the two estimates, as a percentage range of life time used
+    pub fn worst_life_time(&self) -> Option<u8> {
+        self.life_time_a.max(self.life_time_b)
+    }
+
+    // Worth telling whoever is about to put time into this device
+    pub fn is_worn(&self) -> bool {
+        matches!(self.pre_eol, Some(PreEol::Warning | PreEol::Urgent))
+            || self.worst_life_time().is_some_and(|value| value >= 0x09)
+    }
+}
+
+// 0x01 is 0-10% used, 0x0A 90-100%, 0x0B past the estimate
+pub fn describe_life_time(value: u8) -> String {
+    match value {
+        0x0B => "exceeded".to_string(),
+        _ => format!("{}-{}% used", (value - 1) * 10, value * 10),
+    }
+}
+
+impl fmt::Display for PreEol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PreEol::Normal => "normal",
+            PreEol::Warning => "warning",
+            PreEol::Urgent => "urgent",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for StorageHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = || "unknown".to_string();
+        write!(
+            f,
+            "Pre-EOL: {}, life time A: {}, B: {}",
+            self.pre_eol.map_or_else(unknown, |eol| eol.to_string()),
+            self.life_time_a.map_or_else(unknown, describe_life_time),
+            self.life_time_b.map_or_else(unknown, describe_life_time),
+        )
+    }
+}
@@ -0,0 +1,114 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::storage::StorageType;
+use crate::da::DAProtocol;
+use tokio::io::Error;
+
+// The storage behind a StorageOps. Anything the DA didn't answer is None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageInfo {
+    pub storage: StorageType,
+    pub block_size: Option<u32>,
+    // User area size in bytes
+    pub user_size: Option<u64>,
+}
+
+// Raw access to one storage through one protocol, what Device's partition
+// logic sits on. Addresses are bytes into the user area. Protocols that need
+// to know the storage get an implementation per combination (see
+// XFlashStorage), ProtocolStorage does for any other DAProtocol.
+#[async_trait::async_trait]
+pub trait StorageOps: Send {
+    // Reads buf.len() bytes at `addr` into `buf`, returns how many came back
+    async fn read_range(
+        &mut self,
+        addr: u64,
+        buf: &mut [u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<usize, Error>;
+
+    async fn write_range(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error>;
+
+    async fn erase_range(
+        &mut self,
+        addr: u64,
+        size: u64,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error>;
+
+    async fn info(&mut self) -> Result<StorageInfo, Error>;
+
+    // read_range into a new Vec, cut down to what came back
+    async fn read_vec(
+        &mut self,
+        addr: u64,
+        size: usize,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; size];
+        let len = self.read_range(addr, &mut buf, progress).await?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+// StorageOps on top of the plain DAProtocol flash calls, for protocols that
+// pick the storage by themselves
+pub struct ProtocolStorage<'a, 'p> {
+    protocol: &'a mut (dyn DAProtocol + 'p + Send),
+    storage: StorageType,
+}
+
+impl<'a, 'p> ProtocolStorage<'a, 'p> {
+    pub fn new(protocol: &'a mut (dyn DAProtocol + 'p + Send), storage: StorageType) -> Self {
+        Self { protocol, storage }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageOps for ProtocolStorage<'_, '_> {
+    async fn read_range(
+        &mut self,
+        addr: u64,
+        buf: &mut [u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<usize, Error> {
+        self.protocol.read_flash_into(addr, buf, progress).await
+    }
+
+    async fn write_range(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        self.protocol
+            .write_flash(addr, data.len(), data, progress)
+            .await
+    }
+
+    async fn erase_range(
+        &mut self,
+        addr: u64,
+        size: u64,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        self.protocol.format_flash(addr, size, progress).await
+    }
+
+    async fn info(&mut self) -> Result<StorageInfo, Error> {
+        let view = self.protocol.storage_view().await?;
+        Ok(StorageInfo {
+            storage: self.storage,
+            block_size: view.block_size,
+            user_size: view.user_size,
+        })
+    }
+}
@@ -0,0 +1,139 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// EXT_CSD USER_WP (171) and BOOT_WP (173) bits
+const US_PWR_WP_EN: u8 = 1 << 0;
+const US_PERM_WP_EN: u8 = 1 << 2;
+const B_PWR_WP_EN: u8 = 1 << 0;
+const B_PERM_WP_EN: u8 = 1 << 2;
+
+// eMMC write protection covering a range of the user area, as reported by the
+// DA extensions (ExtGetWriteProtect).
+//
+// Power-on protection lasts until the next power cycle and permanent protection
+// forever, only group protection (CMD28) can be cleared with CMD29 right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteProtectStatus {
+    pub user_wp: u8,
+    pub boot_wp: u8,
+    // Size of a write protect group in bytes
+    pub group_size: u32,
+    // Groups in the queried range with temporary (CMD28) protection
+    pub protected_groups: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteProtectKind {
+    // CMD28 on some groups, can be cleared
+    Temporary,
+    // Until the next power cycle, e.g. set by the preloader on boot
+    PowerOn,
+    Permanent,
+    // The write failed and the range is protected, but the DA couldn't say how
+    Unknown,
+}
+
+impl WriteProtectStatus {
+    // Layout: USER_WP u8 | BOOT_WP u8 | reserved u16 | group_size u32 | protected_groups u32
+    pub fn parse(data: &[u8]) -> Option<WriteProtectStatus> {
+        let word = |i: usize| Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+        Some(WriteProtectStatus {
+            user_wp: *data.first()?,
+            boot_wp: *data.get(1)?,
+            group_size: word(4)?,
+            protected_groups: word(8)?,
+        })
+    }
+
+    // The strongest protection on the user area, None if writes should go through
+    pub fn user_protection(&self) -> Option<WriteProtectKind> {
+        if self.user_wp & US_PERM_WP_EN != 0 {
+            Some(WriteProtectKind::Permanent)
+        } else if self.user_wp & US_PWR_WP_EN != 0 {
+            Some(WriteProtectKind::PowerOn)
+        } else if self.protected_groups > 0 {
+            Some(WriteProtectKind::Temporary)
+        } else {
+            None
+        }
+    }
+
+    pub fn boot_protection(&self) -> Option<WriteProtectKind> {
+        if self.boot_wp & B_PERM_WP_EN != 0 {
+            Some(WriteProtectKind::Permanent)
+        } else if self.boot_wp & B_PWR_WP_EN != 0 {
+            Some(WriteProtectKind::PowerOn)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.user_protection().is_some()
+    }
+}
+
+impl fmt::Display for WriteProtectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WriteProtectKind::Temporary => "temporary",
+            WriteProtectKind::PowerOn => "power-on",
+            WriteProtectKind::Permanent => "permanent",
+            WriteProtectKind::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+// A write failed because the target range is write-protected.
+// Wrapped in an io::Error, use `WriteProtected::from_error` to get it back.
+#[derive(Debug, Clone)]
+pub struct WriteProtected {
+    pub addr: u64,
+    pub size: u64,
+    pub kind: WriteProtectKind,
+    // What the DA answered the write with
+    pub status: Option<u32>,
+}
+
+impl WriteProtected {
+    pub fn from_error(err: &Error) -> Option<&WriteProtected> {
+        err.get_ref()?.downcast_ref::<WriteProtected>()
+    }
+}
+
+impl fmt::Display for WriteProtected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Region {:#X}+{:#X} is write-protected ({})",
+            self.addr, self.size, self.kind
+        )?;
+        if let Some(status) = self.status {
+            write!(f, ", status 0x{:08X}", status)?;
+        }
+        match self.kind {
+            WriteProtectKind::Temporary => {
+                write!(f, ". It can be cleared with clear_write_protect()")
+            }
+            WriteProtectKind::PowerOn => write!(
+                f,
+                ". It goes away after a power cycle, reconnect before the preloader sets it again"
+            ),
+            WriteProtectKind::Permanent => write!(f, ". It can't be cleared"),
+            WriteProtectKind::Unknown => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for WriteProtected {}
+
+impl From<WriteProtected> for Error {
+    fn from(err: WriteProtected) -> Self {
+        Error::new(ErrorKind::PermissionDenied, err)
+    }
+}
@@ -12,21 +12,35 @@
     the combined work is subject to the networking terms of the AGPL-3.0-or-later,
     as for term 13 of the GPL-3.0-or-later license.
 */
+// Every command of the V5 (XFlash) DA protocol known so far. The high half
+// of a code says what it is, see CmdKind: 0x01 are commands of their own, the
+// rest go through DeviceCtrl.
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Cmd {
+    // Starts every packet header
     Magic = 0xFEEEEEEF,
+    // "SYNC", sent by DA2 once it's up
     SyncSignal = 0x434E5953,
 
     Unknown = 0x010000,
+    // Flashes an image to a partition by name
     Download = 0x010001,
+    // Reads an image back by name
     Upload = 0x010002,
+    // Erases a range
     Format = 0x010003,
+    // Writes a range, in checksummed packets
     WriteData = 0x010004,
+    // Reads a range
     ReadData = 0x010005,
+    // Erases a partition by name
     FormatPartition = 0x010006,
+    // Reboots or powers off, see ShutdownMode
     Shutdown = 0x010007,
+    // Loads data at an address and jumps to it (DA2, extensions)
     BootTo = 0x010008,
+    // Wraps all the 0x02/0x04/0x08/0x0E codes below
     DeviceCtrl = 0x010009,
     InitExtRam = 0x01000A,
     SwitchUsbSpeed = 0x01000B,
@@ -34,11 +48,15 @@ pub enum Cmd {
     WriteOtpZone = 0x01000D,
     WriteEfuse = 0x01000E,
     ReadEfuse = 0x01000F,
+    // Marks NAND blocks bad
     NandBmtRemark = 0x010010,
 
+    // DA log level, log channel, system OS, UFS provisioning
     SetupEnvironment = 0x010100,
+    // Tells DA1 which storage to bring up
     SetupHwInitParams = 0x010101,
 
+    // Set*, devctrl with a parameter
     SetBmtPercentage = 0x020001,
     SetBatteryOpt = 0x020002,
     SetChecksumLevel = 0x020003,
@@ -47,6 +65,7 @@ pub enum Cmd {
     SetMetaBootMode = 0x020006,
     SetEmmcHwresetPin = 0x020007,
     SetGenerateGpx = 0x020008,
+    // Register write, only on DAs that still allow it
     SetRegisterValue = 0x020009,
     SetExternalSig = 0x02000A,
     SetRemoteSecPolicy = 0x02000B,
@@ -55,14 +74,17 @@ pub enum Cmd {
     SetUpdateFw = 0x020010,
     SetUfsConfig = 0x020011,
 
+    // Get*, devctrl answering with data
     GetEmmcInfo = 0x040001,
     GetNandInfo = 0x040002,
     GetNorInfo = 0x040003,
     GetUfsInfo = 0x040004,
     GetDaVersion = 0x040005,
     GetExpireData = 0x040006,
+    // Write and read packet sizes the DA wants
     GetPacketLength = 0x040007,
     GetRandomId = 0x040008,
+    // Which kind of partition table the storage has
     GetPartitionTblCata = 0x040009,
     GetConnectionAgent = 0x04000A,
     GetUsbSpeed = 0x04000B,
@@ -75,9 +97,11 @@ pub enum Cmd {
     GetDramType = 0x040012,
     GetDevFwInfo = 0x040013,
     GetHrid = 0x040014,
+    // Text for the last error status, when the DA has one
     GetErrorDetail = 0x040015,
     SlaEnabledStatus = 0x040016,
 
+    // Actions, devctrl without data either way
     StartDlInfo = 0x080001,
     EndDlInfo = 0x080002,
     ActLockOtpZone = 0x080003,
@@ -85,12 +109,13 @@ pub enum Cmd {
     CcOptionalDownloadAct = 0x080005,
     DaStorLifeCycleCheck = 0x080007,
 
+    // Controls, test and debug codes
     UnknownCtrlCode = 0x0E0000,
     CtrlStorageTest = 0x0E0001,
     CtrlRamTest = 0x0E0002,
     DeviceCtrlReadRegister = 0x0E0003,
 
-    // Extensions
+    // Extensions, only there once da_x.bin is loaded, see exts.rs
     ExtAck = 0x0F0000,
     ExtReadMem = 0x0F0001,
     ExtReadRegister = 0x0F0002,
@@ -103,11 +128,170 @@ pub enum Cmd {
     ExtReadRpmb = 0x0F0009,
     ExtWriteRpmb = 0x0F000A,
     ExtSej = 0x0F000B,
+    ExtReadRegisterMulti = 0x0F000C,
+    ExtWriteRegisterMulti = 0x0F000D,
+    ExtGetWriteProtect = 0x0F000E,
+    ExtClearWriteProtect = 0x0F000F,
+    ExtGetStorageHealth = 0x0F0010,
+    ExtGetCid = 0x0F0011,
+    ExtGetCsd = 0x0F0012,
+    ExtGetRpmbCounter = 0x0F0013,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmdKind {
+    // Magic and sync, never sent as a command
+    Framing,
+    Command,
+    Set,
+    Get,
+    Action,
+    Control,
+    Extension,
+}
+
+// Every variant, for looking codes up
+const ALL: &[Cmd] = &[
+    Cmd::Magic,
+    Cmd::SyncSignal,
+    Cmd::Unknown,
+    Cmd::Download,
+    Cmd::Upload,
+    Cmd::Format,
+    Cmd::WriteData,
+    Cmd::ReadData,
+    Cmd::FormatPartition,
+    Cmd::Shutdown,
+    Cmd::BootTo,
+    Cmd::DeviceCtrl,
+    Cmd::InitExtRam,
+    Cmd::SwitchUsbSpeed,
+    Cmd::ReadOtpZone,
+    Cmd::WriteOtpZone,
+    Cmd::WriteEfuse,
+    Cmd::ReadEfuse,
+    Cmd::NandBmtRemark,
+    Cmd::SetupEnvironment,
+    Cmd::SetupHwInitParams,
+    Cmd::SetBmtPercentage,
+    Cmd::SetBatteryOpt,
+    Cmd::SetChecksumLevel,
+    Cmd::SetResetKey,
+    Cmd::SetHostInfo,
+    Cmd::SetMetaBootMode,
+    Cmd::SetEmmcHwresetPin,
+    Cmd::SetGenerateGpx,
+    Cmd::SetRegisterValue,
+    Cmd::SetExternalSig,
+    Cmd::SetRemoteSecPolicy,
+    Cmd::SetAllInOneSig,
+    Cmd::SetRscInfo,
+    Cmd::SetUpdateFw,
+    Cmd::SetUfsConfig,
+    Cmd::GetEmmcInfo,
+    Cmd::GetNandInfo,
+    Cmd::GetNorInfo,
+    Cmd::GetUfsInfo,
+    Cmd::GetDaVersion,
+    Cmd::GetExpireData,
+    Cmd::GetPacketLength,
+    Cmd::GetRandomId,
+    Cmd::GetPartitionTblCata,
+    Cmd::GetConnectionAgent,
+    Cmd::GetUsbSpeed,
+    Cmd::GetRamInfo,
+    Cmd::GetChipId,
+    Cmd::GetOtpLockStatus,
+    Cmd::GetBatteryVoltage,
+    Cmd::GetRpmbStatus,
+    Cmd::GetExpireDate,
+    Cmd::GetDramType,
+    Cmd::GetDevFwInfo,
+    Cmd::GetHrid,
+    Cmd::GetErrorDetail,
+    Cmd::SlaEnabledStatus,
+    Cmd::StartDlInfo,
+    Cmd::EndDlInfo,
+    Cmd::ActLockOtpZone,
+    Cmd::DisableEmmcHwresetPin,
+    Cmd::CcOptionalDownloadAct,
+    Cmd::DaStorLifeCycleCheck,
+    Cmd::UnknownCtrlCode,
+    Cmd::CtrlStorageTest,
+    Cmd::CtrlRamTest,
+    Cmd::DeviceCtrlReadRegister,
+    Cmd::ExtAck,
+    Cmd::ExtReadMem,
+    Cmd::ExtReadRegister,
+    Cmd::ExtWriteMem,
+    Cmd::ExtWriteRegister,
+    Cmd::ExtSetStorage,
+    Cmd::ExtSetRpmbKey,
+    Cmd::ExtProgRpmbKey,
+    Cmd::ExtInitRpmb,
+    Cmd::ExtReadRpmb,
+    Cmd::ExtWriteRpmb,
+    Cmd::ExtSej,
+    Cmd::ExtReadRegisterMulti,
+    Cmd::ExtWriteRegisterMulti,
+    Cmd::ExtGetWriteProtect,
+    Cmd::ExtClearWriteProtect,
+    Cmd::ExtGetStorageHealth,
+    Cmd::ExtGetCid,
+    Cmd::ExtGetCsd,
+    Cmd::ExtGetRpmbCounter,
+];
+
+impl Cmd {
+    pub fn kind(self) -> CmdKind {
+        match self {
+            Cmd::Magic | Cmd::SyncSignal => CmdKind::Framing,
+            _ => match self as u32 >> 16 {
+                0x02 => CmdKind::Set,
+                0x04 => CmdKind::Get,
+                0x08 => CmdKind::Action,
+                0x0E => CmdKind::Control,
+                0x0F => CmdKind::Extension,
+                _ => CmdKind::Command,
+            },
+        }
+    }
+
+    // Whether it has to be wrapped in DeviceCtrl. Extensions are sent that way too.
+    pub fn is_devctrl(self) -> bool {
+        !matches!(self.kind(), CmdKind::Framing | CmdKind::Command)
+    }
+
+    pub fn from_code(code: u32) -> Option<Cmd> {
+        ALL.iter().copied().find(|&cmd| cmd as u32 == code)
+    }
+}
+
+impl TryFrom<u32> for Cmd {
+    type Error = u32;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        Cmd::from_code(code).ok_or(code)
+    }
 }
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DataType {
+    // Commands, parameters and data
     ProtocolFlow = 1,
+    // Log text
     Message = 2,
 }
+
+impl TryFrom<u32> for DataType {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(DataType::ProtocolFlow),
+            2 => Ok(DataType::Message),
+            other => Err(other),
+        }
+    }
+}
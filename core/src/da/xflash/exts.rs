@@ -14,22 +14,127 @@
 */
 use crate::core::utilities::find_pattern;
 use crate::da::DAProtocol;
-use crate::da::xflash::{Cmd, DataType, XFlash};
-use log::{debug, info};
-use tokio::io::AsyncWriteExt;
+use crate::da::card_regs::CARD_REG_SIZE;
+use crate::da::storage_health::StorageHealth;
+use crate::da::write_protect::WriteProtectStatus;
+use crate::da::xflash::{Cmd, DataType, XFlash, layout};
+use log::{debug, info, warn};
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
 use tokio::io::{Error, ErrorKind};
+use tokio::time::timeout;
 
+#[cfg(not(feature = "build-payloads"))]
 const DA_EXT: &[u8] = include_bytes!("../../../payloads/da_x.bin");
+#[cfg(feature = "build-payloads")]
+const DA_EXT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/da_x.bin"));
+
+// Replaces the embedded extensions for every XFlash session that boots after this,
+// so extension changes can be tested without rebuilding Penumbra.
+static EXT_OVERRIDE: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+
+// What ExtAck answers with once the extensions are running (0xA1A2A3A4)
+const EXT_ACK: [u8; 4] = [0xA4, 0xA3, 0xA2, 0xA1];
+// A DA that doesn't know ExtAck answers right away, this is only for a stuck link
+const EXT_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// Bytes compared against the payload to tell if it's still in memory
+const EXT_RESIDENT_CHECK_LEN: usize = 32;
+
+pub fn set_extension_payload(payload: Option<Vec<u8>>) {
+    if let Ok(mut ext) = EXT_OVERRIDE.write() {
+        *ext = payload;
+    }
+}
+
+pub fn load_extension_payload(path: &Path) -> Result<(), Error> {
+    let payload = std::fs::read(path)?;
+    if payload.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "DA extension payload is empty",
+        ));
+    }
+
+    info!(
+        "Using DA extensions from {} ({} bytes)",
+        path.display(),
+        payload.len()
+    );
+    set_extension_payload(Some(payload));
+    Ok(())
+}
+
+fn extension_payload() -> Vec<u8> {
+    match EXT_OVERRIDE.read() {
+        Ok(ext) => ext.clone().unwrap_or_else(|| DA_EXT.to_vec()),
+        Err(_) => DA_EXT.to_vec(),
+    }
+}
+
+// True if extensions are loaded and answering, e.g. left over from an earlier
+// session on a DA that kept running.
+pub async fn probe_extensions(xflash: &mut XFlash) -> bool {
+    let probe = async {
+        let ack = xflash.devctrl(Cmd::ExtAck, None).await?;
+        xflash.check_status("ExtAck").await?;
+        Ok::<_, Error>(ack)
+    };
+    match timeout(EXT_PROBE_TIMEOUT, probe).await {
+        Ok(Ok(ack)) => ack.starts_with(&EXT_ACK),
+        Ok(Err(_)) => false,
+        Err(_) => {
+            warn!("No answer to ExtAck, assuming the DA extensions aren't loaded");
+            false
+        }
+    }
+}
+
+// Reads back the start of the extensions with the stock register read, to see if
+// an earlier upload is still there even though its commands aren't registered.
+async fn extensions_resident(xflash: &mut XFlash, ext_addr: u32, ext_data: &[u8]) -> bool {
+    let Some(expected) = ext_data.get(..EXT_RESIDENT_CHECK_LEN) else {
+        return false;
+    };
+    for (i, word) in expected.chunks_exact(4).enumerate() {
+        match xflash.read32(ext_addr + (i * 4) as u32).await {
+            Ok(value) if value.to_le_bytes() == word => {}
+            _ => return false,
+        }
+    }
+    true
+}
 
 pub async fn boot_extensions(xflash: &mut XFlash) -> Result<bool, Error> {
     debug!("Trying booting XFlash extensions...");
 
+    // Uploading over extensions the DA is still running from can hang it, so
+    // reconnects to an active session just reuse them
+    if probe_extensions(xflash).await {
+        info!("DA extensions already running, not uploading them again");
+        return Ok(true);
+    }
+
     let ext_data = prepare_extensions(xflash)
         .ok_or_else(|| Error::new(ErrorKind::Other, "Failed to prepare DA extensions"))?;
 
-    let ext_addr = 0x68000000;
+    let ext_addr = layout::ext_addr(&xflash.da, ext_data.len())?;
     let ext_size = ext_data.len() as u32;
 
+    // Still in memory but unregistered: an empty BOOT_TO jumps back into their
+    // entry point, which registers the commands again without touching the code
+    if extensions_resident(xflash, ext_addr, &ext_data).await {
+        info!(
+            "DA extensions still in memory at {:08X}, re-registering them",
+            ext_addr
+        );
+        if xflash.boot_to(ext_addr, &[]).await.is_ok() && probe_extensions(xflash).await {
+            info!("DA extensions re-registered");
+            return Ok(true);
+        }
+        warn!("Could not re-register the DA extensions, uploading them again");
+    }
+
     info!(
         "Uploading DA extensions to {:08X} ({} bytes)",
         ext_addr, ext_size
@@ -44,16 +149,9 @@ pub async fn boot_extensions(xflash: &mut XFlash) -> Result<bool, Error> {
     info!("DA extensions uploaded");
 
     let ack = xflash.devctrl(Cmd::ExtAck, None).await?;
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("DA extensions failed to start: {:#X}", status),
-        ));
-    }
+    xflash.check_status("DA extensions start").await?;
 
-    // Ack must be 0xA1A2A3A4
-    if ack.len() < 4 || ack[0..4] != [0xA4, 0xA3, 0xA2, 0xA1] {
+    if !ack.starts_with(&EXT_ACK) {
         return Err(Error::new(
             ErrorKind::Other,
             "DA extensions failed to start (invalid ACK)",
@@ -67,9 +165,10 @@ pub async fn boot_extensions(xflash: &mut XFlash) -> Result<bool, Error> {
 
 fn prepare_extensions(xflash: &XFlash) -> Option<Vec<u8>> {
     let da2 = &xflash.da.get_da2()?.data;
-    let da2address = xflash.da.get_da2()?.addr;
+    // Function pointers patched in below point into DA2, wherever it was loaded
+    let da2address = layout::da2_addr(&xflash.da)?;
 
-    let mut da_ext_data = DA_EXT.to_vec();
+    let mut da_ext_data = extension_payload();
 
     // This allows to register DA Extensions custom commands (0x0F000X)
     let register_devctrl = find_pattern(da2, &[0x38, 0xB5, 0x05, 0x46, 0x0C, 0x20], 0);
@@ -224,39 +323,21 @@ fn prepare_extensions(xflash: &XFlash) -> Option<Vec<u8>> {
     None
 }
 
-// TODO: Rewrite these
 pub async fn read32_ext(xflash: &mut XFlash, addr: u32) -> Result<u32, Error> {
     xflash.send_cmd(Cmd::DeviceCtrl).await?;
-    if xflash.get_status().await? != 0 {
-        return Err(Error::new(ErrorKind::Other, "DEVICE_CTRL failed"));
-    }
+    xflash.check_status("DeviceCtrl").await?;
 
     xflash.send_cmd(Cmd::ExtReadRegister).await?;
-    if xflash.get_status().await? != 0 {
-        return Err(Error::new(ErrorKind::Other, "ExtReadRegister failed"));
-    }
-
-    let addr_bytes = addr.to_le_bytes();
-
-    let mut hdr = [0u8; 12];
-    hdr[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-    hdr[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
-    hdr[8..12].copy_from_slice(&4u32.to_le_bytes()); // length = 4
+    xflash.check_status("ExtReadRegister").await?;
 
     debug!("[TX] Ext: sending address: 0x{:08X}", addr);
-    xflash.conn.port.write_all(&hdr).await?;
-    xflash.conn.port.write_all(&addr_bytes).await?;
-    xflash.conn.port.flush().await?;
+    xflash
+        .send(&addr.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
 
     let payload = xflash.read_data().await?;
     if payload.len() >= 4 {
-        let status = xflash.get_status().await?;
-        if status != 0 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("ExtReadRegister failed: {:#X}", status),
-            ));
-        }
+        xflash.check_status("ExtReadRegister").await?;
         Ok(u32::from_le_bytes(payload[0..4].try_into().unwrap()))
     } else {
         let value = xflash.get_status().await?;
@@ -264,48 +345,286 @@ pub async fn read32_ext(xflash: &mut XFlash, addr: u32) -> Result<u32, Error> {
     }
 }
 
-pub async fn write32_ext(xflash: &mut XFlash, addr: u32, value: u32) -> Result<(), Error> {
-    xflash.send_cmd(Cmd::DeviceCtrl).await?;
-    if xflash.get_status().await? != 0 {
-        return Err(Error::new(ErrorKind::Other, "DEVICE_CTRL failed"));
+// Biggest read done with a single ExtReadMem
+pub const EXT_READMEM_MAX: usize = 0x10000;
+
+pub async fn read_mem_ext(xflash: &mut XFlash, addr: u64, size: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(size);
+
+    while data.len() < size {
+        let chunk_addr = addr + data.len() as u64;
+        let chunk_size = (size - data.len()).min(EXT_READMEM_MAX);
+
+        xflash.send_cmd(Cmd::DeviceCtrl).await?;
+        xflash.check_status("DeviceCtrl").await?;
+
+        xflash.send_cmd(Cmd::ExtReadMem).await?;
+        xflash.check_status("ExtReadMem").await?;
+
+        debug!(
+            "[TX] Ext: reading {:#X} bytes at 0x{:08X}",
+            chunk_size, chunk_addr
+        );
+        xflash
+            .send(&chunk_addr.to_le_bytes(), DataType::ProtocolFlow as u32)
+            .await?;
+        xflash
+            .send(
+                &(chunk_size as u32).to_le_bytes(),
+                DataType::ProtocolFlow as u32,
+            )
+            .await?;
+
+        let chunk = xflash.read_data().await?;
+        xflash.check_status("ExtReadMem").await?;
+        if chunk.len() < chunk_size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Short memory read at 0x{:08X}: expected {:#X} bytes, got {:#X}",
+                    chunk_addr,
+                    chunk_size,
+                    chunk.len()
+                ),
+            ));
+        }
+        data.extend_from_slice(&chunk[..chunk_size]);
     }
 
-    xflash.send_cmd(Cmd::ExtWriteRegister).await?;
-    if xflash.get_status().await? != 0 {
-        return Err(Error::new(ErrorKind::Other, "ExtWriteRegister failed"));
+    Ok(data)
+}
+
+// How many registers go in a single batched command, keeps the DA side buffers small
+pub const EXT_MULTI_MAX: usize = 64;
+
+// Reads all `addrs` in one round trip per EXT_MULTI_MAX registers.
+// Payload: count (u32), then one u32 address per register. The DA answers with
+// one u32 value per register, in the same order.
+pub async fn read32_multi_ext(xflash: &mut XFlash, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+    let mut values = Vec::with_capacity(addrs.len());
+
+    for chunk in addrs.chunks(EXT_MULTI_MAX) {
+        xflash.send_cmd(Cmd::DeviceCtrl).await?;
+        xflash.check_status("DeviceCtrl").await?;
+
+        let mut payload = Vec::with_capacity(4 + chunk.len() * 4);
+        payload.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        for addr in chunk {
+            payload.extend_from_slice(&addr.to_le_bytes());
+        }
+
+        debug!("[TX] Ext: reading {} registers", chunk.len());
+        xflash
+            .send_cmd_with_payload(Cmd::ExtReadRegisterMulti, &payload)
+            .await?;
+
+        let data = xflash.read_data().await?;
+        xflash.check_status("ExtReadRegisterMulti").await?;
+        if data.len() < chunk.len() * 4 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Short batched register read: expected {} bytes, got {}",
+                    chunk.len() * 4,
+                    data.len()
+                ),
+            ));
+        }
+
+        values.extend(
+            data.chunks_exact(4)
+                .take(chunk.len())
+                .map(|v| u32::from_le_bytes(v.try_into().unwrap())),
+        );
     }
 
-    let addr_bytes = addr.to_le_bytes();
+    Ok(values)
+}
 
-    let mut hdr1 = [0u8; 12];
-    hdr1[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-    hdr1[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
-    hdr1[8..12].copy_from_slice(&4u32.to_le_bytes());
+// Payload: count (u32), then (address, value) u32 pairs, written in order.
+pub async fn write32_multi_ext(xflash: &mut XFlash, writes: &[(u32, u32)]) -> Result<(), Error> {
+    for chunk in writes.chunks(EXT_MULTI_MAX) {
+        xflash.send_cmd(Cmd::DeviceCtrl).await?;
+        xflash.check_status("DeviceCtrl").await?;
+
+        let mut payload = Vec::with_capacity(4 + chunk.len() * 8);
+        payload.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        for (addr, value) in chunk {
+            payload.extend_from_slice(&addr.to_le_bytes());
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
 
-    debug!("[TX] Ext: sending address: 0x{:08X}", addr);
-    xflash.conn.port.write_all(&hdr1).await?;
-    xflash.conn.port.write_all(&addr_bytes).await?;
-    xflash.conn.port.flush().await?;
+        debug!("[TX] Ext: writing {} registers", chunk.len());
+        xflash
+            .send_cmd_with_payload(Cmd::ExtWriteRegisterMulti, &payload)
+            .await?;
+        xflash.check_status("ExtWriteRegisterMulti").await?;
+    }
 
-    let value_bytes = value.to_le_bytes();
+    Ok(())
+}
 
-    let mut hdr2 = [0u8; 12];
-    hdr2[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-    hdr2[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
-    hdr2[8..12].copy_from_slice(&4u32.to_le_bytes());
+pub async fn write32_ext(xflash: &mut XFlash, addr: u32, value: u32) -> Result<(), Error> {
+    xflash.send_cmd(Cmd::DeviceCtrl).await?;
+    xflash.check_status("DeviceCtrl").await?;
+
+    xflash.send_cmd(Cmd::ExtWriteRegister).await?;
+    xflash.check_status("ExtWriteRegister").await?;
+
+    debug!("[TX] Ext: sending address: 0x{:08X}", addr);
+    xflash
+        .send(&addr.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
 
     debug!("[TX] Ext: sending value: 0x{:08X}", value);
-    xflash.conn.port.write_all(&hdr2).await?;
-    xflash.conn.port.write_all(&value_bytes).await?;
-    xflash.conn.port.flush().await?;
+    xflash
+        .send(&value.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
+
+    xflash.check_status("ExtWriteRegister").await?;
+
+    Ok(())
+}
+
+// Write protection on the user area range addr..addr+size, see WriteProtectStatus
+// for what comes back. The DA sends EXT_CSD USER_WP/BOOT_WP and asks the card
+// (CMD31) which groups in the range have temporary protection.
+pub async fn get_write_protect_ext(
+    xflash: &mut XFlash,
+    addr: u64,
+    size: u64,
+) -> Result<WriteProtectStatus, Error> {
+    xflash.send_cmd(Cmd::DeviceCtrl).await?;
+    xflash.check_status("DeviceCtrl").await?;
+
+    xflash.send_cmd(Cmd::ExtGetWriteProtect).await?;
+    xflash.check_status("ExtGetWriteProtect").await?;
+
+    debug!("[TX] Ext: write protect of {:#X}+{:#X}", addr, size);
+    xflash
+        .send(&addr.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
+    xflash
+        .send(&size.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
+
+    let data = xflash.read_data().await?;
+    xflash.check_status("ExtGetWriteProtect").await?;
+    WriteProtectStatus::parse(&data).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("ExtGetWriteProtect returned {} bytes", data.len()),
+        )
+    })
+}
+
+// Clears temporary (CMD28) protection on every group in the range with CMD29.
+// Power-on and permanent protection make the DA answer with an error.
+pub async fn clear_write_protect_ext(
+    xflash: &mut XFlash,
+    addr: u64,
+    size: u64,
+) -> Result<(), Error> {
+    xflash.send_cmd(Cmd::DeviceCtrl).await?;
+    xflash.check_status("DeviceCtrl").await?;
 
-    let status = xflash.get_status().await?;
-    if status != 0 {
+    xflash.send_cmd(Cmd::ExtClearWriteProtect).await?;
+    xflash.check_status("ExtClearWriteProtect").await?;
+
+    debug!(
+        "[TX] Ext: clearing write protect of {:#X}+{:#X}",
+        addr, size
+    );
+    xflash
+        .send(&addr.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
+    xflash
+        .send(&size.to_le_bytes(), DataType::ProtocolFlow as u32)
+        .await?;
+
+    xflash.check_status("ExtClearWriteProtect").await
+}
+
+// Flash wear, see StorageHealth. The DA answers with the raw EXT_CSD on eMMC or
+// the health descriptor on UFS, prefixed with which one it is.
+pub async fn get_storage_health_ext(xflash: &mut XFlash) -> Result<StorageHealth, Error> {
+    xflash.send_cmd(Cmd::DeviceCtrl).await?;
+    xflash.check_status("DeviceCtrl").await?;
+
+    xflash.send_cmd(Cmd::ExtGetStorageHealth).await?;
+    xflash.check_status("ExtGetStorageHealth").await?;
+
+    let data = xflash.read_data().await?;
+    xflash.check_status("ExtGetStorageHealth").await?;
+    StorageHealth::parse(&data).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("ExtGetStorageHealth returned {} bytes", data.len()),
+        )
+    })
+}
+
+// The card's CID or CSD (ExtGetCid / ExtGetCsd), straight from the mmc driver in
+// DA2, for DA builds whose GetEmmcInfo is missing or leaves the CID out.
+// The DA answers with the register as four LE words, see card_regs.
+pub async fn get_card_register_ext(xflash: &mut XFlash, cmd: Cmd) -> Result<Vec<u8>, Error> {
+    let name = format!("{:?}", cmd);
+    xflash.send_cmd(Cmd::DeviceCtrl).await?;
+    xflash.check_status("DeviceCtrl").await?;
+
+    xflash.send_cmd(cmd).await?;
+    xflash.check_status(&name).await?;
+
+    let data = xflash.read_data().await?;
+    xflash.check_status(&name).await?;
+    if data.len() < CARD_REG_SIZE {
         return Err(Error::new(
-            ErrorKind::Other,
-            format!("ExtWriteRegister failed: {:#X}", status),
+            ErrorKind::InvalidData,
+            format!("{} returned {} bytes", name, data.len()),
         ));
     }
+    Ok(data)
+}
 
-    Ok(())
+// RPMB result codes, the low 7 bits of the result field
+const RPMB_RESULT_OK: u16 = 0x00;
+const RPMB_RESULT_NO_KEY: u16 = 0x07;
+// Set once the counter hit its maximum, RPMB can't be written anymore
+const RPMB_COUNTER_EXPIRED: u16 = 0x80;
+
+// RPMB write counter, through a counter read frame sent with the patched
+// mmc_rpmb_send_command. Doesn't need the RPMB key, only to trust the answer.
+// Answer: counter u32 | RPMB result u16.
+pub async fn get_rpmb_counter_ext(xflash: &mut XFlash) -> Result<u32, Error> {
+    xflash.send_cmd(Cmd::DeviceCtrl).await?;
+    xflash.check_status("DeviceCtrl").await?;
+
+    xflash.send_cmd(Cmd::ExtGetRpmbCounter).await?;
+    xflash.check_status("ExtGetRpmbCounter").await?;
+
+    let data = xflash.read_data().await?;
+    xflash.check_status("ExtGetRpmbCounter").await?;
+    if data.len() < 6 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("ExtGetRpmbCounter returned {} bytes", data.len()),
+        ));
+    }
+
+    let counter = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let result = u16::from_le_bytes(data[4..6].try_into().unwrap());
+    if result & RPMB_COUNTER_EXPIRED != 0 {
+        warn!("RPMB write counter expired, the RPMB partition is read only now");
+    }
+    match result & !RPMB_COUNTER_EXPIRED {
+        RPMB_RESULT_OK => Ok(counter),
+        RPMB_RESULT_NO_KEY => Err(Error::new(
+            ErrorKind::Unsupported,
+            "The RPMB key isn't programmed yet, there's no write counter",
+        )),
+        other => Err(Error::other(format!(
+            "RPMB counter read failed with result {:#04X}",
+            other
+        ))),
+    }
 }
@@ -2,28 +2,108 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
-use crate::da::DAProtocol;
+use crate::core::checksums::mtk_checksum;
+use crate::core::storage::{EmmcPartition, StorageType, UfsPartition};
+use crate::core::utilities::saturating_usize;
 use crate::da::xflash::XFlash;
 use crate::da::xflash::cmds::*;
+use crate::da::{DAProtocol, DAStatusError};
 use log::{debug, info};
-use std::io::{Error, ErrorKind, Write};
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+// Sent by the DA while a format is still running
+const STATUS_CONTINUE: u32 = 0x40040004;
+
+// Empty packets in a row tolerated while reading, the DA sends them when it's
+// alive but has nothing yet
+const MAX_EMPTY_CHUNKS: usize = 16;
+
+// Erasing a big partition can take a while, and get_status gives up after 500ms
+const FORMAT_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Which storage, and which part of it (eMMC hardware partition or UFS LU), a
+// ReadData, WriteData or Format goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashTarget {
+    pub storage_type: u32,
+    pub partition_type: u32,
+}
+
+impl FlashTarget {
+    // The user area, where the GPT and everything in it lives. LU3 on UFS,
+    // same as mtkclient.
+    pub fn user(storage: StorageType) -> Self {
+        match storage {
+            StorageType::Ufs => Self {
+                storage_type: StorageType::Ufs as u32,
+                partition_type: UfsPartition::Lu3 as u32,
+            },
+            _ => Self {
+                storage_type: StorageType::Emmc as u32,
+                partition_type: EmmcPartition::User as u32,
+            },
+        }
+    }
+
+    // Storage type, partition type, address, size and the NAND specific part
+    // (zeros for anything else), as ReadData, WriteData and Format take them
+    fn param(&self, addr: u64, size: u64) -> Vec<u8> {
+        let nand_ext = [0u32; 8];
+        let mut param = Vec::new();
+        param.extend_from_slice(&self.storage_type.to_le_bytes());
+        param.extend_from_slice(&self.partition_type.to_le_bytes());
+        param.extend_from_slice(&addr.to_le_bytes());
+        param.extend_from_slice(&size.to_le_bytes());
+        param.extend(nand_ext.iter().flat_map(|x| x.to_le_bytes()));
+        param
+    }
+}
+
+// eMMC, until we learn to tell
+impl Default for FlashTarget {
+    fn default() -> Self {
+        Self::user(StorageType::Emmc)
+    }
+}
 
 pub async fn read_flash<F>(
     xflash: &mut XFlash,
+    target: FlashTarget,
     addr: u64,
     size: usize,
-    mut progress: F,
+    progress: F,
 ) -> Result<Vec<u8>, Error>
 where
     F: FnMut(usize, usize),
 {
+    let mut buffer = vec![0u8; size];
+    let len = read_flash_into(xflash, target, addr, &mut buffer, progress).await?;
+    buffer.truncate(len);
+    Ok(buffer)
+}
+
+// Reads buf.len() bytes at `addr` straight into `buf`, no allocation per chunk.
+// Returns how much the DA actually sent.
+pub async fn read_flash_into<F>(
+    xflash: &mut XFlash,
+    target: FlashTarget,
+    addr: u64,
+    buf: &mut [u8],
+    mut progress: F,
+) -> Result<usize, Error>
+where
+    F: FnMut(usize, usize),
+{
+    let size = buf.len();
     info!("Reading flash at address {:#X} with size {:#X}", addr, size);
 
     // Format:
     // Storage Type (EMMC, UFS, NAND) u32
     // PartType u32 (BOOT or USER for EMMC)
-    // Address u32
-    // Size u32
+    // Address u64
+    // Size u64
     // Nand Specific
     //
     // 01000000 u32
@@ -32,70 +112,39 @@ where
     // 4400000000000000 u64
     // 0000000000000000000000000000000000000000000000000000000000000000 8u32
     // The payload above is sent when reading PGPT (addr: 0x0, size: 0x44)
-    let storage_type = 1u32; // TODO: Add support for other storage types
-    let partition_type = 8u32; // USER partition
-    let nand_ext = [0u32; 8]; // Nand specific, set to 0 for non-nand storage types
-
-    let mut param = Vec::new();
-    param.extend_from_slice(&storage_type.to_le_bytes());
-    param.extend_from_slice(&partition_type.to_le_bytes());
-    param.extend_from_slice(&addr.to_le_bytes());
-    param.extend_from_slice(&(size as u64).to_le_bytes());
-    // Which basically means: append it! Improvements are welcome.
-    param.extend_from_slice(
-        &nand_ext
-            .iter()
-            .flat_map(|x| x.to_le_bytes())
-            .collect::<Vec<u8>>(),
-    );
-
-    xflash.send_cmd(Cmd::ReadData).await?;
-
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("Device returned error status: {:#X}", status),
-        ));
-    }
+    let param = target.param(addr, size as u64);
 
-    xflash.send_data(&param).await?;
-
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Device returned error status after sending parameters: {:#X}",
-                status
-            ),
-        ));
-    }
+    xflash.send_cmd_with_payload(Cmd::ReadData, &param).await?;
+    xflash.check_status("ReadData parameters").await?;
 
-    let mut buffer = Vec::with_capacity(size);
     let mut bytes_read = 0;
+    let mut empty_chunks = 0;
+    let timeouts = xflash.conn.transport_config().read;
 
     // Read chunk, send acknowledgment, status, repeat until profit
     loop {
-        let chunk = xflash.read_data().await?;
-        if chunk.is_empty() {
-            debug!("No data received, breaking.");
-            break;
+        let limit = if bytes_read == 0 {
+            timeouts.first_chunk
+        } else {
+            timeouts.chunk
+        };
+        let len = read_chunk_into(xflash, &mut buf[bytes_read..], limit).await?;
+        if len == 0 {
+            empty_chunks += 1;
+            if empty_chunks > MAX_EMPTY_CHUNKS {
+                debug!(
+                    "No data received after {} empty packets, breaking.",
+                    empty_chunks
+                );
+                break;
+            }
+            debug!("Empty packet, DA still working");
+        } else {
+            empty_chunks = 0;
         }
-        buffer.extend_from_slice(&chunk);
-        bytes_read += chunk.len();
+        bytes_read += len;
 
-        // As always, header + payload.
-        // TODO: Consider using self.send() for this.
-        let mut ack_hdr = [0u8; 12];
-        ack_hdr[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-        ack_hdr[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
-        ack_hdr[8..12].copy_from_slice(&4u32.to_le_bytes());
-        let ack_payload = [0u8; 4];
-
-        xflash.conn.port.write_all(&ack_hdr).await?;
-        xflash.conn.port.write_all(&ack_payload).await?;
-        xflash.conn.port.flush().await?;
+        xflash.ack().await?;
 
         let status = xflash.get_status().await?;
         debug!("Status after chunk: 0x{:08X}", status);
@@ -114,12 +163,40 @@ where
         debug!("Read {}/{} bytes...", bytes_read, size);
     }
 
-    Ok(buffer)
+    Ok(bytes_read)
+}
+
+// One ReadData chunk into `buf`, waiting up to `limit` for it. The backends give up on
+// their own well before a slow first chunk shows up, those timeouts are
+// retried until `limit` is reached.
+async fn read_chunk_into(
+    xflash: &mut XFlash,
+    buf: &mut [u8],
+    limit: Duration,
+) -> Result<usize, Error> {
+    let deadline = Instant::now() + limit;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match timeout(remaining, xflash.read_data_into(buf)).await {
+            Ok(Err(e)) if e.kind() == ErrorKind::TimedOut && Instant::now() < deadline => {
+                debug!("Still waiting for read data...");
+            }
+            Ok(Err(e)) if e.kind() != ErrorKind::TimedOut => return Err(e),
+            Ok(Ok(len)) => return Ok(len),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("No read data from the DA within {:?}", limit),
+                ));
+            }
+        }
+    }
 }
 
 // TODO: Actually verify if the partition allows writing data.len() bytes
 pub async fn write_flash<F>(
     xflash: &mut XFlash,
+    target: FlashTarget,
     addr: u64,
     size: usize,
     data: &[u8],
@@ -137,8 +214,14 @@ where
     // Note to self:
     // Next time, don't put this after Cmd::WriteData,
     // or don't expect it to work :/
-    let chunk_size = get_write_packet_length(xflash).await?;
+    let mut chunk_size = get_write_packet_length(xflash).await?;
     // let chunk_size = 0x2000;
+    if let Some(max) = xflash.power.write_packet
+        && chunk_size > max
+    {
+        debug!("Capping write packets at {} bytes to save power", max);
+        chunk_size = max.max(1);
+    }
     info!("Using chunk size of {} bytes", chunk_size);
 
     // It is mandatory to make data size the same as size, or we will be leaving
@@ -163,40 +246,20 @@ where
         );
     }
 
-    let storage_type = 1u32; // TODO: Add support for other storage types
-    let partition_type = 8u32;
-    let nand_ext = [0u32; 8];
-    let mut param = Vec::new();
-    param.extend_from_slice(&storage_type.to_le_bytes());
-    param.extend_from_slice(&partition_type.to_le_bytes());
-    param.extend_from_slice(&addr.to_le_bytes());
-    param.extend_from_slice(&(size as u64).to_le_bytes());
-    param.extend_from_slice(
-        &nand_ext
-            .iter()
-            .flat_map(|x| x.to_le_bytes())
-            .collect::<Vec<u8>>(),
-    );
+    let param = target.param(addr, size as u64);
 
-    debug!("Sending write data cmd!");
-    // TODO: Consider making a send_cmd_with_payload function
-    xflash.send_cmd(Cmd::WriteData).await?;
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("Device returned error status: {:#X}", status),
-        ));
-    }
     debug!("actual_data.len() = {}, size = {}", actual_data.len(), size);
-    debug!("Write data cmd sent, sending parameters...");
+    debug!("Sending write data cmd and parameters...");
     // Note to self: send_data already checks the status, so DON'T check it again!!
-    // Also, perhaps make it return the status DUH!
-    xflash.send_data(&param).await?;
+    xflash.send_cmd_with_payload(Cmd::WriteData, &param).await?;
 
     debug!("Parameters sent!");
     let mut bytes_written = 0;
     let mut pos = 0;
+    let mut limiter = xflash.throttle.limiter();
+    if !xflash.throttle.is_unlimited() {
+        debug!("Throttling writes: {:?}", xflash.throttle);
+    }
 
     debug!(
         "Starting to write data in chunks of {} bytes...",
@@ -214,15 +277,13 @@ where
         // The actual checksum is a additive 16-bit checksum (Good job MTK!!)
         // For whoever is reading this code and has no clue what this is doing:
         // Just sum all bytes then AND with 0xFFFF :D!!!
-        let checksum = chunk.iter().fold(0u32, |total, &byte| total + byte as u32) & 0xFFFF;
+        let checksum = mtk_checksum(chunk) as u32;
 
         // Mediatek be like: "Coherent protocol? What is that?"
         // And that's why here instead of doing the usual of sending the header (checksum included)
         // then the data, we need to send three different parts, with one being all zeros (why???).
         // But alas, who am I to judge, at least they didn't make an XML protocol... right?
-        xflash
-            .send(&0u32.to_be_bytes(), DataType::ProtocolFlow as u32)
-            .await?;
+        xflash.ack().await?;
 
         debug!("Sending checksum {} for chunk {}", checksum, pos);
         xflash
@@ -237,25 +298,58 @@ where
 
         progress(bytes_written, size);
 
+        if !xflash.power.pause.is_zero() {
+            tokio::time::sleep(xflash.power.pause).await;
+        }
+        limiter.wait(chunk.len()).await;
+
         debug!("Written {}/{} bytes...", bytes_written, actual_data.len());
     }
 
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Device returned error status after writing data: {:#X}",
-                status
-            ),
-        ));
-    }
+    xflash.check_status("WriteData").await?;
 
     info!("Flash write completed, {} bytes written.", bytes_written);
 
     Ok(())
 }
 
+// Erases `size` bytes at `addr`, the DA fills the range with zeros (or 0xFF,
+// depending on the storage).
+pub async fn format_flash<F>(
+    xflash: &mut XFlash,
+    target: FlashTarget,
+    addr: u64,
+    size: u64,
+    mut progress: F,
+) -> Result<(), Error>
+where
+    F: FnMut(usize, usize),
+{
+    info!(
+        "Formatting flash at address {:#X} with size {:#X}",
+        addr, size
+    );
+
+    let param = target.param(addr, size);
+
+    xflash.send_cmd_with_payload(Cmd::Format, &param).await?;
+
+    let deadline = Instant::now() + FORMAT_TIMEOUT;
+    loop {
+        match xflash.get_status().await {
+            Ok(0) => break,
+            Ok(STATUS_CONTINUE) => debug!("Format still running..."),
+            Ok(status) => return Err(DAStatusError::new("Format", status).into()),
+            Err(e) if e.kind() == ErrorKind::TimedOut && Instant::now() < deadline => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    progress(saturating_usize(size), saturating_usize(size));
+    info!("Format completed, {:#X} bytes erased.", size);
+    Ok(())
+}
+
 pub async fn download(xflash: &mut XFlash, part_name: String, data: &[u8]) -> Result<(), Error> {
     // Works like write_flash, but instead of address and size, it takes a partition name
     // and writes the whole data to it.
@@ -267,15 +361,9 @@ pub async fn download(xflash: &mut XFlash, part_name: String, data: &[u8]) -> Re
     // let chunk_size = get_write_packet_length(xflash).await?;
 
     xflash.send_cmd(Cmd::Download).await?;
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("Device returned error status: {:#X}", status),
-        ));
-    }
+    xflash.check_status("Download").await?;
 
-    let data_len = data.len();
+    let data_len = data.len() as u64;
 
     xflash
         .send(part_name.as_bytes(), DataType::ProtocolFlow as u32)
@@ -285,23 +373,12 @@ pub async fn download(xflash: &mut XFlash, part_name: String, data: &[u8]) -> Re
         .send(&data_len.to_le_bytes()[..], DataType::ProtocolFlow as u32)
         .await?;
 
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Device returned error status after sending parameters: {:#X}",
-                status
-            ),
-        ));
-    }
+    xflash.check_status("Download parameters").await?;
 
     // TODO: Figure out what this is actually? The same happens in write_flash
-    xflash
-        .send(&0u32.to_le_bytes(), DataType::ProtocolFlow as u32)
-        .await?;
+    xflash.ack().await?;
 
-    let checksum = data.iter().fold(0u32, |total, &byte| total + byte as u32) & 0xFFFF;
+    let checksum = mtk_checksum(data) as u32;
     xflash
         .send(&checksum.to_le_bytes(), DataType::ProtocolFlow as u32)
         .await?;
@@ -310,29 +387,14 @@ pub async fn download(xflash: &mut XFlash, part_name: String, data: &[u8]) -> Re
 
     debug!("Upload completed, {} bytes sent.", data_len);
 
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Device returned error status after data upload: {:#X}",
-                status
-            ),
-        ));
-    }
+    xflash.check_status("Download data").await?;
 
     Ok(())
 }
 
 async fn get_packet_length(xflash: &mut XFlash) -> Result<(usize, usize), Error> {
     let packet_length = xflash.devctrl(Cmd::GetPacketLength, None).await?;
-    let status = xflash.get_status().await?;
-    if status != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("Device returned error status: {:#X}", status),
-        ));
-    }
+    xflash.check_status("GetPacketLength").await?;
 
     if packet_length.len() < 8 {
         return Err(Error::new(
@@ -363,3 +425,22 @@ async fn get_read_packet_length(xflash: &mut XFlash) -> Result<usize, Error> {
     let (_, read_len) = get_packet_length(xflash).await?;
     Ok(read_len)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn param_keeps_u64_addr_and_size() {
+        let target = FlashTarget::user(StorageType::Ufs);
+        let param = target.param(0x1_2345_6789, 0x2_0000_0000);
+
+        // 2 u32s, 2 u64s and the 8 u32s of NAND stuff
+        assert_eq!(param.len(), 4 + 4 + 8 + 8 + 32);
+        assert_eq!(param[0..4], (StorageType::Ufs as u32).to_le_bytes());
+        assert_eq!(param[4..8], (UfsPartition::Lu3 as u32).to_le_bytes());
+        assert_eq!(param[8..16], 0x1_2345_6789u64.to_le_bytes());
+        assert_eq!(param[16..24], 0x2_0000_0000u64.to_le_bytes());
+        assert!(param[24..].iter().all(|&b| b == 0));
+    }
+}
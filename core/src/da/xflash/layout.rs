@@ -0,0 +1,83 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::da::DA;
+use log::info;
+use std::io::{Error, ErrorKind};
+use std::sync::RwLock;
+
+// Default load address of the DA extensions, right after DA2 on most SoCs
+pub const DEFAULT_EXT_ADDR: u32 = 0x68000000;
+
+// Where DA2 and the extensions get loaded. None keeps the default: the address
+// from the DA file for DA2, DEFAULT_EXT_ADDR for the extensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadAddresses {
+    pub da2: Option<u32>,
+    pub extensions: Option<u32>,
+}
+
+// Applies to every XFlash session that boots after this, like set_extension_payload
+static LOAD_OVERRIDE: RwLock<LoadAddresses> = RwLock::new(LoadAddresses {
+    da2: None,
+    extensions: None,
+});
+
+pub fn set_load_addresses(addresses: LoadAddresses) {
+    if let Ok(mut current) = LOAD_OVERRIDE.write() {
+        *current = addresses;
+    }
+}
+
+pub fn load_addresses() -> LoadAddresses {
+    LOAD_OVERRIDE.read().map(|a| *a).unwrap_or_default()
+}
+
+// Address DA2 gets booted at
+pub fn da2_addr(da: &DA) -> Option<u32> {
+    let da2 = da.get_da2()?;
+    Some(load_addresses().da2.unwrap_or(da2.addr))
+}
+
+// Address the extensions get booted at, checked against where DA1 and DA2 live
+// so a bad override fails here instead of overwriting the running DA.
+pub fn ext_addr(da: &DA, ext_size: usize) -> Result<u32, Error> {
+    let addr = load_addresses().extensions.unwrap_or(DEFAULT_EXT_ADDR);
+    if !addr.is_multiple_of(4) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Extension load address 0x{:08X} is not aligned", addr),
+        ));
+    }
+
+    let ext_end = addr as u64 + ext_size as u64;
+    let regions = [
+        ("DA1", da.get_da1().map(|r| (r.addr, r.length))),
+        (
+            "DA2",
+            da.get_da2()
+                .map(|r| (da2_addr(da).unwrap_or(r.addr), r.length)),
+        ),
+    ];
+    for (name, region) in regions {
+        let Some((start, len)) = region else {
+            continue;
+        };
+        let end = start as u64 + len as u64;
+        if (addr as u64) < end && ext_end > start as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Extensions at 0x{:08X}..0x{:08X} would overlap {} at 0x{:08X}..0x{:08X}",
+                    addr, ext_end, name, start, end
+                ),
+            ));
+        }
+    }
+
+    if addr != DEFAULT_EXT_ADDR {
+        info!("Loading DA extensions at 0x{:08X} (override)", addr);
+    }
+    Ok(addr)
+}
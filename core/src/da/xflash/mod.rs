@@ -2,64 +2,139 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
-mod cmds;
+pub mod cmds;
 mod exts;
+pub mod layout;
+pub use exts::{load_extension_payload, set_extension_payload};
+pub use flash::FlashTarget;
+pub use layout::{LoadAddresses, set_load_addresses};
+pub use storage::XFlashStorage;
 pub mod flash;
+mod storage;
 use crate::connection::Connection;
 use crate::connection::port::ConnectionType;
-use crate::core::device::DeviceInfo;
+use crate::core::chip::ChipIdentity;
+use crate::core::device::SharedDeviceInfo;
+use crate::core::power::PowerLimits;
+use crate::core::throttle::WriteThrottle;
+use crate::da::patch::verify_da2_patch;
+use crate::da::signature::{SendDaPayload, SignatureHandling, prepare_send_da};
 use crate::da::xflash::cmds::*;
-use crate::da::xflash::exts::{boot_extensions, read32_ext, write32_ext};
-use crate::da::{DA, DAProtocol};
-use crate::exploit::Exploit;
+use crate::da::xflash::exts::{
+    boot_extensions, clear_write_protect_ext, get_card_register_ext, get_rpmb_counter_ext,
+    get_storage_health_ext, get_write_protect_ext, probe_extensions, read_mem_ext, read32_ext,
+    read32_multi_ext, write32_ext, write32_multi_ext,
+};
+use crate::da::{
+    Capabilities, DA, DAProtocol, DAStatusError, DaEnvConfig, DaStorageView, EmmcCid, EmmcCsd,
+    SecureBootRejection, ShutdownMode, StorageHealth, WriteProtectStatus,
+};
 use crate::exploit::carbonara::Carbonara;
-use log::{debug, info, warn};
+use crate::exploit::{BootStage, Exploit, ExploitPolicy};
+use log::{debug, error, info, warn};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, Error, ErrorKind};
+use std::time::Instant;
+use tokio::io::{Error, ErrorKind};
 use tokio::sync::Mutex;
 use tokio::time::timeout;
 use tokio::time::{Duration, sleep};
 
+// Sent by DA1 once it's running and ready for commands
+const DA1_SYNC_BYTE: u8 = 0xC0;
+
+// Log frames skipped while waiting for a protocol frame. A chatty DA needs a
+// few, an endless stream of them means we lost track of the protocol.
+const MAX_MESSAGE_FRAMES: usize = 64;
+
 pub struct XFlash {
     pub conn: Connection,
     pub da: DA,
-    pub dev_info: Arc<Mutex<DeviceInfo>>,
+    pub dev_info: SharedDeviceInfo,
     using_exts: bool,
+    // Cleared the first time the extensions reject a batched register command
+    // (older da_x.bin builds), so we don't keep asking.
+    ext_batching: bool,
+    da1_signature: SignatureHandling,
+    power: PowerLimits,
+    throttle: WriteThrottle,
+    upload_progress: Option<UploadProgress>,
+    // Set once Carbonara's patched DA2 was the one that got sent
+    da2_patched: bool,
+    exploit: ExploitPolicy,
+    env: DaEnvConfig,
 }
 
+// Called with ("DA1" or "DA2", sent, total) while upload_da() sends the stages
+pub type UploadProgress = Box<dyn FnMut(&str, usize, usize) + Send + Sync>;
+
 #[async_trait::async_trait]
 impl DAProtocol for XFlash {
     async fn upload_da(&mut self) -> Result<bool, Error> {
-        let (da1addr, da1length, da1data, da1sig_len) = match self.da.get_da1() {
-            Some(da1) => (da1.addr, da1.length, da1.data.clone(), da1.sig_len),
+        let (da1addr, da1) = match self.da.get_da1() {
+            Some(da1) => (da1.addr, prepare_send_da(da1, self.da1_signature)?),
             None => return Err(Error::new(ErrorKind::NotFound, "DA1 region not found")),
         };
 
-        self.upload_stage1(da1addr, da1length, da1data, da1sig_len)
-            .await
-            .map_err(|e| Error::new(ErrorKind::Other, format!("Failed to upload DA1: {}", e)))?;
+        self.upload_stage1(da1addr, da1).await.map_err(
+            |e| match SecureBootRejection::from_error(&e) {
+                Some(_) => e,
+                None => Error::new(ErrorKind::Other, format!("Failed to upload DA1: {}", e)),
+            },
+        )?;
 
         let da2 = match self.da.get_da2() {
             Some(da2) => da2.clone(),
             None => return Err(Error::new(ErrorKind::NotFound, "DA2 region not found")),
         };
-        let da2addr = da2.addr;
+        let da2addr = layout::da2_addr(&self.da).unwrap_or(da2.addr);
+        if da2addr != da2.addr {
+            info!(
+                "[Penumbra] Loading DA2 at 0x{:08X} instead of 0x{:08X} (override)",
+                da2addr, da2.addr
+            );
+        }
         let da2sig_len = da2.sig_len as usize;
 
         let da2_original_data = da2.data[..da2.data.len().saturating_sub(da2sig_len)].to_vec();
 
-        // TODO: Patch DA2 with Carbonara
         let carbonara_da = Arc::new(Mutex::new(self.da.clone()));
         let mut carbonara = Carbonara::new(carbonara_da);
-
-        let da2data = match carbonara.run(self).await {
-            Ok(_) => match carbonara.get_patched_da2() {
-                Some(patched_da2) => patched_da2.data.clone(),
-                None => da2_original_data,
-            },
-            Err(_) => da2_original_data,
+        let name = Carbonara::meta().name;
+        let attempted = self.exploit.allows(&name);
+
+        let da2data = if !attempted {
+            info!("[Penumbra] Skipping {}, sending DA2 as is", name);
+            da2_original_data
+        } else {
+            match carbonara.run(self).await {
+                Ok(_) => match carbonara.get_patched_da2() {
+                    Some(patched_da2) => {
+                        // A bad patch hangs DA1 with no way to tell why, better to stop here
+                        verify_da2_patch(&da2, &patched_da2.data, carbonara.get_patches())?;
+                        self.da2_patched = true;
+                        patched_da2.data.to_vec()
+                    }
+                    None => da2_original_data,
+                },
+                Err(e) => {
+                    debug!("[Penumbra] {} failed: {}", name, e);
+                    da2_original_data
+                }
+            }
         };
 
+        if let ExploitPolicy::Force(forced) = &self.exploit
+            && !self.da2_patched
+        {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} was forced, but could not patch DA2 on this device",
+                    forced
+                ),
+            ));
+        }
+
         match self.boot_to(da2addr, &da2data).await {
             Ok(true) => {
                 info!("[Penumbra] Successfully uploaded and executed DA2");
@@ -67,10 +142,16 @@ impl DAProtocol for XFlash {
                 Ok(true)
             }
             Ok(false) => Err(Error::new(ErrorKind::Other, "Failed to execute DA2")),
-            Err(e) => Err(Error::new(
-                ErrorKind::Other,
-                format!("Error uploading DA2: {}", e),
-            )),
+            Err(e) => match SecureBootRejection::from_error(&e) {
+                // Carbonara runs before DA2 gets sent, so unless it was skipped
+                // the bypass was tried by now
+                Some(rejection) if attempted => Err(rejection.clone().attempted().into()),
+                Some(rejection) => Err(rejection.clone().into()),
+                None => Err(Error::new(
+                    ErrorKind::Other,
+                    format!("Error uploading DA2: {}", e),
+                )),
+            },
         }
     }
 
@@ -82,14 +163,7 @@ impl DAProtocol for XFlash {
         );
 
         self.send_cmd(Cmd::BootTo).await?;
-
-        let status = self.get_status().await?;
-        if status != 0 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("BOOT_TO command failed with status: 0x{:08X}", status),
-            ));
-        }
+        self.check_status("BootTo").await?;
 
         // Addr (LE) | Padding | Length (LE) | Padding
         // 00000040000000002c83050000000000 -> addr=0x4000000, len=0x0005832c
@@ -99,54 +173,47 @@ impl DAProtocol for XFlash {
         param.extend_from_slice(&(data.len() as u32).to_le_bytes());
         param.extend_from_slice(&[0, 0, 0, 0]);
 
-        // TODO: Use send_data instead of reconstructing header manually
-        let mut hdr = [0u8; 12];
-        hdr[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-        hdr[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
-        hdr[8..12].copy_from_slice(&(param.len() as u32).to_le_bytes());
-
-        debug!(
-            "[TX] Parameter Header: {:02X?}, Data Length: {}",
-            hdr,
-            param.len()
-        );
-
-        self.conn.port.write_all(&hdr).await?;
-        self.conn.port.write_all(&param).await?;
-        self.conn.port.flush().await?;
+        // No status after the parameters, the DA waits for the data right away
+        self.send(&param, DataType::ProtocolFlow as u32).await?;
 
-        // We just need to change the data size,
-        // so let us just reuse what we've got already ;P
-        hdr[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        let hdr = Self::header(data.len());
         debug!(
             "[TX] DA2 Data Header: {:02X?}, Data Length: {}",
             hdr,
             data.len()
         );
 
-        self.conn.port.write_all(&hdr).await?;
+        self.conn.write_all(&hdr).await?;
 
         // Chunks of 1KB
         let chunk_size = 1024;
         let mut pos = 0;
         while pos < data.len() {
             let end = std::cmp::min(pos + chunk_size, data.len());
-            self.conn.port.write_all(&data[pos..end]).await?;
+            self.conn.write_all(&data[pos..end]).await?;
             pos = end;
+            self.report_upload("DA2", pos, data.len());
 
             if pos % (chunk_size * 20) == 0 && pos > 0 {
                 debug!("[TX] Progress: {}/{} bytes sent", pos, data.len());
             }
         }
 
-        self.conn.port.flush().await?;
+        self.conn.flush().await?;
         debug!("[TX] Completed sending {} bytes", data.len());
 
         let status = self.get_status().await?;
         if status != 0 {
+            error!("BOOT_TO status1 is not 0: 0x{:08X}", status);
+            // DA1 verifies the hash of what we've just sent before jumping to it,
+            // but that only means something with DAA on. Anything else is an
+            // ordinary failure (bad address, size...).
+            if self.conn.target_config().is_some_and(|config| config.daa) {
+                return Err(SecureBootRejection::new(BootStage::Da2, Some(status)).into());
+            }
             return Err(Error::new(
                 ErrorKind::Other,
-                format!("BOOT_TO status1 is not 0: 0x{:08X}", status),
+                format!("BOOT_TO failed with status 0x{:08X}", status),
             ));
         }
 
@@ -164,12 +231,7 @@ impl DAProtocol for XFlash {
     }
 
     async fn send_data(&mut self, data: &[u8]) -> Result<bool, Error> {
-        let mut hdr = [0u8; 12];
-
-        // MAGIC | DataType (1) | Data Length
-        hdr[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-        hdr[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
-        hdr[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        let hdr = Self::header(data.len());
 
         debug!(
             "[TX] Data Header: {:02X?}, Data Length: {}",
@@ -177,73 +239,31 @@ impl DAProtocol for XFlash {
             data.len()
         );
 
-        self.conn.port.write_all(&hdr).await?;
+        self.conn.write_all(&hdr).await?;
 
         let mut pos = 0;
         while pos < data.len() {
             let end = std::cmp::min(pos + 64, data.len());
             let chunk = &data[pos..end];
             debug!("[TX] Sending chunk ({} bytes): {:02X?}", chunk.len(), chunk);
-            self.conn.port.write_all(chunk).await?;
+            self.conn.write_all(chunk).await?;
             pos += chunk.len();
         }
 
-        self.conn.port.flush().await?;
-
-        let status = self.get_status().await?;
-        if status != 0 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Data send failed with status: 0x{:08X}", status),
-            ));
-        }
+        self.conn.flush().await?;
 
+        self.check_status("Data send").await?;
         Ok(true)
     }
 
     async fn get_status(&mut self) -> Result<u32, Error> {
-        let mut hdr = [0u8; 12];
-        match timeout(
-            Duration::from_millis(500),
-            self.conn.port.read_exact(&mut hdr),
-        )
-        .await
-        {
-            Ok(result) => result?,
-            Err(_) => return Err(Error::new(ErrorKind::TimedOut, "Status read timed out")),
-        };
-        debug!("[RX] Status Header: {:02X?}", hdr);
-        let magic = u32::from_le_bytes(hdr[0..4].try_into().unwrap());
-        let len = u32::from_le_bytes(hdr[8..12].try_into().unwrap());
-
-        if magic != Cmd::Magic as u32 {
-            return Err(Error::new(ErrorKind::Other, "Invalid magic"));
-        }
-
-        let mut data = vec![0u8; len as usize];
-        self.conn.port.read_exact(&mut data).await?;
-        let status = match len {
-            2 => u16::from_le_bytes(data[0..2].try_into().unwrap()) as u32,
-            4 => {
-                let val = u32::from_le_bytes(data[0..4].try_into().unwrap());
-                if val == Cmd::Magic as u32 { 0 } else { val }
-            }
-            _ if data.len() >= 4 => u32::from_le_bytes(data[0..4].try_into().unwrap()),
-            _ if !data.is_empty() => data[0] as u32,
-            _ => 0xFFFFFFFF,
-        };
-
-        debug!("[RX] Status: 0x{:08X}", status);
-        Ok(status)
+        XFlash::get_status(self).await
     }
 
     async fn send(&mut self, data: &[u8], datatype: u32) -> Result<bool, Error> {
-        let mut hdr = [0u8; 12];
-
         // efeeeefe | 010000000 | 04000000 (Data Length)
-        hdr[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
-        hdr[4..8].copy_from_slice(&(datatype as u32).to_le_bytes());
-        hdr[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        let mut hdr = Self::header(data.len());
+        hdr[4..8].copy_from_slice(&datatype.to_le_bytes());
 
         debug!(
             "[TX] Header: {:02X?}, Payload: [{}]",
@@ -254,10 +274,10 @@ impl DAProtocol for XFlash {
                 .join(" ")
         );
 
-        self.conn.port.write_all(&hdr).await?;
-        self.conn.port.write_all(&data).await?;
-
-        self.conn.port.flush().await?;
+        let started = Instant::now();
+        let result = self.write_packet(&hdr, data).await;
+        self.conn.record_latency("send", started, result.is_ok());
+        result?;
 
         Ok(true)
     }
@@ -268,7 +288,16 @@ impl DAProtocol for XFlash {
         size: usize,
         progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<Vec<u8>, Error> {
-        flash::read_flash(self, addr, size, progress).await
+        flash::read_flash(self, FlashTarget::default(), addr, size, progress).await
+    }
+
+    async fn read_flash_into(
+        &mut self,
+        addr: u64,
+        buf: &mut [u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<usize, Error> {
+        flash::read_flash_into(self, FlashTarget::default(), addr, buf, progress).await
     }
 
     async fn write_flash(
@@ -278,36 +307,277 @@ impl DAProtocol for XFlash {
         data: &[u8],
         progress: &mut (dyn FnMut(usize, usize) + Send),
     ) -> Result<(), Error> {
-        flash::write_flash(self, addr, size, data, progress).await
+        flash::write_flash(self, FlashTarget::default(), addr, size, data, progress).await
+    }
+
+    async fn format_flash(
+        &mut self,
+        addr: u64,
+        size: u64,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        flash::format_flash(self, FlashTarget::default(), addr, size, progress).await
     }
 
     async fn download(&mut self, part_name: String, data: &[u8]) -> Result<(), Error> {
         flash::download(self, part_name, data).await
     }
 
-    async fn get_usb_speed(&mut self) -> Result<u32, Error> {
-        let usb_speed = self.devctrl(Cmd::GetUsbSpeed, None).await?;
-        let status = self.get_status().await?;
-        if status != 0 {
+    async fn attach(&mut self) -> Result<(), Error> {
+        // Any devctrl does, this one is harmless
+        self.get_usb_speed().await.map_err(|e| {
+            Error::new(
+                ErrorKind::NotConnected,
+                format!("The running DA doesn't answer, reboot the device: {}", e),
+            )
+        })?;
+
+        // The extensions may still be loaded from last time, a DA without them
+        // just fails the devctrl and carries on
+        self.using_exts = probe_extensions(self).await;
+        info!(
+            "[Penumbra] Attached to running DA, extensions {}",
+            if self.using_exts {
+                "active"
+            } else {
+                "not loaded"
+            }
+        );
+        Ok(())
+    }
+
+    async fn write_protect_status(
+        &mut self,
+        addr: u64,
+        size: u64,
+    ) -> Result<WriteProtectStatus, Error> {
+        if !self.using_exts {
             return Err(Error::new(
-                ErrorKind::Other,
-                format!("Device returned error status: {:#X}", status),
+                ErrorKind::Unsupported,
+                "Querying write protection needs the DA extensions",
+            ));
+        }
+        get_write_protect_ext(self, addr, size).await
+    }
+
+    async fn clear_write_protect(&mut self, addr: u64, size: u64) -> Result<(), Error> {
+        if !self.using_exts {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Clearing write protection needs the DA extensions",
+            ));
+        }
+        clear_write_protect_ext(self, addr, size).await
+    }
+
+    // Each answer is optional, older DAs and UFS devices don't know every code
+    async fn storage_view(&mut self) -> Result<DaStorageView, Error> {
+        let mut view = DaStorageView::default();
+
+        match self.devctrl_read(Cmd::GetPartitionTblCata).await {
+            Ok(data) if data.len() >= 4 => {
+                view.table_category = Some(u32::from_le_bytes(data[0..4].try_into().unwrap()))
+            }
+            Ok(_) => {}
+            Err(e) => debug!("GetPartitionTblCata failed: {}", e),
+        }
+
+        // type | block size | boot1 | boot2 | rpmb | gp1-4 | user | cid | fw version
+        match self.devctrl_read(Cmd::GetEmmcInfo).await {
+            Ok(data) if data.len() >= 72 => {
+                let block_size = u32::from_le_bytes(data[4..8].try_into().unwrap());
+                let user_size = u64::from_le_bytes(data[64..72].try_into().unwrap());
+                view.block_size = (block_size > 0).then_some(block_size);
+                view.user_size = (user_size > 0).then_some(user_size);
+            }
+            Ok(data) => debug!("GetEmmcInfo returned {} bytes", data.len()),
+            Err(e) => debug!("GetEmmcInfo failed: {}", e),
+        }
+
+        Ok(view)
+    }
+
+    // GetEmmcInfo has the CID, but not every DA build answers it or fills it in
+    async fn emmc_cid(&mut self) -> Result<EmmcCid, Error> {
+        match self.devctrl_read(Cmd::GetEmmcInfo).await {
+            Ok(data) => {
+                if let Some(cid) = data.get(72..88).filter(|cid| cid.iter().any(|&b| b != 0)) {
+                    return EmmcCid::parse(cid).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "GetEmmcInfo CID is too short")
+                    });
+                }
+                debug!("GetEmmcInfo has no CID ({} bytes)", data.len());
+            }
+            Err(e) => debug!("GetEmmcInfo failed: {}", e),
+        }
+
+        if !self.using_exts {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "The DA doesn't report the CID, reading it needs the DA extensions",
+            ));
+        }
+        let data = get_card_register_ext(self, Cmd::ExtGetCid).await?;
+        EmmcCid::parse(&data)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "ExtGetCid answer is too short"))
+    }
+
+    async fn emmc_csd(&mut self) -> Result<EmmcCsd, Error> {
+        if !self.using_exts {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Reading the CSD needs the DA extensions",
+            ));
+        }
+        let data = get_card_register_ext(self, Cmd::ExtGetCsd).await?;
+        EmmcCsd::parse(&data)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "ExtGetCsd answer is too short"))
+    }
+
+    async fn rpmb_write_counter(&mut self) -> Result<u32, Error> {
+        if !self.using_exts {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Reading the RPMB write counter needs the DA extensions",
             ));
         }
+        get_rpmb_counter_ext(self).await
+    }
+
+    async fn storage_health(&mut self) -> Result<StorageHealth, Error> {
+        if !self.using_exts {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Reading storage health needs the DA extensions",
+            ));
+        }
+        get_storage_health_ext(self).await
+    }
+
+    async fn shutdown(&mut self, mode: ShutdownMode) -> Result<(), Error> {
+        // has_flags | wdt | async | boot mode | dl bit | keep rtc | keep pwrkey | reserved
+        let boot_mode: u32 = match mode {
+            ShutdownMode::PowerOff => 0,
+            ShutdownMode::Normal => 1,
+            ShutdownMode::Fastboot => 2,
+        };
+        let has_flags = (boot_mode > 0) as u32;
+        let params: Vec<u8> = [has_flags, 0, 0, boot_mode, 0, 0, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+
+        info!("[Penumbra] Shutting down the DA ({:?})", mode);
+        self.send_cmd_with_payload(Cmd::Shutdown, &params).await
+    }
+
+    async fn get_usb_speed(&mut self) -> Result<u32, Error> {
+        let usb_speed = self.devctrl(Cmd::GetUsbSpeed, None).await?;
+        self.check_status("GetUsbSpeed").await?;
         debug!("USB Speed Data: {:?}", usb_speed);
         Ok(u32::from_le_bytes(usb_speed[0..4].try_into().unwrap()))
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            read_flash: true,
+            write_flash: true,
+            format_flash: true,
+            registers: true,
+            extensions: self.using_exts,
+            rpmb: self.using_exts,
+            // Reads and writes always target the eMMC user area for now
+            ufs: false,
+            write_protect: self.using_exts,
+            shutdown: true,
+            attach: true,
+            storage_view: true,
+            storage_health: self.using_exts,
+            max_read_size: None,
+        }
+    }
+
     fn get_connection(&mut self) -> &mut Connection {
         &mut self.conn
     }
 
     fn set_connection_type(&mut self, conn_type: ConnectionType) -> Result<(), Error> {
-        self.conn.connection_type = conn_type;
+        self.conn.set_connection_type(conn_type);
         Ok(())
     }
 
     async fn read32(&mut self, addr: u32) -> Result<u32, Error> {
+        XFlash::read32(self, addr).await
+    }
+
+    async fn write32(&mut self, addr: u32, value: u32) -> Result<(), Error> {
+        XFlash::write32(self, addr, value).await
+    }
+
+    async fn read32_multi(&mut self, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+        XFlash::read32_multi(self, addrs).await
+    }
+
+    async fn read_mem(&mut self, addr: u32, size: usize) -> Result<Vec<u8>, Error> {
+        if self.using_exts {
+            return read_mem_ext(self, addr as u64, size).await;
+        }
+
+        let addrs: Vec<u32> = (0..size.div_ceil(4) as u32).map(|i| addr + i * 4).collect();
+        let mut data: Vec<u8> = self
+            .read32_multi(&addrs)
+            .await?
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        data.truncate(size);
+        Ok(data)
+    }
+
+    async fn write32_multi(&mut self, writes: &[(u32, u32)]) -> Result<(), Error> {
+        XFlash::write32_multi(self, writes).await
+    }
+}
+
+// The calls that run in tight loops (SEJ, register dumps), kept out of the
+// trait so calling them on a concrete XFlash doesn't box a future each time.
+// The DAProtocol impl above forwards here.
+impl XFlash {
+    pub async fn get_status(&mut self) -> Result<u32, Error> {
+        let started = Instant::now();
+        let result = self.read_status().await;
+        self.conn
+            .record_latency("get_status", started, result.is_ok());
+        result
+    }
+
+    async fn read_status(&mut self) -> Result<u32, Error> {
+        let len = match timeout(Duration::from_millis(500), self.read_frame_header()).await {
+            Ok(result) => result?,
+            Err(_) => {
+                self.conn.record_error();
+                return Err(Error::new(ErrorKind::TimedOut, "Status read timed out"));
+            }
+        };
+
+        let mut data = vec![0u8; len];
+        self.conn.read_exact(&mut data).await?;
+        let status = match len {
+            2 => u16::from_le_bytes(data[0..2].try_into().unwrap()) as u32,
+            4 => {
+                let val = u32::from_le_bytes(data[0..4].try_into().unwrap());
+                if val == Cmd::Magic as u32 { 0 } else { val }
+            }
+            _ if data.len() >= 4 => u32::from_le_bytes(data[0..4].try_into().unwrap()),
+            _ if !data.is_empty() => data[0] as u32,
+            _ => 0xFFFFFFFF,
+        };
+
+        debug!("[RX] Status: 0x{:08X}", status);
+        Ok(status)
+    }
+
+    pub async fn read32(&mut self, addr: u32) -> Result<u32, Error> {
         if self.using_exts {
             return read32_ext(self, addr).await;
         }
@@ -324,7 +594,7 @@ impl DAProtocol for XFlash {
         Ok(u32::from_le_bytes(resp[0..4].try_into().unwrap()))
     }
 
-    async fn write32(&mut self, addr: u32, value: u32) -> Result<(), Error> {
+    pub async fn write32(&mut self, addr: u32, value: u32) -> Result<(), Error> {
         if self.using_exts {
             return write32_ext(self, addr, value).await;
         }
@@ -338,6 +608,47 @@ impl DAProtocol for XFlash {
         self.devctrl(Cmd::SetRegisterValue, Some(&param)).await?;
         Ok(())
     }
+
+    pub async fn read32_multi(&mut self, addrs: &[u32]) -> Result<Vec<u32>, Error> {
+        if self.using_exts && self.ext_batching {
+            match read32_multi_ext(self, addrs).await {
+                Err(e) if DAStatusError::from_error(&e).is_some() => {
+                    warn!(
+                        "DA extensions don't support batched reads, falling back: {}",
+                        e
+                    );
+                    self.ext_batching = false;
+                }
+                result => return result,
+            }
+        }
+
+        let mut values = Vec::with_capacity(addrs.len());
+        for &addr in addrs {
+            values.push(self.read32(addr).await?);
+        }
+        Ok(values)
+    }
+
+    pub async fn write32_multi(&mut self, writes: &[(u32, u32)]) -> Result<(), Error> {
+        if self.using_exts && self.ext_batching {
+            match write32_multi_ext(self, writes).await {
+                Err(e) if DAStatusError::from_error(&e).is_some() => {
+                    warn!(
+                        "DA extensions don't support batched writes, falling back: {}",
+                        e
+                    );
+                    self.ext_batching = false;
+                }
+                result => return result,
+            }
+        }
+
+        for &(addr, value) in writes {
+            self.write32(addr, value).await?;
+        }
+        Ok(())
+    }
 }
 
 impl XFlash {
@@ -347,40 +658,160 @@ impl XFlash {
             .await
     }
 
-    pub fn new(conn: Connection, da: DA, dev_info: Arc<Mutex<DeviceInfo>>) -> Self {
+    // MAGIC | DataType (1) | Data Length
+    fn header(len: usize) -> [u8; 12] {
+        let mut hdr = [0u8; 12];
+        hdr[0..4].copy_from_slice(&(Cmd::Magic as u32).to_le_bytes());
+        hdr[4..8].copy_from_slice(&(DataType::ProtocolFlow as u32).to_le_bytes());
+        hdr[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        hdr
+    }
+
+    // Reads a status and turns anything but 0 into a DAStatusError
+    pub async fn check_status(&mut self, context: &str) -> Result<(), Error> {
+        match self.get_status().await? {
+            0 => Ok(()),
+            status => Err(DAStatusError::new(context, status).into()),
+        }
+    }
+
+    // CMD, status, parameters, status: the framing most commands start with
+    pub async fn send_cmd_with_payload(&mut self, cmd: Cmd, payload: &[u8]) -> Result<(), Error> {
+        if cmd.kind() != CmdKind::Command {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} has to be sent with devctrl", cmd),
+            ));
+        }
+        self.send_cmd(cmd).await?;
+        self.check_status(&format!("{:?}", cmd)).await?;
+        self.send_data(payload).await?;
+        Ok(())
+    }
+
+    // Tells the DA the last chunk arrived and it can go on
+    async fn ack(&mut self) -> Result<(), Error> {
+        self.send(&0u32.to_le_bytes(), DataType::ProtocolFlow as u32)
+            .await?;
+        Ok(())
+    }
+
+    pub fn new(conn: Connection, da: DA, dev_info: SharedDeviceInfo) -> Self {
         XFlash {
             conn,
             da,
             dev_info,
             using_exts: false,
+            ext_batching: true,
+            da1_signature: SignatureHandling::default(),
+            power: PowerLimits::default(),
+            throttle: WriteThrottle::default(),
+            upload_progress: None,
+            da2_patched: false,
+            exploit: ExploitPolicy::default(),
+            env: DaEnvConfig::default(),
         }
     }
 
-    async fn devctrl(&mut self, cmd: Cmd, param: Option<&[u8]>) -> Result<Vec<u8>, Error> {
-        self.send_cmd(Cmd::DeviceCtrl).await?;
+    // The device reset and came back on `conn` without a DA. Whatever the old
+    // DA had going is gone, the settings above stay for the next upload_da().
+    pub fn reconnect(&mut self, conn: Connection) {
+        self.conn = conn;
+        self.using_exts = false;
+        self.ext_batching = true;
+        self.da2_patched = false;
+    }
 
-        let status = self.get_status().await?;
-        if status != 0 {
+    // How the DA1 signature is sent, see SignatureHandling. Only matters before
+    // upload_da().
+    pub fn set_da1_signature(&mut self, handling: SignatureHandling) {
+        self.da1_signature = handling;
+    }
+
+    // Packet size cap and pauses for writes, see PowerProfile
+    pub fn set_power_limits(&mut self, limits: PowerLimits) {
+        self.power = limits;
+    }
+
+    // Reports how far the DA1 and DA2 uploads are, see UploadProgress
+    pub fn set_upload_progress(&mut self, progress: Option<UploadProgress>) {
+        self.upload_progress = progress;
+    }
+
+    fn report_upload(&mut self, stage: &str, sent: usize, total: usize) {
+        if let Some(progress) = self.upload_progress.as_mut() {
+            progress(stage, sent, total);
+        }
+    }
+
+    // Whether upload_da() runs Carbonara, see ExploitPolicy
+    pub fn set_exploit_policy(&mut self, policy: ExploitPolicy) {
+        self.exploit = policy;
+    }
+
+    // Log level, log channel and the other SetupEnvironment flags, see
+    // DaEnvConfig. Only matters before upload_da().
+    pub fn set_env_config(&mut self, env: DaEnvConfig) {
+        self.env = env;
+    }
+
+    pub fn env_config(&self) -> DaEnvConfig {
+        self.env
+    }
+
+    // Whether the running DA2 is the one Carbonara patched
+    pub fn da2_patched(&self) -> bool {
+        self.da2_patched
+    }
+
+    // Rate limit and delay between write packets, see WriteThrottle
+    pub fn set_write_throttle(&mut self, throttle: WriteThrottle) {
+        self.throttle = throttle;
+    }
+
+    // Same codes as GetHwCode/GetHwSwVer but answered by the DA. The DA doesn't
+    // hand out the SoC ID or MEID here, those are left empty.
+    pub async fn get_chip_id(&mut self) -> Result<ChipIdentity, Error> {
+        let chip_id = self.devctrl(Cmd::GetChipId, None).await?;
+        self.check_status("GetChipId").await?;
+        if chip_id.len() < 8 {
             return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Device control command failed with status: 0x{:08X}",
-                    status
-                ),
+                ErrorKind::InvalidData,
+                format!("GetChipId returned {} bytes", chip_id.len()),
             ));
         }
 
-        self.send_cmd(cmd).await?;
-        let status = self.get_status().await?;
-        if status != 0 {
+        let field = |i: usize| u16::from_le_bytes([chip_id[i * 2], chip_id[i * 2 + 1]]);
+        Ok(ChipIdentity {
+            hw_code: field(0),
+            hw_sub_code: field(1),
+            hw_ver: field(2),
+            sw_ver: field(3),
+            ..ChipIdentity::default()
+        })
+    }
+
+    // A Get* devctrl with its final status checked
+    async fn devctrl_read(&mut self, cmd: Cmd) -> Result<Vec<u8>, Error> {
+        let data = self.devctrl(cmd, None).await?;
+        self.check_status(&format!("{:?}", cmd)).await?;
+        Ok(data)
+    }
+
+    // Sends a DeviceCtrl code, then `param` if there is one or reads the answer
+    // otherwise. The caller checks the final status.
+    pub async fn devctrl(&mut self, cmd: Cmd, param: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+        if !cmd.is_devctrl() {
             return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Device control sub-command failed with status: 0x{:08X}",
-                    status
-                ),
+                ErrorKind::InvalidInput,
+                format!("{:?} is not a DeviceCtrl code", cmd),
             ));
         }
+        self.send_cmd(Cmd::DeviceCtrl).await?;
+        self.check_status("DeviceCtrl").await?;
+
+        self.send_cmd(cmd).await?;
+        self.check_status(&format!("DeviceCtrl {:?}", cmd)).await?;
 
         if let Some(p) = param {
             self.send_data(p).await?;
@@ -391,79 +822,121 @@ impl XFlash {
     }
 
     async fn read_data(&mut self) -> Result<Vec<u8>, Error> {
-        let mut hdr = [0u8; 12];
-        self.conn.port.read_exact(&mut hdr).await?;
+        let started = Instant::now();
+        let result = self.read_packet().await;
+        self.conn
+            .record_latency("read_data", started, result.is_ok());
+        result
+    }
 
-        let magic = u32::from_le_bytes(hdr[0..4].try_into().unwrap());
-        let len = u32::from_le_bytes(hdr[8..12].try_into().unwrap());
+    // read_data into `buf`, for the read loops that shouldn't allocate per packet.
+    // Returns the packet length, packets bigger than `buf` are an error.
+    async fn read_data_into(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let started = Instant::now();
+        let result = self.read_packet_into(buf).await;
+        self.conn
+            .record_latency("read_data", started, result.is_ok());
+        result
+    }
 
-        if magic != Cmd::Magic as u32 {
-            return Err(Error::new(ErrorKind::Other, "Invalid magic"));
+    async fn read_packet_into(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.read_frame_header().await?;
+        if len > buf.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("DA sent {} bytes, only {} expected", len, buf.len()),
+            ));
         }
 
-        let mut data = vec![0u8; len as usize];
-        self.conn.port.read_exact(&mut data).await?;
+        self.conn.read_exact(&mut buf[..len]).await?;
+        Ok(len)
+    }
+
+    async fn read_packet(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read_frame_header().await?;
+        let mut data = vec![0u8; len];
+        self.conn.read_exact(&mut data).await?;
 
         Ok(data)
     }
 
-    async fn upload_stage1(
-        &mut self,
-        addr: u32,
-        length: u32,
-        data: Vec<u8>,
-        sig_len: u32,
-    ) -> Result<bool, Error> {
-        info!(
-            "[Penumbra] Uploading DA1 region to address 0x{:08X} with length {}",
-            addr, length
-        );
+    // Header of the next protocol frame, returns its payload length. The DA can
+    // send log text (Message frames) between any two protocol frames, those go
+    // to the log instead of being taken for a status or data.
+    async fn read_frame_header(&mut self) -> Result<usize, Error> {
+        for _ in 0..MAX_MESSAGE_FRAMES {
+            let mut hdr = [0u8; 12];
+            self.conn.read_exact(&mut hdr).await?;
+            debug!("[RX] Header: {:02X?}", hdr);
+
+            let magic = u32::from_le_bytes(hdr[0..4].try_into().unwrap());
+            let data_type = u32::from_le_bytes(hdr[4..8].try_into().unwrap());
+            let len = u32::from_le_bytes(hdr[8..12].try_into().unwrap()) as usize;
+
+            if magic != Cmd::Magic as u32 {
+                return Err(Error::new(ErrorKind::Other, "Invalid magic"));
+            }
+            if DataType::try_from(data_type) != Ok(DataType::Message) {
+                return Ok(len);
+            }
 
-        self.conn.send_da(&data, length, addr, sig_len).await?;
-        info!("[Penumbra] Sent DA1, jumping to address 0x{:08X}...", addr);
-        self.conn.jump_da(addr).await?;
+            let mut text = vec![0u8; len];
+            self.conn.read_exact(&mut text).await?;
+            let text = String::from_utf8_lossy(&text);
+            info!("[DA] {}", text.trim_end_matches(['\0', '\r', '\n']));
+        }
 
-        // Without this, it timed out during my tests, so leave it here for now
-        // self.conn.port.set_timeout(Duration::from_secs(10))?;
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "Too many DA log frames in a row, the connection is probably out of sync",
+        ))
+    }
 
-        let sync_byte = {
-            let mut sync_buf = [0u8; 1];
-            match self.conn.port.read_exact(&mut sync_buf).await {
-                Ok(_) => sync_buf[0],
-                Err(e) if e.kind() == ErrorKind::TimedOut => {
-                    return Err(Error::new(
-                        ErrorKind::TimedOut,
-                        "Timeout waiting for DA sync byte",
-                    ));
-                }
-                Err(e) => return Err(e),
+    async fn write_packet(&self, hdr: &[u8], data: &[u8]) -> Result<(), Error> {
+        self.conn.write_all(hdr).await?;
+        self.conn.write_all(data).await?;
+        self.conn.flush().await
+    }
+
+    async fn upload_stage1(&mut self, addr: u32, da1: SendDaPayload) -> Result<bool, Error> {
+        let kind = if da1.is_signed() {
+            "signed"
+        } else {
+            "unsigned"
+        };
+        info!(
+            "[Penumbra] Uploading {} DA1 region to address 0x{:08X} with length {}",
+            kind, addr, da1.length
+        );
+
+        let progress = &mut self.upload_progress;
+        let mut report = |sent: usize, total: usize| {
+            if let Some(progress) = progress.as_mut() {
+                progress("DA1", sent, total);
             }
         };
+        self.conn
+            .send_da(&da1.data, da1.length, addr, da1.sig_len, &mut report)
+            .await?;
+        info!("[Penumbra] Sent DA1, jumping to address 0x{:08X}...", addr);
+        self.conn.jump_da(addr).await?;
 
+        // DA1 takes a moment to come up, see TransportConfig for how long we wait
+        self.conn.wait_sync_byte(DA1_SYNC_BYTE, "DA1").await?;
         info!("[Penumbra] Received sync byte");
 
-        if sync_byte != 0xC0 {
-            return Err(Error::new(ErrorKind::Other, "Incorrect sync byte received"));
-        }
-
         self.send_cmd(Cmd::SyncSignal).await?;
         self.send_cmd(Cmd::SetupEnvironment).await?;
 
-        let mut env_param = Vec::new();
-        env_param.extend_from_slice(&2u32.to_le_bytes()); // da_log_level = 2 (UART)
-        env_param.extend_from_slice(&1u32.to_le_bytes()); // log_channel = 1
-        env_param.extend_from_slice(&1u32.to_le_bytes()); // system_os = 1 (OS_LINUX)
-        env_param.extend_from_slice(&0u32.to_le_bytes()); // ufs_provision = 0
-        env_param.extend_from_slice(&0u32.to_le_bytes()); // ...
-
-        self.send_data(&env_param).await?;
+        debug!("DA environment: {:?}", self.env);
+        self.send_data(&self.env.to_bytes()).await?;
         self.send_cmd(Cmd::SetupHwInitParams).await?;
         let hw_param = [0x00, 0x00, 0x00, 0x00];
         self.send_data(&hw_param).await?;
 
         let (magic, dtype, len) = {
             let mut sync_hdr = [0u8; 12];
-            match self.conn.port.read_exact(&mut sync_hdr).await {
+            match self.conn.read_exact(&mut sync_hdr).await {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(Error::new(
@@ -486,7 +959,7 @@ impl XFlash {
 
         let sync_signal_value = {
             let mut sync_signal_buf = [0u8; 4];
-            match self.conn.port.read_exact(&mut sync_signal_buf).await {
+            match self.conn.read_exact(&mut sync_signal_buf).await {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(Error::new(
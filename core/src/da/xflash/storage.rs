@@ -0,0 +1,67 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::core::storage::StorageType;
+use crate::da::DAProtocol;
+use crate::da::storage_ops::{StorageInfo, StorageOps};
+use crate::da::xflash::XFlash;
+use crate::da::xflash::flash::{self, FlashTarget};
+use tokio::io::Error;
+
+// XFlash on one kind of storage: every ReadData/WriteData/Format says which
+// storage and which part of it, see FlashTarget
+pub struct XFlashStorage<'a> {
+    xflash: &'a mut XFlash,
+    storage: StorageType,
+    target: FlashTarget,
+}
+
+impl<'a> XFlashStorage<'a> {
+    pub fn new(xflash: &'a mut XFlash, storage: StorageType) -> Self {
+        Self {
+            xflash,
+            storage,
+            target: FlashTarget::user(storage),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageOps for XFlashStorage<'_> {
+    async fn read_range(
+        &mut self,
+        addr: u64,
+        buf: &mut [u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<usize, Error> {
+        flash::read_flash_into(self.xflash, self.target, addr, buf, progress).await
+    }
+
+    async fn write_range(
+        &mut self,
+        addr: u64,
+        data: &[u8],
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        flash::write_flash(self.xflash, self.target, addr, data.len(), data, progress).await
+    }
+
+    async fn erase_range(
+        &mut self,
+        addr: u64,
+        size: u64,
+        progress: &mut (dyn FnMut(usize, usize) + Send),
+    ) -> Result<(), Error> {
+        flash::format_flash(self.xflash, self.target, addr, size, progress).await
+    }
+
+    async fn info(&mut self) -> Result<StorageInfo, Error> {
+        let view = self.xflash.storage_view().await?;
+        Ok(StorageInfo {
+            storage: self.storage,
+            block_size: view.block_size,
+            user_size: view.user_size,
+        })
+    }
+}
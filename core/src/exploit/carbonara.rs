@@ -3,10 +3,11 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 use crate::connection::port::ConnectionType;
+use crate::core::checksums::sha256;
+use crate::da::patch::CodePatch;
 use crate::da::{DA, DAEntryRegion, DAProtocol, DAType};
 use crate::exploit::{BootStage, Exploit, ExploitMeta};
 use log::{debug, info};
-use sha2::{Digest, Sha256};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
@@ -16,18 +17,25 @@ pub struct Carbonara {
     meta: ExploitMeta,
     da: Arc<Mutex<DA>>,
     patched_da2: Option<DAEntryRegion>,
+    // Changes made to the DA2 code, checked with verify_da2_patch before booting it
+    patches: Vec<CodePatch>,
 }
 
 impl Carbonara {
     pub fn new(da: Arc<Mutex<DA>>) -> Self {
         Carbonara {
-            meta: ExploitMeta {
-                name: String::from("Carbonara"),
-                boot_mode: vec![ConnectionType::Brom, ConnectionType::Preloader],
-                boot_stage: BootStage::Da1,
-            },
+            meta: Self::meta(),
             da,
             patched_da2: None,
+            patches: Vec::new(),
+        }
+    }
+
+    pub fn meta() -> ExploitMeta {
+        ExploitMeta {
+            name: String::from("Carbonara"),
+            boot_mode: vec![ConnectionType::Brom, ConnectionType::Preloader],
+            boot_stage: BootStage::Da1,
         }
     }
 
@@ -58,6 +66,11 @@ impl Carbonara {
         self.patched_da2.as_ref()
     }
 
+    // Empty for now, Carbonara only swaps the hash DA1 checks DA2 against
+    pub fn get_patches(&self) -> &[CodePatch] {
+        &self.patches
+    }
+
     // TODO: Consider making this part of da.rs instead, as kamakiri requires it as well
     async fn find_da_hash_offset(&self) -> Option<usize> {
         let da_borrow = self.da.lock().await;
@@ -147,10 +160,11 @@ impl Exploit for Carbonara {
         // 0x2DEA4 -> 0x22DEA4
         let virtual_addr = hash_offset as u32 + da1_addr;
 
-        let mut hasher = Sha256::new();
-        hasher.update(&da2.data);
-        let hash_result = hasher.finalize();
-        debug!("[Exploit] Computed DA2 SHA256 hash: {:x}", hash_result);
+        let hash_result = sha256(&da2.data);
+        debug!(
+            "[Exploit] Computed DA2 SHA256 hash: {}",
+            hex::encode(hash_result)
+        );
 
         match timeout(
             Duration::from_secs(5),
@@ -6,6 +6,7 @@ pub mod carbonara;
 use crate::connection::port::ConnectionType;
 use crate::da::protocol::DAProtocol;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BootStage {
     Brom,
     Preloader,
@@ -13,6 +14,7 @@ pub enum BootStage {
     Da2,
 }
 
+#[derive(Debug, Clone)]
 pub struct ExploitMeta {
     pub name: String,
     pub boot_mode: Vec<ConnectionType>, // In which mode the exploit works (BROM, Preloader, DA)
@@ -25,3 +27,61 @@ pub trait Exploit {
 
     fn get_meta(&self) -> &ExploitMeta;
 }
+
+// Every exploit Penumbra knows about. Used to suggest a way around secure boot
+// when the device refuses a DA.
+pub fn registry() -> Vec<ExploitMeta> {
+    vec![carbonara::Carbonara::meta()]
+}
+
+// Finds an exploit that runs while the device is at `stage`
+pub fn find_exploit(stage: BootStage) -> Option<ExploitMeta> {
+    registry().into_iter().find(|meta| meta.boot_stage == stage)
+}
+
+// Whether exploits get run while booting the DA, chosen per session
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ExploitPolicy {
+    // Try what applies, carry on unpatched if it doesn't work
+    #[default]
+    Auto,
+    // Never, e.g. a signed DA on a device that doesn't enforce DAA. Saves the
+    // time (and the risk) of the attempt.
+    Skip,
+    // Only the named exploit, and fail if it doesn't work
+    Force(String),
+}
+
+impl ExploitPolicy {
+    // "auto", "skip" (or "none"), "force" for the DA1 exploit, or an exploit name
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ExploitPolicy::Auto),
+            "skip" | "none" => Ok(ExploitPolicy::Skip),
+            "force" => find_exploit(BootStage::Da1)
+                .map(|meta| ExploitPolicy::Force(meta.name))
+                .ok_or_else(|| "No exploit to force".to_string()),
+            name => registry()
+                .into_iter()
+                .find(|meta| meta.name.eq_ignore_ascii_case(name))
+                .map(|meta| ExploitPolicy::Force(meta.name))
+                .ok_or_else(|| {
+                    let known: Vec<String> = registry().into_iter().map(|meta| meta.name).collect();
+                    format!(
+                        "Unknown exploit '{}', expected auto, skip, force or one of: {}",
+                        value,
+                        known.join(", ")
+                    )
+                }),
+        }
+    }
+
+    // Whether the exploit called `name` may run
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            ExploitPolicy::Auto => true,
+            ExploitPolicy::Skip => false,
+            ExploitPolicy::Force(forced) => forced.eq_ignore_ascii_case(name),
+        }
+    }
+}
@@ -2,10 +2,13 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
+pub mod about;
 pub mod connection;
 pub mod core;
 pub mod da;
 pub mod exploit;
 
-pub use connection::port::{MTKPort, find_mtk_port};
+pub use connection::MockMTKPort;
+pub use connection::cancel::CancelToken;
+pub use connection::port::{MTKPort, find_mtk_port, find_mtk_ports, try_find_mtk_port};
 pub use core::device::Device;
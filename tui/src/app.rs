@@ -2,10 +2,29 @@
     SPDX-License-Identifier: AGPL-3.0-or-later
     SPDX-FileCopyrightText: 2025 Shomy
 */
+use crate::config::config_path;
+use crate::keys::{Action, Keymap};
 use crate::pages::{DevicePage, Page, WelcomePage};
-use penumbra::da::DAFile;
-use ratatui::crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crate::recent::{MAX_RECENT, RecentLoaders};
+use crate::settings::Settings;
+use crate::theme::Theme;
+use log::error;
+use penumbra::core::autobackup::AutoBackup;
+use penumbra::core::events::{Event as CoreEvent, EventSink};
+use penumbra::core::policy::SafetyPolicy;
+use penumbra::core::power::PowerProfile;
+use penumbra::core::profile::ProfileStore;
+use penumbra::core::throttle::WriteThrottle;
+use penumbra::da::{DAFile, DaEnvConfig, DaLogChannel, DaLogLevel, LoaderCatalog};
+use penumbra::da::xflash::{LoadAddresses, load_extension_payload, set_load_addresses};
+use penumbra::exploit::ExploitPolicy;
+use ratatui::crossterm::event::{self, Event};
+use ratatui::layout::{Constraint, Flex, Layout};
+use ratatui::widgets::{Block, Borders, Clear, Row, Table};
 use ratatui::{DefaultTerminal, Frame};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
 use std::{io::Result, time::Duration};
 
 #[derive(PartialEq, Clone, Copy, Default)]
@@ -18,14 +37,35 @@ pub enum AppPage {
 #[derive(Default)]
 pub struct AppCtx {
     loader: Option<DAFile>,
+    // Fallbacks picked by hw_code when no loader was selected
+    catalog: LoaderCatalog,
+    // Partitions get dumped here before being written, if enabled in the settings
+    auto_backup: Option<AutoBackup>,
+    // Protected partitions and forced backups, from the workspace's .penumbra.conf
+    safety: SafetyPolicy,
+    power_profile: PowerProfile,
+    write_throttle: WriteThrottle,
+    // Known-good settings per device, applied when the same device connects again
+    profiles: Option<ProfileStore>,
+    // Quick picks on the welcome page, None when turned off
+    recent: Option<RecentLoaders>,
+    exploit: ExploitPolicy,
+    // Log level and channel DA1 gets told about
+    da_env: DaEnvConfig,
     exit: bool,
     current_page_id: AppPage,
-    next_page_id: Option<AppPage>
+    next_page_id: Option<AppPage>,
+    keymap: Keymap,
+    theme: Theme,
+    event_sink: Option<EventSink>,
+    // Behind a mutex only to keep AppCtx Sync, it's never contended
+    events: Option<Mutex<Receiver<CoreEvent>>>,
 }
 
 pub struct App {
     current_page: Box<dyn Page + Send>,
     pub context: AppCtx,
+    show_help: bool,
 }
 
 impl AppCtx {
@@ -35,20 +75,223 @@ impl AppCtx {
     pub fn loader(&self) -> Option<&DAFile> {
         self.loader.as_ref()
     }
+    pub fn catalog(&self) -> &LoaderCatalog {
+        &self.catalog
+    }
+    pub fn auto_backup(&self) -> Option<&AutoBackup> {
+        self.auto_backup.as_ref()
+    }
+    pub fn safety(&self) -> &SafetyPolicy {
+        &self.safety
+    }
+    pub fn power_profile(&self) -> PowerProfile {
+        self.power_profile
+    }
+    pub fn write_throttle(&self) -> WriteThrottle {
+        self.write_throttle
+    }
+    pub fn profiles(&self) -> Option<&ProfileStore> {
+        self.profiles.as_ref()
+    }
+    pub fn recent(&self) -> Option<&RecentLoaders> {
+        self.recent.as_ref()
+    }
+    pub fn recent_mut(&mut self) -> Option<&mut RecentLoaders> {
+        self.recent.as_mut()
+    }
+    pub fn exploit(&self) -> &ExploitPolicy {
+        &self.exploit
+    }
+    pub fn da_env(&self) -> DaEnvConfig {
+        self.da_env
+    }
     pub fn change_page(&mut self, page: AppPage) {
         self.next_page_id = Some(page);
     }
     pub fn quit(&mut self) {
         self.exit = true;
     }
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+    pub fn event_sink(&self) -> Option<EventSink> {
+        self.event_sink.clone()
+    }
+    // Events sent by core since the last call
+    pub fn take_events(&self) -> Vec<CoreEvent> {
+        match &self.events {
+            Some(events) => events.lock().unwrap().try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
 }
 
 impl App {
-    pub fn new() -> App {
-        App {
-            current_page: Box::new(WelcomePage::default()),
-            context: AppCtx::default()
+    pub fn new(event_sink: EventSink, events: Receiver<CoreEvent>) -> std::io::Result<App> {
+        let settings = Settings::load_default();
+
+        // Lets extension developers try a freshly built da_x.bin
+        if let Some(path) = settings.get("da_extension")
+            && let Err(e) = load_extension_payload(Path::new(path))
+        {
+            error!("Failed to load DA extensions from {}: {}", path, e);
         }
+
+        // For SoCs with a different DRAM map, e.g. `extensions_address = 0x48000000`
+        let address = |key: &str| {
+            let value = settings.get(key)?;
+            match u32::from_str_radix(value.trim_start_matches("0x"), 16) {
+                Ok(addr) => Some(addr),
+                Err(_) => {
+                    error!("Invalid {} '{}', using the default", key, value);
+                    None
+                }
+            }
+        };
+        set_load_addresses(LoadAddresses {
+            da2: address("da2_address"),
+            extensions: address("extensions_address"),
+        });
+
+        // `loader_dir = /path/to/loaders`, defaults to the loaders dir next to settings.conf
+        let loader_dir = settings
+            .get("loader_dir")
+            .map(PathBuf::from)
+            .or_else(|| config_path("loaders"));
+        let catalog = LoaderCatalog::with_defaults(loader_dir.as_deref());
+
+        // `auto_backup = on`, backups go to `auto_backup_dir` (the backups dir next to
+        // settings.conf by default) and the newest `auto_backup_keep` are kept
+        let auto_backup = match settings.get("auto_backup") {
+            Some("on" | "true" | "yes" | "1") => settings
+                .get("auto_backup_dir")
+                .map(PathBuf::from)
+                .or_else(|| config_path("backups"))
+                .map(|dir| {
+                    let mut backup = AutoBackup::new(dir);
+                    if let Some(keep) = settings.get("auto_backup_keep") {
+                        match keep.parse() {
+                            Ok(keep) => backup.keep = keep,
+                            Err(_) => error!("Invalid auto_backup_keep '{}', using the default", keep),
+                        }
+                    }
+                    backup
+                }),
+            _ => None,
+        };
+
+        // `policy_file = /path/to/.penumbra.conf`, otherwise the closest one from the
+        // working directory up (or $PENUMBRA_POLICY), same as the CLI. A broken one
+        // keeps the app from starting instead of quietly falling back to the defaults
+        let safety = match settings.get("policy_file") {
+            Some(path) => SafetyPolicy::load(Path::new(path))?,
+            None => std::env::current_dir().and_then(|dir| SafetyPolicy::for_workspace(&dir))?,
+        };
+
+        // `power_profile = low` for devices running off USB power alone
+        let power_profile = match settings.get("power_profile") {
+            Some("low") => PowerProfile::LowPower,
+            Some("normal") | None => PowerProfile::Normal,
+            Some(other) => {
+                error!("Unknown power_profile '{}', using normal", other);
+                PowerProfile::Normal
+            }
+        };
+
+        // `write_rate = 512` (KiB/s) and `write_delay = 10` (ms between packets)
+        // for devices that corrupt data at full speed
+        let mut write_throttle = WriteThrottle::default();
+        if let Some(rate) = settings.get("write_rate") {
+            match rate.parse::<u64>() {
+                Ok(rate) => write_throttle = WriteThrottle::rate(rate.saturating_mul(1024)),
+                Err(_) => error!("Invalid write_rate '{}', not limiting", rate),
+            }
+        }
+        if let Some(delay) = settings.get("write_delay") {
+            match delay.parse() {
+                Ok(ms) => write_throttle.delay = Duration::from_millis(ms),
+                Err(_) => error!("Invalid write_delay '{}', not delaying", delay),
+            }
+        }
+
+        // On by default, `profiles = off` makes every session start from the settings
+        // above. A saved profile wins over power_profile and write_rate/write_delay.
+        let profiles = match settings.get("profiles") {
+            Some("off" | "false" | "no" | "0") => None,
+            _ => settings
+                .get("profiles_file")
+                .map(PathBuf::from)
+                .or_else(ProfileStore::default_path)
+                .map(ProfileStore::new),
+        };
+
+        // `recent = off` hides the recent loaders and devices on the welcome page,
+        // `recent_max = 2` shows fewer of each (MAX_RECENT at most)
+        let recent = match settings.get("recent") {
+            Some("off" | "false" | "no" | "0") => None,
+            _ => {
+                let max = match settings.get("recent_max").map(str::parse::<usize>) {
+                    Some(Ok(max)) => max.min(MAX_RECENT),
+                    Some(Err(_)) => {
+                        error!("Invalid recent_max, using {}", MAX_RECENT);
+                        MAX_RECENT
+                    }
+                    None => MAX_RECENT,
+                };
+                RecentLoaders::load_default(max)
+            }
+        };
+
+        // `exploit = skip` for signed DAs on devices without DAA, `force` or an
+        // exploit name to fail instead of booting the stock DA2
+        let exploit = match settings.get("exploit").map(ExploitPolicy::parse) {
+            Some(Ok(exploit)) => exploit,
+            Some(Err(e)) => {
+                error!("{}, using auto", e);
+                ExploitPolicy::Auto
+            }
+            None => ExploitPolicy::Auto,
+        };
+
+        // `da_log_level = debug` and `da_log_channel = usb` to see what a DA that
+        // dies early was up to, `da_log_channel = none` keeps the UART quiet
+        let mut da_env = DaEnvConfig::default();
+        if let Some(level) = settings.get("da_log_level") {
+            match DaLogLevel::parse(level) {
+                Ok(level) => da_env.log_level = level,
+                Err(e) => error!("{}, using info", e),
+            }
+        }
+        if let Some(channel) = settings.get("da_log_channel") {
+            match DaLogChannel::parse(channel) {
+                Ok(channel) => da_env.log_channel = channel,
+                Err(e) => error!("{}, using uart", e),
+            }
+        }
+
+        Ok(App {
+            current_page: Box::new(WelcomePage::default()),
+            context: AppCtx {
+                catalog,
+                auto_backup,
+                safety,
+                power_profile,
+                write_throttle,
+                profiles,
+                recent,
+                exploit,
+                da_env,
+                keymap: Keymap::from_settings(&settings),
+                theme: Theme::from_settings(&settings),
+                event_sink: Some(event_sink),
+                events: Some(Mutex::new(events)),
+                ..Default::default()
+            },
+            show_help: false,
+        })
     }
 
     pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
@@ -70,12 +313,23 @@ impl App {
     async fn handle_events(&mut self) -> Result<()> {
         if event::poll(Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
-                // Force exit: [Ctrl + Delete]
-                if key.code == KeyCode::Delete && key.modifiers.contains(KeyModifiers::CONTROL)
-                {
+                let action = self.context.keymap().action(&key);
+
+                // Force exit: [Ctrl + Delete] by default
+                if action == Some(Action::ForceQuit) {
                     self.context.quit();
                 }
 
+                // Any key closes the help overlay, without reaching the page
+                if self.show_help {
+                    self.show_help = false;
+                    return Ok(());
+                }
+                if action == Some(Action::Help) && self.current_page.accepts_help_key() {
+                    self.show_help = true;
+                    return Ok(());
+                }
+
                 self.current_page.handle_input(&mut self.context, key).await;
             }
         }
@@ -84,6 +338,39 @@ impl App {
 
     fn draw(&mut self, frame: &mut Frame<'_>) {
         self.current_page.render(frame, &mut self.context);
+
+        if self.show_help {
+            self.draw_help(frame);
+        }
+    }
+
+    fn draw_help(&self, frame: &mut Frame<'_>) {
+        let keymap = self.context.keymap();
+        let mut bindings = self.current_page.help();
+        bindings.push((Action::Help, "Show this help"));
+        bindings.push((Action::ForceQuit, "Force quit"));
+
+        let rows: Vec<Row> = bindings
+            .iter()
+            .map(|(action, desc)| Row::new(vec![keymap.label(*action), desc.to_string()]))
+            .collect();
+
+        let height = rows.len() as u16 + 2;
+        let [area] = Layout::vertical([Constraint::Length(height)])
+            .flex(Flex::Center)
+            .areas(frame.area());
+        let [area] = Layout::horizontal([Constraint::Length(60)])
+            .flex(Flex::Center)
+            .areas(area);
+
+        let table = Table::new(rows, [Constraint::Length(20), Constraint::Min(0)]).block(
+            Block::default()
+                .title("Keybindings (press any key to close)")
+                .borders(Borders::ALL),
+        );
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(table, area);
     }
 
     pub async fn switch_to(&mut self, page: AppPage) {
@@ -0,0 +1,23 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use std::env;
+use std::path::PathBuf;
+
+// $XDG_CONFIG_HOME/antumbra (or ~/.config/antumbra), %APPDATA%\antumbra on Windows
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    base.map(|dir| dir.join("antumbra"))
+}
+
+pub fn config_path(name: &str) -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(name))
+}
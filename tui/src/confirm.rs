@@ -0,0 +1,131 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::keys::Action;
+use crate::theme::Theme;
+use penumbra::core::policy::SafetyPolicy;
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Flex, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph, Wrap};
+
+// Only operations that write get a dialog, reads and reboots just run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskLevel {
+    // Overwrites data, recoverable with a backup or the firmware package
+    High,
+    // Touches per device data (seccfg, nvram...), a mistake can brick the device
+    Critical,
+}
+
+impl RiskLevel {
+    // Anything writing to a partition the safety policy protects is critical,
+    // any other write is high.
+    pub fn for_write(partition: &str, policy: &SafetyPolicy) -> Self {
+        if policy.is_protected(partition) {
+            RiskLevel::Critical
+        } else {
+            RiskLevel::High
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RiskLevel::High => "HIGH",
+            RiskLevel::Critical => "CRITICAL",
+        }
+    }
+
+    fn style(&self, theme: &Theme) -> Style {
+        match self {
+            RiskLevel::High => theme.error,
+            RiskLevel::Critical => theme.error.add_modifier(Modifier::BOLD),
+        }
+    }
+}
+
+// Modal asking to confirm an operation before it runs. Pages keep it around
+// while it's open, forward their input to handle_input() and draw it last.
+pub struct ConfirmDialog {
+    pub operation: String,
+    pub target: String,
+    pub risk: RiskLevel,
+    confirm_selected: bool,
+}
+
+impl ConfirmDialog {
+    pub fn new(operation: &str, target: &str, risk: RiskLevel) -> Self {
+        Self {
+            operation: operation.to_string(),
+            target: target.to_string(),
+            risk,
+            // Starts on "Cancel", so a stray Enter does nothing
+            confirm_selected: false,
+        }
+    }
+
+    // Some(true) once confirmed, Some(false) once cancelled, None while still open
+    pub fn handle_input(&mut self, action: Option<Action>) -> Option<bool> {
+        match action {
+            Some(Action::Back) => Some(false),
+            Some(Action::Select) => Some(self.confirm_selected),
+            Some(Action::Up | Action::Down | Action::PrevChunk | Action::NextChunk) => {
+                self.confirm_selected = !self.confirm_selected;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    pub fn help() -> Vec<(Action, &'static str)> {
+        vec![
+            (Action::NextChunk, "Switch between Cancel and Confirm"),
+            (Action::Select, "Apply the selected choice"),
+            (Action::Back, "Cancel"),
+        ]
+    }
+
+    pub fn render(&self, frame: &mut Frame<'_>, theme: &Theme) {
+        let [area] = Layout::vertical([Constraint::Length(9)])
+            .flex(Flex::Center)
+            .areas(frame.area());
+        let [area] = Layout::horizontal([Constraint::Length(60)])
+            .flex(Flex::Center)
+            .areas(area);
+
+        let (cancel, confirm) = if self.confirm_selected {
+            (Style::default(), theme.highlight)
+        } else {
+            (theme.highlight, Style::default())
+        };
+
+        let lines = vec![
+            Line::from(format!("Operation: {}", self.operation)),
+            Line::from(format!("Target: {}", self.target)),
+            Line::from(vec![
+                Span::raw("Risk: "),
+                Span::styled(self.risk.label(), self.risk.style(theme)),
+            ]),
+            Line::from(""),
+            Line::from(vec![
+                Span::styled(" Cancel ", cancel),
+                Span::raw("   "),
+                Span::styled(" Confirm ", confirm),
+            ])
+            .centered(),
+        ];
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(lines).wrap(Wrap { trim: true }).block(
+                Block::default()
+                    .title("Are you sure?")
+                    .borders(Borders::ALL)
+                    .border_style(self.risk.style(theme)),
+            ),
+            area,
+        );
+    }
+}
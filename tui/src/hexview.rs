@@ -0,0 +1,188 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::theme::Theme;
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::widgets::{Block, Borders, Paragraph};
+
+const BYTES_PER_ROW: usize = 16;
+// How much of the partition is read at once, a few pages of headers is plenty
+pub const CHUNK_SIZE: usize = 0x10000;
+
+// Scrollable hexdump of a range of a partition
+pub struct HexView {
+    pub partition: String,
+    pub partition_size: u64,
+    // Offset of data[0] inside the partition
+    pub offset: u64,
+    data: Vec<u8>,
+    scroll: usize,
+    // Rows that fit on screen, updated on render, used for page up/down
+    page_rows: usize,
+}
+
+impl HexView {
+    pub fn new(partition: &str, partition_size: u64, offset: u64, data: Vec<u8>) -> Self {
+        Self {
+            partition: partition.to_string(),
+            partition_size,
+            offset,
+            data,
+            scroll: 0,
+            page_rows: 1,
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.data.len().div_ceil(BYTES_PER_ROW)
+    }
+
+    pub fn scroll_by(&mut self, rows: isize) {
+        let max = self.rows().saturating_sub(self.page_rows);
+        self.scroll = self.scroll.saturating_add_signed(rows).min(max);
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_by(self.page_rows as isize);
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_by(-(self.page_rows as isize));
+    }
+
+    // Range to read for the chunk after (or before) the one being shown
+    pub fn next_chunk(&self) -> Option<(u64, usize)> {
+        let next = self.offset + self.data.len() as u64;
+        chunk_at(next, self.partition_size)
+    }
+
+    pub fn prev_chunk(&self) -> Option<(u64, usize)> {
+        if self.offset == 0 {
+            return None;
+        }
+        chunk_at(self.offset.saturating_sub(CHUNK_SIZE as u64), self.partition_size)
+    }
+
+    pub fn render(&mut self, frame: &mut Frame<'_>, area: Rect, theme: &Theme) {
+        let [summary_area, dump_area] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(3)]).areas(area);
+
+        let summary = if self.offset == 0 {
+            describe(&self.data).unwrap_or("Unknown content")
+        } else {
+            "-"
+        };
+        frame.render_widget(
+            Paragraph::new(summary)
+                .style(theme.info)
+                .block(Block::default().title("Detected").borders(Borders::ALL)),
+            summary_area,
+        );
+
+        self.page_rows = dump_area.height.saturating_sub(2).max(1) as usize;
+        self.scroll_by(0);
+
+        let lines: Vec<String> = self
+            .data
+            .chunks(BYTES_PER_ROW)
+            .enumerate()
+            .skip(self.scroll)
+            .take(self.page_rows)
+            .map(|(row, bytes)| {
+                hexdump_line(self.offset + (row * BYTES_PER_ROW) as u64, bytes)
+            })
+            .collect();
+
+        let end = self.offset + self.data.len() as u64;
+        let title = format!(
+            "{} [{:#X}..{:#X}] of {:#X}",
+            self.partition, self.offset, end, self.partition_size
+        );
+        frame.render_widget(
+            Paragraph::new(lines.join("\n"))
+                .block(Block::default().title(title).borders(Borders::ALL)),
+            dump_area,
+        );
+    }
+}
+
+pub fn chunk_at(offset: u64, partition_size: u64) -> Option<(u64, usize)> {
+    let remaining = partition_size.checked_sub(offset)?;
+    if remaining == 0 {
+        return None;
+    }
+    Some((offset, remaining.min(CHUNK_SIZE as u64) as usize))
+}
+
+// `00000010  41 4e 44 52 4f 49 44 21  00 00 00 00 00 00 00 00  |ANDROID!........|`
+pub fn hexdump_line(offset: u64, bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(BYTES_PER_ROW * 3 + 1);
+    for i in 0..BYTES_PER_ROW {
+        if i == BYTES_PER_ROW / 2 {
+            hex.push(' ');
+        }
+        match bytes.get(i) {
+            Some(b) => hex.push_str(&format!("{:02x} ", b)),
+            None => hex.push_str("   "),
+        }
+    }
+
+    let ascii: String = bytes
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+
+    format!("{:08x}  {} |{}|", offset, hex, ascii)
+}
+
+// Best effort guess of what's at the start of a partition
+pub fn describe(data: &[u8]) -> Option<&'static str> {
+    let le32 = |at: usize| {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    };
+    let le16 = |at: usize| {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+    };
+
+    if data.starts_with(b"ANDROID!") {
+        return Some("Android boot image");
+    }
+    if data.starts_with(b"VNDRBOOT") {
+        return Some("Android vendor boot image");
+    }
+    if data.starts_with(b"AVB0") {
+        return Some("AVB vbmeta image");
+    }
+    if data.get(0x200..0x208) == Some(b"EFI PART") {
+        return Some("GPT (protective MBR + header)");
+    }
+    if le32(0) == Some(0x4D4D4D4D) {
+        return Some("Seccfg v4");
+    }
+    if le32(0) == Some(0x58881688) {
+        return Some("Mediatek image header (LK/logo/...)");
+    }
+    if le32(0) == Some(0xED26FF3A) {
+        return Some("Android sparse image");
+    }
+    if le16(0x438) == Some(0xEF53) {
+        return Some("ext4 filesystem");
+    }
+    if le32(0x400) == Some(0xE0F5E1E2) {
+        return Some("EROFS filesystem");
+    }
+    if le32(0) == Some(0xF2F52010) || le32(0x400) == Some(0xF2F52010) {
+        return Some("F2FS filesystem");
+    }
+    if data.iter().all(|&b| b == 0) {
+        return Some("Empty (all zeroes)");
+    }
+    if data.iter().all(|&b| b == 0xFF) {
+        return Some("Erased (all 0xFF)");
+    }
+    None
+}
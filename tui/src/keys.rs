@@ -0,0 +1,226 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::settings::Settings;
+use log::warn;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    NextChunk,
+    PrevChunk,
+    Select,
+    Back,
+    Sort,
+    Filter,
+    Help,
+    ForceQuit,
+}
+
+impl Action {
+    // Order matters: when a key is bound to more than one action, the first wins
+    pub const ALL: &[Action] = &[
+        Action::ForceQuit,
+        Action::Help,
+        Action::Up,
+        Action::Down,
+        Action::PageUp,
+        Action::PageDown,
+        Action::NextChunk,
+        Action::PrevChunk,
+        Action::Select,
+        Action::Back,
+        Action::Sort,
+        Action::Filter,
+    ];
+
+    // Name used in the settings file, as `key.<name> = ...`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::PageUp => "page_up",
+            Action::PageDown => "page_down",
+            Action::NextChunk => "next_chunk",
+            Action::PrevChunk => "prev_chunk",
+            Action::Select => "select",
+            Action::Back => "back",
+            Action::Sort => "sort",
+            Action::Filter => "filter",
+            Action::Help => "help",
+            Action::ForceQuit => "force_quit",
+        }
+    }
+
+    fn default_keys(&self) -> &'static str {
+        match self {
+            Action::Up => "Up, k",
+            Action::Down => "Down, j",
+            Action::PageUp => "PageUp",
+            Action::PageDown => "PageDown, Space",
+            Action::NextChunk => "Right, l",
+            Action::PrevChunk => "Left, h",
+            Action::Select => "Enter",
+            Action::Back => "Esc",
+            Action::Sort => "s",
+            Action::Filter => "/",
+            Action::Help => "?",
+            Action::ForceQuit => "Ctrl+Delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    // Accepts things like "Enter", "k", "F5", "Ctrl+Delete" or "Alt+x"
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        // "+" on its own or as the last key, e.g. "Ctrl++"
+        if s.ends_with('+') {
+            parts.retain(|p| !p.is_empty());
+            parts.push("+");
+        }
+        let key = parts.pop()?;
+
+        for modifier in parts {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+        }
+
+        let code = match key.to_ascii_lowercase().as_str() {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "space" => KeyCode::Char(' '),
+            f if f.len() > 1 && f.starts_with('f') => KeyCode::F(f[1..].parse().ok()?),
+            _ => {
+                let mut chars = key.chars();
+                let c = chars.next()?;
+                if chars.next().is_some() {
+                    return None;
+                }
+                KeyCode::Char(c)
+            }
+        };
+
+        Some(Self { code, modifiers })
+    }
+
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        // Shift is implied by the character itself (e.g. '?'), so only compare it
+        // when it was explicitly asked for
+        let mods = if self.modifiers.contains(KeyModifiers::SHIFT) {
+            key.modifiers
+        } else {
+            key.modifiers - KeyModifiers::SHIFT
+        };
+        key.code == self.code && mods == self.modifiers
+    }
+
+    pub fn label(&self) -> String {
+        let mut label = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            label.push_str("Ctrl+");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            label.push_str("Alt+");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            label.push_str("Shift+");
+        }
+        label.push_str(&match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::F(n) => format!("F{n}"),
+            other => format!("{other:?}"),
+        });
+        label
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Action, Vec<KeyBinding>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_settings(&Settings::default())
+    }
+}
+
+impl Keymap {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let bindings = Action::ALL
+            .iter()
+            .map(|&action| {
+                let key = format!("key.{}", action.name());
+                let keys = match settings.get(&key).map(parse_keys) {
+                    Some(Some(keys)) => keys,
+                    Some(None) => {
+                        warn!("Invalid binding for {key}, using the default one");
+                        parse_keys(action.default_keys()).unwrap_or_default()
+                    }
+                    None => parse_keys(action.default_keys()).unwrap_or_default(),
+                };
+                (action, keys)
+            })
+            .collect();
+
+        Self { bindings }
+    }
+
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.iter().any(|binding| binding.matches(key)))
+            .map(|(action, _)| *action)
+    }
+
+    // e.g. "Up/k", for the help overlay
+    pub fn label(&self, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map(|(_, keys)| {
+                keys.iter()
+                    .map(KeyBinding::label)
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn parse_keys(s: &str) -> Option<Vec<KeyBinding>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(KeyBinding::parse)
+        .collect()
+}
@@ -3,27 +3,73 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 mod app;
+mod config;
+mod confirm;
+mod hexview;
+mod keys;
 mod pages;
+mod recent;
+mod settings;
+mod table;
+mod tasks;
+mod theme;
 use app::App;
 use env_logger::Builder;
+use log::{Level, LevelFilter, error};
+use penumbra::connection::port::load_port_filter;
+use penumbra::core::events::{Event, EventLogger, EventSink};
+use penumbra::core::report::{DEFAULT_TRACE_EVENTS, SessionTrace};
 use std::fs::File;
 use std::io::Result;
+use std::sync::{Arc, mpsc};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let log_file = File::create("app.log").expect("Failed to create log file");
 
-    Builder::new()
+    let logger = Builder::new()
         .parse_default_env()
         .write_style(env_logger::WriteStyle::Always)
         .target(env_logger::Target::Pipe(Box::new(log_file)))
-        .init();
+        .build();
 
+    // Everything core reports (progress, warnings...) reaches the pages as events,
+    // the log file still gets everything as before
+    let (event_tx, event_rx) = mpsc::channel::<Event>();
+    let sink: EventSink = Arc::new(move |event: &Event| {
+        let _ = event_tx.send(event.clone());
+    });
+    // Kept for `penumbra report`, saved when the app exits
+    let trace = Arc::new(SessionTrace::new(DEFAULT_TRACE_EVENTS));
+    let sink = trace.sink(Some(sink));
+    let max_level = logger.filter().max(LevelFilter::Warn);
+    log::set_boxed_logger(Box::new(EventLogger::new(
+        Box::new(logger),
+        sink.clone(),
+        Level::Warn,
+    )))
+    .expect("Logger already set");
+    log::set_max_level(max_level);
+
+    // Extra VID/PID pairs for vendor customized BROM/Preloader ports
+    if let Some(path) = config::config_path("usb_ports.conf")
+        && path.exists()
+        && let Err(e) = load_port_filter(&path)
+    {
+        error!("Failed to load {}: {}", path.display(), e);
+    }
+
+    // Before taking over the terminal, so a refused policy file gets printed
+    let mut app = App::new(sink, event_rx)?;
     let mut terminal = ratatui::init();
-    let mut app = App::new();
 
     let app_result = app.run(&mut terminal).await;
 
     ratatui::restore();
+    if let Some(path) = SessionTrace::default_path()
+        && let Err(e) = trace.save(&path)
+    {
+        eprintln!("Failed to save the session trace to {}: {}", path.display(), e);
+    }
     app_result
 }
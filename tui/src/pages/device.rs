@@ -3,21 +3,40 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 use crate::app::{AppCtx, AppPage};
+use crate::confirm::{ConfirmDialog, RiskLevel};
+use crate::hexview::{self, HexView};
+use crate::keys::Action;
 use crate::pages::Page;
+use crate::table::{Column, DataTable, TableRow, human_size};
+use crate::tasks::TaskList;
 use hex::encode;
 use penumbra::core::device::DeviceInfo;
+use penumbra::core::events::Event;
+use penumbra::connection::{Connection, HandshakeOptions};
+use penumbra::connection::diagnostics::{Remediation, diagnose};
 use penumbra::core::seccfg::LockFlag;
-use penumbra::{Device, find_mtk_port};
-use ratatui::crossterm::event::{KeyCode, KeyEvent};
+use penumbra::core::storage::Partition;
+use penumbra::da::LoaderMismatch;
+use penumbra::exploit::ExploitPolicy;
+use penumbra::{CancelToken, Device, try_find_mtk_port};
+use ratatui::crossterm::event::KeyEvent;
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    style::Style,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
 };
+use std::cmp::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, watch};
+use tokio::task::JoinHandle;
+
+// How often the device is pinged while idle in DA mode, and how long it has to answer
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(3);
+// 4 chunk sizes x 16 MiB, a few seconds on a healthy USB 2.0 link
+const BENCHMARK_SIZE: usize = 0x100_0000;
 
 #[derive(Clone, PartialEq, Default)]
 enum DeviceStatus {
@@ -25,9 +44,18 @@ enum DeviceStatus {
     WaitingForDevice,
     Initializing,
     DAReady,
+    ConnectionLost(String),
     Error(String),
 }
 
+// What the bottom panel shows
+enum DeviceView {
+    Actions,
+    Partitions,
+    Hex(HexView),
+    Tasks(ListState),
+}
+
 pub struct DevicePage {
     actions_state: ListState,
     actions: Vec<String>,
@@ -36,6 +64,27 @@ pub struct DevicePage {
     status_message: Option<(String, Style)>,
     last_poll: Instant,
     device_info: Option<DeviceInfo>,
+    // Refreshes device_info whenever core learns something new about the device
+    info_rx: Option<watch::Receiver<DeviceInfo>>,
+    init_task: Option<JoinHandle<Result<Device<'static>, DeviceStatus>>>,
+    cancel: Option<CancelToken>,
+    hints: Vec<Remediation>,
+    // A port showed up but couldn't be opened, cleared once one opens or it's gone
+    open_error: Option<String>,
+    heartbeat: Option<JoinHandle<std::io::Result<Duration>>>,
+    latency: Option<Duration>,
+    // Shares the port (and its counters) with the device, readable without locking it
+    connection: Option<Connection>,
+    view: DeviceView,
+    // Kept across views, so sorting and the filter survive a look at the hexdump
+    partitions: DataTable<Partition>,
+    // Lock state change waiting for the user to confirm it
+    confirm: Option<(ConfirmDialog, LockFlag)>,
+    // Latest Progress event of the running operation, and the last warning logged
+    progress: Option<(String, u64, u64)>,
+    last_log: Option<String>,
+    // Unlocks, checks and benchmarks, queued so the UI keeps going while they run
+    tasks: TaskList,
 }
 
 impl DevicePage {
@@ -47,6 +96,10 @@ impl DevicePage {
             actions: vec![
                 "Unlock Bootloader".to_string(),
                 "Lock Bootloader".to_string(),
+                "View Partition".to_string(),
+                "Check GPT".to_string(),
+                "Benchmark Link".to_string(),
+                "Tasks".to_string(),
                 "Back to Menu".to_string(),
             ],
             device: None,
@@ -54,65 +107,442 @@ impl DevicePage {
             status_message: None,
             last_poll: Instant::now(),
             device_info: None,
+            info_rx: None,
+            init_task: None,
+            cancel: None,
+            hints: Vec::new(),
+            open_error: None,
+            heartbeat: None,
+            latency: None,
+            connection: None,
+            view: DeviceView::Actions,
+            partitions: partition_table(),
+            confirm: None,
+            progress: None,
+            last_log: None,
+            tasks: TaskList::new(),
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::OperationStarted { operation } => {
+                self.progress = Some((operation, 0, 0));
+                self.last_log = None;
+            }
+            Event::Progress {
+                operation,
+                item,
+                done,
+                total,
+            } => {
+                let label = match item {
+                    Some(item) => format!("{operation} ({item})"),
+                    None => operation,
+                };
+                self.progress = Some((label, done, total));
+            }
+            Event::LogLine { level, message, .. } => {
+                self.last_log = Some(format!("{level}: {message}"));
+            }
+            Event::OperationFinished { .. } => self.progress = None,
         }
     }
 
     async fn poll_device(&mut self, ctx: &mut AppCtx) -> Result<(), DeviceStatus> {
-        if self.status == DeviceStatus::DAReady || matches!(self.status, DeviceStatus::Error(_)) {
+        if self.status == DeviceStatus::DAReady {
+            return self.poll_heartbeat().await;
+        }
+        if matches!(self.status, DeviceStatus::Error(_) | DeviceStatus::ConnectionLost(_)) {
             return Ok(());
         }
+        if self.status == DeviceStatus::Initializing {
+            return self.poll_init_task(ctx).await;
+        }
         if self.status == DeviceStatus::WaitingForDevice
             && self.last_poll.elapsed() > Duration::from_millis(500)
         {
             self.last_poll = Instant::now();
-            let ports = find_mtk_port().await;
+            let ports = match try_find_mtk_port().await {
+                Ok(ports) => {
+                    if self.open_error.take().is_some() {
+                        self.hints = diagnose(None);
+                    }
+                    ports
+                }
+                Err(e) => {
+                    self.hints = diagnose(Some(&e));
+                    self.open_error = Some(e.to_string());
+                    None
+                }
+            };
             if let Some(port) = ports {
+                // Without a selected loader, one is picked from the catalog once
+                // the device tells us its hw code
+                let da_data = ctx.loader().map(|loader| loader.da_raw_data.clone());
+                let catalog = ctx.catalog().clone();
+                if da_data.is_none() && catalog.is_empty() {
+                    return Err(DeviceStatus::Error(
+                        "No DA selected and no fallback loaders available".to_string(),
+                    ));
+                }
+
                 self.status = DeviceStatus::Initializing;
 
-                let da_data: Vec<u8> = ctx
-                    .loader()
-                    .map(|loader| loader.da_raw_data.as_slice())
-                    .ok_or_else(|| DeviceStatus::Error("No DA loader in context".to_string()))?
-                    .to_vec();
+                // Init runs in its own task, so the UI keeps drawing and the user
+                // can bail out with Esc while we wait for the device to answer.
+                let cancel = CancelToken::new();
+                let handshake = HandshakeOptions {
+                    cancel: Some(cancel.clone()),
+                    ..Default::default()
+                };
+                self.cancel = Some(cancel);
+                // Set before entering DA mode, so the DA upload shows up as progress
+                let event_sink = ctx.event_sink();
+                let power_profile = ctx.power_profile();
+                let write_throttle = ctx.write_throttle();
+                let profiles = ctx.profiles().cloned();
+                let exploit = ctx.exploit().clone();
+                let da_env = ctx.da_env();
+                self.init_task = Some(tokio::spawn(async move {
+                    let init = match da_data {
+                        Some(da_data) => Device::init_with(port, da_data, handshake).await,
+                        None => {
+                            Device::init_from_catalog_with(
+                                port,
+                                &catalog,
+                                handshake,
+                                profiles.as_ref(),
+                            )
+                            .await
+                        }
+                    };
+                    let mut dev = init
+                        .map_err(|e| match LoaderMismatch::from_error(&e) {
+                            // Already says what's wrong and what the DA supports
+                            Some(mismatch) => DeviceStatus::Error(mismatch.to_string()),
+                            None => DeviceStatus::Error(format!("Device init failed: {e}")),
+                        })?;
+
+                    dev.set_event_sink(event_sink);
+                    dev.set_power_profile(power_profile);
+                    dev.set_write_throttle(write_throttle);
+                    if exploit != ExploitPolicy::Auto {
+                        dev.set_exploit_policy(exploit);
+                    }
+                    dev.set_da_env(da_env);
+                    // Whatever worked on this device last time goes on top of the settings
+                    dev.set_profile_store(profiles);
+                    dev.apply_known_profile();
+                    dev.enter_da_mode()
+                        .await
+                        .map_err(|e| DeviceStatus::Error(format!("Failed DA mode: {e}")))?;
+
+                    Ok(dev)
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    async fn poll_init_task(&mut self, ctx: &mut AppCtx) -> Result<(), DeviceStatus> {
+        if !self.init_task.as_ref().is_some_and(|task| task.is_finished()) {
+            return Ok(());
+        }
 
-                let mut dev = Device::init(port, da_data)
-                    .await
-                    .map_err(|e| DeviceStatus::Error(format!("Device init failed: {e}")))?;
+        let task = self.init_task.take().unwrap();
+        self.cancel = None;
+        let mut dev = task
+            .await
+            .map_err(|e| DeviceStatus::Error(format!("Device init task failed: {e}")))??;
+        dev.set_event_sink(ctx.event_sink());
+        dev.set_auto_backup(ctx.auto_backup().cloned());
+        dev.set_safety_policy(ctx.safety().clone());
 
-                dev.enter_da_mode()
-                    .await
-                    .map_err(|e| DeviceStatus::Error(format!("Failed DA mode: {e}")))?;
+        self.info_rx = dev.watch_info();
+        if let Some(info_rx) = &mut self.info_rx {
+            let info = info_rx.borrow_and_update().clone();
+            self.set_device_info(Some(info));
+        }
+        self.connection = Some(dev.connection_handle());
+        self.device = Some(Arc::new(Mutex::new(dev)));
+        self.status = DeviceStatus::DAReady;
+        self.last_poll = Instant::now();
+        Ok(())
+    }
 
-                if let Some(arc_mutex) = dev.dev_info.as_ref() {
-                    let guard = arc_mutex.lock().await;
-                    self.device_info = Some(DeviceInfo::clone(&guard));
+    // Pings the device in the background every HEARTBEAT_INTERVAL, so a dropped
+    // cable shows up here instead of halfway through a long operation.
+    async fn poll_heartbeat(&mut self) -> Result<(), DeviceStatus> {
+        if let Some(task) = self.heartbeat.take_if(|task| task.is_finished()) {
+            self.last_poll = Instant::now();
+            match task.await {
+                Ok(Ok(latency)) => self.latency = Some(latency),
+                Ok(Err(e)) => {
+                    self.latency = None;
+                    return Err(DeviceStatus::ConnectionLost(e.to_string()));
                 }
-                self.device = Some(Arc::new(Mutex::new(dev)));
-                self.status = DeviceStatus::DAReady;
+                Err(e) => return Err(DeviceStatus::Error(format!("Heartbeat task failed: {e}"))),
             }
         }
+
+        if self.heartbeat.is_none() && self.last_poll.elapsed() > HEARTBEAT_INTERVAL {
+            let Some(dev_arc) = self.device.clone() else {
+                return Ok(());
+            };
+            self.heartbeat = Some(tokio::spawn(async move {
+                dev_arc.lock().await.ping(HEARTBEAT_TIMEOUT).await
+            }));
+        }
         Ok(())
     }
 
-    async fn set_device_lock_state(&mut self, flag: LockFlag) -> Result<Vec<u8>, String> {
-        match &self.device {
-            Some(dev_arc) => {
-                let mut dev = dev_arc.lock().await;
-                match dev.set_seccfg_lock_state(flag).await {
-                    Some(response) => Ok(response),
-                    None => Err("Failed to change lock state".to_string()),
+    fn set_device_info(&mut self, info: Option<DeviceInfo>) {
+        let partitions = info.as_ref().map_or(Vec::new(), |info| info.partitions.clone());
+        self.partitions.set_rows(partitions);
+        self.device_info = info;
+    }
+
+    async fn read_hex_chunk(
+        &mut self,
+        name: &str,
+        partition_size: u64,
+        range: (u64, usize),
+    ) -> Result<HexView, String> {
+        let dev_arc = self.device.as_ref().ok_or("No device connected")?;
+        let mut dev = dev_arc.lock().await;
+        let (offset, size) = range;
+        dev.read_partition_range(name, offset, size, &mut |_, _| {})
+            .await
+            .map(|data| HexView::new(name, partition_size, offset, data))
+            .map_err(|e| e.to_string())
+    }
+
+    async fn handle_partitions_input(
+        &mut self,
+        ctx: &mut AppCtx,
+        key: &KeyEvent,
+        action: Option<Action>,
+    ) {
+        // Typing a filter, the keys are text rather than actions
+        if self.partitions.is_editing_filter() && self.partitions.handle_filter_key(key) {
+            return;
+        }
+
+        match action {
+            Some(Action::Back) => self.view = DeviceView::Actions,
+            Some(Action::Up) => self.partitions.select_previous(),
+            Some(Action::Down) => self.partitions.select_next(),
+            Some(Action::Sort) => self.partitions.cycle_sort(),
+            Some(Action::Filter) => self.partitions.start_filter(),
+            Some(Action::Select) => {
+                let Some(part) = self.partitions.selected() else {
+                    return;
+                };
+                let (name, size) = (part.name.clone(), part.size);
+
+                let Some(range) = hexview::chunk_at(0, size) else {
+                    self.status_message =
+                        Some((format!("{} is empty", name), ctx.theme().pending));
+                    return;
+                };
+                match self.read_hex_chunk(&name, size, range).await {
+                    Ok(view) => self.view = DeviceView::Hex(view),
+                    Err(e) => {
+                        self.status_message =
+                            Some((format!("Reading {} failed: {}", name, e), ctx.theme().error));
+                    }
                 }
             }
-            None => Err("No device connected".to_string()),
+            _ => {}
         }
     }
+
+    async fn handle_hex_input(&mut self, ctx: &mut AppCtx, action: Option<Action>) {
+        let DeviceView::Hex(view) = &mut self.view else {
+            return;
+        };
+
+        let range = match action {
+            Some(Action::Back) => {
+                self.view = DeviceView::Partitions;
+                return;
+            }
+            Some(Action::Up) => return view.scroll_by(-1),
+            Some(Action::Down) => return view.scroll_by(1),
+            Some(Action::PageUp) => return view.page_up(),
+            Some(Action::PageDown) => return view.page_down(),
+            Some(Action::NextChunk) => view.next_chunk(),
+            Some(Action::PrevChunk) => view.prev_chunk(),
+            _ => return,
+        };
+
+        let Some(range) = range else {
+            return;
+        };
+        let (name, size) = (view.partition.clone(), view.partition_size);
+        match self.read_hex_chunk(&name, size, range).await {
+            Ok(view) => self.view = DeviceView::Hex(view),
+            Err(e) => {
+                self.status_message =
+                    Some((format!("Reading {} failed: {}", name, e), ctx.theme().error));
+            }
+        }
+    }
+
+    fn check_gpt(&mut self, ctx: &mut AppCtx) {
+        let Some(dev_arc) = self.queue_device(ctx) else {
+            return;
+        };
+        self.tasks.spawn("Check GPT", dev_arc, |mut dev| async move {
+            match dev.check_gpt().await {
+                Ok(report) if report.is_ok() => Ok("GPT is healthy.".to_string()),
+                Ok(report) => {
+                    let issues: Vec<String> = report.issues.iter().map(|i| i.to_string()).collect();
+                    Err(issues.join("\n"))
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        });
+    }
+
+    // Read only, so it's safe to run on any device
+    fn benchmark(&mut self, ctx: &mut AppCtx) {
+        let Some(dev_arc) = self.queue_device(ctx) else {
+            return;
+        };
+        self.tasks.spawn("Benchmark link", dev_arc, |mut dev| async move {
+            let results = dev.benchmark(BENCHMARK_SIZE).await.map_err(|e| e.to_string())?;
+            let lines: Vec<String> = results.iter().map(|r| r.to_string()).collect();
+            Ok(lines.join("\n"))
+        });
+    }
+
+    // The device for a new task, telling the user about it being queued
+    fn queue_device(&mut self, ctx: &mut AppCtx) -> Option<Arc<Mutex<Device<'static>>>> {
+        let Some(dev_arc) = self.device.clone() else {
+            self.status_message = Some(("No device connected".to_string(), ctx.theme().error));
+            return None;
+        };
+        let note = if self.tasks.is_busy() {
+            "Queued, see Tasks."
+        } else {
+            "Started, see Tasks."
+        };
+        self.status_message = Some((note.to_string(), ctx.theme().pending));
+        Some(dev_arc)
+    }
+
+    fn handle_tasks_input(&mut self, ctx: &mut AppCtx, action: Option<Action>) {
+        let DeviceView::Tasks(state) = &mut self.view else {
+            return;
+        };
+        let tasks = self.tasks.tasks();
+
+        match action {
+            Some(Action::Back) => self.view = DeviceView::Actions,
+            Some(Action::Up) => state.select_previous(),
+            Some(Action::Down) => state.select_next(),
+            Some(Action::Select) if !tasks.is_empty() => {
+                let task = &tasks[state.selected().unwrap_or(0).min(tasks.len() - 1)];
+                self.status_message = Some((task.details(), task.style(ctx.theme())));
+            }
+            _ => {}
+        }
+    }
+
+    async fn handle_confirm_input(&mut self, ctx: &mut AppCtx, action: Option<Action>) {
+        let Some((dialog, _)) = &mut self.confirm else {
+            return;
+        };
+        let Some(confirmed) = dialog.handle_input(action) else {
+            return;
+        };
+
+        let (dialog, flag) = self.confirm.take().unwrap();
+        if !confirmed {
+            self.status_message = Some((
+                format!("{} cancelled.", dialog.operation),
+                ctx.theme().pending,
+            ));
+            return;
+        }
+
+        let Some(dev_arc) = self.queue_device(ctx) else {
+            return;
+        };
+        self.tasks.spawn(dialog.operation.clone(), dev_arc, |mut dev| async move {
+            let seccfg = dev.set_seccfg_lock_state(flag).await.map_err(|e| e.to_string())?;
+            Ok(format!("wrote {} bytes to seccfg.", seccfg.len()))
+        });
+    }
 }
 
 #[async_trait::async_trait]
 impl Page for DevicePage {
+    fn help(&self) -> Vec<(Action, &'static str)> {
+        if self.confirm.is_some() {
+            return ConfirmDialog::help();
+        }
+        match self.view {
+            DeviceView::Actions => vec![
+                (Action::Up, "Previous action"),
+                (Action::Down, "Next action"),
+                (Action::Select, "Run the selected action"),
+                (Action::Back, "Cancel while waiting for a device"),
+            ],
+            DeviceView::Partitions => vec![
+                (Action::Up, "Previous partition"),
+                (Action::Down, "Next partition"),
+                (Action::Select, "Show a hexdump of the partition"),
+                (Action::Sort, "Sort by name, size or address"),
+                (Action::Filter, "Filter by name (Enter keeps it, Esc clears it)"),
+                (Action::Back, "Back to the actions"),
+            ],
+            DeviceView::Tasks(_) => vec![
+                (Action::Up, "Previous task"),
+                (Action::Down, "Next task"),
+                (Action::Select, "Show the task's result"),
+                (Action::Back, "Back to the actions"),
+            ],
+            DeviceView::Hex(_) => vec![
+                (Action::Up, "Scroll up"),
+                (Action::Down, "Scroll down"),
+                (Action::PageUp, "Previous page"),
+                (Action::PageDown, "Next page"),
+                (Action::PrevChunk, "Read the previous 64 KiB"),
+                (Action::NextChunk, "Read the next 64 KiB"),
+                (Action::Back, "Back to the partition list"),
+            ],
+        }
+    }
+
+    // '?' is just text while typing a partition filter
+    fn accepts_help_key(&self) -> bool {
+        !(matches!(self.view, DeviceView::Partitions) && self.partitions.is_editing_filter())
+    }
+
     async fn handle_input(&mut self, ctx: &mut AppCtx, key: KeyEvent) {
-        match key.code {
-            KeyCode::Up => {
+        let action = ctx.keymap().action(&key);
+        if self.confirm.is_some() {
+            return self.handle_confirm_input(ctx, action).await;
+        }
+        match self.view {
+            DeviceView::Partitions => {
+                return self.handle_partitions_input(ctx, &key, action).await;
+            }
+            DeviceView::Hex(_) => return self.handle_hex_input(ctx, action).await,
+            DeviceView::Tasks(_) => return self.handle_tasks_input(ctx, action),
+            DeviceView::Actions => {}
+        }
+
+        match action {
+            Some(Action::Back) if self.status == DeviceStatus::Initializing => {
+                if let Some(cancel) = self.cancel.take() {
+                    cancel.cancel();
+                }
+            }
+            Some(Action::Up) => {
                 let selected = self.actions_state.selected().unwrap_or(0);
                 let new = if selected == 0 {
                     self.actions.len() - 1
@@ -121,7 +551,7 @@ impl Page for DevicePage {
                 };
                 self.actions_state.select(Some(new));
             }
-            KeyCode::Down => {
+            Some(Action::Down) => {
                 let selected = self.actions_state.selected().unwrap_or(0);
                 let new = if selected + 1 == self.actions.len() {
                     0
@@ -130,31 +560,31 @@ impl Page for DevicePage {
                 };
                 self.actions_state.select(Some(new));
             }
-            KeyCode::Enter => {
+            Some(Action::Select) => {
                 let idx = self.actions_state.selected().unwrap_or(0);
                 match idx {
                     0 | 1 => {
-                        let flag = if idx == 0 {
-                            LockFlag::Unlock
+                        let (flag, operation) = if idx == 0 {
+                            (LockFlag::Unlock, "Unlock")
                         } else {
-                            LockFlag::Lock
+                            (LockFlag::Lock, "Lock")
                         };
-                        let action = if idx == 0 { "Unlock" } else { "Lock" };
-
-                        match self.set_device_lock_state(flag).await {
-                            Ok(_) => {
-                                self.status_message = Some((
-                                    format!("{} done.", action),
-                                    Style::default().fg(Color::Green).bg(Color::Black),
-                                ));
-                            }
-                            Err(e) => {
-                                self.status =
-                                    DeviceStatus::Error(format!("{} failed: {}", action, e));
-                            }
-                        }
+                        let risk = RiskLevel::for_write("seccfg", ctx.safety());
+                        let dialog = ConfirmDialog::new(operation, "seccfg", risk);
+                        self.confirm = Some((dialog, flag));
                     }
-                    2 => ctx.change_page(AppPage::Welcome),
+                    2 if self.device.is_some() => {
+                        self.status_message = None;
+                        self.view = DeviceView::Partitions;
+                    }
+                    3 => self.check_gpt(ctx),
+                    4 => self.benchmark(ctx),
+                    5 => {
+                        self.status_message = None;
+                        let last = self.tasks.tasks().len().checked_sub(1);
+                        self.view = DeviceView::Tasks(ListState::default().with_selected(last));
+                    }
+                    6 => ctx.change_page(AppPage::Welcome),
                     _ => {}
                 }
             }
@@ -162,7 +592,8 @@ impl Page for DevicePage {
         }
     }
 
-    fn render(&mut self, frame: &mut Frame<'_>, _ctx: &mut AppCtx) {
+    fn render(&mut self, frame: &mut Frame<'_>, ctx: &mut AppCtx) {
+        let theme = ctx.theme();
         let layout = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
@@ -173,25 +604,44 @@ impl Page for DevicePage {
             .split(frame.area());
 
         let (status_line, style) = match &self.status {
-            DeviceStatus::WaitingForDevice => (
-                "Waiting for device...".to_string(),
-                Style::default().fg(Color::Yellow).bg(Color::Black),
-            ),
+            DeviceStatus::WaitingForDevice => match &self.open_error {
+                Some(e) => (format!("Found the device but could not open it: {e}"), theme.error),
+                None => ("Waiting for device...".to_string(), theme.pending),
+            },
             DeviceStatus::Initializing => (
-                "Initializing device...".to_string(),
-                Style::default().fg(Color::Cyan).bg(Color::Black),
+                "Initializing device... (Esc to cancel)".to_string(),
+                theme.info,
             ),
             DeviceStatus::DAReady => (
-                "DA mode active.".to_string(),
-                Style::default().fg(Color::Green).bg(Color::Black),
+                match self.latency {
+                    Some(latency) => format!("DA mode active. (ping {} ms)", latency.as_millis()),
+                    None => "DA mode active.".to_string(),
+                },
+                theme.success,
+            ),
+            DeviceStatus::ConnectionLost(msg) => (
+                format!("Device stopped responding: {msg}. Reconnect it and go back to the menu"),
+                theme.error,
             ),
             DeviceStatus::Error(msg) => (
                 format!("Error: {msg}"),
-                Style::default().fg(Color::Red).bg(Color::Black),
+                theme.error,
             ),
         };
 
         let mut status_lines = vec![status_line];
+        if let Some((operation, done, total)) = &self.progress {
+            status_lines.push(match (done, total) {
+                (_, 0) => format!("{operation}..."),
+                _ => format!("{operation}: {}%", done * 100 / total),
+            });
+        }
+        if let Some(log) = &self.last_log {
+            status_lines.push(log.clone());
+        }
+        if self.status == DeviceStatus::WaitingForDevice {
+            status_lines.extend(self.hints.iter().map(|hint| format!("Hint: {hint}")));
+        }
         let paragraph_style = if let Some((msg, msg_style)) = &self.status_message {
             status_lines.push(msg.clone());
             msg_style.clone()
@@ -202,53 +652,195 @@ impl Page for DevicePage {
         frame.render_widget(
             Paragraph::new(status_lines.join("\n"))
                 .style(paragraph_style)
+                .wrap(Wrap { trim: true })
                 .block(Block::default().borders(Borders::ALL)),
             layout[0],
         );
 
-        let info_lines = match &self.device_info {
+        let mut info_lines = match &self.device_info {
             Some(info) => vec![
-                format!("SoC ID: {}", encode(&info.soc_id)),
-                format!("MeID: {}", encode(&info.meid)),
+                format!("SoC ID: {}", encode(&info.chip.soc_id)),
+                format!("MeID: {}", encode(&info.chip.meid)),
+                format!(
+                    "HW code: {:04X}  Sub: {:04X}  HW ver: {:04X}  SW ver: {:04X}",
+                    info.chip.hw_code, info.chip.hw_sub_code, info.chip.hw_ver, info.chip.sw_ver
+                ),
+                format!(
+                    "BROM ver: {}  Preloader ver: {}",
+                    info.br_ver
+                        .map_or("unknown".to_string(), |ver| format!("{:02X}", ver)),
+                    info.pl_ver
+                        .map_or("none (BROM mode)".to_string(), |ver| format!("{:02X}", ver)),
+                ),
             ],
             None => vec!["No device info available".to_string()],
         };
+        if let Some(health) = self.device_info.as_ref().and_then(|info| info.storage_health) {
+            let worn = if health.is_worn() { " (worn!)" } else { "" };
+            info_lines.push(format!("Storage: {}{}", health, worn));
+        }
+        if let Some(conn) = &self.connection {
+            let stats = conn.stats();
+            info_lines.push(format!(
+                "TX: {} KiB  RX: {} KiB  Retries: {}  Errors: {}",
+                stats.bytes_tx / 1024,
+                stats.bytes_rx / 1024,
+                stats.retries,
+                stats.errors
+            ));
+        }
 
         frame.render_widget(
             Paragraph::new(info_lines.join("\n"))
                 .block(Block::default().title("Device Info").borders(Borders::ALL))
-                .style(Style::default().fg(Color::Cyan)),
+                .style(theme.info),
             layout[1],
         );
 
-        let actions = self
-            .actions
-            .iter()
-            .map(|action| ListItem::new(action.clone()))
-            .collect::<Vec<_>>();
-
-        frame.render_stateful_widget(
-            List::new(actions)
-                .block(Block::default().title("Actions").borders(Borders::ALL))
-                .highlight_style(Style::default().bg(Color::Blue).fg(Color::White)),
-            layout[2],
-            &mut self.actions_state,
-        );
+        match &mut self.view {
+            DeviceView::Actions => {
+                let actions = self
+                    .actions
+                    .iter()
+                    .map(|action| ListItem::new(action.clone()))
+                    .collect::<Vec<_>>();
+
+                frame.render_stateful_widget(
+                    List::new(actions)
+                        .block(Block::default().title("Actions").borders(Borders::ALL))
+                        .highlight_style(theme.highlight),
+                    layout[2],
+                    &mut self.actions_state,
+                );
+            }
+            DeviceView::Partitions => {
+                // No GPT, say why instead of showing an empty table
+                let gpt_error = self.device_info.as_ref().and_then(|info| info.gpt_error.as_ref());
+                let (title, empty) = match gpt_error {
+                    Some(err) => {
+                        ("Partitions (raw mode)", format!("Partition table unreadable: {err}"))
+                    }
+                    None => ("Partitions", "No partitions match the filter".to_string()),
+                };
+                self.partitions.render(frame, layout[2], title, &empty, theme);
+            }
+            DeviceView::Hex(view) => view.render(frame, layout[2], theme),
+            DeviceView::Tasks(state) => {
+                let tasks = self
+                    .tasks
+                    .tasks()
+                    .iter()
+                    .map(|task| {
+                        let took = task
+                            .duration()
+                            .map_or(String::new(), |took| format!("{:.1?}", took));
+                        ListItem::new(format!("{:<8} {:<24} {:>10}", task.label(), task.name, took))
+                            .style(task.style(theme))
+                    })
+                    .collect::<Vec<_>>();
+                let title = if self.tasks.is_busy() { "Tasks (running)" } else { "Tasks" };
+
+                frame.render_stateful_widget(
+                    List::new(tasks)
+                        .block(Block::default().title(title).borders(Borders::ALL))
+                        .highlight_style(theme.highlight),
+                    layout[2],
+                    state,
+                );
+            }
+        }
+
+        if let Some((dialog, _)) = &self.confirm {
+            dialog.render(frame, theme);
+        }
     }
 
-    async fn on_enter(&mut self, _ctx: &mut AppCtx) {
+    async fn on_enter(&mut self, ctx: &mut AppCtx) {
+        // Whatever got logged before this page was opened isn't about this device
+        ctx.take_events();
         self.actions_state.select(Some(0));
         self.status = DeviceStatus::WaitingForDevice;
         self.last_poll = Instant::now();
         self.device = None;
-        self.device_info = None;
+        self.set_device_info(None);
+        self.info_rx = None;
+        if let Some(task) = self.init_task.take() {
+            task.abort();
+        }
+        self.cancel = None;
+        self.heartbeat = None;
+        self.latency = None;
+        self.connection = None;
+        self.view = DeviceView::Actions;
+        self.confirm = None;
+        self.tasks = TaskList::new();
+        self.hints = diagnose(None);
+        self.open_error = None;
     }
 
-    async fn on_exit(&mut self, _ctx: &mut AppCtx) {}
+    async fn on_exit(&mut self, _ctx: &mut AppCtx) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel();
+        }
+        // The token only covers the handshake, a DA upload in flight would keep
+        // the port busy after leaving the page
+        if let Some(task) = self.init_task.take() {
+            task.abort();
+        }
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+    }
 
     async fn update(&mut self, ctx: &mut AppCtx) {
+        for event in ctx.take_events() {
+            self.handle_event(event);
+        }
+        // The latest result goes on top, older ones stay in the Tasks view
+        if let Some(task) = self.tasks.poll().last() {
+            self.status_message = Some((task.details(), task.style(ctx.theme())));
+        }
+        if let Some(info_rx) = &mut self.info_rx
+            && info_rx.has_changed().unwrap_or(false)
+        {
+            let info = info_rx.borrow_and_update().clone();
+            self.set_device_info(Some(info));
+        }
         if let Err(e) = self.poll_device(ctx).await {
             self.status = e;
         }
     }
 }
+
+fn partition_table() -> DataTable<Partition> {
+    DataTable::new(
+        vec![
+            Column::new("Name", Constraint::Min(16)),
+            Column::new("Size", Constraint::Length(12)),
+            Column::new("Address", Constraint::Length(14)),
+        ],
+        Vec::new(),
+    )
+}
+
+impl TableRow for Partition {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            human_size(self.size),
+            format!("{:#X}", self.address),
+        ]
+    }
+
+    fn compare(&self, other: &Self, column: usize) -> Ordering {
+        match column {
+            0 => self.name.cmp(&other.name),
+            1 => self.size.cmp(&other.size),
+            _ => self.address.cmp(&other.address),
+        }
+    }
+
+    fn filter_text(&self) -> &str {
+        &self.name
+    }
+}
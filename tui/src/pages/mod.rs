@@ -8,6 +8,7 @@ pub use device::DevicePage;
 pub use welcome::WelcomePage;
 
 use crate::app::AppCtx;
+use crate::keys::Action;
 use ratatui::Frame;
 use ratatui::crossterm::event::KeyEvent;
 
@@ -21,4 +22,12 @@ pub trait Page {
     async fn on_enter(&mut self, _ctx: &mut AppCtx) {}
     async fn on_exit(&mut self, _ctx: &mut AppCtx) {}
     async fn update(&mut self, _ctx: &mut AppCtx) {}
+    // Bindings shown in the help overlay for this page
+    fn help(&self) -> Vec<(Action, &'static str)> {
+        Vec::new()
+    }
+    // Pages with text input (e.g. a file browser) can return false to get '?' themselves
+    fn accepts_help_key(&self) -> bool {
+        true
+    }
 }
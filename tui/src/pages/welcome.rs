@@ -3,12 +3,17 @@
     SPDX-FileCopyrightText: 2025 Shomy
 */
 use crate::app::{AppCtx, AppPage};
+use crate::keys::Action;
 use crate::pages::Page;
-use penumbra::da::DAFile;
+use crate::recent::MAX_RECENT;
+use log::error;
+use penumbra::core::audit::format_timestamp;
+use penumbra::core::profile::DeviceProfile;
+use penumbra::da::bundle::{BUNDLE_EXTENSION, load_loader};
 use ratatui::crossterm::event::{Event, KeyCode, KeyEvent};
 use ratatui::{prelude::*, widgets::*};
-use ratatui_explorer::{FileExplorer, Theme};
-use std::{fs};
+use ratatui_explorer::{FileExplorer, Theme as ExplorerTheme};
+use std::path::Path;
 
 use super::LOGO;
 
@@ -37,10 +42,119 @@ pub struct WelcomePage {
     state: WelcomeState,
     selected_idx: usize,
     loader_name: Option<String>,
+    // Why the last file picked couldn't be used as a loader
+    load_error: Option<String>,
+    // From the profile store, newest first, refreshed every time the page shows up
+    recent_devices: Vec<DeviceProfile>,
+}
+
+impl WelcomePage {
+    // Selects a DA file or loader bundle, and puts it on top of the recent ones
+    fn select_loader(&mut self, ctx: &mut AppCtx, path: &Path) -> bool {
+        match load_loader(path) {
+            Ok((da_file, bundle)) => {
+                // Bundles carry their own name, plain DAs use the filename
+                let bundle_name = bundle
+                    .map(|b| b.metadata.name)
+                    .filter(|name| !name.is_empty());
+                self.loader_name = Some(bundle_name.unwrap_or_else(|| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .unwrap_or("Unnamed DA")
+                        .to_string()
+                }));
+                ctx.set_loader(da_file);
+                if let Some(recent) = ctx.recent_mut() {
+                    recent.push(path);
+                }
+                self.load_error = None;
+                true
+            }
+            Err(err) => {
+                self.load_error = Some(format!("Could not load {}: {}", path.display(), err));
+                false
+            }
+        }
+    }
+
+    // 1-4 pick a recent loader, 5-8 connect to a recent device with the loader
+    // that worked on it last time
+    fn quick_pick(&mut self, ctx: &mut AppCtx, key: &KeyEvent) {
+        let Some(idx) = (match key.code {
+            KeyCode::Char(c @ '1'..='9') => Some(c as usize - '1' as usize),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        if idx < MAX_RECENT {
+            let Some(path) = ctx.recent().and_then(|r| r.entries().get(idx)).cloned() else {
+                return;
+            };
+            self.select_loader(ctx, &path);
+            return;
+        }
+
+        let Some(profile) = self.recent_devices.get(idx - MAX_RECENT) else {
+            return;
+        };
+        // A DA picked by hand isn't in the catalog, whatever is selected now is used
+        let entry = profile
+            .loader
+            .as_deref()
+            .and_then(|name| ctx.catalog().find_named(name, profile.hw_code))
+            .cloned();
+        if let Some(entry) = entry {
+            self.loader_name = Some(entry.name);
+            ctx.set_loader(entry.da);
+            self.load_error = None;
+        }
+        ctx.change_page(AppPage::DevicePage);
+    }
+
+    fn recent_lines(&self, ctx: &AppCtx) -> Vec<Line<'static>> {
+        let mut lines = vec![Line::from("Recent loaders").style(ctx.theme().info)];
+        let loaders = ctx.recent().map(|r| r.entries()).unwrap_or_default();
+        if loaders.is_empty() {
+            lines.push(Line::from("  None yet"));
+        }
+        for (i, path) in loaders.iter().enumerate() {
+            lines.push(Line::from(format!("  [{}] {}", i + 1, path.display())));
+        }
+
+        lines.push(Line::from(""));
+        lines.push(Line::from("Recent devices").style(ctx.theme().info));
+        if self.recent_devices.is_empty() {
+            lines.push(Line::from("  None yet"));
+        }
+        for (i, profile) in self.recent_devices.iter().enumerate() {
+            let soc_id = profile.soc_id.get(..16).unwrap_or(&profile.soc_id);
+            lines.push(Line::from(format!(
+                "  [{}] {:04X} {}  {}, seen {}",
+                MAX_RECENT + i + 1,
+                profile.hw_code,
+                soc_id,
+                profile.loader.as_deref().unwrap_or("DA picked by hand"),
+                format_timestamp(profile.last_seen)
+            )));
+        }
+        lines
+    }
 }
 
 #[async_trait::async_trait]
 impl Page for WelcomePage {
+    async fn on_enter(&mut self, ctx: &mut AppCtx) {
+        let max = ctx.recent().map_or(0, |recent| recent.max());
+        self.recent_devices = match ctx.profiles() {
+            Some(profiles) if max > 0 => profiles.recent(max).unwrap_or_else(|e| {
+                error!("Failed to load recent devices: {}", e);
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        };
+    }
+
     fn render(&mut self, f: &mut Frame<'_>, ctx: &mut AppCtx) {
         let area = f.area();
 
@@ -59,14 +173,25 @@ impl Page for WelcomePage {
         f.render_widget(logo, vertical_chunks[0]);
 
         // Loader info (show filename or None)
-        let loader_text = ctx.loader()
+        let loader_text = ctx
+            .loader()
             .as_ref()
-            .map(|_| format!("Selected Loader: {}", self.loader_name.as_deref().unwrap_or("Unnamed DA")))
-            .unwrap_or_else(|| "Selected Loader: None".to_string());
+            .map(|_| {
+                format!(
+                    "Selected Loader: {}",
+                    self.loader_name.as_deref().unwrap_or("Unnamed DA")
+                )
+            })
+            .unwrap_or_else(|| match ctx.catalog().entries().len() {
+                0 => "Selected Loader: None".to_string(),
+                n => format!("Selected Loader: Auto (by hw code, {} available)", n),
+            });
 
-        let loader_paragraph = Paragraph::new(loader_text)
-            .style(Style::default().fg(Color::Yellow))
-            .alignment(Alignment::Center);
+        let loader_paragraph = match &self.load_error {
+            Some(err) => Paragraph::new(err.as_str()).style(ctx.theme().error),
+            None => Paragraph::new(loader_text).style(ctx.theme().pending),
+        }
+        .alignment(Alignment::Center);
         f.render_widget(loader_paragraph, vertical_chunks[1]);
 
         // Split horizontal: menu | explorer
@@ -85,74 +210,87 @@ impl Page for WelcomePage {
         list_state.select(Some(self.selected_idx));
         let menu_list = List::new(items)
             .block(block)
-            .highlight_style(Style::default().bg(Color::Gray).fg(Color::Black))
+            .highlight_style(ctx.theme().highlight)
             .highlight_symbol(">> ");
         f.render_stateful_widget(menu_list, horizontal_chunks[0], &mut list_state);
 
-        // File explorer
-        if let WelcomeState::Browsing(explorer) = &mut self.state {
-            f.render_widget(&explorer.widget(), horizontal_chunks[1]);
+        // File explorer, or the quick picks while it's closed
+        match &mut self.state {
+            WelcomeState::Browsing(explorer) => {
+                f.render_widget(&explorer.widget(), horizontal_chunks[1]);
+            }
+            WelcomeState::Idle if ctx.recent().is_some() => {
+                let block = Block::default().title("Recent").borders(Borders::ALL);
+                let recent = Paragraph::new(self.recent_lines(ctx)).block(block);
+                f.render_widget(recent, horizontal_chunks[1]);
+            }
+            WelcomeState::Idle => {}
+        }
+    }
+
+    fn help(&self) -> Vec<(Action, &'static str)> {
+        match self.state {
+            WelcomeState::Browsing(_) => vec![
+                (Action::Select, "Load the selected DA file"),
+                (Action::Back, "Close the file browser"),
+            ],
+            WelcomeState::Idle => vec![
+                (Action::Up, "Previous menu entry"),
+                (Action::Down, "Next menu entry"),
+                (Action::Select, "Confirm"),
+            ],
         }
     }
 
     async fn handle_input(&mut self, ctx: &mut AppCtx, key: KeyEvent) {
+        let action = ctx.keymap().action(&key);
+
         match &mut self.state {
             WelcomeState::Browsing(explorer) => {
                 if let Err(err) = explorer.handle(&Event::Key(key)) {
-                    unimplemented!("Error handling unimplemented: {:?}", err);
+                    self.load_error = Some(format!("File browser error: {}", err));
                 };
 
-                if key.code == KeyCode::Enter {
+                if action == Some(Action::Select) {
                     if !explorer.files().is_empty() {
                         let selected_file = &explorer.files()[explorer.selected_idx()];
-                        let path = &selected_file.path();
-
-                        if path.extension().map_or(false, |ext| ext == "bin") {
-                            match fs::read(path) {
-                                Ok(raw_data) => match DAFile::parse_da(&raw_data) {
-                                    Ok(da_file) => {
-                                        self.loader_name = Some(
-                                            path.file_name()
-                                                .and_then(|name| name.to_str())
-                                                .unwrap_or("Unnamed DA")
-                                                .to_string(),
-                                        );
-                                        self.state = WelcomeState::Idle;
-                                        ctx.set_loader(da_file);
-                                    }
-                                    Err(err) => {
-                                        unimplemented!("Error handling unimplemented: {:?}", err);
-                                    }
-                                },
-                                Err(err) => {
-                                    unimplemented!("Error handling unimplemented: {:?}", err);
-                                }
-                            }
+                        // Owned, the explorer stays borrowed otherwise
+                        let path = selected_file.path().to_path_buf();
+
+                        let is_loader = path
+                            .extension()
+                            .map_or(false, |ext| ext == "bin" || ext == BUNDLE_EXTENSION);
+
+                        if is_loader && self.select_loader(ctx, &path) {
+                            self.state = WelcomeState::Idle;
                         }
                     }
                 }
 
-                if key.code == KeyCode::Esc {
+                if action == Some(Action::Back) {
                     self.state = WelcomeState::Idle;
                 }
             }
 
-            WelcomeState::Idle => match key.code {
-                KeyCode::Up => {
+            WelcomeState::Idle => match action {
+                Some(Action::Up) => {
                     if self.selected_idx > 0 {
                         self.selected_idx -= 1;
                     }
                 }
-                KeyCode::Down => {
+                Some(Action::Down) => {
                     if self.selected_idx < MENU_ITEMS.len() - 1 {
                         self.selected_idx += 1;
                     }
                 }
-                KeyCode::Enter => {
+                Some(Action::Select) => {
                     let action = MENU_ITEMS[self.selected_idx].0;
                     match action {
                         MenuAction::SelectDa => {
-                            let theme = Theme::default().add_default_title();
+                            let theme = ExplorerTheme::default()
+                                .add_default_title()
+                                .with_highlight_item_style(ctx.theme().highlight)
+                                .with_highlight_dir_style(ctx.theme().highlight);
                             match FileExplorer::with_theme(theme) {
                                 Ok(explorer) => {
                                     self.state = WelcomeState::Browsing(explorer);
@@ -163,9 +301,10 @@ impl Page for WelcomePage {
                             }
                         }
                         MenuAction::EnterDaMode => ctx.change_page(AppPage::DevicePage),
-                        MenuAction::Quit => ctx.quit()
+                        MenuAction::Quit => ctx.quit(),
                     }
                 }
+                None if ctx.recent().is_some() => self.quick_pick(ctx, &key),
                 _ => {}
             },
         }
@@ -0,0 +1,78 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::config::config_path;
+use log::error;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub const RECENT_LOADERS_FILE: &str = "recent_loaders";
+// Both lists share the digit keys on the welcome page, loaders get 1-4 and devices 5-8
+pub const MAX_RECENT: usize = 4;
+
+// DA files picked by hand, newest first, one path per line next to settings.conf.
+// Devices don't need their own list, the profile store already knows when each
+// one was last seen (see ProfileStore::recent).
+#[derive(Debug, Clone)]
+pub struct RecentLoaders {
+    path: PathBuf,
+    max: usize,
+    entries: Vec<PathBuf>,
+}
+
+impl RecentLoaders {
+    // A missing or unreadable file is just an empty list
+    pub fn load(path: PathBuf, max: usize) -> Self {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(data) => data
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from)
+                .take(max)
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                error!("Failed to load {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self { path, max, entries }
+    }
+
+    pub fn load_default(max: usize) -> Option<Self> {
+        config_path(RECENT_LOADERS_FILE).map(|path| Self::load(path, max))
+    }
+
+    pub fn entries(&self) -> &[PathBuf] {
+        &self.entries
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    // Moves `path` to the top, dropping the oldest entry when full
+    pub fn push(&mut self, path: &Path) {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.entries.retain(|entry| *entry != path);
+        self.entries.insert(0, path);
+        self.entries.truncate(self.max);
+        if let Err(e) = self.save() {
+            error!("Failed to save {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut data = String::new();
+        for entry in &self.entries {
+            data.push_str(&entry.to_string_lossy());
+            data.push('\n');
+        }
+        std::fs::write(&self.path, data)
+    }
+}
@@ -0,0 +1,73 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::config::config_path;
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const SETTINGS_FILE: &str = "settings.conf";
+
+// Flat `key = value` settings, one per line, `#` starts a comment:
+//
+//   key.up = Up, k
+//   key.help = ?
+//   theme = high-contrast
+//   da_extension = /path/to/da_x.bin
+//   loader_dir = /path/to/loaders
+//   auto_backup = on
+//   power_profile = low
+//   write_rate = 512
+//   profiles = off
+//   recent = off
+//   exploit = skip
+//   da_log_level = debug
+//   da_log_channel = usb
+//   policy_file = /path/to/.penumbra.conf
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    values: HashMap<String, String>,
+}
+
+impl Settings {
+    pub fn parse(content: &str) -> Self {
+        let values = content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        Self { values }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    // Loads settings.conf from the config dir, falling back to defaults if it's missing
+    pub fn load_default() -> Self {
+        let Some(path) = config_path(SETTINGS_FILE) else {
+            return Self::default();
+        };
+        if !path.exists() {
+            return Self::default();
+        }
+
+        match Self::load(&path) {
+            Ok(settings) => {
+                info!("Loaded settings from {}", path.display());
+                settings
+            }
+            Err(e) => {
+                error!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+}
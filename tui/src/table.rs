@@ -0,0 +1,215 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::theme::Theme;
+use ratatui::Frame;
+use ratatui::crossterm::event::{KeyCode, KeyEvent};
+use ratatui::layout::{Constraint, Rect};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState, Wrap};
+use std::cmp::Ordering;
+
+// Something a DataTable can show, sort and filter
+pub trait TableRow {
+    // One string per column
+    fn cells(&self) -> Vec<String>;
+    fn compare(&self, other: &Self, column: usize) -> Ordering;
+    // What the filter is matched against, case insensitive
+    fn filter_text(&self) -> &str;
+}
+
+pub struct Column {
+    pub title: &'static str,
+    pub width: Constraint,
+}
+
+impl Column {
+    pub fn new(title: &'static str, width: Constraint) -> Self {
+        Self { title, width }
+    }
+}
+
+// Table with a selection, sorting by any column and an incremental filter.
+// Rows are kept as given, sorting and filtering only change what's shown.
+pub struct DataTable<T> {
+    columns: Vec<Column>,
+    rows: Vec<T>,
+    // Indexes into `rows`, filtered and sorted
+    visible: Vec<usize>,
+    state: TableState,
+    // Column and whether it's descending, None keeps the original order
+    sort: Option<(usize, bool)>,
+    filter: String,
+    editing_filter: bool,
+}
+
+impl<T: TableRow> DataTable<T> {
+    pub fn new(columns: Vec<Column>, rows: Vec<T>) -> Self {
+        let mut table = Self {
+            columns,
+            rows,
+            visible: Vec::new(),
+            state: TableState::default(),
+            sort: None,
+            filter: String::new(),
+            editing_filter: false,
+        };
+        table.refresh();
+        table
+    }
+
+    // New data (e.g. the partition table was read again), sorting and filter stay
+    pub fn set_rows(&mut self, rows: Vec<T>) {
+        self.rows = rows;
+        self.refresh();
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        let idx = *self.visible.get(self.state.selected()?)?;
+        self.rows.get(idx)
+    }
+
+    pub fn select_next(&mut self) {
+        let last = self.visible.len().checked_sub(1);
+        let next = self.state.selected().map_or(0, |i| i + 1);
+        self.state.select(last.map(|last| next.min(last)));
+    }
+
+    pub fn select_previous(&mut self) {
+        let previous = self.state.selected().map_or(0, |i| i.saturating_sub(1));
+        self.state
+            .select((!self.visible.is_empty()).then_some(previous));
+    }
+
+    // Ascending, descending, then the next column. Back to the original order
+    // after the last one.
+    pub fn cycle_sort(&mut self) {
+        self.sort = match self.sort {
+            None => Some((0, false)),
+            Some((column, false)) => Some((column, true)),
+            Some((column, true)) if column + 1 < self.columns.len() => Some((column + 1, false)),
+            Some(_) => None,
+        };
+        self.refresh();
+    }
+
+    pub fn start_filter(&mut self) {
+        self.editing_filter = true;
+    }
+
+    pub fn is_editing_filter(&self) -> bool {
+        self.editing_filter
+    }
+
+    // Typing while the filter is being edited. Enter keeps the filter, Esc
+    // drops it. Returns false for keys it doesn't use.
+    pub fn handle_filter_key(&mut self, key: &KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char(c) => self.filter.push(c),
+            KeyCode::Backspace => {
+                self.filter.pop();
+            }
+            KeyCode::Enter => self.editing_filter = false,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.editing_filter = false;
+            }
+            _ => return false,
+        }
+        self.refresh();
+        true
+    }
+
+    fn refresh(&mut self) {
+        let selected = self.selected_index();
+        let filter = self.filter.to_lowercase();
+        self.visible = (0..self.rows.len())
+            .filter(|&i| self.rows[i].filter_text().to_lowercase().contains(&filter))
+            .collect();
+        if let Some((column, descending)) = self.sort {
+            let rows = &self.rows;
+            self.visible.sort_by(|&a, &b| {
+                let order = rows[a].compare(&rows[b], column);
+                if descending { order.reverse() } else { order }
+            });
+        }
+
+        // Stay on the same row if it's still there
+        let position = selected
+            .and_then(|idx| self.visible.iter().position(|&i| i == idx))
+            .or((!self.visible.is_empty()).then_some(0));
+        self.state.select(position);
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.visible.get(self.state.selected()?).copied()
+    }
+
+    // `empty` is shown instead of the table when no row is visible
+    pub fn render(
+        &mut self,
+        frame: &mut Frame<'_>,
+        area: Rect,
+        title: &str,
+        empty: &str,
+        theme: &Theme,
+    ) {
+        let mut title = format!("{} ({}/{})", title, self.visible.len(), self.rows.len());
+        if self.editing_filter || !self.filter.is_empty() {
+            let cursor = if self.editing_filter { "_" } else { "" };
+            title.push_str(&format!(" filter: {}{}", self.filter, cursor));
+        }
+        let block = Block::default().title(title).borders(Borders::ALL);
+
+        if self.visible.is_empty() {
+            frame.render_widget(
+                Paragraph::new(empty)
+                    .style(theme.info)
+                    .wrap(Wrap { trim: true })
+                    .block(block),
+                area,
+            );
+            return;
+        }
+
+        let header = Row::new(
+            self.columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| match self.sort {
+                    Some((sorted, descending)) if sorted == i => {
+                        format!("{} {}", column.title, if descending { "v" } else { "^" })
+                    }
+                    _ => column.title.to_string(),
+                }),
+        )
+        .style(theme.info);
+        let rows = self.visible.iter().map(|&i| Row::new(self.rows[i].cells()));
+        let widths: Vec<Constraint> = self.columns.iter().map(|column| column.width).collect();
+
+        frame.render_stateful_widget(
+            Table::new(rows, widths)
+                .header(header)
+                .block(block)
+                .row_highlight_style(theme.highlight),
+            area,
+            &mut self.state,
+        );
+    }
+}
+
+// 1536 -> "1.5 KiB", small values stay in bytes
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,171 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::theme::Theme;
+use penumbra::Device;
+use ratatui::style::Style;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedMutexGuard, mpsc};
+
+// What a task ends with, a short summary either way
+pub type TaskResult = Result<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    // Waiting for the device, another task (or the heartbeat) has it
+    Queued,
+    Running,
+    Done(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub name: String,
+    pub state: TaskState,
+    queued: Instant,
+    started: Option<Instant>,
+    took: Option<Duration>,
+}
+
+impl Task {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, TaskState::Done(_) | TaskState::Failed(_))
+    }
+
+    // Time spent running, so far or in total. Queued time doesn't count.
+    pub fn duration(&self) -> Option<Duration> {
+        self.took
+            .or_else(|| self.started.map(|started| started.elapsed()))
+    }
+
+    pub fn waited(&self) -> Duration {
+        match self.started {
+            Some(started) => started - self.queued,
+            None => self.queued.elapsed(),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self.state {
+            TaskState::Queued => "queued",
+            TaskState::Running => "running",
+            TaskState::Done(_) => "done",
+            TaskState::Failed(_) => "failed",
+        }
+    }
+
+    pub fn style(&self, theme: &Theme) -> Style {
+        match self.state {
+            TaskState::Queued => theme.pending,
+            TaskState::Running => theme.info,
+            TaskState::Done(_) => theme.success,
+            TaskState::Failed(_) => theme.error,
+        }
+    }
+
+    // The result (or error) in full, for when the task gets selected
+    pub fn details(&self) -> String {
+        match &self.state {
+            TaskState::Queued => format!(
+                "{} is waiting for the device ({:.1?})",
+                self.name,
+                self.waited()
+            ),
+            TaskState::Running => format!("{} is running", self.name),
+            TaskState::Done(msg) => format!("{}: {}", self.name, msg),
+            TaskState::Failed(err) => format!("{} failed: {}", self.name, err),
+        }
+    }
+}
+
+enum TaskUpdate {
+    Started(usize),
+    Finished(usize, TaskResult),
+}
+
+// Device operations run as tasks, one at a time in the order they were queued.
+// Ordering comes from the device mutex, which hands out the lock first come
+// first served, so queueing is just spawning.
+pub struct TaskList {
+    tasks: Vec<Task>,
+    tx: mpsc::UnboundedSender<TaskUpdate>,
+    rx: mpsc::UnboundedReceiver<TaskUpdate>,
+}
+
+impl TaskList {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            tasks: Vec::new(),
+            tx,
+            rx,
+        }
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.tasks.iter().any(|task| !task.is_finished())
+    }
+
+    // Queues `op` to run once it gets the device
+    pub fn spawn<F, Fut>(
+        &mut self,
+        name: impl Into<String>,
+        device: Arc<Mutex<Device<'static>>>,
+        op: F,
+    ) where
+        F: FnOnce(OwnedMutexGuard<Device<'static>>) -> Fut + Send + 'static,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+    {
+        let id = self.tasks.len();
+        self.tasks.push(Task {
+            name: name.into(),
+            state: TaskState::Queued,
+            queued: Instant::now(),
+            started: None,
+            took: None,
+        });
+
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let dev = device.lock_owned().await;
+            let _ = tx.send(TaskUpdate::Started(id));
+            let result = op(dev).await;
+            let _ = tx.send(TaskUpdate::Finished(id, result));
+        });
+    }
+
+    // Applies what the tasks reported since the last call, returns the tasks
+    // that finished
+    pub fn poll(&mut self) -> Vec<&Task> {
+        let mut finished = Vec::new();
+        while let Ok(update) = self.rx.try_recv() {
+            match update {
+                TaskUpdate::Started(id) => {
+                    if let Some(task) = self.tasks.get_mut(id) {
+                        task.state = TaskState::Running;
+                        task.started = Some(Instant::now());
+                    }
+                }
+                TaskUpdate::Finished(id, result) => {
+                    if let Some(task) = self.tasks.get_mut(id) {
+                        task.took = task.started.map(|started| started.elapsed());
+                        task.state = match result {
+                            Ok(msg) => TaskState::Done(msg),
+                            Err(err) => TaskState::Failed(err),
+                        };
+                        finished.push(id);
+                    }
+                }
+            }
+        }
+        finished.iter().map(|&id| &self.tasks[id]).collect()
+    }
+}
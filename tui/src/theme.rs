@@ -0,0 +1,97 @@
+/*
+    SPDX-License-Identifier: AGPL-3.0-or-later
+    SPDX-FileCopyrightText: 2025 Shomy
+*/
+use crate::settings::Settings;
+use log::warn;
+use ratatui::style::{Color, Modifier, Style};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThemeKind {
+    #[default]
+    Default,
+    // Avoids telling states apart by red/green alone
+    HighContrast,
+    // No colors at all, only bold/reversed. Also picked when NO_COLOR is set
+    Monochrome,
+}
+
+impl ThemeKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "default" => Some(ThemeKind::Default),
+            "high-contrast" | "high_contrast" | "highcontrast" => Some(ThemeKind::HighContrast),
+            "monochrome" | "mono" | "none" => Some(ThemeKind::Monochrome),
+            _ => None,
+        }
+    }
+}
+
+// Styles for the things pages need to color, so they don't pick colors themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    // Waiting on the user or the device, selected loader
+    pub pending: Style,
+    // Work in progress, informational panels
+    pub info: Style,
+    pub success: Style,
+    pub error: Style,
+    // Selected entry in lists and the file explorer
+    pub highlight: Style,
+}
+
+impl Theme {
+    pub fn new(kind: ThemeKind) -> Self {
+        match kind {
+            ThemeKind::Default => Self {
+                pending: Style::default().fg(Color::Yellow).bg(Color::Black),
+                info: Style::default().fg(Color::Cyan).bg(Color::Black),
+                success: Style::default().fg(Color::Green).bg(Color::Black),
+                error: Style::default().fg(Color::Red).bg(Color::Black),
+                highlight: Style::default().bg(Color::Blue).fg(Color::White),
+            },
+            ThemeKind::HighContrast => Self {
+                pending: Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                info: Style::default().fg(Color::White),
+                success: Style::default().fg(Color::LightBlue).add_modifier(Modifier::BOLD),
+                error: Style::default()
+                    .fg(Color::LightMagenta)
+                    .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                highlight: Style::default()
+                    .bg(Color::White)
+                    .fg(Color::Black)
+                    .add_modifier(Modifier::BOLD),
+            },
+            ThemeKind::Monochrome => Self {
+                pending: Style::default().add_modifier(Modifier::ITALIC),
+                info: Style::default(),
+                success: Style::default().add_modifier(Modifier::BOLD),
+                error: Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
+                highlight: Style::default().add_modifier(Modifier::REVERSED),
+            },
+        }
+    }
+
+    // `theme = default | high-contrast | monochrome` in settings.conf.
+    // Without it, NO_COLOR (https://no-color.org) selects the monochrome theme.
+    pub fn from_settings(settings: &Settings) -> Self {
+        let kind = match settings.get("theme") {
+            Some(name) => ThemeKind::from_name(name).unwrap_or_else(|| {
+                warn!("Unknown theme '{}', using the default one", name);
+                ThemeKind::Default
+            }),
+            None if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => {
+                ThemeKind::Monochrome
+            }
+            None => ThemeKind::Default,
+        };
+
+        Self::new(kind)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::new(ThemeKind::Default)
+    }
+}